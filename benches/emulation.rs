@@ -0,0 +1,87 @@
+/*
+ The hot paths: the CPU's instruction dispatch, the PPU drawing scanlines
+ and whole frames through `Nes`. `cargo bench` runs them and criterion
+ compares each run with the last one it kept under target/criterion.
+*/
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use nes::{
+    cartridge::{Cartridge, Mirroring},
+    Nes,
+};
+
+// a dot a tick, a scanline's worth
+const SCANLINE_DOTS: usize = 341;
+const STEPS: u64 = 1000;
+
+// busy in RAM with the background and sprites on, the NMI handler does an
+// OAM DMA like most games
+fn nes() -> Nes {
+    let mut prgrom = vec![0; 0x4000];
+    let program = [
+        0xa9, 0x80, 0x8d, 0x00, 0x20, // $8000: LDA #$80, STA $2000
+        0xa9, 0x1e, 0x8d, 0x01, 0x20, // LDA #$1E, STA $2001
+        0xa2, 0x00, // LDX #$00
+        0xb5, 0x00, 0x18, 0x69, 0x01, // $800C: LDA $00,X, CLC, ADC #$01
+        0x9d, 0x00, 0x02, 0xe8, // STA $0200,X, INX
+        0x4c, 0x0c, 0x80, // JMP $800C
+        0xa9, 0x02, 0x8d, 0x14, 0x40, 0x40, // $8018 NMI: LDA #$02, STA $4014, RTI
+    ];
+    prgrom[..program.len()].copy_from_slice(&program);
+    prgrom[0x3ffa..].copy_from_slice(&[0x18, 0x80, 0x00, 0x80, 0x00, 0x80]);
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge {
+        prgrom,
+        chrrom: (0..0x2000).map(|n| (n * 7) as u8).collect(),
+        mirroring: Mirroring::Vertical,
+        ..Default::default()
+    })
+    .unwrap();
+    // past the first frame, where the PPU's switched on
+    nes.run_frame().unwrap();
+    nes.run_frame().unwrap();
+    nes
+}
+
+fn cpu(c: &mut Criterion) {
+    let mut nes = nes();
+    let mut group = c.benchmark_group("cpu");
+    group.throughput(Throughput::Elements(STEPS));
+    group.bench_function("step", |b| {
+        b.iter(|| {
+            for _ in 0..STEPS {
+                black_box(nes.cpu_mut().step());
+            }
+        })
+    });
+    group.finish()
+}
+
+fn ppu(c: &mut Criterion) {
+    let mut nes = nes();
+    let mut group = c.benchmark_group("ppu");
+    group.throughput(Throughput::Elements(SCANLINE_DOTS as u64));
+    group.bench_function("scanline", |b| {
+        b.iter(|| nes.cpu_mut().ppu_mut().tick(black_box(SCANLINE_DOTS)))
+    });
+    group.finish()
+}
+
+fn frame(c: &mut Criterion) {
+    let mut nes = nes();
+    let mut group = c.benchmark_group("nes");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("frame", |b| {
+        b.iter(|| black_box(nes.run_frame().unwrap().data()[0]))
+    });
+    nes.set_skip_rendering(true);
+    group.bench_function("frame_skipped", |b| {
+        b.iter(|| black_box(nes.run_frame().unwrap().data()[0]))
+    });
+    group.finish()
+}
+
+criterion_group!(benches, cpu, ppu, frame);
+criterion_main!(benches);
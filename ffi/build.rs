@@ -0,0 +1,15 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=../src/ffi.rs");
+    println!("cargo:rerun-if-changed=../cbindgen.toml");
+    let out = std::env::var("OUT_DIR").expect("cargo sets this");
+    // what include/nes.h should be, the tests check it is
+    let config =
+        cbindgen::Config::from_file("../cbindgen.toml").expect("Unable to read cbindgen.toml");
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src("../src/ffi.rs")
+        .generate()
+        .expect("Unable to generate the C header")
+        .write_to_file(format!("{}/nes.h", out));
+}
@@ -0,0 +1,23 @@
+/*
+ The C API from the `ffi` feature as a shared library: `cargo build
+ --release -p nes-ffi` makes libnes_ffi.so (.dylib, nes_ffi.dll) in
+ target/release, declared in include/nes.h.
+*/
+pub use nes::ffi::*;
+
+#[cfg(test)]
+mod header_test {
+    // include/nes.h is checked in for people without a Rust toolchain handy,
+    // when src/ffi.rs changes the build makes a new one to copy over it
+    #[test]
+    fn test_header() {
+        assert!(
+            include_str!(concat!(env!("OUT_DIR"), "/nes.h")) == include_str!("../../include/nes.h"),
+            concat!(
+                "include/nes.h is out of date with src/ffi.rs, copy ",
+                env!("OUT_DIR"),
+                "/nes.h over it"
+            )
+        );
+    }
+}
@@ -0,0 +1,132 @@
+#ifndef NES_H
+#define NES_H
+
+/* Generated from src/ffi.rs by cbindgen, edit that instead */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define NES_WIDTH 256
+
+#define NES_HEIGHT 240
+
+#define NES_SAMPLE_RATE 44100
+
+/*
+ * The console and what the last call that failed said about it.
+ */
+typedef struct NesHandle NesHandle;
+
+/*
+ * A console with nothing in it, null if it couldn't be made. Free it with
+ * `nes_free`.
+ */
+struct NesHandle *nes_new(void);
+
+/*
+ * # Safety
+ * `handle` is from `nes_new` and isn't used again.
+ */
+void nes_free(struct NesHandle *handle);
+
+/*
+ * Put in the iNES or UNIF image in `data` and turn the power on. 0 when it
+ * worked, -1 when it didn't and `nes_last_error` says why.
+ *
+ * # Safety
+ * `data` points at `len` bytes, it isn't kept.
+ */
+int nes_load_rom(struct NesHandle *handle, const uint8_t *data, size_t len);
+
+/*
+ * Why the last call that returned -1 failed, null before any did. Good
+ * until the next call that fails or `nes_free`.
+ *
+ * # Safety
+ * `handle` is from `nes_new`.
+ */
+const char *nes_last_error(const struct NesHandle *handle);
+
+/*
+ * The reset button, RAM is kept. 0, or -1 if it panicked.
+ *
+ * # Safety
+ * `handle` is from `nes_new`.
+ */
+int nes_reset(struct NesHandle *handle);
+
+/*
+ * Off and on again with the same game in. 0, or -1 if it panicked.
+ *
+ * # Safety
+ * `handle` is from `nes_new`.
+ */
+int nes_power_cycle(struct NesHandle *handle);
+
+/*
+ * Run until the next picture's drawn. 0, or -1 when the CPU's halted on an
+ * opcode it doesn't have (the picture's still there) or the emulator
+ * panicked, `nes_last_error` says which.
+ *
+ * # Safety
+ * `handle` is from `nes_new`.
+ */
+int nes_run_frame(struct NesHandle *handle);
+
+/*
+ * The last picture, `NES_WIDTH` x `NES_HEIGHT` RGB24 pixels row by row.
+ * `len` gets the size in bytes if it isn't null. Good until the next
+ * `nes_run_frame`.
+ *
+ * # Safety
+ * `handle` is from `nes_new`, `len` is null or can be written.
+ */
+const uint8_t *nes_framebuffer(const struct NesHandle *handle, size_t *len);
+
+/*
+ * Hold down `buttons` on controller `player` (0 is the first) until they're
+ * set again. A bit each, from the lowest: A, B, Select, Start, Up, Down,
+ * Left, Right. 0, or -1 if it panicked.
+ *
+ * # Safety
+ * `handle` is from `nes_new`.
+ */
+int nes_set_input(struct NesHandle *handle, uint32_t player, uint8_t buttons);
+
+/*
+ * Copy up to `max` samples of the last frame's sound (mono, signed 16 bit
+ * at `NES_SAMPLE_RATE`) into `out`, returning how many there were.
+ *
+ * # Safety
+ * `handle` is from `nes_new`, `out` has room for `max` samples.
+ */
+size_t nes_audio(const struct NesHandle *handle, int16_t *out, size_t max);
+
+/*
+ * The machine as a save state, `len` bytes to hand back to
+ * `nes_load_state`, null if it panicked. Free it with `nes_free_state`.
+ *
+ * # Safety
+ * `handle` is from `nes_new`, `len` can be written.
+ */
+uint8_t *nes_save_state(const struct NesHandle *handle, size_t *len);
+
+/*
+ * # Safety
+ * `state` and `len` are from `nes_save_state`, and `state` isn't used again.
+ */
+void nes_free_state(uint8_t *state, size_t len);
+
+/*
+ * 0 when the machine's back where `state` was taken, -1 with
+ * `nes_last_error` set when it's not from this game or not a save state.
+ *
+ * # Safety
+ * `handle` is from `nes_new`, `state` points at `len` bytes.
+ */
+int nes_load_state(struct NesHandle *handle, const uint8_t *state, size_t len);
+
+#endif  /* NES_H */
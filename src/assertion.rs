@@ -0,0 +1,253 @@
+use std::fmt;
+
+use crate::cpu::CPU;
+
+// PPUMASK's show background and show sprites
+const RENDERING: u8 = 0x18;
+const VISIBLE_SCANLINES: usize = 240;
+const PRE_RENDER: usize = 261;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Operand {
+    A,
+    X,
+    Y,
+    P,
+    Sp,
+    Pc,
+    Scanline,
+    Dot,
+    // the PPU registers as last written, see `PpuRegisters`
+    Ctrl,
+    Mask,
+    Status,
+    // a byte of CPU memory, read without side effects
+    Memory(u16),
+}
+
+impl Operand {
+    fn parse(text: &str) -> Option<Operand> {
+        let operand = match text {
+            "a" => Operand::A,
+            "x" => Operand::X,
+            "y" => Operand::Y,
+            "p" => Operand::P,
+            "sp" => Operand::Sp,
+            "pc" => Operand::Pc,
+            "scanline" => Operand::Scanline,
+            "dot" => Operand::Dot,
+            "ctrl" => Operand::Ctrl,
+            "mask" => Operand::Mask,
+            "status" => Operand::Status,
+            _ => {
+                let addr = text.strip_prefix('[')?.strip_suffix(']')?;
+                Operand::Memory(hex(addr)?)
+            }
+        };
+        Some(operand)
+    }
+
+    fn value(self, cpu: &CPU) -> u16 {
+        let regs = cpu.registers();
+        let ppu = cpu.ppu().registers();
+        match self {
+            Operand::A => regs.a as u16,
+            Operand::X => regs.x as u16,
+            Operand::Y => regs.y as u16,
+            Operand::P => regs.p as u16,
+            Operand::Sp => regs.sp as u16,
+            Operand::Pc => regs.pc,
+            Operand::Scanline => ppu.scanline as u16,
+            Operand::Dot => ppu.dot as u16,
+            Operand::Ctrl => ppu.ctrl as u16,
+            Operand::Mask => ppu.mask as u16,
+            Operand::Status => ppu.status as u16,
+            Operand::Memory(addr) => cpu.peek(addr) as u16,
+        }
+    }
+
+    // the beam's position is counted in decimal, like `break scanline`
+    fn decimal(self) -> bool {
+        matches!(self, Operand::Scanline | Operand::Dot)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn parse(text: &str) -> Option<Comparison> {
+        let comparison = match text {
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            "<=" => Comparison::LessOrEqual,
+            ">" => Comparison::Greater,
+            ">=" => Comparison::GreaterOrEqual,
+            _ => return None,
+        };
+        Some(comparison)
+    }
+
+    fn holds(self, left: u16, right: u16) -> bool {
+        match self {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+        }
+    }
+}
+
+fn hex(text: &str) -> Option<u16> {
+    let text = text.trim_start_matches('$');
+    let text = text.strip_prefix("0x").unwrap_or(text);
+    u16::from_str_radix(text, 16).ok()
+}
+
+/**
+ * Something that should always be true of the machine, like "sp >= $20" or
+ * "ctrl & 80 == 0 while rendering":
+ *
+ *   <operand> [& <mask>] <comparison> <value> [while rendering] [every frame]
+ *
+ * Operands are a, x, y, p, sp, pc, ctrl, mask, status, scanline, dot and
+ * [addr] for a byte of memory. Numbers are hex, but scanlines and dots are
+ * decimal. It's checked after every instruction, or only as frames finish.
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub struct Assertion {
+    text: String,
+    operand: Operand,
+    mask: Option<u16>,
+    comparison: Comparison,
+    value: u16,
+    // only while the PPU is drawing the picture
+    rendering: bool,
+    pub per_frame: bool,
+}
+
+impl Assertion {
+    pub fn parse(text: &str) -> Result<Assertion, String> {
+        let lower = text.to_ascii_lowercase();
+        let mut words = lower.split_whitespace().peekable();
+        let operand = words.next().ok_or("An assertion needs a condition")?;
+        let operand = Operand::parse(operand).ok_or(format!("Unknown operand {}", operand))?;
+        let number = |text: &str| {
+            let prefixed = text.starts_with('$') || text.starts_with("0x");
+            let value = if operand.decimal() && !prefixed {
+                text.parse().ok()
+            } else {
+                hex(text)
+            };
+            value.ok_or(format!("Not a number: {}", text))
+        };
+        let mask = match words.next_if_eq(&"&") {
+            Some(_) => Some(number(words.next().ok_or("& needs a mask")?)?),
+            None => None,
+        };
+        let comparison = words.next().ok_or("Missing the comparison")?;
+        let comparison =
+            Comparison::parse(comparison).ok_or(format!("Unknown comparison {}", comparison))?;
+        let value = number(words.next().ok_or("Missing the value to compare with")?)?;
+        let rest: Vec<&str> = words.collect();
+        let (rendering, per_frame) = match rest.join(" ").as_str() {
+            "" => (false, false),
+            "while rendering" => (true, false),
+            "every frame" => (false, true),
+            "while rendering every frame" => (true, true),
+            other => {
+                return Err(format!(
+                    "Expected while rendering or every frame: {}",
+                    other
+                ))
+            }
+        };
+        Ok(Assertion {
+            text: text.split_whitespace().collect::<Vec<_>>().join(" "),
+            operand,
+            mask,
+            comparison,
+            value,
+            rendering,
+            per_frame,
+        })
+    }
+
+    /**
+     * Whether it's true of the machine as it is now. One that only counts
+     * while rendering holds outside of it.
+     */
+    pub fn holds(&self, cpu: &CPU) -> bool {
+        if self.rendering {
+            let ppu = cpu.ppu().registers();
+            let drawing = ppu.scanline < VISIBLE_SCANLINES || ppu.scanline == PRE_RENDER;
+            if ppu.mask & RENDERING == 0 || !drawing {
+                return true;
+            }
+        }
+        let value = self.operand.value(cpu) & self.mask.unwrap_or(u16::MAX);
+        self.comparison.holds(value, self.value)
+    }
+}
+
+impl fmt::Display for Assertion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+#[cfg(test)]
+mod assertion_test {
+    use crate::cpu::cpu_with_program;
+
+    use super::{Assertion, Comparison, Operand};
+
+    #[test]
+    fn test_parse() {
+        let assertion = Assertion::parse("SP >= $20").unwrap();
+        assert_eq!(assertion.operand, Operand::Sp);
+        assert_eq!(assertion.comparison, Comparison::GreaterOrEqual);
+        assert_eq!(assertion.value, 0x20);
+        assert_eq!(assertion.to_string(), "SP >= $20");
+
+        let assertion = Assertion::parse("ctrl & 80 == 0 while rendering").unwrap();
+        assert_eq!(assertion.mask, Some(0x80));
+        assert!(assertion.rendering && !assertion.per_frame);
+        assert_eq!(
+            Assertion::parse("[0300] != ff every frame")
+                .unwrap()
+                .operand,
+            Operand::Memory(0x300)
+        );
+        assert_eq!(Assertion::parse("scanline < 240").unwrap().value, 240);
+
+        assert!(Assertion::parse("").is_err());
+        assert!(Assertion::parse("sp").is_err());
+        assert!(Assertion::parse("q == 1").is_err());
+        assert!(Assertion::parse("a =< 1").is_err());
+        assert!(Assertion::parse("a == 1 sometimes").is_err());
+    }
+
+    #[test]
+    fn test_holds() {
+        let mut cpu = cpu_with_program(&[]);
+        cpu.poke(0x0300, 0x42);
+        assert!(Assertion::parse("[300] == 42").unwrap().holds(&cpu));
+        assert!(Assertion::parse("[300] & f0 == 40").unwrap().holds(&cpu));
+        assert!(!Assertion::parse("sp < 20").unwrap().holds(&cpu));
+        // the PPU isn't drawing anything yet
+        assert!(Assertion::parse("pc == 0 while rendering")
+            .unwrap()
+            .holds(&cpu));
+    }
+}
@@ -0,0 +1,205 @@
+use std::path::Path;
+
+use nes::{
+    config::{Config, ConfigError, InputConfig, KeyBindings},
+    controller::Buttons,
+    power_pad,
+};
+
+// the order they're asked for
+const BUTTONS: [(Buttons, &str); 8] = [
+    (Buttons::UP, "Up"),
+    (Buttons::DOWN, "Down"),
+    (Buttons::LEFT, "Left"),
+    (Buttons::RIGHT, "Right"),
+    (Buttons::SELECT, "Select"),
+    (Buttons::START, "Start"),
+    (Buttons::B, "B"),
+    (Buttons::A, "A"),
+];
+
+/**
+ * Something with keys to bind: one of the four controllers, or the Power Pad.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Device {
+    // 0 to 3
+    Controller(usize),
+    PowerPad,
+}
+
+impl Device {
+    /**
+     * "1" to "4" or "power-pad", as on the command line.
+     */
+    pub fn parse(text: &str) -> Option<Device> {
+        match text {
+            "power-pad" => Some(Device::PowerPad),
+            _ => match text.parse::<usize>() {
+                Ok(player @ 1..=4) => Some(Device::Controller(player - 1)),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Device::Controller(player) => format!("Player {}", player + 1),
+            Device::PowerPad => "Power Pad".to_string(),
+        }
+    }
+
+    fn inputs(self) -> usize {
+        match self {
+            Device::Controller(_) => BUTTONS.len(),
+            Device::PowerPad => power_pad::BUTTONS,
+        }
+    }
+
+    // the SDL and gui frontends ask for keys, the tests stand in for them
+    #[cfg(any(feature = "sdl", feature = "gui", test))]
+    fn input_name(self, idx: usize) -> String {
+        match self {
+            Device::Controller(_) => BUTTONS[idx].1.to_string(),
+            Device::PowerPad => format!("sensor {}", idx + 1),
+        }
+    }
+}
+
+/**
+ * Walks through a device's buttons one at a time, taking whichever key gets
+ * pressed next. Nothing changes until `apply`, so it can be abandoned halfway.
+ */
+pub struct Binder {
+    device: Device,
+    keys: Vec<String>,
+}
+
+impl Binder {
+    #[cfg(any(feature = "sdl", feature = "gui", test))]
+    pub fn new(device: Device) -> Binder {
+        Binder {
+            device,
+            keys: Vec::new(),
+        }
+    }
+
+    pub fn done(&self) -> bool {
+        self.keys.len() == self.device.inputs()
+    }
+
+    /**
+     * What to tell the user, "Player 1: press the key for Up".
+     */
+    #[cfg(any(feature = "sdl", feature = "gui", test))]
+    pub fn prompt(&self) -> String {
+        if self.done() {
+            return format!("{}: done", self.device.name());
+        }
+        format!(
+            "{}: press the key for {}",
+            self.device.name(),
+            self.device.input_name(self.keys.len())
+        )
+    }
+
+    /**
+     * Bind the current button to `key`, an SDL key name. A key that's already
+     * taken by this device is ignored, that's usually a bounce or a slip.
+     * Returns true once every button has a key.
+     */
+    #[cfg(any(feature = "sdl", feature = "gui", test))]
+    pub fn press(&mut self, key: &str) -> bool {
+        if !self.done() && !self.keys.iter().any(|k| k == key) {
+            self.keys.push(key.to_string())
+        }
+        self.done()
+    }
+
+    pub fn apply(&self, input: &mut InputConfig) {
+        assert!(self.done(), "Not every button has a key yet");
+        let bindings: &mut KeyBindings = match self.device {
+            Device::Controller(0) => &mut input.player1,
+            Device::Controller(1) => &mut input.player2,
+            Device::Controller(2) => &mut input.player3,
+            Device::Controller(_) => &mut input.player4,
+            Device::PowerPad => {
+                for (slot, key) in input.power_pad_keys.iter_mut().zip(&self.keys) {
+                    *slot = key.clone()
+                }
+                return;
+            }
+        };
+        for ((button, _), key) in BUTTONS.iter().zip(&self.keys) {
+            bindings.set(*button, key)
+        }
+    }
+
+    /**
+     * Write the new keys into the config at `path` and leave the rest of the
+     * file alone.
+     */
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        let mut config = Config::load_or_create(path)?;
+        self.apply(&mut config.input);
+        config.save(path)
+    }
+}
+
+#[cfg(test)]
+mod binding_test {
+    use nes::config::{InputConfig, KeyBindings};
+
+    use super::{Binder, Device};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Device::parse("2"), Some(Device::Controller(1)));
+        assert_eq!(Device::parse("power-pad"), Some(Device::PowerPad));
+        assert_eq!(Device::parse("5"), None);
+        assert_eq!(Device::parse("0"), None);
+    }
+
+    #[test]
+    fn test_bind_controller() {
+        let mut binder = Binder::new(Device::Controller(1));
+        assert_eq!(binder.prompt(), "Player 2: press the key for Up");
+        for key in ["I", "K", "J", "L", "L", "Q", "W", "E"] {
+            assert!(!binder.press(key));
+        }
+        // the second L didn't count
+        assert_eq!(binder.prompt(), "Player 2: press the key for A");
+        assert!(binder.press("R"));
+
+        let mut input = InputConfig::default();
+        binder.apply(&mut input);
+        assert_eq!(
+            input.player2,
+            KeyBindings {
+                a: "R".to_string(),
+                b: "E".to_string(),
+                select: "Q".to_string(),
+                start: "W".to_string(),
+                up: "I".to_string(),
+                down: "K".to_string(),
+                left: "J".to_string(),
+                right: "L".to_string(),
+            }
+        );
+        assert_eq!(input.player1, KeyBindings::player1());
+    }
+
+    #[test]
+    fn test_bind_power_pad() {
+        let mut binder = Binder::new(Device::PowerPad);
+        let keys = ["1", "2", "3", "4", "Q", "W", "E", "R", "A", "S", "D", "F"];
+        for key in &keys[..11] {
+            binder.press(key);
+        }
+        assert_eq!(binder.prompt(), "Power Pad: press the key for sensor 12");
+        assert!(binder.press("F"));
+        let mut input = InputConfig::default();
+        binder.apply(&mut input);
+        assert_eq!(input.power_pad_keys, keys.map(String::from));
+    }
+}
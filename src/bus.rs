@@ -1,123 +1,640 @@
-use crate::ppu::PPU;
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::ops::{Range, RangeInclusive};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cartridge::{Cartridge, CartridgeError, Mirroring, Region},
+    cheats::Code,
+    controller::{Controller, LatchMode, Ports},
+    debug::Access,
+    device::BusDevice,
+    events::EventLog,
+    fds::{check_bios, DiskImage, FdsError},
+    mapper::{self, Fds, Nrom, SharedMapper},
+    power_pad::PowerPad,
+    ppu::{Frame, Palette, PPU},
+    zapper::Zapper,
+};
+#[cfg(feature = "std")]
+use crate::{controller::FourScore, savestate};
 
 const CPU_INTERNAL_RAM: usize = 2048;
 const PAGE_SIZE: usize = 0xff;
-// Zero page reserved for a number of special addressing modes
-pub struct Bus {
+
+/**
+ * What's in CPU RAM at power on. Real consoles come up with whatever the
+ * chips settle to, this picks one answer so every run starts the same.
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum PowerOnRam {
+    #[default]
+    Zeros,
+    // all 0xff
+    Ones,
+    // four 0x00 then four 0xff, over and over, close to what a lot of
+    // consoles show
+    Pattern,
+}
+
+impl PowerOnRam {
+    fn byte(self, addr: usize) -> u8 {
+        match self {
+            PowerOnRam::Zeros => 0x00,
+            PowerOnRam::Ones => 0xff,
+            PowerOnRam::Pattern if addr & 4 == 0 => 0x00,
+            PowerOnRam::Pattern => 0xff,
+        }
+    }
+}
+
+/**
+ * The bus's part of a `SaveState`. The PPU and the mapper (in its own format)
+ * are chunks of their own in the file.
+ */
+#[cfg(feature = "std")]
+#[derive(Serialize, Deserialize)]
+pub struct BusState {
+    #[serde(with = "savestate::array")]
     ram: [u8; CPU_INTERNAL_RAM],
-    prgrom: Vec<u8>,
+    controllers: [Controller; 4],
+    four_score: Option<FourScore>,
+    zapper: Option<Zapper>,
+    power_pad: Option<PowerPad>,
+    last_read: u16,
+    // to tell it's the same game
+    rom_sizes: (usize, usize),
+    #[serde(skip, default = "PPU::new")]
+    pub(crate) ppu: PPU,
+    #[serde(skip)]
+    pub(crate) mapper: Vec<u8>,
+}
+
+/**
+ * The console's 2KB, mirrored through $0000-$1FFF. Zero page is in here,
+ * reserved for a number of special addressing modes.
+ */
+#[derive(Clone)]
+struct Ram([u8; CPU_INTERNAL_RAM]);
+
+impl BusDevice for Ram {
+    fn box_clone(&self) -> Box<dyn BusDevice> {
+        Box::new(self.clone())
+    }
+    fn read(&mut self, addr: u16) -> u8 {
+        self.peek(addr)
+    }
+    fn write(&mut self, addr: u16, data: u8) {
+        self.0[(addr & 0x7ff) as usize] = data
+    }
+    fn peek(&self, addr: u16) -> u8 {
+        self.0[(addr & 0x7ff) as usize]
+    }
+}
+
+/**
+ * The CPU's bus. Everything on it is a `BusDevice` answering at the
+ * addresses it claims, see `device` for the console's own and `attach` for
+ * adding more. OAM DMA and the cheats sit between the CPU and the devices.
+ */
+#[derive(Clone)]
+pub struct Bus {
+    ram: Ram,
+    mapper: SharedMapper,
     ppu: PPU,
+    ports: Ports,
+    // plugged in over the console's own, last one first
+    devices: Vec<(RangeInclusive<u16>, Box<dyn BusDevice>)>,
+    // repeat $4016/$4017 reads hit by DMC DMA, see `dmc_dma`
+    dmc_conflict: bool,
+    last_read: u16,
+    // every read and write goes in here while it's on, for the cpu hooks
+    accesses: Option<Vec<Access>>,
+    // PRG and CHR ROM, 0 for RAM
+    rom_sizes: (usize, usize),
+    // register accesses by scanline and dot, for the event viewer
+    events: Option<EventLog>,
+    // the cheats switched on, see `Cheats`
+    cheats: Vec<Code>,
+    // what RAM is filled with again on a power cycle
+    power_on_ram: PowerOnRam,
+    // dots the PPU is behind the CPU, and how many it can fall behind
+    // before it has to catch up on its own, see `catch_up`
+    ppu_owed: u64,
+    ppu_due: u64,
 }
 
 impl Bus {
     pub fn new(ppu: PPU) -> Bus {
+        // an empty cartridge until a real one is inserted
+        let mapper = mapper::share(Box::new(Nrom::new(Cartridge {
+            prgrom: vec![0; 0x4000],
+            chrrom: Vec::new(),
+            mirroring: Mirroring::Horizontal,
+            mapper: 0,
+            submapper: 0,
+            ..Default::default()
+        })));
         Bus {
-            ram: [0; CPU_INTERNAL_RAM],
-            prgrom: Vec::new(),
+            ram: Ram([0; CPU_INTERNAL_RAM]),
+            mapper,
             ppu,
+            ports: Ports::new(),
+            devices: Vec::new(),
+            dmc_conflict: false,
+            last_read: 0,
+            accesses: None,
+            rom_sizes: (0, 0),
+            events: None,
+            cheats: Vec::new(),
+            power_on_ram: PowerOnRam::Zeros,
+            ppu_owed: 0,
+            ppu_due: 0,
+        }
+    }
+    /**
+     * Port 0 is read through $4016, port 1 through $4017. Ports 2 and 3 are
+     * the extra players on a Four Score.
+     */
+    pub fn controller(&mut self, port: usize) -> &mut Controller {
+        self.ports.controller(port)
+    }
+    pub fn plug_zapper(&mut self, plugged: bool) {
+        self.ports.plug_zapper(plugged)
+    }
+    pub fn plug_four_score(&mut self, plugged: bool) {
+        self.ports.plug_four_score(plugged)
+    }
+    pub fn plug_power_pad(&mut self, plugged: bool) {
+        self.ports.plug_power_pad(plugged)
+    }
+    pub fn set_latch_mode(&mut self, mode: LatchMode) {
+        self.ports.set_latch_mode(mode)
+    }
+    pub fn set_dmc_conflict(&mut self, enabled: bool) {
+        self.dmc_conflict = enabled
+    }
+    pub fn fill_ram(&mut self, fill: PowerOnRam) {
+        self.power_on_ram = fill;
+        for (addr, byte) in self.ram.0.iter_mut().enumerate() {
+            *byte = fill.byte(addr)
+        }
+    }
+    /**
+     * Snapshot the host input for `LatchMode::PerFrame`.
+     */
+    pub fn start_frame(&mut self) {
+        self.ports.start_frame();
+        if let Some(events) = &mut self.events {
+            events.start_frame()
+        }
+    }
+    /**
+     * DMC DMA stalling the cpu. On a real NES the stalled cpu repeats its
+     * last read, so if that was $4016/$4017 the port gets clocked an extra
+     * time and the game misses a bit (why games re-read until two polls
+     * agree). Nothing drives this until there's an APU.
+     */
+    pub fn dmc_dma(&mut self) {
+        if self.dmc_conflict && matches!(self.last_read, 0x4016 | 0x4017) {
+            self.ports.read_port((self.last_read - 0x4016) as usize);
         }
     }
+    pub fn zapper(&mut self) -> Option<&mut Zapper> {
+        self.ports.zapper()
+    }
+    pub fn power_pad(&mut self) -> Option<&mut PowerPad> {
+        self.ports.power_pad()
+    }
     pub fn poll_generate_nmi(&self) -> bool {
         self.ppu.poll_generate_nmi()
     }
-    pub fn clear_generate_nmi(&mut self) { 
+    pub fn clear_generate_nmi(&mut self) {
         self.ppu.clear_generate_nmi()
     }
-    pub fn tick(&mut self, cpu_cycles: u64) {
-        self.ppu.tick(cpu_cycles * 3)
+    pub fn poll_frame_complete(&self) -> bool {
+        self.ppu.poll_frame_complete()
+    }
+    pub fn clear_frame_complete(&mut self) {
+        self.ppu.clear_frame_complete()
+    }
+    pub fn frame(&self) -> &Frame {
+        self.ppu.frame()
+    }
+    /**
+     * The PPU as of the last `catch_up`. `CPU::step` and `CPU::run_frame`
+     * leave it caught up.
+     */
+    pub fn ppu(&self) -> &PPU {
+        &self.ppu
+    }
+    pub fn ppu_mut(&mut self) -> &mut PPU {
+        self.catch_up();
+        // whatever's done with it can move the next vblank
+        self.ppu_due = 0;
+        &mut self.ppu
+    }
+    pub fn set_palette(&mut self, palette: &Palette) {
+        self.ppu.set_palette(palette)
     }
-    pub fn load_rom(&mut self, rom: Vec<u8>) {
-        self.prgrom = rom;
+    /**
+     * Frame timing from here on, a cartridge brings its own.
+     */
+    pub fn set_region(&mut self, region: Region) {
+        self.catch_up();
+        self.ppu.set_region(region);
+        self.ppu_due = 0
     }
+    pub fn region(&self) -> Region {
+        self.ppu.region()
+    }
+    /**
+     * Catch up with the CPU over `cycles`, the CPU's count before and after
+     * what it just ran. The mapper's clocked right away, the PPU's dots are
+     * only owed until vblank or until something could tell, see `catch_up`.
+     */
+    pub fn tick(&mut self, cycles: Range<u64>) {
+        self.mapper.borrow_mut().tick(cycles.end - cycles.start);
+        let region = self.ppu.region();
+        self.ppu_owed += region.ppu_dots(cycles.end) - region.ppu_dots(cycles.start);
+        if self.ppu_owed >= self.ppu_due {
+            self.catch_up()
+        }
+    }
+    /**
+     * Run the PPU for the dots it's owed, in one go. Nothing the CPU can see
+     * changes between two catch ups: they happen on every access to the
+     * PPU's registers, OAM DMA, the mapper's registers and the Zapper, and
+     * by themselves at vblank for the NMI and the end of the frame. The PPU
+     * sees the CPU where it was after its last instruction, as it did when
+     * it was ticked after every one.
+     */
+    pub fn catch_up(&mut self) {
+        self.ppu.tick(core::mem::take(&mut self.ppu_owed) as usize);
+        self.ppu_due = self.ppu.dots_to_vblank()
+    }
+    /**
+     * Off and on again: RAM, the PPU and the cartridge's registers come up
+     * as they do at power on. The cartridge stays in with its save, and
+     * what's plugged in stays plugged in.
+     */
+    pub fn power_cycle(&mut self) {
+        self.fill_ram(self.power_on_ram);
+        self.ppu_owed = 0;
+        self.ppu.power_cycle();
+        self.mapper.borrow_mut().power_cycle();
+        self.last_read = 0;
+        self.catch_up()
+    }
+    /**
+     * The reset line. It reaches the PPU, RAM and the cartridge don't see it.
+     */
+    pub fn reset(&mut self) {
+        self.catch_up();
+        self.ppu.reset()
+    }
+    pub fn poll_irq(&self) -> bool {
+        self.mapper.borrow().irq_pending()
+    }
+    pub fn load_cartridge(&mut self, mut cartridge: Cartridge) -> crate::Result<()> {
+        let trainer = cartridge.trainer.take();
+        let region = cartridge.region;
+        let number = cartridge.mapper;
+        let sizes = (cartridge.prgrom.len(), cartridge.chrrom.len());
+        let mut mapper = mapper::from_cartridge(cartridge)?;
 
-    fn read_io_registers(&mut self, reg: u8) -> u8 {
-        match reg {
-            0x2 => self.ppu.read_ppustatus(),
-            0x4 => self.ppu.read_oamdata(),
-            0x7 => self.ppu.read_ppudata(),
-            _ => panic!(
-                "Attempting to read a non-readable resgister: {:#x}",
-                reg as usize + 0x2000
-            ),
+        // Trainers expect to be sitting in PRG RAM at $7000..=$71FF on power up
+        if let Some(trainer) = trainer {
+            if !mapper.load_trainer(&trainer) {
+                return Err(CartridgeError::TrainerWithoutPrgRam(number).into());
+            }
         }
+        self.rom_sizes = sizes;
+        self.mapper = mapper::share(mapper);
+        self.ppu.load_mapper(self.mapper.clone());
+        self.ppu.set_region(region);
+        Ok(())
     }
-    fn write_io_registers(&mut self, reg: u8, data: u8) {
-        match reg {
-            0x0 => self.ppu.write_ppu_ctrl(data),
-            0x1 => self.ppu.write_ppumask(data),
-            0x3 => self.ppu.write_oamaddr(data),
-            0x4 => self.ppu.write_oamdata(data),
-            0x5 => self.ppu.write_ppuscroll(data),
-            0x6 => self.ppu.write_ppuaddr(data),
-            0x7 => self.ppu.write_ppudata(data),
-            _ => panic!(
-                "Attempting to write a non-writeable resgister: {:#x}",
-                reg as usize + 0x2000
-            ),
+    pub fn load_disk(&mut self, bios: Vec<u8>, disk: DiskImage) -> Result<(), FdsError> {
+        check_bios(&bios)?;
+        self.mapper = mapper::share(Box::new(Fds::new(bios, disk)));
+        self.rom_sizes = (0, 0);
+        self.ppu.load_mapper(self.mapper.clone());
+        Ok(())
+    }
+    /**
+     * A copy sharing nothing with this one. `clone` alone leaves both
+     * pointing at the same mapper.
+     */
+    pub fn deep_clone(&self) -> Bus {
+        let mut bus = self.clone();
+        bus.mapper = mapper::share(self.mapper.borrow().box_clone());
+        bus.ppu.load_mapper(bus.mapper.clone());
+        bus
+    }
+    #[cfg(feature = "std")]
+    pub fn save_state(&self) -> BusState {
+        BusState {
+            ram: self.ram.0,
+            ppu: self.ppu.clone(),
+            controllers: self.ports.controllers.clone(),
+            four_score: self.ports.four_score.clone(),
+            zapper: self.ports.zapper.clone(),
+            power_pad: self.ports.power_pad.clone(),
+            last_read: self.last_read,
+            rom_sizes: self.rom_sizes,
+            mapper: self.mapper.borrow().save_state(),
+        }
+    }
+    /**
+     * Nothing changes unless it all loads.
+     */
+    #[cfg(feature = "std")]
+    pub fn load_state(&mut self, state: &BusState) -> Result<(), savestate::StateError> {
+        if state.rom_sizes != self.rom_sizes {
+            return Err(savestate::StateError::Mismatch("ROM sizes"));
         }
+        self.mapper.borrow_mut().load_state(&state.mapper)?;
+        self.ram.0 = state.ram;
+        // what was owed is from before the state
+        self.ppu_owed = 0;
+        self.ppu.load_state(&state.ppu);
+        self.catch_up();
+        self.ports.controllers = state.controllers.clone();
+        self.ports.four_score = state.four_score.clone();
+        self.ports.zapper = state.zapper.clone();
+        self.ports.power_pad = state.power_pad.clone();
+        self.last_read = state.last_read;
+        Ok(())
+    }
+    pub fn rom_sizes(&self) -> (usize, usize) {
+        self.rom_sizes
+    }
+    pub fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.mapper.borrow().prg_rom_offset(addr)
+    }
+    pub fn disk_sides(&self) -> usize {
+        self.mapper.borrow().disk_sides()
+    }
+    pub fn insert_disk(&mut self, side: Option<usize>) {
+        self.mapper.borrow_mut().insert_disk(side)
     }
+    pub fn save_data(&self) -> Option<Vec<u8>> {
+        self.mapper.borrow().save_data()
+    }
+
     fn oamdma(&mut self, page: u8) {
-        let addrs = ((page as u16) << 8)..((page as u16) << 8 | 0xff);
-        let bytes: Vec<u8> = addrs
-            .map(|addr| match addr {
-                0x0..=0x1ff => self.ram[(addr & 0x7ff) as usize],
-                0x8000..=0xffff => self.read_rom(addr),
-                _ => panic!(
-                    "Can only oamdma internal ram and cartridge rom. Bad address: {:#x}",
-                    addr
-                ),
-            })
-            .collect();
+        let addrs = ((page as u16) << 8)..=((page as u16) << 8 | 0xff);
+        let bytes: Vec<u8> = addrs.map(|addr| self.read_memory(addr)).collect();
         self.ppu.write_dma(&bytes)
     }
 
-    fn read_rom(&self, addr: u16) -> u8 {
-        let offset = addr - 0x8000;
-        let addr = if self.prgrom.len() == 0x4000 && offset >= 0x4000 {
-            offset % 0x4000
-        } else {
-            offset
-        };
-        self.prgrom[addr as usize]
+    pub fn polled(&self) -> bool {
+        self.ports.polled()
     }
 
-    // Only considering cpu internal ram and simplified ROM for the time being.
-    pub fn read_memory(&mut self, addr: u16) -> u8 {
+    /**
+     * Put `device` on the bus at `addrs`, over whatever answers there now.
+     * For expansion hardware and debugging aids, they aren't in save states.
+     */
+    pub fn attach(&mut self, addrs: RangeInclusive<u16>, device: Box<dyn BusDevice>) {
+        self.devices.push((addrs, device))
+    }
+    /**
+     * Take off the device last attached at `addr`, whatever was under it
+     * answers again.
+     */
+    pub fn detach(&mut self, addr: u16) -> Option<Box<dyn BusDevice>> {
+        let idx = self.attached(addr)?;
+        Some(self.devices.remove(idx).1)
+    }
+    fn attached(&self, addr: u16) -> Option<usize> {
+        self.devices
+            .iter()
+            .rposition(|(addrs, _)| addrs.contains(&addr))
+    }
+
+    /**
+     * Who answers at `addr`. Nothing does for the APU and test registers
+     * (there's no APU yet), they read as 0.
+     */
+    fn device(&self, addr: u16) -> Option<&dyn BusDevice> {
+        if let Some(idx) = self.attached(addr) {
+            return Some(self.devices[idx].1.as_ref());
+        }
         match addr {
-            // Internal ram
-            0x0..=0x1ff => {
-                let mirrored = (addr & 0x7ff) as usize;
-                self.ram[mirrored]
-            }
-            // io registers
-            0x2000..=0x3fff => {
-                let mirrored = (addr & 0xf) % 8;
-                self.read_io_registers(mirrored as u8)
-            }
-            // rom
-            0x8000..=0xffff => self.read_rom(addr),
-            _ => self.ram[addr as usize],
+            0x0000..=0x1fff => Some(&self.ram),
+            0x2000..=0x3fff => Some(&self.ppu),
+            0x4016..=0x4017 => Some(&self.ports),
+            0x4020..=0xffff => Some(&self.mapper),
+            _ => None,
+        }
+    }
+    fn device_mut(&mut self, addr: u16) -> Option<&mut dyn BusDevice> {
+        if let Some(idx) = self.attached(addr) {
+            return Some(self.devices[idx].1.as_mut());
+        }
+        match addr {
+            0x0000..=0x1fff => Some(&mut self.ram),
+            0x2000..=0x3fff => Some(&mut self.ppu),
+            0x4016..=0x4017 => Some(&mut self.ports),
+            0x4020..=0xffff => Some(&mut self.mapper),
+            _ => None,
         }
     }
 
+    /**
+     * Read without side effects, for debuggers and test harnesses. The PPU
+     * and controller registers read as 0 rather than being clocked.
+     */
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.device(addr).map_or(0, |device| device.peek(addr))
+    }
+
+    /**
+     * Start (or stop) keeping a list of every access, emptied by
+     * `take_accesses`.
+     */
+    pub fn log_accesses(&mut self, enabled: bool) {
+        self.accesses = enabled.then(Vec::new)
+    }
+    pub fn log_events(&mut self, enabled: bool) {
+        if enabled != self.events.is_some() {
+            self.events = enabled.then(EventLog::new)
+        }
+    }
+    pub fn events(&self) -> Option<&EventLog> {
+        self.events.as_ref()
+    }
+    pub fn take_accesses(&mut self) -> Vec<Access> {
+        self.accesses
+            .as_mut()
+            .map(core::mem::take)
+            .unwrap_or_default()
+    }
+
+    pub fn set_cheats(&mut self, cheats: Vec<Code>) {
+        self.cheats = cheats
+    }
+
+    pub fn read_memory(&mut self, addr: u16) -> u8 {
+        if self.events.is_some() {
+            self.catch_up()
+        }
+        let value = self.read(addr);
+        // the cpu sees the cheat's value in place of what's there
+        let value = self
+            .cheats
+            .iter()
+            .find(|code| code.applies(addr, value))
+            .map_or(value, |code| code.value);
+        if let Some(events) = &mut self.events {
+            events.record(addr, value, false, self.ppu.scanline(), self.ppu.dot())
+        }
+        if let Some(accesses) = &mut self.accesses {
+            accesses.push(Access {
+                addr,
+                value,
+                write: false,
+            })
+        }
+        value
+    }
+
     pub fn write_memory(&mut self, addr: u16, byte: u8) {
-        match addr {
-            // Internal ram
-            0x0..=0x1ff => {
-                let mirrored = (addr & 0x7ff) as usize;
-                self.ram[mirrored] = byte
-            }
-            // io registers
-            0x2000..=0x3fff => {
-                let mirrored = (addr & 0xf) % 8;
-                self.write_io_registers(mirrored as u8, byte)
-            }
-            // TODO There will be more registers here eventually, only accounting for
-            // oamdma at the moment.
-            0x4014 => self.oamdma(byte),
-            // rom
-            0x8000..=0xffff => panic!("Attempted to write to Read-only memory"),
-            _ => self.ram[addr as usize] = byte,
+        if self.events.is_some() {
+            self.catch_up()
+        }
+        if let Some(events) = &mut self.events {
+            events.record(addr, byte, true, self.ppu.scanline(), self.ppu.dot())
+        }
+        if let Some(accesses) = &mut self.accesses {
+            accesses.push(Access {
+                addr,
+                value: byte,
+                write: true,
+            })
         }
+        self.write(addr, byte)
+    }
+
+    /**
+     * A write from outside the machine, for scripts and debuggers. It has
+     * the same effects as one from the CPU but isn't logged.
+     */
+    pub fn poke(&mut self, addr: u16, byte: u8) {
+        self.write(addr, byte)
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        self.last_read = addr;
+        if (0x2000..=0x3fff).contains(&addr) || (addr == 0x4017 && self.ports.zapper.is_some()) {
+            self.catch_up()
+        }
+        // the Zapper looks at the screen, which isn't on the bus
+        if let (0x4017, Some(zapper)) = (addr, &mut self.ports.zapper) {
+            zapper.sense(self.ppu.frame(), self.ppu.scanline(), self.ppu.dot())
+        }
+        self.device_mut(addr).map_or(0, |device| device.read(addr))
+    }
+
+    fn write(&mut self, addr: u16, byte: u8) {
+        // bank switching and mirroring change what the PPU draws
+        if matches!(addr, 0x2000..=0x3fff | 0x4014 | 0x4020..=0xffff) {
+            self.catch_up()
+        }
+        // the DMA unit takes the bus over from the CPU, it's not on it
+        if addr == 0x4014 {
+            return self.oamdma(byte);
+        }
+        if let Some(device) = self.device_mut(addr) {
+            device.write(addr, byte)
+        }
+    }
+}
+
+#[cfg(test)]
+mod bus_test {
+    use crate::{
+        cartridge::{Cartridge, CartridgeError},
+        device::BusDevice,
+        ppu::PPU,
+        Error,
+    };
+
+    use super::Bus;
+
+    // a register that remembers what's written to it, like a test ROM's
+    // debug port
+    #[derive(Clone)]
+    struct Port(Vec<u8>);
+
+    impl BusDevice for Port {
+        fn box_clone(&self) -> Box<dyn BusDevice> {
+            Box::new(self.clone())
+        }
+        fn read(&mut self, _: u16) -> u8 {
+            self.0.len() as u8
+        }
+        fn write(&mut self, _: u16, data: u8) {
+            self.0.push(data)
+        }
+    }
+
+    fn with_trainer(mapper: u8) -> Cartridge {
+        Cartridge {
+            prgrom: vec![0; 0x8000],
+            chrrom: vec![0; 0x2000],
+            mapper,
+            trainer: Some((0..0x200).map(|i| i as u8).collect()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_trainer() {
+        // VRC6 ignores PRG RAM writes until the game enables it, the trainer
+        // has to be there anyway
+        let mut bus = Bus::new(PPU::new());
+        bus.load_cartridge(with_trainer(24)).unwrap();
+        bus.write_memory(0xb003, 0x80);
+        assert_eq!(bus.read_memory(0x7000), 0);
+        assert_eq!(bus.read_memory(0x7001), 1);
+        assert_eq!(bus.read_memory(0x71ff), 0xff);
+        assert_eq!(bus.read_memory(0x6fff), 0);
+
+        // Namco 108 boards have nowhere to put it
+        match bus.load_cartridge(with_trainer(206)) {
+            Err(Error::Cartridge(CartridgeError::TrainerWithoutPrgRam(206))) => {}
+            other => panic!("expected the trainer to be rejected, got {:?}", other),
+        }
+        // and what was plugged in stays plugged in
+        assert_eq!(bus.read_memory(0x7001), 1);
+    }
+
+    #[test]
+    fn test_attach() {
+        let mut bus = Bus::new(PPU::new());
+        bus.write_memory(0x10, 0xaa);
+        bus.attach(0x4018..=0x401f, Box::new(Port(Vec::new())));
+        // over RAM too
+        bus.attach(0x0010..=0x0010, Box::new(Port(Vec::new())));
+        bus.write_memory(0x401a, 1);
+        bus.write_memory(0x401a, 2);
+        assert_eq!(bus.read_memory(0x4018), 2);
+        assert_eq!(bus.peek(0x4018), 0);
+        assert_eq!(bus.read_memory(0x10), 0);
+        // the mirror is still RAM
+        assert_eq!(bus.read_memory(0x810), 0xaa);
+
+        assert!(bus.detach(0x10).is_some());
+        assert_eq!(bus.read_memory(0x10), 0xaa);
+        assert!(bus.detach(0x10).is_none());
+        // a copy of the machine gets one of its own
+        let mut copy = bus.clone();
+        copy.write_memory(0x4018, 3);
+        assert_eq!(copy.read_memory(0x4018), 3);
+        assert_eq!(bus.read_memory(0x4018), 2);
     }
 }
@@ -1,40 +1,435 @@
-use core::panic;
-use std::{error::Error, fs};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{error::Error, fmt};
+#[cfg(feature = "std")]
+use std::{
+    fs,
+    io::{self, Cursor, Read},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{romdb, unif};
 
 // NES follow by MS-DOS end of file
 const NES_TAG: [u8; 4] = [0x4e, 0x45, 0x53, 0x1a];
+// "PK" local file header
+#[cfg(feature = "std")]
+const ZIP_TAG: [u8; 4] = [0x50, 0x4b, 0x03, 0x04];
+// the most we'll unpack from a zip, far past any real cartridge
+#[cfg(feature = "std")]
+const MAX_ROM_SIZE: usize = 0x400_0000;
 const CHR_ROM_SIZE: usize = 0x2000;
 const PRG_ROM_SIZE: usize = 0x4000;
+const TRAINER_SIZE: usize = 512;
+// where the trainer ends up in PRG RAM
+pub const TRAINER_ADDR: u16 = 0x7000;
 
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
 pub enum Mirroring {
+    #[default]
     Horizontal,
     Vertical,
     FourScreen,
+    // mapper controlled, all four nametables point at the same 1KB of vram
+    SingleScreenLower,
+    SingleScreenUpper,
+}
+/**
+ * Console type from flag 7. Arcade boards run the same games with extra
+ * hardware around them.
+ */
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum ConsoleType {
+    #[default]
+    Nes,
+    VsSystem,
+    PlayChoice10,
+}
+
+/**
+ * TV system the game was made for, byte 9 of an iNES header or byte 12 of a
+ * NES 2.0 one. The console runs to it: how many scanlines a frame has, how
+ * fast the PPU goes next to the CPU and how many frames there are a second.
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    // the Famicom clones sold in Russia, PAL but keeping NTSC's CPU timing
+    Dendy,
+}
+
+impl Region {
+    pub fn parse(text: &str) -> Option<Region> {
+        match text.to_ascii_lowercase().as_str() {
+            "ntsc" => Some(Region::Ntsc),
+            "pal" => Some(Region::Pal),
+            "dendy" => Some(Region::Dendy),
+            _ => None,
+        }
+    }
+    /**
+     * NTSC: 39375000 / 655171, a hair over 60. The other two run at
+     * 1662607 / 33247.5 and 1773448 / 35464, both a hair over 50.
+     */
+    pub fn frame_rate(&self) -> f64 {
+        match self {
+            Region::Ntsc => 60.0988,
+            Region::Pal | Region::Dendy => 50.0070,
+        }
+    }
+    /**
+     * Scanlines in a frame, counting the pre-render line (the last one).
+     */
+    pub fn scanlines(&self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+    /**
+     * The scanline vblank (and the NMI) starts on. Dendy keeps NTSC's 20
+     * lines of vblank and idles after the picture for the other 50.
+     */
+    pub fn vblank_line(&self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+    /**
+     * PPU dots from power on to CPU cycle `cycles`, 3 a cycle but 3.2 on a
+     * PAL console. Counted from the CPU's cycles so the PPU stays in step
+     * across save states.
+     */
+    pub fn ppu_dots(&self, cycles: u64) -> u64 {
+        match self {
+            Region::Ntsc | Region::Dendy => cycles * 3,
+            Region::Pal => cycles * 16 / 5,
+        }
+    }
+    /**
+     * CPU cycles into the APU frame counter's 4-step sequence where it
+     * clocks the envelopes and the rest, for when there's an APU. Dendy's
+     * APU runs to NTSC's.
+     */
+    pub fn frame_counter_steps(&self) -> [u32; 4] {
+        match self {
+            Region::Ntsc | Region::Dendy => [7457, 14913, 22371, 29829],
+            Region::Pal => [8313, 16627, 24939, 33253],
+        }
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Region::Ntsc => write!(f, "NTSC"),
+            Region::Pal => write!(f, "PAL"),
+            Region::Dendy => write!(f, "Dendy"),
+        }
+    }
+}
+
+/**
+ * File format the rom came in.
+ */
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum RomFormat {
+    #[default]
+    INes,
+    Nes2,
+    Unif,
+}
+
+impl fmt::Display for RomFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomFormat::INes => write!(f, "iNES 1.0"),
+            RomFormat::Nes2 => write!(f, "NES 2.0"),
+            RomFormat::Unif => write!(f, "UNIF"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    // missing the "NES<EOF>"/"UNIF" tag
+    BadMagic,
+    // the header promises more data than the file holds
+    Truncated {
+        expected: usize,
+        actual: usize,
+    },
+    UnsupportedVersion(u8),
+    // NES 2.0 numbers boards past 255, none of which we have
+    UnsupportedMapper(u16),
+    NoPrgRom,
+    #[cfg(feature = "std")]
+    Zip(zip::result::ZipError),
+    // what's in the zip archive unpacks to more than MAX_ROM_SIZE
+    #[cfg(feature = "std")]
+    RomTooLarge(usize),
+    // zip archive without a .nes file in it
+    NoRomInArchive,
+    // UNIF board name we can't map onto a mapper number
+    UnknownBoard(String),
+    UnsupportedConsole(ConsoleType),
+    // a trainer for a board with no PRG RAM at $7000 to load it into
+    TrainerWithoutPrgRam(u8),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            CartridgeError::Io(e) => write!(f, "Unable to read rom: {}", e),
+            CartridgeError::BadMagic => {
+                write!(f, "File is not in the iNES or UNIF file format.")
+            }
+            CartridgeError::Truncated { expected, actual } => write!(
+                f,
+                "Rom is truncated, expected at least {} bytes but found {}.",
+                expected, actual
+            ),
+            CartridgeError::UnsupportedVersion(v) => {
+                write!(f, "iNES version {} is not supported.", v)
+            }
+            CartridgeError::UnsupportedMapper(mapper) => {
+                write!(f, "Mapper {} is not supported.", mapper)
+            }
+            CartridgeError::NoPrgRom => write!(f, "Rom doesn't contain any PRG ROM."),
+            #[cfg(feature = "std")]
+            CartridgeError::Zip(e) => write!(f, "Unable to read zip archive: {}", e),
+            #[cfg(feature = "std")]
+            CartridgeError::RomTooLarge(limit) => {
+                write!(
+                    f,
+                    "Rom in the zip archive is over the {} byte limit.",
+                    limit
+                )
+            }
+            CartridgeError::NoRomInArchive => write!(f, "Zip archive doesn't contain a .nes file."),
+            CartridgeError::UnknownBoard(board) => write!(f, "Unknown UNIF board \"{}\".", board),
+            CartridgeError::UnsupportedConsole(console) => {
+                write!(f, "{:?} roms are not supported.", console)
+            }
+            CartridgeError::TrainerWithoutPrgRam(mapper) => write!(
+                f,
+                "Rom has a trainer but mapper {} has no PRG RAM to load it into.",
+                mapper
+            ),
+        }
+    }
+}
+
+impl Error for CartridgeError {
+    #[cfg(feature = "std")]
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CartridgeError::Io(e) => Some(e),
+            CartridgeError::Zip(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for CartridgeError {
+    fn from(e: io::Error) -> Self {
+        CartridgeError::Io(e)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<zip::result::ZipError> for CartridgeError {
+    fn from(e: zip::result::ZipError) -> Self {
+        CartridgeError::Zip(e)
+    }
+}
+
+/**
+ * Contents of the first .nes (or .unf) entry in a zip archive.
+ */
+#[cfg(feature = "std")]
+fn extract_from_zip(bytes: &[u8]) -> Result<Vec<u8>, CartridgeError> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+    for idx in 0..archive.len() {
+        let file = archive.by_index(idx)?;
+        let name = file.name()?.to_ascii_lowercase();
+        if file.is_file()
+            && [".nes", ".unf", ".unif"]
+                .iter()
+                .any(|ext| name.ends_with(ext))
+        {
+            return read_limited(file, MAX_ROM_SIZE);
+        }
+    }
+    Err(CartridgeError::NoRomInArchive)
+}
+
+/**
+ * All of `reader`, up to `limit` bytes. The size in a zip's header is
+ * whatever whoever made it says, so it's not trusted for the allocation.
+ */
+#[cfg(feature = "std")]
+fn read_limited(reader: impl Read, limit: usize) -> Result<Vec<u8>, CartridgeError> {
+    let mut rom = Vec::new();
+    reader.take(limit as u64 + 1).read_to_end(&mut rom)?;
+    if rom.len() > limit {
+        return Err(CartridgeError::RomTooLarge(limit));
+    }
+    Ok(rom)
+}
+
+/**
+ * Bounds checked `&bytes[start..start + len]`
+ */
+fn slice(bytes: &[u8], start: usize, len: usize) -> Result<&[u8], CartridgeError> {
+    // NES 2.0 sizes can be absurd, they're just too big for the file
+    let end = start.saturating_add(len);
+    bytes.get(start..end).ok_or(CartridgeError::Truncated {
+        expected: end,
+        actual: bytes.len(),
+    })
+}
+
+/**
+ * NES 2.0 ROM size from its byte in the header and the nibble of byte 9 on
+ * top of it, in `unit`s. A nibble of $F means the byte is 2^E * (MM*2 + 1)
+ * bytes instead, EEEEEEMM.
+ */
+fn nes2_rom_size(lsb: u8, msb: u8, unit: usize) -> usize {
+    if msb == 0xf {
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        1usize
+            .checked_shl((lsb >> 2) as u32)
+            .map_or(usize::MAX, |size| size.saturating_mul(multiplier))
+    } else {
+        unit * ((msb as usize) << 8 | lsb as usize)
+    }
+}
+
+/**
+ * Hashes of PRG + CHR (no header), what rom databases key on.
+ */
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct RomHashes {
+    pub crc32: u32,
+    pub sha1: [u8; 20],
 }
+
+impl RomHashes {
+    pub fn compute(prgrom: &[u8], chrrom: &[u8]) -> RomHashes {
+        let mut crc = crc32fast::Hasher::new();
+        let mut sha1 = sha1_smol::Sha1::new();
+        for data in [prgrom, chrrom] {
+            crc.update(data);
+            sha1.update(data);
+        }
+        RomHashes {
+            crc32: crc.finalize(),
+            sha1: sha1.digest().bytes(),
+        }
+    }
+    pub fn sha1_hex(&self) -> String {
+        self.sha1.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+#[derive(Default)]
 pub struct Cartridge {
     pub prgrom: Vec<u8>,
     pub chrrom: Vec<u8>,
     pub mirroring: Mirroring,
     pub mapper: u8,
+    pub submapper: u8,
+    pub console: ConsoleType,
+    pub region: Region,
+    pub trainer: Option<Vec<u8>>,
+    // battery backed PRG RAM, the game saves
+    pub battery: bool,
+    pub format: RomFormat,
+    pub hashes: RomHashes,
+    // canonical name from the rom database, if we know the game
+    pub title: Option<String>,
 }
 
 impl Cartridge {
-    pub fn load(path: &str) -> Result<Cartridge, Box<dyn Error>> {
+    /**
+     * Load an iNES or UNIF file, or the first rom inside a zip archive.
+     */
+    #[cfg(feature = "std")]
+    pub fn load(path: &str) -> Result<Cartridge, CartridgeError> {
         let bytes = fs::read(path)?;
-        let header = &bytes[0..=15];
+        if bytes.starts_with(&ZIP_TAG) {
+            return Cartridge::from_bytes(&extract_from_zip(&bytes)?);
+        }
+        Cartridge::from_bytes(&bytes)
+    }
+
+    /**
+     * Parse an iNES, NES 2.0 or UNIF image that's already in memory (embedded fixtures,
+     * network loaded roms, fuzzers...).
+     */
+    pub fn from_bytes(bytes: &[u8]) -> Result<Cartridge, CartridgeError> {
+        if bytes.starts_with(&unif::UNIF_TAG) {
+            return unif::parse(bytes);
+        }
+        if bytes.len() < 4 || bytes[0..4] != NES_TAG {
+            return Err(CartridgeError::BadMagic);
+        }
+        let header = slice(bytes, 0, 16)?;
         let flag6 = header[6];
         let flag7 = header[7];
 
         // validation
-        if header[0..4] != NES_TAG {
-            panic!("File is not in the iNES file format.")
-        }
         let ines_version = (flag7 >> 2) & 0b11;
-        if ines_version != 0 {
-            panic!("Only iNES1 version is supported.")
+        if ines_version != 0 && ines_version != 2 {
+            return Err(CartridgeError::UnsupportedVersion(ines_version));
+        }
+        let nes2 = ines_version == 2;
+        let (prgrom_size, chrrom_size) = if nes2 {
+            (
+                nes2_rom_size(header[4], header[9] & 0x0f, PRG_ROM_SIZE),
+                nes2_rom_size(header[5], header[9] >> 4, CHR_ROM_SIZE),
+            )
+        } else {
+            (
+                PRG_ROM_SIZE * (header[4] as usize),
+                CHR_ROM_SIZE * (header[5] as usize),
+            )
+        };
+        if prgrom_size == 0 {
+            return Err(CartridgeError::NoPrgRom);
         }
+        let mut mapper = (flag7 & 0b11110000 | flag6 >> 4) as u16;
+        if nes2 {
+            mapper |= ((header[8] & 0x0f) as u16) << 8
+        }
+        let Ok(mapper) = u8::try_from(mapper) else {
+            return Err(CartridgeError::UnsupportedMapper(mapper));
+        };
         // ********
 
+        // Vs. System games need coin slots, DIP switches and their own PPU
+        // palettes, refuse them rather than run them wrong. PlayChoice-10 games
+        // are plain NES games with an INST-ROM tacked on the end.
+        let console = match flag7 & 0b11 {
+            0b01 => return Err(CartridgeError::UnsupportedConsole(ConsoleType::VsSystem)),
+            0b10 => ConsoleType::PlayChoice10,
+            _ => ConsoleType::Nes,
+        };
+
         let four_screen = (flag6 >> 3) & 0b1 == 1;
         let vertical = flag6 & 0b1 == 1;
         let mirroring = match (four_screen, vertical) {
@@ -43,22 +438,249 @@ impl Cartridge {
             (_, false) => Mirroring::Horizontal,
         };
 
+        // hardly any iNES dumps set this, the rom database is the better bet.
+        // Games made for both are run as NTSC
+        let region = match (nes2, header[12] & 0b11, header[9] & 0b1) {
+            (true, 1, _) | (false, _, 1) => Region::Pal,
+            (true, 3, _) => Region::Dendy,
+            _ => Region::Ntsc,
+        };
+
+        let battery = (flag6 >> 1) & 0b1 == 0b1;
         let has_trainer = (flag6 >> 2) & 0b1 == 0b1;
-        let prgrom_start = (if has_trainer { 512 } else { 0 } + 16) as usize;
-        let prgrom_size = PRG_ROM_SIZE * (header[4] as usize);
-        let chrrom_start = prgrom_start + prgrom_size;
-        let chrrom_size = CHR_ROM_SIZE * (header[5] as usize);
+        let trainer = if has_trainer {
+            Some(slice(bytes, 16, TRAINER_SIZE)?.to_vec())
+        } else {
+            None
+        };
+        let prgrom_start = if has_trainer { TRAINER_SIZE } else { 0 } + 16;
+        let chrrom_start = prgrom_start.saturating_add(prgrom_size);
 
-        let prgrom: Vec<u8> = bytes[prgrom_start..(prgrom_start + prgrom_size)].to_vec();
-        let chrrom = bytes[chrrom_start..(chrrom_start + chrrom_size)].to_vec();
+        let prgrom: Vec<u8> = slice(bytes, prgrom_start, prgrom_size)?.to_vec();
+        // anything after the CHR ROM (the PlayChoice-10 INST-ROM and PROM) isn't
+        // part of the game, don't let it leak into CHR
+        let chrrom = slice(bytes, chrrom_start, chrrom_size)?.to_vec();
 
-        let mapper = flag7 & 0b11110000 | flag6 >> 4;
+        // only NES 2.0 headers carry a submapper
+        let submapper = if nes2 { header[8] >> 4 } else { 0 };
 
-        Ok(Cartridge {
-            prgrom: prgrom,
+        let hashes = RomHashes::compute(&prgrom, &chrrom);
+        let mut cartridge = Cartridge {
+            prgrom,
             chrrom,
             mirroring,
             mapper,
-        })
+            submapper,
+            console,
+            region,
+            trainer,
+            battery,
+            format: if nes2 {
+                RomFormat::Nes2
+            } else {
+                RomFormat::INes
+            },
+            hashes,
+            title: None,
+        };
+        cartridge.apply_romdb();
+        Ok(cartridge)
+    }
+
+    /**
+     * Plenty of dumps floating around have bad headers, if we know the game
+     * trust the database over the header.
+     */
+    pub(crate) fn apply_romdb(&mut self) {
+        if let Some(entry) = romdb::lookup(self.hashes.crc32) {
+            if let Some(mapper) = entry.mapper {
+                self.mapper = mapper;
+            }
+            if let Some(mirroring) = entry.mirroring {
+                self.mirroring = mirroring;
+            }
+            self.title = Some(entry.title.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod cartridge_test {
+    use std::io::{Cursor, Write};
+
+    use super::{
+        extract_from_zip, read_limited, Cartridge, CartridgeError, ConsoleType, Mirroring, Region,
+        RomFormat, RomHashes,
+    };
+
+    fn make_ines(flag6: u8, trainer: bool) -> Vec<u8> {
+        let mut bytes = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1, flag6, 0xa0];
+        bytes.resize(16, 0);
+        if trainer {
+            bytes.extend(vec![0xee; 512]);
+        }
+        bytes.extend(vec![0xaa; 0x4000]);
+        bytes.extend(vec![0xbb; 0x2000]);
+        bytes
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let cartridge = Cartridge::from_bytes(&make_ines(0xa1, false)).unwrap();
+        assert_eq!(cartridge.mapper, 0xaa);
+        assert_eq!(cartridge.mirroring, Mirroring::Vertical);
+        assert_eq!(cartridge.prgrom, vec![0xaa; 0x4000]);
+        assert_eq!(cartridge.chrrom, vec![0xbb; 0x2000]);
+        assert!(cartridge.trainer.is_none());
+        assert!(!cartridge.battery);
+        assert_eq!(cartridge.region, Region::Ntsc);
+        assert_eq!(cartridge.format, RomFormat::INes);
+
+        let battery = Cartridge::from_bytes(&make_ines(0b10, false)).unwrap();
+        assert!(battery.battery);
+
+        let mut pal = make_ines(0, false);
+        pal[9] = 1;
+        assert_eq!(Cartridge::from_bytes(&pal).unwrap().region, Region::Pal);
+    }
+
+    #[test]
+    fn test_trainer() {
+        let cartridge = Cartridge::from_bytes(&make_ines(0b100, true)).unwrap();
+        assert_eq!(cartridge.trainer, Some(vec![0xee; 512]));
+        assert_eq!(cartridge.prgrom, vec![0xaa; 0x4000]);
+    }
+
+    #[test]
+    fn test_console_type() {
+        let mut vs = make_ines(0, false);
+        vs[7] = 0b01;
+        assert!(matches!(
+            Cartridge::from_bytes(&vs),
+            Err(CartridgeError::UnsupportedConsole(ConsoleType::VsSystem))
+        ));
+
+        let mut pc10 = make_ines(0, false);
+        pc10[7] = 0b10;
+        // 8KB INST-ROM + PROM
+        pc10.extend(vec![0xdd; 0x2000 + 32]);
+        let cartridge = Cartridge::from_bytes(&pc10).unwrap();
+        assert_eq!(cartridge.console, ConsoleType::PlayChoice10);
+        assert_eq!(cartridge.chrrom, vec![0xbb; 0x2000]);
+    }
+
+    #[test]
+    fn test_zip() {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default();
+        zip.start_file("readme.txt", options).unwrap();
+        zip.write_all(b"not a rom").unwrap();
+        zip.start_file("Game.NES", options).unwrap();
+        zip.write_all(&make_ines(0, false)).unwrap();
+        let archive = zip.finish().unwrap().into_inner();
+
+        assert_eq!(extract_from_zip(&archive).unwrap(), make_ines(0, false));
+
+        let rom = make_ines(0, false);
+        assert_eq!(read_limited(&rom[..], rom.len()).unwrap(), rom);
+        assert!(matches!(
+            read_limited(&rom[..], rom.len() - 1),
+            Err(CartridgeError::RomTooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn test_hashes() {
+        let hashes = RomHashes::compute(b"123456789", b"");
+        assert_eq!(hashes.crc32, 0xcbf43926);
+
+        let hashes = RomHashes::compute(b"a", b"bc");
+        assert_eq!(
+            hashes.sha1_hex(),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            Cartridge::from_bytes(b"NOPE"),
+            Err(CartridgeError::BadMagic)
+        ));
+        assert!(matches!(
+            Cartridge::from_bytes(&[0x4e, 0x45, 0x53, 0x1a, 1]),
+            Err(CartridgeError::Truncated { .. })
+        ));
+
+        let mut ines = make_ines(0, false);
+        ines.truncate(0x3000);
+        assert!(matches!(
+            Cartridge::from_bytes(&ines),
+            Err(CartridgeError::Truncated {
+                expected: 0x4010,
+                actual: 0x3000
+            })
+        ));
+
+        let mut archaic = make_ines(0, false);
+        archaic[7] = 0b0100;
+        assert!(matches!(
+            Cartridge::from_bytes(&archaic),
+            Err(CartridgeError::UnsupportedVersion(1))
+        ));
+
+        let mut nes2 = make_ines(0, false);
+        nes2[7] = 0b1000;
+        nes2[8] = 0x01;
+        let e = Cartridge::from_bytes(&nes2).err().unwrap();
+        assert!(matches!(e, CartridgeError::UnsupportedMapper(0x100)));
+        assert_eq!(e.to_string(), "Mapper 256 is not supported.");
+
+        // 2^60 bytes of PRG
+        nes2[8] = 0;
+        nes2[4] = 60 << 2;
+        nes2[9] = 0x0f;
+        assert!(matches!(
+            Cartridge::from_bytes(&nes2),
+            Err(CartridgeError::Truncated { .. })
+        ));
+    }
+
+    #[test]
+    fn test_nes2() {
+        let mut nes2 = make_ines(0x41, false);
+        nes2[7] = 0x08;
+        nes2[8] = 0x30;
+        nes2[12] = 3;
+        let cartridge = Cartridge::from_bytes(&nes2).unwrap();
+        assert_eq!(cartridge.format, RomFormat::Nes2);
+        assert_eq!((cartridge.mapper, cartridge.submapper), (4, 3));
+        assert_eq!(cartridge.region, Region::Dendy);
+        assert_eq!(cartridge.prgrom, vec![0xaa; 0x4000]);
+        assert_eq!(cartridge.chrrom, vec![0xbb; 0x2000]);
+
+        // byte 9 is sizes now, not the region
+        nes2[9] = 0x01;
+        nes2[4] = 0;
+        nes2[12] = 1;
+        nes2.splice(16..16, vec![0xaa; 0x3fc000]);
+        let cartridge = Cartridge::from_bytes(&nes2).unwrap();
+        assert_eq!(cartridge.prgrom.len(), 0x400000);
+        assert_eq!(cartridge.region, Region::Pal);
+
+        // games for both run as NTSC
+        nes2[12] = 2;
+        assert_eq!(Cartridge::from_bytes(&nes2).unwrap().region, Region::Ntsc);
+
+        // 2^13 * 3 bytes of PRG
+        let mut small = make_ines(0, false);
+        small[7] = 0x08;
+        small[4] = 13 << 2 | 1;
+        small[5] = 0;
+        small[9] = 0x0f;
+        let cartridge = Cartridge::from_bytes(&small).unwrap();
+        assert_eq!(cartridge.prgrom[..0x4000], vec![0xaa; 0x4000]);
+        assert_eq!(cartridge.prgrom.len(), 0x6000);
+        assert!(cartridge.chrrom.is_empty());
     }
 }
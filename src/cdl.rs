@@ -0,0 +1,179 @@
+use alloc::{vec, vec::Vec};
+#[cfg(feature = "std")]
+use std::{fs, io, path::Path};
+
+use crate::{
+    debug::Access,
+    disasm::{self, Mode},
+};
+
+// FCEUX's flags for a PRG byte. Bits 2 and 3 hold which 8KB of $8000-$FFFF
+// it was last seen through
+pub const CODE: u8 = 0x01;
+pub const DATA: u8 = 0x02;
+pub const INDIRECT_CODE: u8 = 0x10;
+pub const INDIRECT_DATA: u8 = 0x20;
+
+/**
+ * A code/data log in FCEUX's .cdl format: a byte of flags for every byte of
+ * PRG ROM, then one for every byte of CHR ROM. CHR isn't logged, that part
+ * is there so other tools take the file.
+ */
+pub struct CodeDataLog {
+    prg: Vec<u8>,
+    // only for the file
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    chr_len: usize,
+    // the last instruction was a JMP ($xxxx)
+    jumped_indirect: bool,
+}
+
+impl CodeDataLog {
+    pub fn new(prg_len: usize, chr_len: usize) -> CodeDataLog {
+        CodeDataLog {
+            prg: vec![0; prg_len],
+            chr_len,
+            jumped_indirect: false,
+        }
+    }
+
+    /**
+     * Carry on from the log at `path` when there's one for a ROM this size,
+     * so sessions add up.
+     */
+    #[cfg(feature = "std")]
+    pub fn load_or_new(path: &Path, prg_len: usize, chr_len: usize) -> io::Result<CodeDataLog> {
+        let mut log = CodeDataLog::new(prg_len, chr_len);
+        match fs::read(path) {
+            Ok(bytes) if bytes.len() == prg_len + chr_len => {
+                log.prg.copy_from_slice(&bytes[..prg_len]);
+                Ok(log)
+            }
+            Ok(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is for a different ROM", path.display()),
+            )),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(log),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn flags(&self, offset: usize) -> u8 {
+        self.prg[offset]
+    }
+
+    pub fn prg_flags(&self) -> &[u8] {
+        &self.prg
+    }
+
+    /**
+     * Sort what an instruction at `pc` read into code and data. `prg_offset`
+     * is where an address is in PRG ROM right now, see
+     * `Mapper::prg_rom_offset`.
+     */
+    pub fn log_instruction(
+        &mut self,
+        pc: u16,
+        opcode: u8,
+        accesses: &[Access],
+        prg_offset: impl Fn(u16) -> Option<usize>,
+    ) {
+        // unofficial opcodes are taken to be a byte long, it's the best guess
+        let mode = disasm::decode(opcode).map(|(_, mode)| mode);
+        let len = 1 + mode.map_or(0, Mode::operand_len);
+        let code = if self.jumped_indirect {
+            CODE | INDIRECT_CODE
+        } else {
+            CODE
+        };
+        let data = match mode {
+            Some(Mode::IndirectX | Mode::IndirectY) => DATA | INDIRECT_DATA,
+            _ => DATA,
+        };
+        for access in accesses.iter().filter(|access| !access.write) {
+            if let Some(offset) = prg_offset(access.addr) {
+                let is_code = access.addr.wrapping_sub(pc) < len;
+                let window = ((access.addr >> 13) & 0b11) as u8;
+                self.prg[offset] |= if is_code { code } else { data } | window << 2;
+            }
+        }
+        self.jumped_indirect = opcode == 0x6c;
+    }
+
+    /**
+     * Bytes of PRG ROM seen as code and as data, a byte can be both.
+     */
+    pub fn counts(&self) -> (usize, usize) {
+        let count = |flag| self.prg.iter().filter(|&&b| b & flag != 0).count();
+        (count(CODE), count(DATA))
+    }
+
+    #[cfg(feature = "std")]
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut bytes = self.prg.clone();
+        bytes.resize(self.prg.len() + self.chr_len, 0);
+        fs::write(path, bytes)
+    }
+}
+
+#[cfg(test)]
+mod cdl_test {
+    use crate::debug::Access;
+
+    use super::{CodeDataLog, CODE, DATA, INDIRECT_CODE, INDIRECT_DATA};
+
+    fn read(addr: u16) -> Access {
+        Access {
+            addr,
+            value: 0,
+            write: false,
+        }
+    }
+
+    #[test]
+    fn test_log_instruction() {
+        let mut log = CodeDataLog::new(0x8000, 0x2000);
+        let offset = |addr: u16| (addr >= 0x8000).then(|| (addr - 0x8000) as usize);
+        // LDA ($10),Y at $C000 reading $D234 through a pointer in zero page
+        let accesses = [
+            read(0xc000),
+            read(0xc001),
+            read(0x10),
+            read(0x11),
+            read(0xd234),
+        ];
+        log.log_instruction(0xc000, 0xb1, &accesses, offset);
+        assert_eq!(log.flags(0x4000), CODE | 2 << 2);
+        assert_eq!(log.flags(0x4001), CODE | 2 << 2);
+        assert_eq!(log.flags(0x5234), DATA | INDIRECT_DATA | 2 << 2);
+        // JMP ($8000), then what it lands on
+        log.log_instruction(0xc002, 0x6c, &[read(0xc002), read(0x8000)], offset);
+        assert_eq!(log.flags(0x0000), DATA);
+        log.log_instruction(0x9000, 0xea, &[read(0x9000)], offset);
+        assert_eq!(log.flags(0x1000), CODE | INDIRECT_CODE);
+        // writes don't count
+        let write = Access {
+            addr: 0x8010,
+            value: 1,
+            write: true,
+        };
+        log.log_instruction(0x9001, 0x8d, &[read(0x9001), write], offset);
+        assert_eq!(log.flags(0x0010), 0);
+        assert_eq!(log.counts(), (5, 2));
+    }
+
+    #[test]
+    fn test_save() {
+        let path = std::env::temp_dir().join("cdl_test.cdl");
+        let mut log = CodeDataLog::new(0x4000, 0x2000);
+        log.log_instruction(0x8000, 0xea, &[read(0x8000)], |addr| {
+            Some((addr - 0x8000) as usize)
+        });
+        log.save(&path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap().len(), 0x6000);
+        let log = CodeDataLog::load_or_new(&path, 0x4000, 0x2000).unwrap();
+        assert_eq!(log.flags(0), CODE);
+        assert!(CodeDataLog::load_or_new(&path, 0x8000, 0).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,431 @@
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{error::Error, fmt};
+#[cfg(feature = "std")]
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+// a Game Genie letter's place is its value
+const GENIE_LETTERS: &str = "APZLGITYEOXUKSVN";
+
+#[derive(Debug)]
+pub enum CheatError {
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    #[cfg(feature = "std")]
+    Parse(toml::de::Error),
+    #[cfg(feature = "std")]
+    Serialize(toml::ser::Error),
+    BadCode(String),
+    NoCheat(usize),
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            CheatError::Io(e) => write!(f, "Unable to access the cheat file: {}", e),
+            #[cfg(feature = "std")]
+            CheatError::Parse(e) => write!(f, "Unable to read the cheat file: {}", e),
+            #[cfg(feature = "std")]
+            CheatError::Serialize(e) => write!(f, "Unable to write the cheat file: {}", e),
+            CheatError::BadCode(code) => write!(
+                f,
+                "{} isn't a Game Genie code or AAAA:VV / AAAA?CC:VV",
+                code
+            ),
+            CheatError::NoCheat(idx) => write!(f, "There's no cheat {}", idx),
+        }
+    }
+}
+
+impl Error for CheatError {
+    #[cfg(feature = "std")]
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CheatError::Io(e) => Some(e),
+            CheatError::Parse(e) => Some(e),
+            CheatError::Serialize(e) => Some(e),
+            CheatError::BadCode(_) | CheatError::NoCheat(_) => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for CheatError {
+    fn from(e: io::Error) -> Self {
+        CheatError::Io(e)
+    }
+}
+
+/**
+ * What a cheat does: reads of `addr` see `value` instead, only when what's
+ * really there is `compare` if there is one. That's how a Game Genie patches
+ * ROM, and for RAM it freezes the byte as far as the game can tell.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Code {
+    pub addr: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+}
+
+impl Code {
+    /**
+     * A 6 or 8 letter Game Genie code, or raw: "0300:63" or "C123?12:63"
+     * with a compare value.
+     */
+    pub fn parse(text: &str) -> Option<Code> {
+        let text = text.trim().to_ascii_uppercase();
+        if text.contains(':') {
+            return Code::parse_raw(&text);
+        }
+        Code::parse_genie(&text)
+    }
+
+    fn parse_raw(text: &str) -> Option<Code> {
+        let hex = |text: &str| u16::from_str_radix(text.trim_start_matches('$'), 16).ok();
+        let (addr, value) = text.split_once(':')?;
+        let (addr, compare) = match addr.split_once('?') {
+            Some((addr, compare)) => (addr, Some(u8::try_from(hex(compare)?).ok()?)),
+            None => (addr, None),
+        };
+        Some(Code {
+            addr: hex(addr)?,
+            value: u8::try_from(hex(value)?).ok()?,
+            compare,
+        })
+    }
+
+    // see https://www.nesdev.org/wiki/Game_Genie
+    fn parse_genie(text: &str) -> Option<Code> {
+        let n: Vec<u16> = text
+            .chars()
+            .map(|c| GENIE_LETTERS.find(c).map(|n| n as u16))
+            .collect::<Option<_>>()?;
+        if n.len() != 6 && n.len() != 8 {
+            return None;
+        }
+        let addr = 0x8000
+            | ((n[3] & 7) << 12)
+            | ((n[5] & 7) << 8)
+            | ((n[4] & 8) << 8)
+            | ((n[2] & 7) << 4)
+            | ((n[1] & 8) << 4)
+            | (n[4] & 7)
+            | (n[3] & 8);
+        // the last letter's high bit goes with the value
+        let last = n[n.len() - 1];
+        let value = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7) | (last & 8);
+        let compare =
+            (n.len() == 8).then(|| ((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8));
+        Some(Code {
+            addr,
+            value: value as u8,
+            compare: compare.map(|c| c as u8),
+        })
+    }
+
+    pub fn applies(&self, addr: u16, value: u8) -> bool {
+        self.addr == addr && self.compare.is_none_or(|compare| compare == value)
+    }
+}
+
+/**
+ * A change to the list, from the command line or the debugger.
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub enum CheatEdit {
+    Add { code: String, name: String },
+    Remove(usize),
+    Enable(usize),
+    Disable(usize),
+}
+
+impl CheatEdit {
+    /**
+     * "add <code> [name...]", "remove N", "enable N" or "disable N".
+     */
+    pub fn parse<'a>(mut words: impl Iterator<Item = &'a str>) -> Option<CheatEdit> {
+        let action = words.next()?;
+        if action == "add" {
+            let code = words.next()?.to_string();
+            let name = words.collect::<Vec<_>>().join(" ");
+            return Some(CheatEdit::Add { code, name });
+        }
+        let idx = words.next()?.parse().ok()?;
+        if words.next().is_some() {
+            return None;
+        }
+        match action {
+            "remove" => Some(CheatEdit::Remove(idx)),
+            "enable" => Some(CheatEdit::Enable(idx)),
+            "disable" => Some(CheatEdit::Disable(idx)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Cheat {
+    pub name: String,
+    // as it was typed in, see `Code::parse`
+    pub code: String,
+    pub enabled: bool,
+}
+
+#[cfg(feature = "std")]
+#[derive(Serialize, Deserialize, Default)]
+struct CheatFile {
+    #[serde(default)]
+    cheat: Vec<Cheat>,
+}
+
+/**
+ * A game's cheats, kept in cheats/<crc32>.toml next to config.toml. Every
+ * change is the caller's to `save`.
+ */
+#[derive(Default, Debug)]
+pub struct Cheats {
+    // None for cheats that aren't kept anywhere
+    #[cfg(feature = "std")]
+    path: Option<PathBuf>,
+    cheats: Vec<Cheat>,
+}
+
+impl Cheats {
+    pub fn new() -> Cheats {
+        Cheats::default()
+    }
+
+    /**
+     * cheats/ in the same directory as the config file.
+     */
+    #[cfg(feature = "std")]
+    pub fn path(config_path: &Path, crc32: u32) -> PathBuf {
+        config_path
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join("cheats")
+            .join(format!("{:08x}.toml", crc32))
+    }
+
+    /**
+     * None of them when the game doesn't have a file yet, `save` makes it.
+     */
+    #[cfg(feature = "std")]
+    pub fn load(path: &Path) -> Result<Cheats, CheatError> {
+        let mut cheats = Cheats {
+            path: Some(path.to_path_buf()),
+            cheats: Vec::new(),
+        };
+        if !path.exists() {
+            return Ok(cheats);
+        }
+        let text = fs::read_to_string(path)?;
+        let file: CheatFile = toml::from_str(&text).map_err(CheatError::Parse)?;
+        if let Some(bad) = file.cheat.iter().find(|c| Code::parse(&c.code).is_none()) {
+            return Err(CheatError::BadCode(bad.code.clone()));
+        }
+        cheats.cheats = file.cheat;
+        Ok(cheats)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn save(&self) -> Result<(), CheatError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?
+        }
+        let file = CheatFile {
+            cheat: self.cheats.clone(),
+        };
+        let text = toml::to_string(&file).map_err(CheatError::Serialize)?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn list(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    /**
+     * Added switched on.
+     */
+    pub fn add(&mut self, name: &str, code: &str) -> Result<(), CheatError> {
+        if Code::parse(code).is_none() {
+            return Err(CheatError::BadCode(code.to_string()));
+        }
+        self.cheats.push(Cheat {
+            name: name.to_string(),
+            code: code.trim().to_string(),
+            enabled: true,
+        });
+        Ok(())
+    }
+
+    pub fn remove(&mut self, idx: usize) -> Option<Cheat> {
+        (idx < self.cheats.len()).then(|| self.cheats.remove(idx))
+    }
+
+    /**
+     * False when there's no cheat `idx`.
+     */
+    pub fn set_enabled(&mut self, idx: usize, enabled: bool) -> bool {
+        match self.cheats.get_mut(idx) {
+            Some(cheat) => {
+                cheat.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn apply(&mut self, edit: &CheatEdit) -> Result<(), CheatError> {
+        let (idx, found) = match *edit {
+            CheatEdit::Add { ref code, ref name } => return self.add(name, code),
+            CheatEdit::Remove(idx) => (idx, self.remove(idx).is_some()),
+            CheatEdit::Enable(idx) => (idx, self.set_enabled(idx, true)),
+            CheatEdit::Disable(idx) => (idx, self.set_enabled(idx, false)),
+        };
+        if !found {
+            return Err(CheatError::NoCheat(idx));
+        }
+        Ok(())
+    }
+
+    /**
+     * What the switched on cheats do, for the bus.
+     */
+    pub fn codes(&self) -> Vec<Code> {
+        self.cheats
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .filter_map(|cheat| Code::parse(&cheat.code))
+            .collect()
+    }
+}
+
+impl fmt::Display for Cheats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.cheats.is_empty() {
+            return write!(f, "No cheats");
+        }
+        for (idx, cheat) in self.cheats.iter().enumerate() {
+            if idx > 0 {
+                writeln!(f)?
+            }
+            let state = if cheat.enabled { "on" } else { "off" };
+            write!(f, "{}: [{}] {}", idx, state, cheat.code)?;
+            if !cheat.name.is_empty() {
+                write!(f, " {}", cheat.name)?
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod cheats_test {
+    use super::{CheatEdit, Cheats, Code};
+
+    #[test]
+    fn test_parse() {
+        // the example from the nesdev wiki
+        assert_eq!(
+            Code::parse("GOSSIP"),
+            Some(Code {
+                addr: 0xd1dd,
+                value: 0x14,
+                compare: None
+            })
+        );
+        assert_eq!(
+            Code::parse("zexpyglA"),
+            Some(Code {
+                addr: 0x94a7,
+                value: 0x02,
+                compare: Some(0x03)
+            })
+        );
+        assert_eq!(
+            Code::parse("0300:63"),
+            Some(Code {
+                addr: 0x0300,
+                value: 0x63,
+                compare: None
+            })
+        );
+        assert_eq!(Code::parse("C123?12:63").unwrap().compare, Some(0x12));
+        assert_eq!(Code::parse("GOSSIPS"), None);
+        assert_eq!(Code::parse("0300:163"), None);
+        assert_eq!(Code::parse("BOGUS1"), None);
+
+        let code = Code::parse("C123?12:63").unwrap();
+        assert!(code.applies(0xc123, 0x12));
+        assert!(!code.applies(0xc123, 0x13));
+    }
+
+    #[test]
+    fn test_edit() {
+        let words = |line: &'static str| line.split_whitespace();
+        assert_eq!(
+            CheatEdit::parse(words("add SXIOPO Infinite lives")),
+            Some(CheatEdit::Add {
+                code: "SXIOPO".to_string(),
+                name: "Infinite lives".to_string()
+            })
+        );
+        assert_eq!(
+            CheatEdit::parse(words("disable 2")),
+            Some(CheatEdit::Disable(2))
+        );
+        assert_eq!(CheatEdit::parse(words("remove")), None);
+        assert_eq!(CheatEdit::parse(words("enable 1 2")), None);
+        assert_eq!(CheatEdit::parse(words("toggle 1")), None);
+
+        let mut cheats = Cheats::new();
+        cheats
+            .apply(&CheatEdit::parse(words("add 0300:63")).unwrap())
+            .unwrap();
+        cheats.apply(&CheatEdit::Disable(0)).unwrap();
+        assert!(cheats.codes().is_empty());
+        assert!(cheats.apply(&CheatEdit::Remove(1)).is_err());
+        cheats.apply(&CheatEdit::Remove(0)).unwrap();
+        assert_eq!(cheats.to_string(), "No cheats");
+    }
+
+    #[test]
+    fn test_file() {
+        let path = std::env::temp_dir()
+            .join("cheats_test")
+            .join("cheats")
+            .join("0000abcd.toml");
+        let _ = std::fs::remove_file(&path);
+        let mut cheats = Cheats::load(&path).unwrap();
+        assert!(cheats.list().is_empty());
+        cheats.add("Infinite lives", "SXIOPO").unwrap();
+        cheats.add("99 coins", "075E:63").unwrap();
+        assert!(cheats.add("Nonsense", "XYZ").is_err());
+        cheats.set_enabled(0, false);
+        cheats.save().unwrap();
+
+        let cheats = Cheats::load(&path).unwrap();
+        assert_eq!(cheats.list().len(), 2);
+        assert!(!cheats.list()[0].enabled);
+        assert_eq!(cheats.codes(), [Code::parse("075E:63").unwrap()]);
+        assert_eq!(
+            cheats.to_string(),
+            "0: [off] SXIOPO Infinite lives\n1: [on] 075E:63 99 coins"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,693 @@
+use std::{error::Error, fmt, iter::Peekable};
+
+use nes::{
+    assertion::Assertion, cartridge::Region, cheats::CheatEdit, config::Aspect, hexdump::Space,
+};
+
+use crate::binding::Device;
+
+pub const USAGE: &str = "\
+Usage: nes [run] [options] <rom>
+       nes record <rom> <movie> [options]
+       nes play <rom> <movie> [options]
+       nes rominfo <rom>
+       nes crashdump <file>
+       nes disasm [--symbols <file>]... <rom>
+       nes debug [--symbols <file>]... <rom>
+       nes tracediff [--start <addr>] [--context N] <rom> <log>
+       nes hexdump [--frames N] [--space cpu|ppu|oam] <rom>
+       nes test [--frames N] <rom|dir|tests.toml>...
+       nes test --bless [--frames N] <tests.toml>...
+       nes bind <1-4|power-pad>
+       nes cheats <rom> [add <code> [name] | remove|enable|disable <N>]
+
+Run options:
+  --record <movie>       record the controllers to a movie
+  --play <movie>         play a movie back
+  --scale <1-4>          window scale
+  --aspect <1:1|8:7>     pixel aspect ratio
+  --region <name>        console to run as, ntsc, pal or dendy
+  --fit                  fill the window, keeping the aspect ratio
+  --borderless           no title bar or frame around the window
+  --headless             no window, as fast as possible
+  --frames <N>           stop after N frames (headless)
+  --report <text|json>   how to report a headless run, json implies --headless
+  --profile              show the time spent per subsystem
+  --trace <file>         write a nestest.log style line per instruction
+  --symbols <file>       label addresses in traces (.nl, ca65 .dbg or ld65 -Ln)
+  --script <file>        run a rhai script alongside the game
+  --cdl <file>           log PRG code and data to an FCEUX .cdl file
+  --coverage <file>      write how much of PRG ROM ran, as json for a .json file
+  --assert <condition>   stop in the debugger once it's false, implies --headless
+  --load-state <file>    start from a save state of the same game
+  --save-state <file>    save the machine's state on the way out
+  --host <port>          wait for a second player to join over the network
+  --join <address>       play as player 2 in a game hosted at host:port
+  --input-delay <N>      netplay frames of input lag, 0-30 (host, default 2)
+  --fresh                start from power on, not where the game was left
+  --recent [N]           list the recently opened roms, or open one
+  --audio-device <name>  play through this device
+  --audio-buffer <N>     device buffer in sample frames, a power of two
+  --audio-devices        list the audio devices";
+
+const COMMANDS: [&str; 13] = [
+    "run",
+    "record",
+    "play",
+    "rominfo",
+    "crashdump",
+    "disasm",
+    "debug",
+    "tracediff",
+    "hexdump",
+    "test",
+    "bind",
+    "cheats",
+    "help",
+];
+
+// a second in, past most games' startup
+const HEXDUMP_FRAMES: u64 = 60;
+
+// blargg's suites take a few seconds at most, this is plenty
+const TEST_FRAMES: u64 = 60 * 60;
+
+// lines shown from before a trace diverges
+const TRACE_CONTEXT: usize = 10;
+
+#[derive(Debug, PartialEq)]
+pub enum CliError {
+    MissingValue(String),
+    BadValue(String, String),
+    MissingArgument(&'static str),
+    UnknownOption(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::MissingValue(option) => write!(f, "{} needs a value", option),
+            CliError::BadValue(option, value) => write!(f, "Bad value for {}: {}", option, value),
+            CliError::MissingArgument(name) => write!(f, "Missing the {} argument", name),
+            CliError::UnknownOption(option) => write!(f, "Unknown option {}", option),
+        }
+    }
+}
+
+impl Error for CliError {}
+
+/**
+ * What a headless run prints when it's done.
+ */
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub enum ReportFormat {
+    // a line with the frame count and speed
+    #[default]
+    Text,
+    // the machine state, for scripts
+    Json,
+}
+
+/**
+ * Playing a game, with or without a window.
+ */
+#[derive(Default, Debug, PartialEq)]
+pub struct RunOptions {
+    pub rom: Option<String>,
+    pub record: Option<String>,
+    pub play: Option<String>,
+    pub scale: Option<u32>,
+    pub aspect: Option<Aspect>,
+    pub region: Option<Region>,
+    pub fit: bool,
+    pub borderless: bool,
+    pub headless: bool,
+    pub frames: Option<u64>,
+    pub report: ReportFormat,
+    pub profile: bool,
+    pub trace: Option<String>,
+    // on top of any FCEUX .nl files next to the rom
+    pub symbols: Vec<String>,
+    pub script: Option<String>,
+    pub cdl: Option<String>,
+    // counts what --cdl carried on from too
+    pub coverage: Option<String>,
+    pub assertions: Vec<Assertion>,
+    pub load_state: Option<String>,
+    pub save_state: Option<String>,
+    pub host: Option<u16>,
+    pub join: Option<String>,
+    pub input_delay: Option<u8>,
+    // turns down resuming
+    pub fresh: bool,
+    // on its own lists them, with a number opens that one
+    pub recent: Option<Option<usize>>,
+    pub audio_device: Option<String>,
+    pub audio_buffer: Option<u16>,
+}
+
+// there's only ever the one, made at startup
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run(RunOptions),
+    RomInfo(String),
+    // print a crash dump written when the emulator panicked
+    CrashDump(String),
+    Disasm {
+        rom: String,
+        symbols: Vec<String>,
+    },
+    // step through at a prompt, forwards and back
+    Debug {
+        rom: String,
+        symbols: Vec<String>,
+    },
+    // run alongside another emulator's trace until they disagree
+    TraceDiff {
+        rom: String,
+        log: String,
+        start: Option<u16>,
+        context: usize,
+    },
+    // memory after running a while, marking what the last frame changed
+    Hexdump {
+        rom: String,
+        space: Space,
+        frames: u64,
+    },
+    Test {
+        roms: Vec<String>,
+        frames: u64,
+        // record the frame hashes instead of checking them
+        bless: bool,
+    },
+    // ask for a key per button and save them
+    Bind(Device),
+    // list the game's cheats, or change them
+    Cheats {
+        rom: String,
+        edit: Option<CheatEdit>,
+    },
+    AudioDevices,
+    Help,
+}
+
+fn value(option: &str, args: &mut impl Iterator<Item = String>) -> Result<String, CliError> {
+    args.next()
+        .ok_or_else(|| CliError::MissingValue(option.to_string()))
+}
+
+fn parsed<T: std::str::FromStr>(
+    option: &str,
+    args: &mut impl Iterator<Item = String>,
+) -> Result<T, CliError> {
+    let text = value(option, args)?;
+    text.parse()
+        .map_err(|_| CliError::BadValue(option.to_string(), text))
+}
+
+fn rom(args: &mut impl Iterator<Item = String>) -> Result<String, CliError> {
+    args.next().ok_or(CliError::MissingArgument("rom"))
+}
+
+fn run_options<I: Iterator<Item = String>>(
+    mut options: RunOptions,
+    args: &mut Peekable<I>,
+) -> Result<Command, CliError> {
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record" => options.record = Some(value(&arg, args)?),
+            "--play" => options.play = Some(value(&arg, args)?),
+            "--scale" => match parsed(&arg, args)? {
+                n @ 1..=4 => options.scale = Some(n),
+                n => return Err(CliError::BadValue(arg, n.to_string())),
+            },
+            "--aspect" => {
+                let text = value(&arg, args)?;
+                options.aspect = Some(Aspect::parse(&text).ok_or(CliError::BadValue(arg, text))?)
+            }
+            "--region" => {
+                let text = value(&arg, args)?;
+                options.region = Some(Region::parse(&text).ok_or(CliError::BadValue(arg, text))?)
+            }
+            "--fit" => options.fit = true,
+            "--borderless" => options.borderless = true,
+            "--headless" => options.headless = true,
+            "--frames" => options.frames = Some(parsed(&arg, args)?),
+            "--report" => {
+                options.report = match value(&arg, args)?.as_str() {
+                    "text" => ReportFormat::Text,
+                    "json" => {
+                        options.headless = true;
+                        ReportFormat::Json
+                    }
+                    other => return Err(CliError::BadValue(arg, other.to_string())),
+                }
+            }
+            "--profile" => options.profile = true,
+            "--trace" => options.trace = Some(value(&arg, args)?),
+            "--symbols" => options.symbols.push(value(&arg, args)?),
+            "--script" => options.script = Some(value(&arg, args)?),
+            "--cdl" => options.cdl = Some(value(&arg, args)?),
+            "--coverage" => options.coverage = Some(value(&arg, args)?),
+            "--assert" => {
+                let text = value(&arg, args)?;
+                let assertion =
+                    Assertion::parse(&text).map_err(|_| CliError::BadValue(arg, text))?;
+                options.assertions.push(assertion);
+                options.headless = true
+            }
+            "--load-state" => options.load_state = Some(value(&arg, args)?),
+            "--save-state" => options.save_state = Some(value(&arg, args)?),
+            "--host" => options.host = Some(parsed(&arg, args)?),
+            "--join" => options.join = Some(value(&arg, args)?),
+            "--input-delay" => match parsed(&arg, args)? {
+                n @ 0..=30 => options.input_delay = Some(n),
+                n => return Err(CliError::BadValue(arg, n.to_string())),
+            },
+            "--fresh" => options.fresh = true,
+            "--recent" => {
+                options.recent = Some(
+                    args.next_if(|n| n.parse::<usize>().is_ok())
+                        .map(|n| n.parse::<usize>().unwrap()),
+                )
+            }
+            "--audio-devices" => return Ok(Command::AudioDevices),
+            "--audio-device" => options.audio_device = Some(value(&arg, args)?),
+            "--audio-buffer" => match parsed::<u16>(&arg, args)? {
+                n if n.is_power_of_two() => options.audio_buffer = Some(n),
+                n => return Err(CliError::BadValue(arg, n.to_string())),
+            },
+            "-h" | "--help" => return Ok(Command::Help),
+            _ if arg.starts_with("--") => return Err(CliError::UnknownOption(arg)),
+            _ => options.rom = Some(arg),
+        }
+    }
+    Ok(Command::Run(options))
+}
+
+fn hexdump_options(mut args: impl Iterator<Item = String>) -> Result<Command, CliError> {
+    let mut rom = None;
+    let mut space = Space::Cpu;
+    let mut frames = HEXDUMP_FRAMES;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => frames = parsed(&arg, &mut args)?,
+            "--space" => {
+                let text = value(&arg, &mut args)?;
+                space = Space::parse(&text).ok_or(CliError::BadValue(arg, text))?
+            }
+            _ if arg.starts_with("--") => return Err(CliError::UnknownOption(arg)),
+            _ => rom = Some(arg),
+        }
+    }
+    let rom = rom.ok_or(CliError::MissingArgument("rom"))?;
+    Ok(Command::Hexdump { rom, space, frames })
+}
+
+// disasm and debug take the same arguments
+fn rom_and_symbols(
+    mut args: impl Iterator<Item = String>,
+) -> Result<(String, Vec<String>), CliError> {
+    let mut rom = None;
+    let mut symbols = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--symbols" => symbols.push(value(&arg, &mut args)?),
+            _ if arg.starts_with("--") => return Err(CliError::UnknownOption(arg)),
+            _ => rom = Some(arg),
+        }
+    }
+    let rom = rom.ok_or(CliError::MissingArgument("rom"))?;
+    Ok((rom, symbols))
+}
+
+fn trace_diff_options(mut args: impl Iterator<Item = String>) -> Result<Command, CliError> {
+    let mut files = Vec::new();
+    let mut start = None;
+    let mut context = TRACE_CONTEXT;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--start" => {
+                let text = value(&arg, &mut args)?;
+                let addr = u16::from_str_radix(text.trim_start_matches('$'), 16)
+                    .map_err(|_| CliError::BadValue(arg, text))?;
+                start = Some(addr)
+            }
+            "--context" => context = parsed(&arg, &mut args)?,
+            _ if arg.starts_with("--") => return Err(CliError::UnknownOption(arg)),
+            _ => files.push(arg),
+        }
+    }
+    let mut files = files.into_iter();
+    let rom = files.next().ok_or(CliError::MissingArgument("rom"))?;
+    let log = files.next().ok_or(CliError::MissingArgument("log"))?;
+    Ok(Command::TraceDiff {
+        rom,
+        log,
+        start,
+        context,
+    })
+}
+
+fn test_options(mut args: impl Iterator<Item = String>) -> Result<Command, CliError> {
+    let mut roms = Vec::new();
+    let mut frames = TEST_FRAMES;
+    let mut bless = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => frames = parsed(&arg, &mut args)?,
+            "--bless" => bless = true,
+            _ if arg.starts_with("--") => return Err(CliError::UnknownOption(arg)),
+            _ => roms.push(arg),
+        }
+    }
+    if roms.is_empty() {
+        return Err(CliError::MissingArgument("rom"));
+    }
+    Ok(Command::Test {
+        roms,
+        frames,
+        bless,
+    })
+}
+
+/**
+ * Arguments without the program name. Without a command it's `run`, so the
+ * old `nes <rom> --scale 3` style still works.
+ */
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, CliError> {
+    let mut args = args.into_iter().peekable();
+    let command = args.next_if(|arg| COMMANDS.contains(&arg.as_str()));
+    match command.as_deref() {
+        Some("record") | Some("play") => {
+            let rom = rom(&mut args)?;
+            let movie = args.next().ok_or(CliError::MissingArgument("movie"))?;
+            let mut options = RunOptions {
+                rom: Some(rom),
+                ..Default::default()
+            };
+            if command.as_deref() == Some("record") {
+                options.record = Some(movie)
+            } else {
+                options.play = Some(movie)
+            }
+            run_options(options, &mut args)
+        }
+        Some("rominfo") => Ok(Command::RomInfo(rom(&mut args)?)),
+        Some("crashdump") => {
+            let path = args.next().ok_or(CliError::MissingArgument("file"))?;
+            Ok(Command::CrashDump(path))
+        }
+        Some("disasm") => {
+            let (rom, symbols) = rom_and_symbols(args)?;
+            Ok(Command::Disasm { rom, symbols })
+        }
+        Some("debug") => {
+            let (rom, symbols) = rom_and_symbols(args)?;
+            Ok(Command::Debug { rom, symbols })
+        }
+        Some("tracediff") => trace_diff_options(args),
+        Some("hexdump") => hexdump_options(args),
+        Some("test") => test_options(args),
+        Some("bind") => {
+            let text = args.next().ok_or(CliError::MissingArgument("device"))?;
+            Device::parse(&text)
+                .map(Command::Bind)
+                .ok_or(CliError::BadValue("bind".to_string(), text))
+        }
+        Some("cheats") => {
+            let rom = rom(&mut args)?;
+            let words: Vec<String> = args.collect();
+            if words.is_empty() {
+                return Ok(Command::Cheats { rom, edit: None });
+            }
+            match CheatEdit::parse(words.iter().map(String::as_str)) {
+                Some(edit) => Ok(Command::Cheats {
+                    rom,
+                    edit: Some(edit),
+                }),
+                None => Err(CliError::BadValue("cheats".to_string(), words.join(" "))),
+            }
+        }
+        Some("help") => Ok(Command::Help),
+        _ => run_options(RunOptions::default(), &mut args),
+    }
+}
+
+#[cfg(test)]
+mod cli_test {
+    use nes::{
+        assertion::Assertion, cartridge::Region, cheats::CheatEdit, config::Aspect, hexdump::Space,
+    };
+
+    use crate::binding::Device;
+
+    use super::{
+        parse, CliError, Command, ReportFormat, RunOptions, HEXDUMP_FRAMES, TEST_FRAMES,
+        TRACE_CONTEXT,
+    };
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_run() {
+        let expected = Command::Run(RunOptions {
+            rom: Some("smb.nes".to_string()),
+            scale: Some(3),
+            aspect: Some(Aspect::Ntsc),
+            borderless: true,
+            recent: Some(None),
+            ..Default::default()
+        });
+        assert_eq!(
+            parse(args(
+                "run smb.nes --scale 3 --aspect 8:7 --borderless --recent"
+            )),
+            Ok(expected)
+        );
+        // no command is run
+        assert_eq!(
+            parse(args("--headless --frames 10 --profile smb.nes")),
+            Ok(Command::Run(RunOptions {
+                rom: Some("smb.nes".to_string()),
+                headless: true,
+                frames: Some(10),
+                profile: true,
+                ..Default::default()
+            }))
+        );
+        assert_eq!(
+            parse(args(
+                "smb.nes --frames 600 --report json --trace smb.log --script hud.rhai --cdl smb.cdl --coverage smb.json"
+            )),
+            Ok(Command::Run(RunOptions {
+                rom: Some("smb.nes".to_string()),
+                headless: true,
+                frames: Some(600),
+                report: ReportFormat::Json,
+                trace: Some("smb.log".to_string()),
+                script: Some("hud.rhai".to_string()),
+                cdl: Some("smb.cdl".to_string()),
+                coverage: Some("smb.json".to_string()),
+                ..Default::default()
+            }))
+        );
+        assert_eq!(
+            parse(args(
+                "smb.nes --load-state 1-2.state --save-state 1-3.state --fresh"
+            )),
+            Ok(Command::Run(RunOptions {
+                rom: Some("smb.nes".to_string()),
+                load_state: Some("1-2.state".to_string()),
+                save_state: Some("1-3.state".to_string()),
+                fresh: true,
+                ..Default::default()
+            }))
+        );
+        assert_eq!(
+            parse(args("smb.nes --host 7000 --input-delay 4")),
+            Ok(Command::Run(RunOptions {
+                rom: Some("smb.nes".to_string()),
+                host: Some(7000),
+                input_delay: Some(4),
+                ..Default::default()
+            }))
+        );
+        assert_eq!(
+            parse(args("smb.nes --join 10.0.0.2:7000")),
+            Ok(Command::Run(RunOptions {
+                rom: Some("smb.nes".to_string()),
+                join: Some("10.0.0.2:7000".to_string()),
+                ..Default::default()
+            }))
+        );
+        assert_eq!(
+            parse(args("smb.nes --region Dendy")),
+            Ok(Command::Run(RunOptions {
+                rom: Some("smb.nes".to_string()),
+                region: Some(Region::Dendy),
+                ..Default::default()
+            }))
+        );
+        assert_eq!(
+            parse(args("smb.nes --region secam")),
+            Err(CliError::BadValue(
+                "--region".to_string(),
+                "secam".to_string()
+            ))
+        );
+        assert_eq!(
+            parse(args("smb.nes --input-delay 60")),
+            Err(CliError::BadValue(
+                "--input-delay".to_string(),
+                "60".to_string()
+            ))
+        );
+        assert_eq!(
+            parse(args("--recent 2")),
+            Ok(Command::Run(RunOptions {
+                recent: Some(Some(2)),
+                ..Default::default()
+            }))
+        );
+    }
+
+    #[test]
+    fn test_commands() {
+        assert_eq!(
+            parse(args("record smb.nes run.fm2 --scale 2")),
+            Ok(Command::Run(RunOptions {
+                rom: Some("smb.nes".to_string()),
+                record: Some("run.fm2".to_string()),
+                scale: Some(2),
+                ..Default::default()
+            }))
+        );
+        assert_eq!(
+            parse(args("rominfo smb.nes")),
+            Ok(Command::RomInfo("smb.nes".to_string()))
+        );
+        assert_eq!(
+            parse(args("test a.nes b.nes")),
+            Ok(Command::Test {
+                roms: args("a.nes b.nes"),
+                frames: TEST_FRAMES,
+                bless: false,
+            })
+        );
+        assert_eq!(
+            parse(args("test --bless --frames 30 golden.toml")),
+            Ok(Command::Test {
+                roms: args("golden.toml"),
+                frames: 30,
+                bless: true,
+            })
+        );
+        assert_eq!(
+            parse(args("hexdump --space oam smb.nes")),
+            Ok(Command::Hexdump {
+                rom: "smb.nes".to_string(),
+                space: Space::Oam,
+                frames: HEXDUMP_FRAMES
+            })
+        );
+        assert_eq!(
+            parse(args(
+                "disasm --symbols game.nes.0.nl --symbols game.dbg game.nes"
+            )),
+            Ok(Command::Disasm {
+                rom: "game.nes".to_string(),
+                symbols: args("game.nes.0.nl game.dbg"),
+            })
+        );
+        assert_eq!(
+            parse(args("debug --symbols game.dbg game.nes")),
+            Ok(Command::Debug {
+                rom: "game.nes".to_string(),
+                symbols: args("game.dbg"),
+            })
+        );
+        assert_eq!(
+            parse(args("tracediff --start C000 nestest.nes nestest.log")),
+            Ok(Command::TraceDiff {
+                rom: "nestest.nes".to_string(),
+                log: "nestest.log".to_string(),
+                start: Some(0xc000),
+                context: TRACE_CONTEXT,
+            })
+        );
+        assert_eq!(
+            parse(args("crashdump smb-crash.json")),
+            Ok(Command::CrashDump("smb-crash.json".to_string()))
+        );
+        assert_eq!(
+            parse(args("bind power-pad")),
+            Ok(Command::Bind(Device::PowerPad))
+        );
+        assert_eq!(
+            parse(args("cheats smb.nes")),
+            Ok(Command::Cheats {
+                rom: "smb.nes".to_string(),
+                edit: None
+            })
+        );
+        assert_eq!(
+            parse(args("cheats smb.nes enable 3")),
+            Ok(Command::Cheats {
+                rom: "smb.nes".to_string(),
+                edit: Some(CheatEdit::Enable(3))
+            })
+        );
+        assert_eq!(parse(args("--audio-devices")), Ok(Command::AudioDevices));
+        let line = ["smb.nes", "--assert", "sp >= 20"].map(String::from);
+        assert_eq!(
+            parse(line),
+            Ok(Command::Run(RunOptions {
+                rom: Some("smb.nes".to_string()),
+                headless: true,
+                assertions: vec![Assertion::parse("sp >= 20").unwrap()],
+                ..Default::default()
+            }))
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            parse(args("--scale 5 smb.nes")),
+            Err(CliError::BadValue("--scale".to_string(), "5".to_string()))
+        );
+        assert_eq!(
+            parse(args("smb.nes --play")),
+            Err(CliError::MissingValue("--play".to_string()))
+        );
+        assert_eq!(
+            parse(args("play smb.nes")),
+            Err(CliError::MissingArgument("movie"))
+        );
+        assert_eq!(parse(args("test")), Err(CliError::MissingArgument("rom")));
+        assert_eq!(
+            parse(args("bind 5")),
+            Err(CliError::BadValue("bind".to_string(), "5".to_string()))
+        );
+        assert_eq!(
+            parse(args("cheats smb.nes remove")),
+            Err(CliError::BadValue(
+                "cheats".to_string(),
+                "remove".to_string()
+            ))
+        );
+        assert_eq!(
+            parse(args("run --turbo")),
+            Err(CliError::UnknownOption("--turbo".to_string()))
+        );
+        assert_eq!(
+            parse(args("smb.nes --assert sp")),
+            Err(CliError::BadValue("--assert".to_string(), "sp".to_string()))
+        );
+    }
+}
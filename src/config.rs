@@ -0,0 +1,695 @@
+use std::{
+    env,
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bus::PowerOnRam,
+    cartridge::Region,
+    controller::{Buttons, LatchMode},
+    cpu::CPU,
+    power_pad,
+};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Serialize(toml::ser::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "Unable to access config: {}", e),
+            ConfigError::Parse(e) => write!(f, "Unable to parse config: {}", e),
+            ConfigError::Serialize(e) => write!(f, "Unable to write config: {}", e),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io(e) => Some(e),
+            ConfigError::Parse(e) => Some(e),
+            ConfigError::Serialize(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+/**
+ * Keyboard layout for one controller. Keys are SDL key names ("Z", "Return",
+ * "Right Shift"...) so the file stays readable and editable by hand.
+ */
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct KeyBindings {
+    pub a: String,
+    pub b: String,
+    pub select: String,
+    pub start: String,
+    pub up: String,
+    pub down: String,
+    pub left: String,
+    pub right: String,
+}
+
+impl KeyBindings {
+    pub fn player1() -> KeyBindings {
+        KeyBindings {
+            a: "X".to_string(),
+            b: "Z".to_string(),
+            select: "Right Shift".to_string(),
+            start: "Return".to_string(),
+            up: "Up".to_string(),
+            down: "Down".to_string(),
+            left: "Left".to_string(),
+            right: "Right".to_string(),
+        }
+    }
+    pub fn player2() -> KeyBindings {
+        KeyBindings {
+            a: "G".to_string(),
+            b: "F".to_string(),
+            select: "R".to_string(),
+            start: "T".to_string(),
+            up: "W".to_string(),
+            down: "S".to_string(),
+            left: "A".to_string(),
+            right: "D".to_string(),
+        }
+    }
+    pub fn player3() -> KeyBindings {
+        KeyBindings {
+            a: "Keypad 3".to_string(),
+            b: "Keypad 2".to_string(),
+            select: "Keypad 7".to_string(),
+            start: "Keypad 9".to_string(),
+            up: "Keypad 8".to_string(),
+            down: "Keypad 5".to_string(),
+            left: "Keypad 4".to_string(),
+            right: "Keypad 6".to_string(),
+        }
+    }
+    pub fn player4() -> KeyBindings {
+        KeyBindings {
+            a: "O".to_string(),
+            b: "U".to_string(),
+            select: "7".to_string(),
+            start: "8".to_string(),
+            up: "I".to_string(),
+            down: "K".to_string(),
+            left: "J".to_string(),
+            right: "L".to_string(),
+        }
+    }
+    /**
+     * Every button with the key bound to it.
+     */
+    pub fn bindings(&self) -> [(Buttons, &str); 8] {
+        [
+            (Buttons::A, &self.a),
+            (Buttons::B, &self.b),
+            (Buttons::SELECT, &self.select),
+            (Buttons::START, &self.start),
+            (Buttons::UP, &self.up),
+            (Buttons::DOWN, &self.down),
+            (Buttons::LEFT, &self.left),
+            (Buttons::RIGHT, &self.right),
+        ]
+    }
+    /**
+     * Rebind a single button. `button` is one of the `Buttons`, anything
+     * else (none, several) is left alone.
+     */
+    pub fn set(&mut self, button: Buttons, key: &str) {
+        let slot = match button {
+            Buttons::A => &mut self.a,
+            Buttons::B => &mut self.b,
+            Buttons::SELECT => &mut self.select,
+            Buttons::START => &mut self.start,
+            Buttons::UP => &mut self.up,
+            Buttons::DOWN => &mut self.down,
+            Buttons::LEFT => &mut self.left,
+            Buttons::RIGHT => &mut self.right,
+            _ => return,
+        };
+        *slot = key.to_string()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct InputConfig {
+    #[serde(default = "KeyBindings::player1")]
+    pub player1: KeyBindings,
+    #[serde(default = "KeyBindings::player2")]
+    pub player2: KeyBindings,
+    // players 3 and 4 only do something with the Four Score
+    #[serde(default = "KeyBindings::player3")]
+    pub player3: KeyBindings,
+    #[serde(default = "KeyBindings::player4")]
+    pub player4: KeyBindings,
+    #[serde(default)]
+    pub four_score: bool,
+    // Zapper on port 2 instead of the second controller, aimed with the mouse
+    #[serde(default)]
+    pub zapper: bool,
+    // Power Pad on port 2 instead, one key per sensor
+    #[serde(default)]
+    pub power_pad: bool,
+    #[serde(default = "power_pad_keys")]
+    pub power_pad_keys: [String; power_pad::BUTTONS],
+    // "per-strobe" or "per-frame"
+    #[serde(default)]
+    pub latch: LatchMode,
+    // emulate DMC DMA corrupting controller reads
+    #[serde(default)]
+    pub dmc_conflict: bool,
+}
+
+/**
+ * The mat's 3x4 grid laid over the right hand side of the keyboard, sensors
+ * 1 to 12.
+ */
+fn power_pad_keys() -> [String; power_pad::BUTTONS] {
+    ["Y", "U", "I", "O", "H", "J", "K", "L", "N", "M", ",", "."].map(String::from)
+}
+
+impl InputConfig {
+    /**
+     * Bindings for every player, in controller port order.
+     */
+    pub fn players(&self) -> [&KeyBindings; 4] {
+        [&self.player1, &self.player2, &self.player3, &self.player4]
+    }
+    /**
+     * Plug in the devices this config asks for, window or not.
+     */
+    pub fn plug_into(&self, cpu: &mut CPU) {
+        cpu.plug_four_score(self.four_score);
+        cpu.plug_zapper(self.zapper);
+        cpu.plug_power_pad(self.power_pad);
+        cpu.set_latch_mode(self.latch);
+        cpu.set_dmc_conflict(self.dmc_conflict);
+    }
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        InputConfig {
+            player1: KeyBindings::player1(),
+            player2: KeyBindings::player2(),
+            player3: KeyBindings::player3(),
+            player4: KeyBindings::player4(),
+            four_score: false,
+            zapper: false,
+            power_pad: false,
+            power_pad_keys: power_pad_keys(),
+            latch: LatchMode::PerStrobe,
+            dmc_conflict: false,
+        }
+    }
+}
+
+/**
+ * Emulator shortcuts, SDL key names like the controller bindings.
+ */
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct Hotkeys {
+    // flip to the next FDS disk side
+    pub swap_disk: String,
+    // stop movie playback and keep recording from the current frame
+    pub take_over: String,
+    pub toggle_input_display: String,
+    pub scale_up: String,
+    pub scale_down: String,
+    pub toggle_aspect: String,
+    // held
+    pub fast_forward: String,
+    // cycles 100%, 50% and 25%
+    pub slow_motion: String,
+    pub pause: String,
+    // one frame at a time while paused
+    pub frame_advance: String,
+    // write the last few seconds out as a GIF
+    pub save_clip: String,
+    // none, scanlines, phosphor, crt, around again
+    pub cycle_filter: String,
+    // built in palettes, then the .pal files
+    pub cycle_palette: String,
+    // menu bar and windows, with the gui feature
+    pub toggle_gui: String,
+    // PPU viewers in windows of their own, SDL only
+    pub toggle_nametables: String,
+    pub toggle_patterns: String,
+    pub toggle_sprites: String,
+    // register accesses by scanline and dot
+    pub toggle_events: String,
+    // to and from the current slot, cycling picks another
+    pub save_state: String,
+    pub load_state: String,
+    pub next_slot: String,
+    pub previous_slot: String,
+}
+
+impl Default for Hotkeys {
+    fn default() -> Self {
+        Hotkeys {
+            swap_disk: "F3".to_string(),
+            take_over: "F4".to_string(),
+            toggle_input_display: "F8".to_string(),
+            scale_up: "=".to_string(),
+            scale_down: "-".to_string(),
+            toggle_aspect: "F9".to_string(),
+            fast_forward: "Tab".to_string(),
+            slow_motion: "Backspace".to_string(),
+            pause: "P".to_string(),
+            frame_advance: "\\".to_string(),
+            save_clip: "F12".to_string(),
+            cycle_filter: "F10".to_string(),
+            cycle_palette: "F7".to_string(),
+            toggle_gui: "F1".to_string(),
+            toggle_nametables: "F5".to_string(),
+            toggle_patterns: "F6".to_string(),
+            toggle_sprites: "F2".to_string(),
+            toggle_events: "F11".to_string(),
+            save_state: "Insert".to_string(),
+            load_state: "Home".to_string(),
+            next_slot: "PageUp".to_string(),
+            previous_slot: "PageDown".to_string(),
+        }
+    }
+}
+
+/**
+ * How the frontend keeps games at the right speed.
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Pacing {
+    // sleep to the console's frame rate, 60.0988Hz on NTSC
+    #[default]
+    Limiter,
+    // one frame per display refresh, right on a 60Hz monitor and tear free
+    Vsync,
+    // as fast as the machine goes
+    Unlimited,
+}
+
+/**
+ * Shape of a single NES pixel on screen.
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum Aspect {
+    // square pixels, the raw 256x240
+    #[default]
+    #[serde(rename = "1:1")]
+    Square,
+    // NTSC TVs drew pixels a bit wider than tall
+    #[serde(rename = "8:7")]
+    Ntsc,
+}
+
+impl Aspect {
+    pub fn parse(text: &str) -> Option<Aspect> {
+        match text {
+            "1:1" => Some(Aspect::Square),
+            "8:7" => Some(Aspect::Ntsc),
+            _ => None,
+        }
+    }
+    pub fn pixel_ratio(self) -> f64 {
+        match self {
+            Aspect::Square => 1.0,
+            Aspect::Ntsc => 8.0 / 7.0,
+        }
+    }
+    pub fn toggle(self) -> Aspect {
+        match self {
+            Aspect::Square => Aspect::Ntsc,
+            Aspect::Ntsc => Aspect::Square,
+        }
+    }
+}
+
+/**
+ * Post-processing on the picture, done in software after the overlays.
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Filter {
+    #[default]
+    None,
+    // dark gaps between the lines
+    Scanlines,
+    // colors bleed into their neighbours like a soft TV
+    Phosphor,
+    // both of the above on a curved screen
+    Crt,
+}
+
+impl Filter {
+    pub fn next(self) -> Filter {
+        match self {
+            Filter::None => Filter::Scanlines,
+            Filter::Scanlines => Filter::Phosphor,
+            Filter::Phosphor => Filter::Crt,
+            Filter::Crt => Filter::None,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Filter::None => "none",
+            Filter::Scanlines => "scanlines",
+            Filter::Phosphor => "phosphor",
+            Filter::Crt => "crt",
+        }
+    }
+}
+
+/**
+ * What ends up on screen.
+ */
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct DisplayConfig {
+    // held buttons for every controller drawn over the picture
+    pub input_display: bool,
+    pub pacing: Pacing,
+    // 1 to 4
+    pub scale: u32,
+    pub aspect: Aspect,
+    // grow the picture to fill the window instead of a fixed scale
+    pub fit: bool,
+    // with fit, only grow by whole steps so every NES pixel is the same size
+    pub integer_scale: bool,
+    // no title bar or frame around the window
+    pub borderless: bool,
+    // where the window was when it was last closed
+    pub window: Option<WindowGeometry>,
+    // messages for hotkeys and such drawn over the picture
+    pub osd: bool,
+    pub show_fps: bool,
+    // time per subsystem and emulated cycles a second, see `Profiler`
+    pub show_profile: bool,
+    pub filter: Filter,
+    // "default", "grayscale" or one of the palette_files to start with
+    pub palette: String,
+    // .pal files to cycle through after the built in palettes
+    pub palette_files: Vec<String>,
+}
+
+/**
+ * Window position and size, in screen pixels.
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        DisplayConfig {
+            input_display: false,
+            pacing: Pacing::Limiter,
+            scale: 3,
+            aspect: Aspect::Square,
+            fit: false,
+            integer_scale: false,
+            borderless: false,
+            window: None,
+            osd: true,
+            show_fps: false,
+            show_profile: false,
+            filter: Filter::None,
+            palette: "default".to_string(),
+            palette_files: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum FastForward {
+    #[default]
+    #[serde(rename = "uncapped")]
+    Uncapped,
+    #[serde(rename = "2x")]
+    Double,
+    #[serde(rename = "4x")]
+    Quadruple,
+}
+
+impl FastForward {
+    // None is as fast as the machine goes
+    pub fn multiplier(self) -> Option<f64> {
+        match self {
+            FastForward::Uncapped => None,
+            FastForward::Double => Some(2.0),
+            FastForward::Quadruple => Some(4.0),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(default)]
+pub struct SpeedConfig {
+    // speed while the fast forward key is held
+    pub fast_forward: FastForward,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct CaptureConfig {
+    // how far back a saved clip goes, 0 turns the recording off
+    pub clip_seconds: u32,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        CaptureConfig { clip_seconds: 10 }
+    }
+}
+
+/**
+ * How the machine itself behaves. Everything here is fixed for a run so the
+ * same input always gives the same frames, movies and netplay rely on it.
+ */
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(default)]
+pub struct EmulationConfig {
+    // "zeros", "ones" or "pattern"
+    pub power_on_ram: PowerOnRam,
+    // "ntsc", "pal" or "dendy" for every game, left out it's each game's own
+    pub region: Option<Region>,
+}
+
+/**
+ * Sound output. There's no APU yet so this is silence, but it's real silence
+ * on the real device, which is enough to sort out latency and dropouts.
+ */
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct AudioConfig {
+    pub enabled: bool,
+    // a name from --audio-devices, the system default when not set
+    pub device: Option<String>,
+    pub sample_rate: u32,
+    // samples per device buffer, lower is less latency but more dropouts
+    pub buffer_frames: u16,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            enabled: true,
+            device: None,
+            sample_rate: 44100,
+            buffer_frames: 1024,
+        }
+    }
+}
+
+/**
+ * Whether a game picks up where it was left last time.
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Resume {
+    #[default]
+    Off,
+    // asks in the terminal first
+    Ask,
+    Always,
+}
+
+/**
+ * Most recently opened roms first, updated every time one loads.
+ */
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct RecentConfig {
+    // how many to keep
+    pub max: usize,
+    pub roms: Vec<String>,
+    // save a state on the way out and start from it the next time
+    pub resume: Resume,
+}
+
+impl Default for RecentConfig {
+    fn default() -> Self {
+        RecentConfig {
+            max: 10,
+            roms: Vec::new(),
+            resume: Resume::Off,
+        }
+    }
+}
+
+impl RecentConfig {
+    /**
+     * Moves `path` to the top, absolute so it still opens from anywhere.
+     */
+    pub fn add(&mut self, path: &str) {
+        let path = fs::canonicalize(path)
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| path.to_string());
+        self.roms.retain(|rom| *rom != path);
+        self.roms.insert(0, path);
+        self.roms.truncate(self.max);
+    }
+}
+
+/**
+ * Everything persisted in config.toml. Missing sections/fields fall back to
+ * the defaults so older files keep working as options get added.
+ */
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(default)]
+pub struct Config {
+    pub input: InputConfig,
+    pub display: DisplayConfig,
+    pub speed: SpeedConfig,
+    pub capture: CaptureConfig,
+    pub audio: AudioConfig,
+    pub emulation: EmulationConfig,
+    pub hotkeys: Hotkeys,
+    pub recent: RecentConfig,
+}
+
+impl Config {
+    /**
+     * $XDG_CONFIG_HOME/nes/config.toml, falling back to ~/.config and then the
+     * working directory.
+     */
+    pub fn default_path() -> PathBuf {
+        let dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+        match dir {
+            Some(dir) => dir.join("nes").join("config.toml"),
+            None => PathBuf::from("config.toml"),
+        }
+    }
+    pub fn from_toml(text: &str) -> Result<Config, ConfigError> {
+        toml::from_str(text).map_err(ConfigError::Parse)
+    }
+    pub fn to_toml(&self) -> Result<String, ConfigError> {
+        toml::to_string_pretty(self).map_err(ConfigError::Serialize)
+    }
+    /**
+     * Read the config, writing out the defaults first if there isn't one yet.
+     */
+    pub fn load_or_create(path: &Path) -> Result<Config, ConfigError> {
+        if !path.exists() {
+            let config = Config::default();
+            config.save(path)?;
+            return Ok(config);
+        }
+        Config::from_toml(&fs::read_to_string(path)?)
+    }
+    /**
+     * Write the window geometry into the config at `path` and leave the rest
+     * of the file alone, the settings in memory may be this run's overrides.
+     */
+    pub fn save_window(path: &Path, window: WindowGeometry) -> Result<(), ConfigError> {
+        let mut config = Config::from_toml(&fs::read_to_string(path)?)?;
+        config.display.window = Some(window);
+        config.save(path)
+    }
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_toml()?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod config_test {
+    use crate::controller::{Buttons, LatchMode};
+
+    use super::{Config, KeyBindings, RecentConfig, WindowGeometry};
+
+    #[test]
+    fn test_round_trip() {
+        let mut config = Config::default();
+        config.input.player1.set(Buttons::START, "Space");
+        config.display.window = Some(WindowGeometry {
+            x: 40,
+            y: -10,
+            width: 800,
+            height: 600,
+        });
+        let text = config.to_toml().unwrap();
+        assert_eq!(Config::from_toml(&text).unwrap(), config);
+    }
+
+    #[test]
+    fn test_partial_file() {
+        let config = Config::from_toml(
+            r#"
+            [input.player1]
+            a = "K"
+            b = "J"
+            select = "Tab"
+            start = "Return"
+            up = "W"
+            down = "S"
+            left = "A"
+            right = "D"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.input.player1.a, "K");
+        assert_eq!(config.input.player2, KeyBindings::player2());
+        assert_eq!(config.hotkeys.swap_disk, "F3");
+        assert_eq!(config.input.latch, LatchMode::PerStrobe);
+    }
+
+    #[test]
+    fn test_recent() {
+        let mut recent = RecentConfig {
+            max: 3,
+            ..Default::default()
+        };
+        for rom in ["a.nes", "b.nes", "c.nes", "a.nes", "d.nes"] {
+            recent.add(rom)
+        }
+        assert_eq!(recent.roms, vec!["d.nes", "a.nes", "c.nes"]);
+    }
+}
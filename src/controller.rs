@@ -0,0 +1,386 @@
+use alloc::boxed::Box;
+
+use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
+
+use crate::{device::BusDevice, power_pad::PowerPad, zapper::Zapper};
+
+bitflags! {
+  // In the order they're shifted out of the controller
+  #[derive(Serialize, Deserialize)]
+  pub struct Buttons: u8 {
+    const A = 0b00000001;
+    const B = 0b00000010;
+    const SELECT = 0b00000100;
+    const START = 0b00001000;
+    const UP = 0b00010000;
+    const DOWN = 0b00100000;
+    const LEFT = 0b01000000;
+    const RIGHT = 0b10000000;
+  }
+}
+
+/**
+ * When the host's input is sampled into the controllers.
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum LatchMode {
+    // every strobe sees whatever is held right then
+    #[default]
+    PerStrobe,
+    // input is snapshotted once at the start of each frame, games that poll
+    // several times a frame see the same buttons every time
+    PerFrame,
+}
+
+/**
+ * Standard controller, a 4021 shift register behind $4016/$4017.
+ *
+ * While strobe (bit 0 of a $4016 write) is high the register keeps reloading
+ * the buttons so every read returns A. Once it goes low each read shifts out
+ * one button: A, B, Select, Start, Up, Down, Left, Right and 1s after that.
+ *
+ * The frontend sets the buttons every frame, the game sees them the next time
+ * it strobes.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Controller {
+    // what the host is holding
+    buttons: Buttons,
+    // `buttons` as of the start of the frame, for `LatchMode::PerFrame`
+    frame_buttons: Buttons,
+    mode: LatchMode,
+    strobe: bool,
+    shift: u8,
+}
+
+impl Default for Controller {
+    fn default() -> Self {
+        Controller::new()
+    }
+}
+
+impl Controller {
+    pub fn new() -> Controller {
+        Controller {
+            buttons: Buttons::empty(),
+            frame_buttons: Buttons::empty(),
+            mode: LatchMode::PerStrobe,
+            strobe: false,
+            shift: 0,
+        }
+    }
+    pub fn buttons(&self) -> Buttons {
+        self.buttons
+    }
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.buttons = buttons
+    }
+    pub fn set_button(&mut self, button: Buttons, pressed: bool) {
+        self.buttons.set(button, pressed)
+    }
+    pub fn set_latch_mode(&mut self, mode: LatchMode) {
+        self.mode = mode
+    }
+    /**
+     * Called at the start of every frame, takes the snapshot `PerFrame` reads.
+     */
+    pub fn start_frame(&mut self) {
+        self.frame_buttons = self.buttons
+    }
+    /**
+     * The buttons the game sees when it strobes.
+     */
+    pub fn sampled(&self) -> Buttons {
+        match self.mode {
+            LatchMode::PerStrobe => self.buttons,
+            LatchMode::PerFrame => self.frame_buttons,
+        }
+    }
+    pub fn write(&mut self, data: u8) {
+        let was_strobe = self.strobe;
+        self.strobe = data & 0x01 != 0;
+        // latch whatever is held at the moment strobe drops
+        if self.strobe || was_strobe {
+            self.shift = self.sampled().bits()
+        }
+    }
+    pub fn read(&mut self) -> u8 {
+        if self.strobe {
+            return self.sampled().contains(Buttons::A) as u8;
+        }
+        let bit = self.shift & 0x01;
+        // the serial input is tied high so 1s come out once the buttons are gone
+        self.shift = self.shift >> 1 | 0x80;
+        bit
+    }
+}
+
+// reported after the two controllers so games can tell a Four Score is there
+const FOUR_SCORE_SIGNATURES: [u32; 2] = [0x10, 0x20];
+
+/**
+ * Four Score multitap. Controllers 1 and 3 go out through $4016, 2 and 4
+ * through $4017, as one 24 bit report per port:
+ * 8 bits first controller, 8 bits second controller, 8 bits signature.
+ * 1s after that, like a plain controller.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FourScore {
+    strobe: bool,
+    shift: [u32; 2],
+}
+
+impl Default for FourScore {
+    fn default() -> Self {
+        FourScore::new()
+    }
+}
+
+impl FourScore {
+    pub fn new() -> FourScore {
+        FourScore {
+            strobe: false,
+            shift: [0; 2],
+        }
+    }
+    fn reload(&mut self, controllers: &[Controller; 4]) {
+        for port in 0..2 {
+            self.shift[port] = controllers[port].sampled().bits() as u32
+                | (controllers[port + 2].sampled().bits() as u32) << 8
+                | FOUR_SCORE_SIGNATURES[port] << 16
+        }
+    }
+    pub fn write(&mut self, data: u8, controllers: &[Controller; 4]) {
+        let was_strobe = self.strobe;
+        self.strobe = data & 0x01 != 0;
+        if self.strobe || was_strobe {
+            self.reload(controllers)
+        }
+    }
+    pub fn read(&mut self, port: usize, controllers: &[Controller; 4]) -> u8 {
+        if self.strobe {
+            self.reload(controllers);
+            return (self.shift[port] & 0x01) as u8;
+        }
+        let bit = (self.shift[port] & 0x01) as u8;
+        self.shift[port] = self.shift[port] >> 1 | 0x80_0000;
+        bit
+    }
+}
+
+/**
+ * The two controller ports and what's plugged into them. Reads of $4016 and
+ * $4017 clock port 1 and port 2, a write to $4016 strobes everything at once.
+ */
+#[derive(Clone)]
+pub struct Ports {
+    // 3 and 4 are only seen through the Four Score
+    pub(crate) controllers: [Controller; 4],
+    pub(crate) four_score: Option<FourScore>,
+    // plugged into port 2 in place of the second controller
+    pub(crate) zapper: Option<Zapper>,
+    pub(crate) power_pad: Option<PowerPad>,
+    // the game read a port this frame, it's a lag frame if not
+    polled: bool,
+}
+
+impl Default for Ports {
+    fn default() -> Self {
+        Ports::new()
+    }
+}
+
+impl Ports {
+    pub fn new() -> Ports {
+        Ports {
+            controllers: [
+                Controller::new(),
+                Controller::new(),
+                Controller::new(),
+                Controller::new(),
+            ],
+            four_score: None,
+            zapper: None,
+            power_pad: None,
+            polled: false,
+        }
+    }
+    /**
+     * Port 0 is read through $4016, port 1 through $4017. Ports 2 and 3 are
+     * the extra players on a Four Score.
+     */
+    pub fn controller(&mut self, port: usize) -> &mut Controller {
+        &mut self.controllers[port]
+    }
+    /**
+     * Swap controller 2 for a Zapper (or back).
+     */
+    pub fn plug_zapper(&mut self, plugged: bool) {
+        self.zapper = if plugged { Some(Zapper::new()) } else { None };
+        if plugged {
+            self.four_score = None;
+            self.power_pad = None
+        }
+    }
+    /**
+     * The Four Score takes both ports, so it unplugs whatever else was in
+     * port 2.
+     */
+    pub fn plug_four_score(&mut self, plugged: bool) {
+        self.four_score = if plugged {
+            Some(FourScore::new())
+        } else {
+            None
+        };
+        if plugged {
+            self.zapper = None;
+            self.power_pad = None
+        }
+    }
+    /**
+     * Swap controller 2 for a Power Pad (or back).
+     */
+    pub fn plug_power_pad(&mut self, plugged: bool) {
+        self.power_pad = if plugged { Some(PowerPad::new()) } else { None };
+        if plugged {
+            self.zapper = None;
+            self.four_score = None
+        }
+    }
+    pub fn zapper(&mut self) -> Option<&mut Zapper> {
+        self.zapper.as_mut()
+    }
+    pub fn power_pad(&mut self) -> Option<&mut PowerPad> {
+        self.power_pad.as_mut()
+    }
+    pub fn set_latch_mode(&mut self, mode: LatchMode) {
+        self.controllers
+            .iter_mut()
+            .for_each(|c| c.set_latch_mode(mode))
+    }
+    /**
+     * Snapshot the host input for `LatchMode::PerFrame`.
+     */
+    pub fn start_frame(&mut self) {
+        self.controllers.iter_mut().for_each(|c| c.start_frame());
+        self.polled = false
+    }
+    pub fn polled(&self) -> bool {
+        self.polled
+    }
+    /**
+     * The next bit out of `port` (0 or 1), clocking it.
+     */
+    pub fn read_port(&mut self, port: usize) -> u8 {
+        self.polled = true;
+        if let Some(four_score) = &mut self.four_score {
+            return four_score.read(port, &self.controllers);
+        }
+        if let (Some(power_pad), 1) = (&mut self.power_pad, port) {
+            return power_pad.read();
+        }
+        match (&self.zapper, port) {
+            (Some(zapper), 1) => zapper.read(),
+            _ => self.controllers[port].read(),
+        }
+    }
+}
+
+// $4016 and $4017, the Zapper has to `sense` the screen before a read
+impl BusDevice for Ports {
+    fn box_clone(&self) -> Box<dyn BusDevice> {
+        Box::new(self.clone())
+    }
+    fn read(&mut self, addr: u16) -> u8 {
+        // only the low bits are driven, the rest is open bus (usually 0x40)
+        self.read_port((addr & 1) as usize) | 0x40
+    }
+    fn write(&mut self, addr: u16, data: u8) {
+        // both ports share the strobe line, $4017 writes are the APU's
+        if addr != 0x4016 {
+            return;
+        }
+        self.controllers.iter_mut().for_each(|c| c.write(data));
+        if let Some(four_score) = &mut self.four_score {
+            four_score.write(data, &self.controllers)
+        }
+        if let Some(power_pad) = &mut self.power_pad {
+            power_pad.write(data)
+        }
+    }
+}
+
+#[cfg(test)]
+mod controller_test {
+    use super::{Buttons, Controller, FourScore, LatchMode};
+
+    #[test]
+    fn test_shift_out() {
+        let mut controller = Controller::new();
+        controller.set_buttons(Buttons::A | Buttons::START | Buttons::RIGHT);
+        controller.write(1);
+        controller.write(0);
+        let reads: Vec<u8> = (0..10).map(|_| controller.read()).collect();
+        assert_eq!(reads, vec![1, 0, 0, 1, 0, 0, 0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_strobe_high() {
+        let mut controller = Controller::new();
+        controller.set_button(Buttons::A, true);
+        controller.write(1);
+        assert_eq!(controller.read(), 1);
+        assert_eq!(controller.read(), 1);
+        // buttons changing while strobe is high are picked up
+        controller.set_button(Buttons::A, false);
+        assert_eq!(controller.read(), 0);
+    }
+
+    #[test]
+    fn test_per_frame_latch() {
+        let mut controller = Controller::new();
+        controller.set_latch_mode(LatchMode::PerFrame);
+        controller.set_button(Buttons::A, true);
+        controller.write(1);
+        // not until the next frame
+        assert_eq!(controller.read(), 0);
+        controller.start_frame();
+        assert_eq!(controller.read(), 1);
+        controller.set_button(Buttons::A, false);
+        assert_eq!(controller.read(), 1);
+    }
+
+    #[test]
+    fn test_four_score() {
+        let mut controllers = [
+            Controller::new(),
+            Controller::new(),
+            Controller::new(),
+            Controller::new(),
+        ];
+        controllers[0].set_buttons(Buttons::A);
+        controllers[2].set_buttons(Buttons::B);
+        controllers[3].set_buttons(Buttons::RIGHT);
+        let mut four_score = FourScore::new();
+        four_score.write(1, &controllers);
+        four_score.write(0, &controllers);
+
+        let port1: Vec<u8> = (0..25).map(|_| four_score.read(0, &controllers)).collect();
+        let mut expected = vec![0; 24];
+        expected[0] = 1;
+        expected[9] = 1;
+        // signature 0x10
+        expected[20] = 1;
+        expected.push(1);
+        assert_eq!(port1, expected);
+
+        let port2: Vec<u8> = (0..24).map(|_| four_score.read(1, &controllers)).collect();
+        let mut expected = vec![0; 24];
+        expected[15] = 1;
+        // signature 0x20
+        expected[21] = 1;
+        assert_eq!(port2, expected);
+    }
+}
@@ -0,0 +1,160 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::{
+    cdl::{CodeDataLog, CODE, DATA},
+    symbols::BANK_SIZE,
+};
+
+#[derive(Serialize, PartialEq, Debug)]
+pub struct BankCoverage {
+    pub bank: usize,
+    pub size: usize,
+    pub executed: usize,
+    // read by an instruction, and never run
+    pub data: usize,
+}
+
+/**
+ * Bytes of PRG ROM no instruction was run from, as offsets into PRG ROM.
+ */
+#[derive(Serialize, PartialEq, Debug)]
+pub struct Range {
+    pub start: usize,
+    pub end: usize,
+}
+
+/**
+ * How much of PRG ROM ran, from the CODE flags of a code/data log: for
+ * seeing what a test rom exercised, or what's left of a game to map.
+ */
+#[derive(Serialize, PartialEq, Debug)]
+pub struct Coverage {
+    pub prg_size: usize,
+    pub executed: usize,
+    pub banks: Vec<BankCoverage>,
+    pub uncovered: Vec<Range>,
+}
+
+fn percent(part: usize, whole: usize) -> f64 {
+    100.0 * part as f64 / whole.max(1) as f64
+}
+
+impl Coverage {
+    pub fn new(log: &CodeDataLog) -> Coverage {
+        let flags = log.prg_flags();
+        let banks = flags
+            .chunks(BANK_SIZE)
+            .enumerate()
+            .map(|(bank, flags)| BankCoverage {
+                bank,
+                size: flags.len(),
+                executed: flags.iter().filter(|&&f| f & CODE != 0).count(),
+                data: flags.iter().filter(|&&f| f & (CODE | DATA) == DATA).count(),
+            })
+            .collect();
+        let mut uncovered: Vec<Range> = Vec::new();
+        for (offset, _) in flags.iter().enumerate().filter(|(_, &f)| f & CODE == 0) {
+            match uncovered.last_mut() {
+                Some(range) if range.end + 1 == offset => range.end = offset,
+                _ => uncovered.push(Range {
+                    start: offset,
+                    end: offset,
+                }),
+            }
+        }
+        Coverage {
+            prg_size: flags.len(),
+            executed: flags.iter().filter(|&&f| f & CODE != 0).count(),
+            banks,
+            uncovered,
+        }
+    }
+
+    pub fn percent(&self) -> f64 {
+        percent(self.executed, self.prg_size)
+    }
+}
+
+impl fmt::Display for Coverage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Executed {} of {} bytes of PRG ROM ({:.1}%)",
+            self.executed,
+            self.prg_size,
+            self.percent()
+        )?;
+        writeln!(f, "Bank  Executed         Data only")?;
+        for bank in &self.banks {
+            writeln!(
+                f,
+                "{:>4}  {:>6} ({:>5.1}%)  {:>9}",
+                bank.bank,
+                bank.executed,
+                percent(bank.executed, bank.size),
+                bank.data
+            )?
+        }
+        write!(f, "Not executed:")?;
+        for range in &self.uncovered {
+            write!(
+                f,
+                "\n  ${:05X}-${:05X}  bank {:>2}  {} bytes",
+                range.start,
+                range.end,
+                range.start / BANK_SIZE,
+                range.end - range.start + 1
+            )?
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod coverage_test {
+    use crate::{debug::Access, symbols::BANK_SIZE};
+
+    use super::{CodeDataLog, Coverage, Range};
+
+    #[test]
+    fn test_coverage() {
+        let mut log = CodeDataLog::new(2 * BANK_SIZE, 0);
+        let offset = |addr: u16| Some((addr - 0x8000) as usize);
+        let read = |addr| Access {
+            addr,
+            value: 0,
+            write: false,
+        };
+        // LDA $8100 at $8000, NOP at $C000
+        log.log_instruction(
+            0x8000,
+            0xad,
+            &[read(0x8000), read(0x8001), read(0x8002), read(0x8100)],
+            offset,
+        );
+        log.log_instruction(0xc000, 0xea, &[read(0xc000)], offset);
+        let coverage = Coverage::new(&log);
+        assert_eq!(coverage.executed, 4);
+        assert_eq!(coverage.banks[0].executed, 3);
+        assert_eq!(coverage.banks[0].data, 1);
+        assert_eq!(coverage.banks[1].executed, 1);
+        assert_eq!(
+            coverage.uncovered,
+            [
+                Range {
+                    start: 3,
+                    end: BANK_SIZE - 1
+                },
+                Range {
+                    start: BANK_SIZE + 1,
+                    end: 2 * BANK_SIZE - 1
+                }
+            ]
+        );
+        let text = coverage.to_string();
+        assert!(text.starts_with("Executed 4 of 32768 bytes of PRG ROM (0.0%)"));
+        assert!(text.ends_with("$04001-$07FFF  bank  1  16383 bytes"));
+    }
+}
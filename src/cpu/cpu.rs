@@ -1,10 +1,31 @@
-use std::result;
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::{error::Error, fmt, ops::RangeInclusive};
+#[cfg(feature = "std")]
+use std::{io::Write, time::Instant};
 
 use crate::{
-    bus::Bus,
-    cartridge::Cartridge,
-    debug::CpuState,
+    bus::{Bus, PowerOnRam},
+    cartridge::{Cartridge, Region},
+    cdl::CodeDataLog,
+    cheats::Cheats,
+    controller::{Buttons, Controller, LatchMode},
+    debug::{Hooks, Registers},
+    device::BusDevice,
+    events::EventLog,
+    fds::{DiskImage, FdsError},
+    power_pad::PowerPad,
+    ppu::{Frame, Palette, PPU},
     utils::{as_lo_hi, get_bit, join_hi_low, msb},
+    zapper::Zapper,
+};
+// tracing, symbols, profiling and save states are for a machine with an OS
+#[cfg(feature = "std")]
+use crate::{
+    debug::{CpuState, TraceLine},
+    disasm,
+    profiler::{ProfileStats, Profiler, Subsystem},
+    savestate::{SaveState, StateError},
+    symbols::{self, Symbols},
 };
 
 // flag locations (1-indexed) for processor status register
@@ -28,6 +49,71 @@ const NON_MASKABLE_IH: u16 = 0xfffa;
 const POWER_RESET_IH: u16 = 0xfffc;
 const BRK_IH: u16 = 0xfffe;
 
+// instructions kept for crash dumps
+pub const HISTORY: usize = 64;
+
+// the unofficial opcodes that lock the CPU up
+const KIL: [u8; 12] = [
+    0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xb2, 0xd2, 0xf2,
+];
+
+/**
+ * What pulled the IRQ line. The line's low while any of them holds it, see
+ * `CPU::set_irq_pending`.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IrqSource {
+    FrameCounter,
+    Dmc,
+    Mapper,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Interrupt {
+    Nmi,
+    Irq(IrqSource),
+    Brk,
+}
+
+/**
+ * An opcode the CPU doesn't have (the unofficial ones other than the NOPs
+ * and KILs), and where it was. The CPU halts on it as it would on a KIL.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct UnsupportedOpcode {
+    pub opcode: u8,
+    pub pc: u16,
+}
+
+impl fmt::Display for UnsupportedOpcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Opcode ${:02X} at ${:04X} is not supported, the CPU has halted.",
+            self.opcode, self.pc
+        )
+    }
+}
+
+impl Error for UnsupportedOpcode {}
+
+/**
+ * The machine at one instant, to go back to. Holds a copy of the cartridge,
+ * so they're kept sparingly.
+ */
+pub struct Snapshot {
+    registers: Registers,
+    bus: Bus,
+    history: [Registers; HISTORY],
+    executed: usize,
+}
+
+impl Snapshot {
+    pub fn executed(&self) -> usize {
+        self.executed
+    }
+}
+
 pub struct CPU {
     pc: u16,
     sp: u8,
@@ -39,6 +125,31 @@ pub struct CPU {
     cycles: u64,
     stack_push_count: u8,
     stack_pop_count: u8,
+    // nestest.log style lines go here when it's set
+    #[cfg(feature = "std")]
+    trace: Option<Box<dyn Write>>,
+    // names for the addresses in the trace
+    #[cfg(feature = "std")]
+    symbols: Option<Symbols>,
+    cdl: Option<CodeDataLog>,
+    cheats: Cheats,
+    hooks: Option<Box<dyn Hooks>>,
+    #[cfg(feature = "std")]
+    profiler: Option<Profiler>,
+    // registers at the start of the last HISTORY instructions, a ring
+    history: [Registers; HISTORY],
+    executed: usize,
+    // where a KIL or an unsupported opcode stopped it and which it was, see
+    // `halted`
+    halted: Option<(u16, u8)>,
+    // the IRQ line as each source holds it, the cartridge's is the mapper's
+    irq_lines: [bool; 3],
+    // CLI, SEI and PLP change I after the interrupt poll, this is I as the
+    // poll saw it when the last instruction was one of them
+    polled_i: Option<bool>,
+    // the cycle the rest of the console's been run to, within an instruction
+    // or interrupt, see `nmi_by`
+    ticked: u64,
 }
 
 impl CPU {
@@ -54,81 +165,626 @@ impl CPU {
             cycles: 0,
             stack_push_count: 0,
             stack_pop_count: 0,
+            #[cfg(feature = "std")]
+            trace: None,
+            #[cfg(feature = "std")]
+            symbols: None,
+            cdl: None,
+            cheats: Cheats::new(),
+            hooks: None,
+            #[cfg(feature = "std")]
+            profiler: None,
+            history: [Registers::default(); HISTORY],
+            executed: 0,
+            halted: None,
+            irq_lines: [false; 3],
+            polled_i: None,
+            ticked: 0,
         }
     }
 
-    fn reset(&mut self) {
+    // the registers as they come up, starting at the reset vector
+    fn boot(&mut self) {
+        self.halted = None;
+        self.polled_i = None;
+        self.accum = 0;
         self.rx = 0;
         self.ry = 0;
-        self.st = 0;
+        // IRQs masked until the game's ready for them
+        self.st = 0x24;
+        self.sp = 0xfd;
 
         self.pc = join_hi_low(
             self.read_memory(POWER_RESET_IH),
             self.read_memory(POWER_RESET_IH + 1),
         )
     }
+    /**
+     * Turning the console off and on again, with the same cartridge in: RAM
+     * is filled as at power on (see `fill_ram`), the PPU and the mapper's
+     * registers start over and the cpu boots from the reset vector. The
+     * cartridge's own RAM keeps the save.
+     */
+    pub fn power_cycle(&mut self) {
+        self.bus.power_cycle();
+        self.boot()
+    }
+    /**
+     * The reset button: registers and memory are left alone, the cpu just
+     * "pushes" 3 bytes (without writing), masks interrupts and jumps to the
+     * reset vector. The PPU clears its control, mask and scroll registers
+     * (see `PPU::reset`), and there's no APU yet to silence.
+     */
+    pub fn soft_reset(&mut self) {
+        self.halted = None;
+        self.polled_i = None;
+        self.bus.reset();
+        self.sp = self.sp.wrapping_sub(3);
+        self.set_interrupt_disable();
+        self.pc = join_hi_low(
+            self.read_memory(POWER_RESET_IH),
+            self.read_memory(POWER_RESET_IH + 1),
+        )
+    }
     fn nmi(&mut self) {
+        self.bus.clear_generate_nmi();
+        self.interrupt(NON_MASKABLE_IH);
+    }
+    // NMI and IRQ alike, only the vector differs. Unlike BRK, the status
+    // goes on the stack with B clear. Returns the vector it went through, an
+    // NMI can take over an IRQ's (see `nmi_by`)
+    fn interrupt(&mut self, vector: u16) -> u16 {
+        let start = self.cycles;
+        self.ticked = start;
         let low_pc = (self.pc & 0xff) as u8;
         let hi_pc = ((self.pc >> 8) & 0xff) as u8;
 
-        // nmi takes 7 cycles but only the explicit `read_memory`
+        // an interrupt takes 7 cycles but only the explicit `read_memory`
         // will increment the cycle count (which there are two) so we add 5 here.
         self.cycles += 5;
+        self.polled_i = None;
 
         // push current pc and status flag to stack (in that orer)
         self.stack_push(hi_pc);
         self.stack_push(low_pc);
 
-        self.stack_push(self.st);
+        self.stack_push(self.st & !(1 << (BRK_CMD - 1)));
 
-        self.bus.clear_generate_nmi();
         self.set_interrupt_disable();
 
-        // load NMI interrupt vector
-        let low_addr = self.read_memory(NON_MASKABLE_IH);
-        let hi_addr = self.read_memory(NON_MASKABLE_IH + 1);
+        let vector = if vector == BRK_IH && self.nmi_by(start + 4) {
+            NON_MASKABLE_IH
+        } else {
+            vector
+        };
+        // load the interrupt vector
+        let low_addr = self.read_memory(vector);
+        let hi_addr = self.read_memory(vector + 1);
 
         let ih_addr = join_hi_low(low_addr, hi_addr);
-        self.pc = ih_addr
+        self.pc = ih_addr;
+        self.bus.tick(self.ticked..self.cycles);
+        vector
+    }
+    // BRK and IRQ fetch their vector late enough for an NMI that comes in by
+    // the 4th cycle of the sequence to take it over: the handler's the NMI's,
+    // with what's on the stack as BRK or the IRQ left it. That NMI's been
+    // taken then. The rest of the console's run up to `cycle` to see
+    fn nmi_by(&mut self, cycle: u64) -> bool {
+        self.bus.tick(self.ticked..cycle);
+        self.ticked = cycle;
+        if !self.bus.poll_generate_nmi() {
+            return false;
+        }
+        self.bus.clear_generate_nmi();
+        true
     }
     fn incr_stack_pop_count(&mut self) {
         self.stack_pop_count += if self.stack_pop_count == 0 { 2 } else { 1 }
     }
-    pub fn load_cartridge(&mut self, cartridge: Cartridge) {
-        self.bus.load_rom(cartridge.prgrom);
-        self.reset()
+    pub fn load_cartridge(&mut self, cartridge: Cartridge) -> crate::Result<()> {
+        self.bus.load_cartridge(cartridge)?;
+        self.boot();
+        Ok(())
+    }
+    /**
+     * Controller plugged into `port` (0 or 1, 2 and 3 with a Four Score), the
+     * frontend fills in the buttons every frame.
+     */
+    pub fn controller(&mut self, port: usize) -> &mut Controller {
+        self.bus.controller(port)
+    }
+    /**
+     * Hold down exactly `buttons` on controller `player` (0 is the first
+     * port) from now on, for driving games without a frontend.
+     */
+    pub fn set_controller_state(&mut self, player: usize, buttons: Buttons) {
+        self.controller(player).set_buttons(buttons)
+    }
+    pub fn set_latch_mode(&mut self, mode: LatchMode) {
+        self.bus.set_latch_mode(mode)
+    }
+    pub fn set_dmc_conflict(&mut self, enabled: bool) {
+        self.bus.set_dmc_conflict(enabled)
+    }
+    /**
+     * RAM as it comes up at power on, before the game's run anything.
+     */
+    pub fn fill_ram(&mut self, fill: PowerOnRam) {
+        self.bus.fill_ram(fill)
+    }
+    pub fn plug_zapper(&mut self, plugged: bool) {
+        self.bus.plug_zapper(plugged)
+    }
+    pub fn plug_four_score(&mut self, plugged: bool) {
+        self.bus.plug_four_score(plugged)
+    }
+    pub fn zapper(&mut self) -> Option<&mut Zapper> {
+        self.bus.zapper()
+    }
+    pub fn plug_power_pad(&mut self, plugged: bool) {
+        self.bus.plug_power_pad(plugged)
+    }
+    pub fn power_pad(&mut self) -> Option<&mut PowerPad> {
+        self.bus.power_pad()
+    }
+    /**
+     * Extra hardware on the bus at `addrs`, see `Bus::attach`.
+     */
+    pub fn attach(&mut self, addrs: RangeInclusive<u16>, device: Box<dyn BusDevice>) {
+        self.bus.attach(addrs, device)
+    }
+    pub fn detach(&mut self, addr: u16) -> Option<Box<dyn BusDevice>> {
+        self.bus.detach(addr)
+    }
+    /**
+     * Boot a Famicom Disk System image, `bios` is the user's disksys.rom.
+     * One that isn't 8KB leaves the console as it was.
+     */
+    pub fn load_disk(&mut self, bios: Vec<u8>, disk: DiskImage) -> Result<(), FdsError> {
+        self.bus.load_disk(bios, disk)?;
+        self.boot();
+        Ok(())
+    }
+    pub fn disk_sides(&self) -> usize {
+        self.bus.disk_sides()
+    }
+    /**
+     * Flip/swap the disk, `None` ejects it.
+     */
+    pub fn insert_disk(&mut self, side: Option<usize>) {
+        self.bus.insert_disk(side)
+    }
+    pub fn save_data(&self) -> Option<Vec<u8>> {
+        self.bus.save_data()
+    }
+    /**
+     * Write a line per instruction to `out` from here on, in the format of
+     * nestest.log. None stops, and drops (flushing) the old one.
+     */
+    #[cfg(feature = "std")]
+    pub fn set_trace(&mut self, out: Option<Box<dyn Write>>) {
+        self.trace = out
+    }
+    #[cfg(feature = "std")]
+    pub fn set_symbols(&mut self, symbols: Option<Symbols>) {
+        self.symbols = symbols
+    }
+    pub fn set_cheats(&mut self, cheats: Cheats) {
+        self.bus.set_cheats(cheats.codes());
+        self.cheats = cheats
+    }
+    pub fn cheats(&self) -> &Cheats {
+        &self.cheats
+    }
+    /**
+     * Change the cheats, they take effect straight away. Saving them is up to
+     * the caller.
+     */
+    pub fn edit_cheats<T>(&mut self, edit: impl FnOnce(&mut Cheats) -> T) -> T {
+        let result = edit(&mut self.cheats);
+        self.bus.set_cheats(self.cheats.codes());
+        result
+    }
+    /**
+     * Where the label `name` is, for breakpoints.
+     */
+    #[cfg(feature = "std")]
+    pub fn symbol(&self, name: &str) -> Option<u16> {
+        self.symbols.as_ref()?.addr(name)
+    }
+    /**
+     * The name for `addr` as things are mapped right now, if there's one.
+     */
+    #[cfg(feature = "std")]
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        let bank = self
+            .prg_rom_offset(addr)
+            .map(|offset| offset / symbols::BANK_SIZE);
+        self.symbols.as_ref()?.label(addr, bank)
+    }
+    /**
+     * Log which PRG bytes run as code and which are read as data from here
+     * on. None stops, handing back the log so far.
+     */
+    pub fn set_cdl(&mut self, cdl: Option<CodeDataLog>) -> Option<CodeDataLog> {
+        let old = core::mem::replace(&mut self.cdl, cdl);
+        self.update_access_log();
+        old
+    }
+    /**
+     * Sizes of the cartridge's PRG and CHR ROM, 0 for RAM.
+     */
+    pub fn rom_sizes(&self) -> (usize, usize) {
+        self.bus.rom_sizes()
+    }
+    /**
+     * Where in PRG ROM `addr` reads from right now, see `Mapper::prg_rom_offset`.
+     */
+    pub fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.bus.prg_rom_offset(addr)
     }
 
-    // TODO assuming that we run one instruction
-    // and then yield to the ppu
-    // we can certainly do better than this.
-    fn run(&mut self) {
-        let mut start_cycles: u64;
-        loop {
-            if self.bus.poll_generate_nmi() {
-                self.nmi()
-            }
+    /**
+     * Run one instruction (servicing a pending interrupt first) and let the
+     * rest of the system catch up. Returns the cpu cycles it took, the
+     * interrupt's included.
+     */
+    pub fn step(&mut self) -> u64 {
+        let cycles = self.execute();
+        self.bus.catch_up();
+        cycles
+    }
+    // `step`, leaving the PPU behind until something needs it caught up, see
+    // `Bus::catch_up`
+    fn execute(&mut self) -> u64 {
+        // hooks and traces look at the PPU between any two instructions
+        #[cfg(feature = "std")]
+        let watched = self.hooks.is_some() || self.trace.is_some();
+        #[cfg(not(feature = "std"))]
+        let watched = self.hooks.is_some();
+        if watched {
+            self.bus.catch_up()
+        }
+        // stuck, with the rest of the console carrying on
+        if self.halted.is_some() {
+            self.cycles += 1;
+            self.bus.tick(self.cycles - 1..self.cycles);
+            return 1;
+        }
+        let began = self.cycles;
+        self.enter_interrupt();
 
-            start_cycles = self.cycles;
-            self.stack_pop_count = 0;
-            self.stack_push_count = 0;
+        self.call_hooks(|hooks, cpu| hooks.instruction(cpu));
 
-            let opcode = self.bus.read_memory(self.pc);
-            self.cycles += 1;
+        let start_cycles = self.cycles;
+        self.ticked = start_cycles;
+        self.stack_pop_count = 0;
+        self.stack_push_count = 0;
 
-            self.exec_opcode(opcode);
+        self.history[self.executed % HISTORY] = self.registers();
+        self.executed += 1;
 
-            // Make sure to check cycle diff count _before_ applying
-            // any cycles due to accessing the stack
-            if self.cycles - start_cycles == 1 {
-                self.cycles += 1
+        let pc = self.pc;
+        let opcode = self.bus.read_memory(self.pc);
+        self.cycles += 1;
+        let masked = self.get_st(INTERRUPT_DISABLE - 1) == 1;
+
+        #[cfg(feature = "std")]
+        if self.trace.is_some() {
+            let line = self.trace_exec(opcode);
+            let written = self.trace.as_mut().map(|out| writeln!(out, "{}", line));
+            if let Some(Err(e)) = written {
+                eprintln!("Unable to write the trace, stopping it: {}", e);
+                self.trace = None
             }
-            // TODO don't love this...
-            self.cycles += (self.stack_pop_count + self.stack_push_count) as u64;
+        } else {
+            self.exec_opcode(opcode);
+        }
+        #[cfg(not(feature = "std"))]
+        self.exec_opcode(opcode);
+        // CLI, SEI and PLP
+        self.polled_i = matches!(opcode, 0x58 | 0x78 | 0x28).then_some(masked);
+
+        // Make sure to check cycle diff count _before_ applying
+        // any cycles due to accessing the stack
+        if self.cycles - start_cycles == 1 {
+            self.cycles += 1
+        }
+        // TODO don't love this...
+        self.cycles += (self.stack_pop_count + self.stack_push_count) as u64;
 
-            let cycles_run = self.cycles - start_cycles;
-            self.bus.tick(cycles_run)
+        let cycles_run = self.cycles - began;
+        #[cfg(feature = "std")]
+        if let Some(profiler) = &mut self.profiler {
+            let start = Instant::now();
+            self.bus.tick(self.ticked..self.cycles);
+            profiler.add(Subsystem::Ppu, start.elapsed());
+        } else {
+            self.bus.tick(self.ticked..self.cycles);
+        }
+        #[cfg(not(feature = "std"))]
+        self.bus.tick(self.ticked..self.cycles);
+        let accesses = self.bus.take_accesses();
+        if let Some(cdl) = &mut self.cdl {
+            cdl.log_instruction(pc, opcode, &accesses, |addr| self.bus.prg_rom_offset(addr))
+        }
+        if !accesses.is_empty() && self.hooks.is_some() {
+            self.call_hooks(|hooks, cpu| hooks.accesses(cpu, &accesses));
+        }
+        cycles_run
+    }
+    /**
+     * One instruction, finishing the frame when that completes it the way
+     * `run_frame` would. For debuggers, returns true at the end of a frame.
+     */
+    pub fn advance(&mut self) -> bool {
+        self.step();
+        if !self.bus.poll_frame_complete() {
+            return false;
         }
+        self.bus.clear_frame_complete();
+        self.call_hooks(|hooks, cpu| hooks.frame(cpu));
+        self.bus.start_frame();
+        true
+    }
+    /**
+     * Start a pending NMI, or IRQ when interrupts aren't masked, as `step`
+     * does before the instruction, returning which interrupt it was. PC is
+     * then at the top of the handler, where a debugger wants to stop.
+     */
+    pub fn enter_interrupt(&mut self) -> Option<Interrupt> {
+        if self.bus.poll_generate_nmi() {
+            self.nmi();
+            return Some(Interrupt::Nmi);
+        }
+        let masked = self
+            .polled_i
+            .unwrap_or(self.get_st(INTERRUPT_DISABLE - 1) == 1);
+        let source = self.irq_pending().filter(|_| !masked)?;
+        if self.interrupt(BRK_IH) == NON_MASKABLE_IH {
+            return Some(Interrupt::Nmi);
+        }
+        Some(Interrupt::Irq(source))
+    }
+    /**
+     * Hold the IRQ line low for `source` or let it go. It stays held until
+     * it's let go, taking an IRQ doesn't acknowledge it, the handler has to.
+     * The cartridge's line is the mapper's own, see `Mapper::irq_pending`.
+     */
+    pub fn set_irq_pending(&mut self, source: IrqSource, pending: bool) {
+        self.irq_lines[source as usize] = pending
+    }
+    /**
+     * Who's holding the IRQ line, masked or not.
+     */
+    pub fn irq_pending(&self) -> Option<IrqSource> {
+        if self.bus.poll_irq() {
+            return Some(IrqSource::Mapper);
+        }
+        [IrqSource::FrameCounter, IrqSource::Dmc, IrqSource::Mapper]
+            .into_iter()
+            .find(|&source| self.irq_lines[source as usize])
+    }
+    /**
+     * Where the CPU ran into a KIL (or JAM) opcode, which stops it for good:
+     * no more instructions or interrupts until a reset or a power cycle. The
+     * PPU and everything else carry on, frames are still made. An opcode it
+     * doesn't have stops it the same way, see `unsupported_opcode`.
+     */
+    pub fn halted(&self) -> Option<u16> {
+        self.halted.map(|(pc, _)| pc)
+    }
+    /**
+     * When it's halted on an opcode it doesn't have rather than a KIL, which
+     * one. That's the emulator's fault, not the game's.
+     */
+    pub fn unsupported_opcode(&self) -> Option<UnsupportedOpcode> {
+        self.halted
+            .filter(|(_, opcode)| !KIL.contains(opcode))
+            .map(|(pc, opcode)| UnsupportedOpcode { opcode, pc })
+    }
+    /**
+     * Instructions run since power on.
+     */
+    pub fn executed(&self) -> usize {
+        self.executed
+    }
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            registers: self.registers(),
+            bus: self.bus.deep_clone(),
+            history: self.history,
+            executed: self.executed,
+        }
+    }
+    /**
+     * Back to `snapshot`, which can be restored again later. Traces, hooks
+     * and the rest of the tooling carry on as they are.
+     */
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.set_registers(snapshot.registers);
+        self.bus = snapshot.bus.deep_clone();
+        self.history = snapshot.history;
+        self.executed = snapshot.executed;
+        // the ones switched on now, not when the snapshot was taken
+        self.bus.set_cheats(self.cheats.codes())
+    }
+    #[cfg(feature = "std")]
+    pub fn save_state(&self) -> SaveState {
+        SaveState {
+            registers: self.registers(),
+            executed: self.executed,
+            bus: self.bus.save_state(),
+        }
+    }
+    /**
+     * Carry on from `state`, which has to be from the same game. Like
+     * `restore` the tooling carries on as it is, and the crash dump history
+     * starts over.
+     */
+    #[cfg(feature = "std")]
+    pub fn load_state(&mut self, state: &SaveState) -> Result<(), StateError> {
+        self.bus.load_state(&state.bus)?;
+        self.set_registers(state.registers);
+        self.history = [Registers::default(); HISTORY];
+        self.executed = state.executed;
+        Ok(())
+    }
+    // a CPU that's halted there halts again on its first step
+    fn set_registers(&mut self, registers: Registers) {
+        self.halted = None;
+        self.polled_i = None;
+        self.pc = registers.pc;
+        self.accum = registers.a;
+        self.rx = registers.x;
+        self.ry = registers.y;
+        self.st = registers.p;
+        self.sp = registers.sp;
+        self.cycles = registers.cycles;
+    }
+    /**
+     * Run `instructions` again after a `restore`, without tracing, logging
+     * or calling the hooks a second time.
+     */
+    pub fn replay(&mut self, instructions: usize) {
+        #[cfg(feature = "std")]
+        let trace = self.trace.take();
+        let hooks = self.hooks.take();
+        let cdl = self.set_cdl(None);
+        for _ in 0..instructions {
+            self.advance();
+        }
+        #[cfg(feature = "std")]
+        {
+            self.trace = trace;
+        }
+        self.hooks = hooks;
+        self.set_cdl(cdl);
+    }
+    /**
+     * Run until the ppu has finished drawing a frame.
+     */
+    pub fn run_frame(&mut self) {
+        #[cfg(feature = "std")]
+        let (start, start_cycles) = (Instant::now(), self.cycles);
+        #[cfg(feature = "std")]
+        let ppu_before = self.profiler.as_ref().map(|p| p.total(Subsystem::Ppu));
+        self.bus.start_frame();
+        while !self.bus.poll_frame_complete() {
+            self.execute();
+        }
+        self.bus.catch_up();
+        self.bus.clear_frame_complete();
+        self.call_hooks(|hooks, cpu| hooks.frame(cpu));
+        // the CPU gets whatever the PPU didn't
+        #[cfg(feature = "std")]
+        if let (Some(profiler), Some(ppu_before)) = (&mut self.profiler, ppu_before) {
+            let ppu = profiler.total(Subsystem::Ppu) - ppu_before;
+            profiler.add(Subsystem::Cpu, start.elapsed().saturating_sub(ppu));
+            profiler.end_frame(self.cycles - start_cycles);
+        }
+    }
+    /**
+     * Start or stop timing the subsystems, see `Profiler`.
+     */
+    #[cfg(feature = "std")]
+    pub fn set_profiling(&mut self, enabled: bool) {
+        if enabled != self.profiler.is_some() {
+            self.profiler = enabled.then(Profiler::new)
+        }
+    }
+    /**
+     * None unless profiling. The frontend adds its own time through this.
+     */
+    #[cfg(feature = "std")]
+    pub fn profiler(&mut self) -> Option<&mut Profiler> {
+        self.profiler.as_mut()
+    }
+    /**
+     * Keep the register accesses of the last frame by scanline and dot, for
+     * the event viewer.
+     */
+    pub fn set_event_logging(&mut self, enabled: bool) {
+        self.bus.log_events(enabled)
+    }
+    pub fn events(&self) -> Option<&EventLog> {
+        self.bus.events()
+    }
+    #[cfg(feature = "std")]
+    pub fn profile_stats(&self) -> Option<ProfileStats> {
+        self.profiler.as_ref().map(Profiler::stats)
+    }
+    pub fn frame(&self) -> &Frame {
+        self.bus.frame()
+    }
+    /**
+     * Whether the last frame went by without the game reading the
+     * controllers, so the input for it made no difference.
+     */
+    pub fn lag_frame(&self) -> bool {
+        !self.bus.polled()
+    }
+    pub fn ppu(&self) -> &PPU {
+        self.bus.ppu()
+    }
+    pub fn ppu_mut(&mut self) -> &mut PPU {
+        self.bus.ppu_mut()
+    }
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.bus.peek(addr)
+    }
+    /**
+     * `peek` over each of `addrs`.
+     */
+    pub fn read_range(&self, addrs: RangeInclusive<u16>) -> Vec<u8> {
+        addrs.map(|addr| self.bus.peek(addr)).collect()
+    }
+    /**
+     * Write without taking any cycles, see `Bus::poke`.
+     */
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        self.bus.poke(addr, value)
+    }
+    pub fn registers(&self) -> Registers {
+        Registers {
+            pc: self.pc,
+            a: self.accum,
+            x: self.rx,
+            y: self.ry,
+            p: self.st,
+            sp: self.sp,
+            cycles: self.cycles,
+        }
+    }
+    /**
+     * Registers as the last instructions started, oldest first. The last
+     * one is still running if it panicked.
+     */
+    pub fn history(&self) -> Vec<Registers> {
+        let kept = self.executed.min(HISTORY);
+        (self.executed - kept..self.executed)
+            .map(|n| self.history[n % HISTORY])
+            .collect()
+    }
+    /**
+     * Carry on from `pc`, like nestest's automation mode at $C000.
+     */
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc
+    }
+    pub fn set_palette(&mut self, palette: &Palette) {
+        self.bus.set_palette(palette)
+    }
+    /**
+     * Run as an NTSC, PAL or Dendy console from here on, in place of what
+     * the cartridge's header said.
+     */
+    pub fn set_region(&mut self, region: Region) {
+        self.bus.set_region(region)
+    }
+    pub fn region(&self) -> Region {
+        self.bus.region()
     }
     fn read_memory(&mut self, addr: u16) -> u8 {
         self.cycles += 1;
@@ -138,6 +794,7 @@ impl CPU {
         self.cycles += 1;
         self.bus.write_memory(addr, data)
     }
+    #[cfg(feature = "std")]
     fn debug_exec(&mut self, opcode: u8) -> CpuState {
         let mut state = CpuState::default();
         state.opcode = opcode;
@@ -153,6 +810,63 @@ impl CPU {
 
         state
     }
+    /**
+     * Hand control to `hooks` around every instruction and frame from here
+     * on, None takes them out.
+     */
+    pub fn set_hooks(&mut self, hooks: Option<Box<dyn Hooks>>) {
+        self.hooks = hooks;
+        self.update_access_log()
+    }
+    // the bus only keeps a list of accesses for someone who wants it
+    fn update_access_log(&mut self) {
+        let hooks = self
+            .hooks
+            .as_ref()
+            .is_some_and(|hooks| hooks.wants_accesses());
+        self.bus.log_accesses(hooks || self.cdl.is_some())
+    }
+    pub fn overlay(&self) -> Vec<(usize, usize, String)> {
+        self.hooks
+            .as_ref()
+            .map_or_else(Vec::new, |hooks| hooks.overlay())
+    }
+    // taken out while they run so they can have the whole cpu
+    fn call_hooks(&mut self, call: impl FnOnce(&mut dyn Hooks, &mut CPU)) {
+        if let Some(mut hooks) = self.hooks.take() {
+            call(hooks.as_mut(), self);
+            // unless they swapped themselves out
+            if self.hooks.is_none() {
+                self.hooks = Some(hooks)
+            }
+        }
+    }
+    #[cfg(feature = "std")]
+    fn trace_exec(&mut self, opcode: u8) -> TraceLine {
+        let pc = self.pc;
+        let fetched = [
+            opcode,
+            self.peek(pc.wrapping_add(1)),
+            self.peek(pc.wrapping_add(2)),
+        ];
+        let bytes = disasm::disassemble_one(&fetched, pc).bytes;
+        let text = disasm::trace_text(
+            &bytes,
+            pc,
+            self.rx,
+            self.ry,
+            |addr| self.peek(addr),
+            &|addr| self.label(addr).map(String::from),
+        );
+        let ppu = self.bus.ppu().registers();
+        TraceLine {
+            state: self.debug_exec(opcode),
+            bytes,
+            disasm: text,
+            scanline: ppu.scanline,
+            dot: ppu.dot,
+        }
+    }
     fn exec_opcode(&mut self, opcode: u8) {
         match opcode {
             // ADC - Add with Carry
@@ -173,11 +887,13 @@ impl CPU {
                 self.adc(absolute.0)
             }
             0x7d => {
-                let absolute_x = self.absolute_x(Op::Read);
-                self.adc(absolute_x.0)
+                let (v, _, crossed) = self.absolute_x();
+                self.cycles += crossed as u64;
+                self.adc(v)
             }
             0x79 => {
-                let (v, _) = self.absolute_y(Op::Read);
+                let (v, _, crossed) = self.absolute_y();
+                self.cycles += crossed as u64;
                 self.adc(v)
             }
             0x61 => {
@@ -185,7 +901,8 @@ impl CPU {
                 self.adc(v)
             }
             0x71 => {
-                let (v, _) = self.indirect_y(Op::Read);
+                let (v, _, crossed) = self.indirect_y();
+                self.cycles += crossed as u64;
                 self.adc(v)
             }
             // ********
@@ -207,11 +924,13 @@ impl CPU {
                 self.and(absolute.0)
             }
             0x3d => {
-                let absolute_x = self.absolute_x(Op::Read);
-                self.and(absolute_x.0)
+                let (v, _, crossed) = self.absolute_x();
+                self.cycles += crossed as u64;
+                self.and(v)
             }
             0x39 => {
-                let (v, _) = self.absolute_y(Op::Read);
+                let (v, _, crossed) = self.absolute_y();
+                self.cycles += crossed as u64;
                 self.and(v)
             }
             0x21 => {
@@ -219,7 +938,8 @@ impl CPU {
                 self.and(v)
             }
             0x31 => {
-                let (v, _) = self.indirect_y(Op::Read);
+                let (v, _, crossed) = self.indirect_y();
+                self.cycles += crossed as u64;
                 self.and(v)
             }
             // ********
@@ -247,40 +967,20 @@ impl CPU {
                 self.write_memory(addr, result)
             }
             0x1E => {
-                let (v, addr) = self.absolute_x(Op::RMW);
+                let (v, addr, _) = self.absolute_x();
+                self.cycles += 1;
                 let result = self.asl(v);
                 self.cycles += 1;
                 self.write_memory(addr, result)
             }
             // BCC - Branch if Carry Clear
-            0x90 => {
-                let arg = self.read_memory(self.pc + 1);
-                if self.get_st(CARRY_FLAG - 1) == 0 {
-                    self.cycles += 1;
-                    self.pc += arg as u16;
-                }
-                self.pc += 2;
-            }
+            0x90 => self.branch(self.get_st(CARRY_FLAG - 1) == 0),
             // ********
             // BCS - Branch if Carry Set
-            0xb0 => {
-                let arg = self.read_memory(self.pc + 1);
-                if self.get_st(CARRY_FLAG - 1) == 1 {
-                    self.cycles += 1;
-                    self.pc += arg as u16;
-                }
-                self.pc += 2;
-            }
+            0xb0 => self.branch(self.get_st(CARRY_FLAG - 1) == 1),
             // ********
             // BEQ - Branch if Equal
-            0xf0 => {
-                let arg = self.read_memory(self.pc + 1);
-                if self.get_st(ZERO_FLAG - 1) == 1 {
-                    self.cycles += 1;
-                    self.pc += arg as u16;
-                }
-                self.pc += 2;
-            }
+            0xf0 => self.branch(self.get_st(ZERO_FLAG - 1) == 1),
             // ********
             // BIT - Bit Test
             0x24 => {
@@ -293,60 +993,24 @@ impl CPU {
             }
             // ********
             // BMI - Branch if Minus
-            0x30 => {
-                let arg = self.read_memory(self.pc + 1);
-                if self.get_st(NEGATIVE_FLAG - 1) == 1 {
-                    self.cycles += 1;
-                    self.pc += arg as u16;
-                }
-                self.pc += 2
-            }
+            0x30 => self.branch(self.get_st(NEGATIVE_FLAG - 1) == 1),
             // ********
             // BNE - Branch if Not Equal
-            0xd0 => {
-                let arg = self.read_memory(self.pc + 1);
-                if self.get_st(ZERO_FLAG - 1) == 0 {
-                    self.cycles += 1;
-                    self.pc += arg as u16;
-                }
-                self.pc += 2
-            }
+            0xd0 => self.branch(self.get_st(ZERO_FLAG - 1) == 0),
             // ********
             // BPL - Branch if Positive
-            0x10 => {
-                let arg = self.read_memory(self.pc + 1);
-                if self.get_st(NEGATIVE_FLAG - 1) == 0 {
-                    self.cycles += 1;
-                    self.pc += arg as u16;
-                }
-                self.pc += 2
-            }
+            0x10 => self.branch(self.get_st(NEGATIVE_FLAG - 1) == 0),
             // ********
             // BRK - Force Interrupt
             0x00 => {
                 self.brk();
                 self.cycles += 1;
-                self.pc += 1
             }
             // BVC - Branch if Overflow Clear
-            0x50 => {
-                let arg = self.read_memory(self.pc + 1);
-                if self.get_st(OVERFLOW_FLAG - 1) == 0 {
-                    self.cycles += 1;
-                    self.pc += arg as u16;
-                }
-                self.pc += 2
-            }
+            0x50 => self.branch(self.get_st(OVERFLOW_FLAG - 1) == 0),
             // ********
             // BVS - Branch if Overflow Set
-            0x70 => {
-                let arg = self.read_memory(self.pc + 1);
-                if self.get_st(OVERFLOW_FLAG - 1) == 1 {
-                    self.cycles += 1;
-                    self.pc += arg as u16;
-                }
-                self.pc += 2
-            }
+            0x70 => self.branch(self.get_st(OVERFLOW_FLAG - 1) == 1),
             // ********
             // CLC - Clear Carry Flag
             0x18 => {
@@ -390,11 +1054,13 @@ impl CPU {
                 self.cmp(absolute.0)
             }
             0xdd => {
-                let absolute_x = self.absolute_x(Op::Read);
-                self.cmp(absolute_x.0)
+                let (v, _, crossed) = self.absolute_x();
+                self.cycles += crossed as u64;
+                self.cmp(v)
             }
             0xd9 => {
-                let (v, _) = self.absolute_y(Op::Read);
+                let (v, _, crossed) = self.absolute_y();
+                self.cycles += crossed as u64;
                 self.cmp(v)
             }
             0xc1 => {
@@ -402,7 +1068,8 @@ impl CPU {
                 self.cmp(v)
             }
             0xd1 => {
-                let (v, _) = self.indirect_y(Op::Read);
+                let (v, _, crossed) = self.indirect_y();
+                self.cycles += crossed as u64;
                 self.cmp(v)
             }
             // ********
@@ -454,7 +1121,8 @@ impl CPU {
                 self.write_memory(addr, result)
             }
             0xde => {
-                let (arg, addr) = self.absolute_x(Op::RMW);
+                let (arg, addr, _) = self.absolute_x();
+                self.cycles += 1;
                 let result = self.dec(arg);
                 self.cycles += 1;
                 self.write_memory(addr, result)
@@ -496,11 +1164,13 @@ impl CPU {
                 self.eor(absolute.0)
             }
             0x5d => {
-                let absolute_x = self.absolute_x(Op::Read);
-                self.eor(absolute_x.0)
+                let (v, _, crossed) = self.absolute_x();
+                self.cycles += crossed as u64;
+                self.eor(v)
             }
             0x59 => {
-                let (v, _) = self.absolute_y(Op::Read);
+                let (v, _, crossed) = self.absolute_y();
+                self.cycles += crossed as u64;
                 self.eor(v)
             }
             0x41 => {
@@ -508,7 +1178,8 @@ impl CPU {
                 self.eor(v)
             }
             0x51 => {
-                let (v, _) = self.indirect_y(Op::Read);
+                let (v, _, crossed) = self.indirect_y();
+                self.cycles += crossed as u64;
                 self.eor(v)
             }
             // ********
@@ -532,7 +1203,8 @@ impl CPU {
                 self.write_memory(addr, result)
             }
             0xfe => {
-                let (arg, addr) = self.absolute_x(Op::RMW);
+                let (arg, addr, _) = self.absolute_x();
+                self.cycles += 1;
                 let result = self.inc(arg);
                 self.cycles += 1;
                 self.write_memory(addr, result)
@@ -612,11 +1284,13 @@ impl CPU {
                 self.lda(absolute.0)
             }
             0xbd => {
-                let absolute_x = self.absolute_x(Op::Read);
-                self.lda(absolute_x.0)
+                let (v, _, crossed) = self.absolute_x();
+                self.cycles += crossed as u64;
+                self.lda(v)
             }
             0xb9 => {
-                let (v, _) = self.absolute_y(Op::Read);
+                let (v, _, crossed) = self.absolute_y();
+                self.cycles += crossed as u64;
                 self.lda(v)
             }
             0xa1 => {
@@ -624,7 +1298,8 @@ impl CPU {
                 self.lda(v)
             }
             0xb1 => {
-                let (v, _) = self.indirect_y(Op::Read);
+                let (v, _, crossed) = self.indirect_y();
+                self.cycles += crossed as u64;
                 self.lda(v)
             }
             // ********
@@ -646,7 +1321,8 @@ impl CPU {
                 self.ldx(absolute.0)
             }
             0xbe => {
-                let (v, _) = self.absolute_y(Op::Read);
+                let (v, _, crossed) = self.absolute_y();
+                self.cycles += crossed as u64;
                 self.ldx(v)
             }
             // ********
@@ -668,8 +1344,9 @@ impl CPU {
                 self.ldy(absolute.0)
             }
             0xbc => {
-                let absolute_x = self.absolute_x(Op::Read);
-                self.ldy(absolute_x.0)
+                let (v, _, crossed) = self.absolute_x();
+                self.cycles += crossed as u64;
+                self.ldy(v)
             }
             // ********
             // LSR - Logical Shift Right
@@ -696,7 +1373,8 @@ impl CPU {
                 self.write_memory(addr, result);
             }
             0x5e => {
-                let (v, addr) = self.absolute_x(Op::RMW);
+                let (v, addr, _) = self.absolute_x();
+                self.cycles += 1;
                 let result = self.lsr(v);
                 self.cycles += 1;
                 self.write_memory(addr, result);
@@ -704,6 +1382,25 @@ impl CPU {
             // ********
             // NOP - No Operation
             0xea => self.pc += 1,
+            // unofficial NOPs, the same but for 0xea they read their operand
+            // (and whatever it points at) and throw it away
+            0x1a | 0x3a | 0x5a | 0x7a | 0xda | 0xfa => self.pc += 1,
+            0x80 | 0x82 | 0x89 | 0xc2 | 0xe2 => {
+                self.immediate();
+            }
+            0x04 | 0x44 | 0x64 => {
+                self.zero_page();
+            }
+            0x14 | 0x34 | 0x54 | 0x74 | 0xd4 | 0xf4 => {
+                self.zero_page_x();
+            }
+            0x0c => {
+                self.absolute();
+            }
+            0x1c | 0x3c | 0x5c | 0x7c | 0xdc | 0xfc => {
+                let (_, _, crossed) = self.absolute_x();
+                self.cycles += crossed as u64;
+            }
             // ********
             // ORA - Logical Inclusive OR
             0x09 => {
@@ -723,11 +1420,13 @@ impl CPU {
                 self.ora(absolute.0)
             }
             0x1d => {
-                let absolute_x = self.absolute_x(Op::Read);
-                self.ora(absolute_x.0)
+                let (v, _, crossed) = self.absolute_x();
+                self.cycles += crossed as u64;
+                self.ora(v)
             }
             0x19 => {
-                let (v, _) = self.absolute_y(Op::Read);
+                let (v, _, crossed) = self.absolute_y();
+                self.cycles += crossed as u64;
                 self.ora(v)
             }
             0x01 => {
@@ -735,7 +1434,8 @@ impl CPU {
                 self.ora(v)
             }
             0x11 => {
-                let (v, _) = self.indirect_y(Op::Read);
+                let (v, _, crossed) = self.indirect_y();
+                self.cycles += crossed as u64;
                 self.ora(v)
             }
             // ********
@@ -796,7 +1496,8 @@ impl CPU {
                 self.write_memory(addr, result);
             }
             0x3e => {
-                let (v, addr) = self.absolute_x(Op::RMW);
+                let (v, addr, _) = self.absolute_x();
+                self.cycles += 1;
                 let result = self.rol(v);
                 self.cycles += 1;
                 self.write_memory(addr, result);
@@ -826,7 +1527,8 @@ impl CPU {
                 self.write_memory(addr, result);
             }
             0x7e => {
-                let (v, addr) = self.absolute_x(Op::RMW);
+                let (v, addr, _) = self.absolute_x();
+                self.cycles += 1;
                 let result = self.ror(v);
                 self.cycles += 1;
                 self.write_memory(addr, result);
@@ -870,11 +1572,13 @@ impl CPU {
                 self.sbc(absolute.0)
             }
             0xfd => {
-                let absolute_x = self.absolute_x(Op::Read);
-                self.sbc(absolute_x.0)
+                let (v, _, crossed) = self.absolute_x();
+                self.cycles += crossed as u64;
+                self.sbc(v)
             }
             0xf9 => {
-                let (v, _) = self.absolute_y(Op::Read);
+                let (v, _, crossed) = self.absolute_y();
+                self.cycles += crossed as u64;
                 self.sbc(v)
             }
             0xe1 => {
@@ -882,7 +1586,8 @@ impl CPU {
                 self.sbc(v)
             }
             0xf1 => {
-                let (v, _) = self.indirect_y(Op::Read);
+                let (v, _, crossed) = self.indirect_y();
+                self.cycles += crossed as u64;
                 self.sbc(v)
             }
             // ********
@@ -918,11 +1623,11 @@ impl CPU {
                 self.write_memory(addr, self.accum)
             }
             0x9d => {
-                let (_, addr) = self.absolute_x(Op::Write);
+                let (_, addr, _) = self.absolute_x();
                 self.write_memory(addr, self.accum)
             }
             0x99 => {
-                let (_, addr) = self.absolute_y(Op::Write);
+                let (_, addr, _) = self.absolute_y();
                 self.write_memory(addr, self.accum)
             }
             0x81 => {
@@ -930,7 +1635,7 @@ impl CPU {
                 self.write_memory(addr, self.accum)
             }
             0x91 => {
-                let (_, addr) = self.indirect_y(Op::Write);
+                let (_, addr, _) = self.indirect_y();
                 self.write_memory(addr, self.accum)
             }
             // ********
@@ -1008,9 +1713,9 @@ impl CPU {
                 self.pc += 1
             }
             // ********
-            _ => {
-                panic!("Unexpected opcode found: {:#x}\nSkipping...", opcode)
-            }
+            // KIL - the unofficial opcodes that lock the CPU up, PC stays on
+            // them. The ones we don't have yet stop it just the same
+            _ => self.halted = Some((self.pc, opcode)),
         }
     }
 
@@ -1058,8 +1763,10 @@ impl CPU {
     }
 
     fn brk(&mut self) {
-        let low_pc = (self.pc & 0xff) as u8;
-        let hi_pc = ((self.pc >> 8) & 0xff) as u8;
+        // BRK has a padding byte after it, RTI comes back past that
+        let ret = self.pc.wrapping_add(2);
+        let low_pc = (ret & 0xff) as u8;
+        let hi_pc = ((ret >> 8) & 0xff) as u8;
 
         self.set_brk();
 
@@ -1072,14 +1779,36 @@ impl CPU {
         let p = self.st | 1 << BRK_CMD - 1;
         self.stack_push(p);
 
-        // load BRK interrupt vector
-        let low_addr = self.read_memory(BRK_IH);
-        let hi_addr = self.read_memory(BRK_IH + 1);
+        // load BRK interrupt vector, unless an NMI's taken it over. The
+        // opcode fetch was the first of its 7 cycles
+        let vector = if self.nmi_by(self.ticked + 4) {
+            NON_MASKABLE_IH
+        } else {
+            BRK_IH
+        };
+        let low_addr = self.read_memory(vector);
+        let hi_addr = self.read_memory(vector + 1);
 
         let ih_addr = join_hi_low(low_addr, hi_addr);
         self.pc = ih_addr
     }
 
+    // the operand's an offset from the next instruction, -128 to 127. Taken
+    // it costs a cycle, and another when it lands on a different page
+    fn branch(&mut self, taken: bool) {
+        let offset = self.read_memory(self.pc + 1) as i8;
+        self.pc = self.pc.wrapping_add(2);
+        if !taken {
+            return;
+        }
+        let target = self.pc.wrapping_add(offset as u16);
+        self.cycles += 1;
+        if CPU::page_boundary_crossed(self.pc, target) {
+            self.cycles += 1
+        }
+        self.pc = target
+    }
+
     fn cmp(&mut self, v: u8) {
         self.cond_set_carry(self.accum >= v);
         self.cond_set_zero(self.accum == v);
@@ -1228,34 +1957,25 @@ impl CPU {
     fn page_boundary_crossed(base: u16, indexed: u16) -> bool {
         (base & 0xff00) != (indexed & 0xff00)
     }
-    fn absolute_x(&mut self, op: Op) -> (u8, u16) {
-        let lo = self.read_memory(self.pc + 1);
-        let hi = self.read_memory(self.pc + 2);
-        let base_addr = join_hi_low(lo, hi);
-        let indexed_addr = base_addr.wrapping_add(self.rx as u16);
-        if op == Op::Read && CPU::page_boundary_crossed(base_addr, indexed_addr) {
-            self.cycles += 1
-        }
-
-        if op == Op::RMW {
-            self.cycles += 1
-        }
-
-        self.pc += 3;
-        let result = self.read_memory(indexed_addr);
-        (result, indexed_addr)
+    // the indexed modes also say whether the index carried into the high
+    // byte. Reads take a cycle more when it did, writes and read-modify-writes
+    // always spend that cycle fixing the high byte up, it's for the caller to
+    // charge
+    fn absolute_x(&mut self) -> (u8, u16, bool) {
+        self.absolute_indexed(self.rx)
     }
-    fn absolute_y(&mut self, op: Op) -> (u8, u16) {
+    fn absolute_y(&mut self) -> (u8, u16, bool) {
+        self.absolute_indexed(self.ry)
+    }
+    fn absolute_indexed(&mut self, index: u8) -> (u8, u16, bool) {
         let lo = self.read_memory(self.pc + 1);
         let hi = self.read_memory(self.pc + 2);
         let base_addr = join_hi_low(lo, hi);
-        let indexed_addr = base_addr.wrapping_add(self.ry as u16);
-        if op == Op::Read && CPU::page_boundary_crossed(base_addr, indexed_addr) {
-            self.cycles += 1
-        }
+        let indexed_addr = base_addr.wrapping_add(index as u16);
         self.pc += 3;
         let result = self.read_memory(indexed_addr);
-        (result, indexed_addr)
+        let crossed = CPU::page_boundary_crossed(base_addr, indexed_addr);
+        (result, indexed_addr, crossed)
     }
     fn indirect_x(&mut self) -> (u8, u16) {
         let addr = self.indirect_x_no_result();
@@ -1271,18 +1991,16 @@ impl CPU {
         self.pc += 2;
         addr
     }
-    fn indirect_y(&mut self, op: Op) -> (u8, u16) {
+    fn indirect_y(&mut self) -> (u8, u16, bool) {
         let arg = self.read_memory(self.pc + 1);
         let lo = self.read_memory(arg as u16);
         let hi = self.read_memory(arg.wrapping_add(1) as u16);
         let base_addr = join_hi_low(lo, hi);
         let indexed_addr = base_addr.wrapping_add(self.ry as u16);
-        if op == Op::Read && CPU::page_boundary_crossed(base_addr, indexed_addr) {
-            self.cycles += 1
-        }
         self.pc += 2;
         let result = self.read_memory(indexed_addr);
-        (result, indexed_addr)
+        let crossed = CPU::page_boundary_crossed(base_addr, indexed_addr);
+        (result, indexed_addr, crossed)
     }
     // ********
 
@@ -1393,11 +2111,31 @@ impl CPU {
     // ********
 }
 
-#[derive(PartialEq)]
-enum Op {
-    Read,
-    Write,
-    RMW,
+/**
+ * A CPU on a 16KB NROM cartridge with `program` at $8000, which is where it
+ * resets to, for tests around the crate that need a few instructions run.
+ */
+#[cfg(test)]
+pub(crate) fn cpu_with_program(program: &[u8]) -> CPU {
+    let mut prgrom = vec![0; 0x4000];
+    prgrom[..program.len()].copy_from_slice(program);
+    prgrom[0x3ffc..0x3ffe].copy_from_slice(&[0x00, 0x80]);
+    cpu_with_prg(prgrom)
+}
+
+/**
+ * The same with PRG of the test's own making, for other sizes or vectors.
+ */
+#[cfg(test)]
+pub(crate) fn cpu_with_prg(prgrom: Vec<u8>) -> CPU {
+    let mut cpu = CPU::new(Bus::new(PPU::new()));
+    cpu.load_cartridge(Cartridge {
+        prgrom,
+        mirroring: crate::cartridge::Mirroring::Horizontal,
+        ..Default::default()
+    })
+    .expect("Error loading cartridge");
+    cpu
 }
 
 #[cfg(test)]
@@ -2,13 +2,15 @@ use regex::Regex;
 use std::{default, fs};
 
 use crate::{
-    bus::Bus,
+    bus::{Bus, PowerOnRam},
     cartridge::{Cartridge, Mirroring},
+    controller::Buttons,
     debug::CpuState,
-    ppu::PPU,
+    headless::frame_hash,
+    ppu::{PPU, SYSTEM_PALLETE},
 };
 
-use super::CPU;
+use super::{cpu_with_prg, cpu_with_program, IrqSource, CPU};
 
 fn make_cpu_with_empty_bus() -> CPU {
     let bus = Bus::new(PPU::new());
@@ -86,7 +88,7 @@ fn nestest() {
     let file_path = "./test_roms/cpu/nestest.nes";
     let cartridge = Cartridge::load(file_path).expect("Error loading file");
     let mut cpu = make_cpu_with_empty_bus();
-    cpu.load_cartridge(cartridge);
+    cpu.load_cartridge(cartridge).expect("Error loading cartridge");
 
     let actual = run_debug_until(&mut cpu, 5003);
     let expected = parse_nestest_log();
@@ -104,3 +106,470 @@ fn nestest() {
         prev = (a, e);
     }
 }
+
+#[test]
+fn test_run_frame() {
+    let mut prgrom = vec![0; 0x4000];
+    let program = [
+        0xa9, 0x80, // LDA #$80
+        0x8d, 0x00, 0x20, // STA $2000, nmi on
+        0x4c, 0x05, 0x80, // JMP $8005
+        0xe6, 0x00, // nmi: INC $00
+        0x40, // RTI
+    ];
+    prgrom[..program.len()].copy_from_slice(&program);
+    // nmi and reset vectors
+    prgrom[0x3ffa..0x3ffe].copy_from_slice(&[0x08, 0x80, 0x00, 0x80]);
+
+    let mut cpu = cpu_with_prg(prgrom);
+
+    // each frame's nmi is serviced at the start of the next one
+    for _ in 0..3 {
+        cpu.run_frame();
+    }
+    assert_eq!(cpu.bus.read_memory(0x00), 2);
+}
+
+#[test]
+fn test_set_controller_state() {
+    let mut prgrom = vec![0; 0x4000];
+    let program = [
+        0xa9, 0x80, // LDA #$80
+        0x8d, 0x00, 0x20, // STA $2000, nmi on
+        0x4c, 0x05, 0x80, // JMP $8005
+        0xa9, 0x01, // nmi: LDA #$01
+        0x8d, 0x16, 0x40, // STA $4016, strobe
+        0x4a, // LSR A
+        0x8d, 0x16, 0x40, // STA $4016
+        0xad, 0x16, 0x40, // LDA $4016, A button
+        0x29, 0x01, // AND #$01
+        0x85, 0x00, // STA $00
+        0x40, // RTI
+    ];
+    prgrom[..program.len()].copy_from_slice(&program);
+    prgrom[0x3ffa..0x3ffe].copy_from_slice(&[0x08, 0x80, 0x00, 0x80]);
+
+    let mut cpu = cpu_with_prg(prgrom);
+
+    cpu.run_frame();
+    cpu.set_controller_state(0, Buttons::A);
+    cpu.run_frame();
+    assert_eq!(cpu.bus.read_memory(0x00), 1);
+    cpu.set_controller_state(0, Buttons::START);
+    cpu.run_frame();
+    assert_eq!(cpu.bus.read_memory(0x00), 0);
+}
+
+#[test]
+fn test_pattern_table() {
+    let mut chrrom = vec![0; 0x2000];
+    // tile 1, top row all color 1
+    chrrom[0x10] = 0xff;
+    let mut cpu = make_cpu_with_empty_bus();
+    cpu.load_cartridge(Cartridge {
+        prgrom: vec![0; 0x4000],
+        chrrom,
+        mirroring: Mirroring::Horizontal,
+        ..Default::default()
+    })
+    .expect("Error loading cartridge");
+    // $3f01 = white
+    cpu.bus.write_memory(0x2006, 0x3f);
+    cpu.bus.write_memory(0x2006, 0x01);
+    cpu.bus.write_memory(0x2007, 0x30);
+
+    let (r, g, b) = SYSTEM_PALLETE[0x30];
+    let pixels = cpu.ppu().pattern_table(0, 0);
+    assert_eq!(pixels.len(), 128 * 128 * 3);
+    assert_eq!(&pixels[8 * 3..9 * 3], &[r, g, b]);
+    assert_eq!(&pixels[15 * 3..16 * 3], &[r, g, b]);
+    let (r, g, b) = SYSTEM_PALLETE[0];
+    assert_eq!(&pixels[..3], &[r, g, b]);
+    assert_eq!(cpu.ppu().palette_ram()[1], SYSTEM_PALLETE[0x30]);
+}
+
+#[test]
+fn test_nametables_and_sprites() {
+    let mut chrrom = vec![0; 0x2000];
+    // tile 1, top row all color 1
+    chrrom[0x10] = 0xff;
+    let mut cpu = make_cpu_with_empty_bus();
+    cpu.load_cartridge(Cartridge {
+        prgrom: vec![0; 0x4000],
+        chrrom,
+        mirroring: Mirroring::Horizontal,
+        ..Default::default()
+    })
+    .expect("Error loading cartridge");
+    let mut write_vram = |addr: u16, data: u8| {
+        cpu.bus.write_memory(0x2006, (addr >> 8) as u8);
+        cpu.bus.write_memory(0x2006, addr as u8);
+        cpu.bus.write_memory(0x2007, data);
+    };
+    // second tile of the first nametable, white, and red sprites
+    write_vram(0x2001, 1);
+    write_vram(0x3f01, 0x30);
+    write_vram(0x3f11, 0x16);
+    // sprite 0 shows tile 1, copied in with OAM DMA from page 2
+    cpu.bus.write_memory(0x201, 1);
+    cpu.bus.write_memory(0x4014, 2);
+
+    let (r, g, b) = SYSTEM_PALLETE[0x30];
+    let nametables = cpu.ppu().nametables();
+    assert_eq!(nametables.len(), 512 * 480 * 3);
+    assert_eq!(&nametables[8 * 3..9 * 3], &[r, g, b]);
+    // horizontal mirroring, $2400 is $2000
+    assert_eq!(&nametables[(256 + 8) * 3..(256 + 9) * 3], &[r, g, b]);
+    assert_eq!(&nametables[..3], &{
+        let (r, g, b) = SYSTEM_PALLETE[0];
+        [r, g, b]
+    });
+
+    let (r, g, b) = SYSTEM_PALLETE[0x16];
+    let sprites = cpu.ppu().sprites();
+    assert_eq!(sprites.len(), 64 * 128 * 3);
+    assert_eq!(&sprites[..3], &[r, g, b]);
+    assert_eq!(&sprites[7 * 3..8 * 3], &[r, g, b]);
+}
+
+// lets the test read back what the cpu wrote
+struct SharedBuffer(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+impl std::io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_trace() {
+    let program = [
+        0xa2, 0x05, // LDX #$05
+        0xb5, 0x10, // LDA $10,X
+        0x8d, 0x00, 0x02, // STA $0200
+        0x4c, 0x00, 0x80, // JMP $8000
+    ];
+    let mut cpu = cpu_with_program(&program);
+    cpu.bus.write_memory(0x15, 0x42);
+
+    let buffer = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    cpu.set_trace(Some(Box::new(SharedBuffer(buffer.clone()))));
+    for _ in 0..5 {
+        cpu.step();
+    }
+    let trace = String::from_utf8(buffer.borrow().clone()).unwrap();
+    assert_eq!(
+        trace.lines().collect::<Vec<_>>(),
+        [
+            "8000  A2 05     LDX #$05                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0,  0 CYC:2",
+            "8002  B5 10     LDA $10,X @ 15 = 42             A:00 X:05 Y:00 P:24 SP:FD PPU:  0,  6 CYC:4",
+            "8004  8D 00 02  STA $0200 = 00                  A:42 X:05 Y:00 P:24 SP:FD PPU:  0, 18 CYC:8",
+            "8007  4C 00 80  JMP $8000                       A:42 X:05 Y:00 P:24 SP:FD PPU:  0, 30 CYC:12",
+            "8000  A2 05     LDX #$05                        A:42 X:05 Y:00 P:24 SP:FD PPU:  0, 39 CYC:15",
+        ]
+    );
+}
+
+fn frame_hashes(fill: PowerOnRam, frames: usize) -> Vec<u32> {
+    let program = [
+        0xa9, 0x3f, 0x8d, 0x06, 0x20, // LDA #$3F, STA $2006
+        0xa9, 0x00, 0x8d, 0x06, 0x20, // LDA #$00, STA $2006
+        0xad, 0x05, 0x03, // LDA $0305, never written
+        0x8d, 0x07, 0x20, // STA $2007, the backdrop
+        0xa9, 0x08, 0x8d, 0x01, 0x20, // LDA #$08, STA $2001
+        0xee, 0x06, 0x03, // $8015: INC $0306
+        0xad, 0x06, 0x03, // LDA $0306
+        0x8d, 0x05, 0x20, 0x8d, 0x05, 0x20, // STA $2005 twice
+        0x4c, 0x15, 0x80, // JMP $8015
+    ];
+    let mut cpu = cpu_with_program(&program);
+    cpu.fill_ram(fill);
+    (0..frames)
+        .map(|_| {
+            cpu.run_frame();
+            frame_hash(cpu.frame())
+        })
+        .collect()
+}
+
+#[test]
+fn test_deterministic() {
+    for fill in [PowerOnRam::Zeros, PowerOnRam::Ones, PowerOnRam::Pattern] {
+        assert_eq!(frame_hashes(fill, 10), frame_hashes(fill, 10));
+    }
+    // the game sees what was asked for
+    assert_ne!(
+        frame_hashes(PowerOnRam::Zeros, 2),
+        frame_hashes(PowerOnRam::Ones, 2)
+    );
+    assert_eq!(
+        frame_hashes(PowerOnRam::Ones, 2),
+        frame_hashes(PowerOnRam::Pattern, 2)
+    );
+}
+
+#[test]
+fn test_catch_up() {
+    // a split: waits for the sprite 0 hit, scrolls, waits for it to clear
+    let mut prgrom = vec![0; 0x4000];
+    let program = [
+        0xa9, 0x80, 0x8d, 0x00, 0x20, // $8000: LDA #$80, STA $2000
+        0xa9, 0x1e, 0x8d, 0x01, 0x20, // LDA #$1E, STA $2001
+        0xad, 0x02, 0x20, 0x29, 0x40, // $800A: LDA $2002, AND #$40
+        0xd0, 0x03, 0x4c, 0x0a, 0x80, // BNE $8014, JMP $800A
+        0xe6, 0x10, 0xa5, 0x10, // $8014: INC $10, LDA $10
+        0x8d, 0x05, 0x20, 0x8d, 0x05, 0x20, // STA $2005 twice
+        0xad, 0x02, 0x20, 0x29, 0x40, // $801E: LDA $2002, AND #$40
+        0xf0, 0x03, 0x4c, 0x1e, 0x80, // BEQ $8028, JMP $801E
+        0x4c, 0x0a, 0x80, // $8028: JMP $800A
+        0xa9, 0x00, 0x8d, 0x05, 0x20, // $802B NMI: LDA #$00, STA $2005
+        0x8d, 0x05, 0x20, 0x40, // STA $2005, RTI
+    ];
+    prgrom[..program.len()].copy_from_slice(&program);
+    prgrom[0x3ffa..].copy_from_slice(&[0x2b, 0x80, 0x00, 0x80, 0x00, 0x80]);
+    let cpu = || {
+        let mut cpu = make_cpu_with_empty_bus();
+        cpu.load_cartridge(Cartridge {
+            prgrom: prgrom.clone(),
+            chrrom: (0..0x2000).map(|n| (n * 7) as u8).collect(),
+            mirroring: Mirroring::Vertical,
+            ..Default::default()
+        })
+        .expect("Error loading cartridge");
+        cpu
+    };
+
+    // the PPU run in batches draws what it did a step at a time
+    let (mut lazy, mut stepped) = (cpu(), cpu());
+    for _ in 0..5 {
+        lazy.run_frame();
+        while !stepped.advance() {}
+        assert_eq!(lazy.registers(), stepped.registers());
+        assert_eq!(lazy.ppu().registers(), stepped.ppu().registers());
+        assert_eq!(frame_hash(lazy.frame()), frame_hash(stepped.frame()));
+    }
+    assert!(lazy.peek(0x10) >= 4);
+}
+
+#[test]
+fn test_unofficial_nops() {
+    let mut cpu = make_cpu_with_empty_bus();
+    let program = [
+        0x1a, // NOP
+        0x80, 0xff, // NOP #$FF
+        0x04, 0x10, // NOP $10
+        0x14, 0x10, // NOP $10,X
+        0x0c, 0x00, 0x03, // NOP $0300
+        0x3c, 0x00, 0x03, // NOP $0300,X
+        0xfc, 0xff, 0x03, // NOP $03FF,X, crossing a page
+    ];
+    for (addr, byte) in (0x200..).zip(program) {
+        cpu.bus.write_memory(addr, byte);
+    }
+    cpu.pc = 0x200;
+    cpu.rx = 1;
+    let (a, st) = (cpu.accum, cpu.st);
+    // (pc after, cycles taken)
+    let expected = [
+        (0x201, 2),
+        (0x203, 2),
+        (0x205, 3),
+        (0x207, 4),
+        (0x20a, 4),
+        (0x20d, 4),
+        (0x210, 5),
+    ];
+    for (pc, cycles) in expected {
+        assert_eq!(cpu.step(), cycles);
+        assert_eq!(cpu.pc, pc);
+    }
+    assert_eq!((cpu.accum, cpu.rx, cpu.st), (a, 1, st));
+}
+
+#[test]
+fn test_halted() {
+    let mut prgrom = vec![0; 0x4000];
+    let program = [
+        0xa9, 0x80, 0x8d, 0x00, 0x20, // LDA #$80, STA $2000
+        0xe6, 0x10, // INC $10
+        0x02, // $8007: KIL
+        0xe6, 0x11, 0x40, // $8008 NMI: INC $11, RTI
+    ];
+    prgrom[..program.len()].copy_from_slice(&program);
+    prgrom[0x3ffa..].copy_from_slice(&[0x08, 0x80, 0x00, 0x80, 0x00, 0x80]);
+    let mut cpu = cpu_with_prg(prgrom);
+
+    // frames go on without it, NMIs and all are ignored
+    for _ in 0..3 {
+        cpu.run_frame();
+    }
+    assert_eq!(cpu.halted(), Some(0x8007));
+    assert_eq!(cpu.pc, 0x8007);
+    assert_eq!((cpu.peek(0x10), cpu.peek(0x11)), (1, 0));
+
+    cpu.soft_reset();
+    assert_eq!(cpu.halted(), None);
+    cpu.run_frame();
+    assert_eq!(cpu.peek(0x10), 2);
+    assert_eq!(cpu.halted(), Some(0x8007));
+}
+
+#[test]
+fn test_branches() {
+    let mut cpu = make_cpu_with_empty_bus();
+    // Z is clear, so BNE is taken and BEQ isn't
+    cpu.st = 0;
+    // (where, the branch, pc after, cycles taken)
+    let cases = [
+        (0x0210, [0xf0, 0xfb], 0x0212, 2),
+        (0x0210, [0xd0, 0x04], 0x0216, 3),
+        (0x0210, [0xd0, 0xfb], 0x020d, 3),
+        (0x02fd, [0xd0, 0x05], 0x0304, 4),
+        (0x0300, [0xd0, 0xfa], 0x02fc, 4),
+        (0x0380, [0xd0, 0x80], 0x0302, 3),
+    ];
+    for (addr, branch, pc, cycles) in cases {
+        cpu.bus.write_memory(addr, branch[0]);
+        cpu.bus.write_memory(addr + 1, branch[1]);
+        cpu.pc = addr;
+        assert_eq!(cpu.step(), cycles);
+        assert_eq!(cpu.pc, pc);
+    }
+}
+
+#[test]
+fn test_page_crossing() {
+    let mut cpu = make_cpu_with_empty_bus();
+    // ($10) points at $02F0, so ($10),Y crosses from Y = $10
+    cpu.bus.write_memory(0x10, 0xf0);
+    cpu.bus.write_memory(0x11, 0x02);
+    // (instruction, X and Y, cycles taken)
+    let cases: [(&[u8], u8, u8, u64); 12] = [
+        (&[0xbd, 0xf0, 0x02], 0x0f, 0, 4), // LDA $02F0,X
+        (&[0xbd, 0xf0, 0x02], 0x10, 0, 5),
+        (&[0xb9, 0xf0, 0x02], 0, 0x0f, 4), // LDA $02F0,Y
+        (&[0xb9, 0xf0, 0x02], 0, 0x10, 5),
+        (&[0xb1, 0x10], 0, 0x0f, 5), // LDA ($10),Y
+        (&[0xb1, 0x10], 0, 0x10, 6),
+        (&[0xbe, 0xf0, 0x02], 0, 0x10, 5), // LDX $02F0,Y
+        (&[0xbc, 0xf0, 0x02], 0x10, 0, 5), // LDY $02F0,X
+        // writes and read-modify-writes take the same either way
+        (&[0x9d, 0xf0, 0x02], 0x0f, 0, 5), // STA $02F0,X
+        (&[0x99, 0xf0, 0x02], 0, 0x10, 5), // STA $02F0,Y
+        (&[0x91, 0x10], 0, 0x10, 6),       // STA ($10),Y
+        (&[0xfe, 0xf0, 0x02], 0x0f, 0, 7), // INC $02F0,X
+    ];
+    for (instruction, x, y, cycles) in cases {
+        for (addr, byte) in (0x400..).zip(instruction) {
+            cpu.bus.write_memory(addr, *byte);
+        }
+        cpu.pc = 0x400;
+        (cpu.rx, cpu.ry) = (x, y);
+        assert_eq!(cpu.step(), cycles, "{:02X?}", instruction);
+    }
+}
+
+#[test]
+fn test_irq() {
+    let mut prgrom = vec![0; 0x4000];
+    let program = [
+        0x58, // $8000: CLI
+        0xe6, 0x10, // INC $10
+        0x78, // SEI
+        0x4c, 0x04, 0x80, // $8004: JMP $8004
+        0xe6, 0x11, 0x40, // $8007 IRQ: INC $11, RTI
+    ];
+    prgrom[..program.len()].copy_from_slice(&program);
+    prgrom[0x3ffa..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x07, 0x80]);
+    let mut cpu = cpu_with_prg(prgrom);
+    cpu.set_irq_pending(IrqSource::Dmc, true);
+    assert_eq!(cpu.irq_pending(), Some(IrqSource::Dmc));
+
+    // the instruction after CLI runs before the IRQ's taken
+    cpu.step();
+    cpu.step();
+    assert_eq!((cpu.peek(0x10), cpu.peek(0x11)), (1, 0));
+    let sp = cpu.sp;
+    cpu.step();
+    assert_eq!(cpu.peek(0x11), 1);
+    assert_eq!(cpu.pc, 0x8009);
+    assert_eq!(cpu.sp, sp.wrapping_sub(3));
+    // the return address is SEI's, and B is clear in the status pushed
+    assert_eq!(cpu.peek(0x100 + sp as u16 - 1), 0x03);
+    assert_eq!(cpu.peek(0x100 + sp as u16), 0x80);
+    assert_eq!(cpu.peek(0x100 + sp as u16 - 2) & 0x14, 0);
+
+    // still held, taken again as soon as RTI clears I
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.peek(0x11), 2);
+    cpu.set_irq_pending(IrqSource::Dmc, false);
+    assert_eq!(cpu.irq_pending(), None);
+    cpu.step();
+    assert_eq!(cpu.pc, 0x8003);
+
+    // SEI lets one more through, then it's masked though still pending
+    cpu.step();
+    cpu.set_irq_pending(IrqSource::FrameCounter, true);
+    cpu.step();
+    assert_eq!(cpu.peek(0x11), 3);
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.pc, 0x8004);
+    assert_eq!(cpu.peek(0x11), 3);
+    assert_eq!(cpu.irq_pending(), Some(IrqSource::FrameCounter));
+}
+
+#[test]
+fn test_interrupt_hijacking() {
+    // NOPs all the way, BRK at $9000, the NMI handler at $A000 and BRK's
+    // and the IRQ's at $B000
+    let mut prgrom = vec![0xea; 0x4000];
+    prgrom[0x1000..0x1002].copy_from_slice(&[0x00, 0x00]);
+    prgrom[0x3ffa..].copy_from_slice(&[0x00, 0xa0, 0x00, 0x80, 0x00, 0xb0]);
+    let mut cpu = cpu_with_prg(prgrom);
+    cpu.bus.write_memory(0x2000, 0x80);
+    // NOPs until vblank's at most `dots` away, 6 dots a NOP
+    let approach = |cpu: &mut CPU, dots: u64| {
+        while cpu.ppu().dots_to_vblank() > dots {
+            cpu.pc = 0x8000;
+            cpu.step();
+        }
+    };
+
+    // the NMI comes in by BRK's 4th cycle and takes the vector, the B flag
+    // on the stack still says BRK
+    approach(&mut cpu, 9);
+    let sp = cpu.sp;
+    cpu.pc = 0x9000;
+    cpu.step();
+    assert_eq!(cpu.pc, 0xa000);
+    assert_eq!(cpu.sp, sp.wrapping_sub(3));
+    assert_eq!(cpu.peek(0x100 + sp as u16 - 2) & 0x10, 0x10);
+    // and it's been taken
+    cpu.step();
+    assert_eq!((cpu.pc, cpu.sp), (0xa001, sp.wrapping_sub(3)));
+
+    // too late, BRK gets its handler and the NMI's taken after
+    approach(&mut cpu, 18);
+    let sp = cpu.sp;
+    cpu.pc = 0x9000;
+    cpu.step();
+    assert_eq!(cpu.pc, 0xb000);
+    cpu.step();
+    assert_eq!((cpu.pc, cpu.sp), (0xa001, sp.wrapping_sub(6)));
+
+    // an IRQ's taken over the same way, B clear as the IRQ pushed it
+    approach(&mut cpu, 9);
+    let sp = cpu.sp;
+    cpu.st &= !0x04;
+    cpu.set_irq_pending(IrqSource::Mapper, true);
+    cpu.step();
+    assert_eq!((cpu.pc, cpu.sp), (0xa001, sp.wrapping_sub(3)));
+    assert_eq!(cpu.peek(0x100 + sp as u16 - 2) & 0x10, 0);
+    assert!(!cpu.bus.poll_generate_nmi());
+}
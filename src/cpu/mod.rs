@@ -1,3 +1,6 @@
-pub use cpu::CPU;
+pub use cpu::{Interrupt, IrqSource, Snapshot, UnsupportedOpcode, CPU};
+
+#[cfg(test)]
+pub(crate) use cpu::{cpu_with_prg, cpu_with_program};
 
 mod cpu;
\ No newline at end of file
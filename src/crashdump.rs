@@ -0,0 +1,221 @@
+use std::{any::Any, error::Error, fmt, fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cpu::CPU,
+    debug::{PpuRegisters, Registers},
+    debugger::instruction_line,
+    hexdump::{Space, ROW_BYTES},
+};
+
+#[derive(Debug)]
+pub enum DumpError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+}
+
+impl fmt::Display for DumpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DumpError::Io(e) => write!(f, "Unable to access crash dump: {}", e),
+            DumpError::Parse(e) => write!(f, "Unable to read crash dump: {}", e),
+        }
+    }
+}
+
+impl Error for DumpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DumpError::Io(e) => Some(e),
+            DumpError::Parse(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for DumpError {
+    fn from(e: io::Error) -> Self {
+        DumpError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for DumpError {
+    fn from(e: serde_json::Error) -> Self {
+        DumpError::Parse(e)
+    }
+}
+
+/**
+ * What a panic was raised with, they're nearly always strings.
+ */
+pub fn panic_message(panic: &(dyn Any + Send)) -> String {
+    panic
+        .downcast_ref::<String>()
+        .cloned()
+        .or_else(|| panic.downcast_ref::<&str>().map(|s| s.to_string()))
+        .unwrap_or_default()
+}
+
+/**
+ * The whole machine as it was when the emulator fell over, as JSON for bug
+ * reports. The mapper is down to which PRG ROM it had mapped where, it's
+ * the part that goes wrong.
+ */
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct CrashDump {
+    pub reason: String,
+    pub rom: String,
+    pub cpu: Registers,
+    // the last instructions, the one that crashed last
+    pub trace: Vec<String>,
+    pub ram: Vec<u8>,
+    // $6000-$7FFF
+    pub prg_ram: Vec<u8>,
+    pub ppu: PpuRegisters,
+    // the PPU's address space, pattern tables through palettes
+    pub vram: Vec<u8>,
+    pub oam: Vec<u8>,
+    // PRG ROM offset of each 8KB window from $8000, None for RAM or open bus
+    pub prg_banks: Vec<Option<usize>>,
+}
+
+impl CrashDump {
+    pub fn capture(cpu: &CPU, reason: &str, rom: &str) -> CrashDump {
+        let trace = cpu
+            .history()
+            .iter()
+            .map(|regs| instruction_line(cpu, regs))
+            .collect();
+        CrashDump {
+            reason: reason.to_string(),
+            rom: rom.to_string(),
+            cpu: cpu.registers(),
+            trace,
+            ram: cpu.read_range(0..=0x7ff),
+            prg_ram: cpu.read_range(0x6000..=0x7fff),
+            ppu: cpu.ppu().registers(),
+            vram: Space::Ppu.read(cpu),
+            oam: Space::Oam.read(cpu),
+            prg_banks: (0..4)
+                .map(|window| cpu.prg_rom_offset(0x8000 + window * 0x2000))
+                .collect(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), DumpError> {
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<CrashDump, DumpError> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+fn hex_rows(f: &mut fmt::Formatter<'_>, start: u16, bytes: &[u8]) -> fmt::Result {
+    for (row, chunk) in bytes.chunks(ROW_BYTES).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+        writeln!(
+            f,
+            "{:04X}  {}",
+            start as usize + row * ROW_BYTES,
+            hex.join(" ")
+        )?
+    }
+    Ok(())
+}
+
+/**
+ * For reading a dump back: the registers, the trace and then the memory.
+ */
+impl fmt::Display for CrashDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cpu = &self.cpu;
+        writeln!(f, "{} crashed: {}", self.rom, self.reason)?;
+        writeln!(
+            f,
+            "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+            cpu.pc, cpu.a, cpu.x, cpu.y, cpu.p, cpu.sp, cpu.cycles
+        )?;
+        let ppu = &self.ppu;
+        writeln!(
+            f,
+            "PPUCTRL:{:02X} PPUMASK:{:02X} PPUSTATUS:{:02X} scanline {} dot {}",
+            ppu.ctrl, ppu.mask, ppu.status, ppu.scanline, ppu.dot
+        )?;
+        let banks: Vec<String> = self
+            .prg_banks
+            .iter()
+            .zip([0x8000, 0xa000, 0xc000, 0xe000])
+            .map(|(offset, addr)| match offset {
+                Some(offset) => format!("${:04X}=${:05X}", addr, offset),
+                None => format!("${:04X}=-", addr),
+            })
+            .collect();
+        writeln!(f, "PRG ROM: {}", banks.join(" "))?;
+        writeln!(f, "\nLast instructions:")?;
+        for line in &self.trace {
+            writeln!(f, "{}", line)?
+        }
+        writeln!(f, "\nRAM:")?;
+        hex_rows(f, 0, &self.ram)?;
+        writeln!(f, "\nPRG-RAM:")?;
+        hex_rows(f, 0x6000, &self.prg_ram)?;
+        writeln!(f, "\nOAM:")?;
+        hex_rows(f, 0, &self.oam)
+    }
+}
+
+#[cfg(test)]
+mod crashdump_test {
+    use std::panic::{self, AssertUnwindSafe};
+
+    use crate::{
+        cpu::{cpu_with_program, CPU},
+        debug::Hooks,
+    };
+
+    use super::{panic_message, CrashDump};
+
+    // falls over on the third instruction
+    struct Crash;
+
+    impl Hooks for Crash {
+        fn instruction(&mut self, cpu: &mut CPU) {
+            if cpu.registers().pc == 0x8004 {
+                panic!("Fell over at $8004")
+            }
+        }
+    }
+
+    #[test]
+    fn test_capture() {
+        let program = [
+            0xa2, 0x05, // LDX #$05
+            0x86, 0x10, // STX $10
+            0xe8, // INX
+        ];
+        let mut cpu = cpu_with_program(&program);
+        cpu.set_hooks(Some(Box::new(Crash)));
+
+        let crashed = panic::catch_unwind(AssertUnwindSafe(|| cpu.run_frame())).unwrap_err();
+        let dump = CrashDump::capture(&cpu, &panic_message(crashed.as_ref()), "test.nes");
+        assert_eq!(dump.reason, "Fell over at $8004");
+        assert_eq!(dump.trace.len(), 2);
+        assert!(dump.trace[1].contains("STX $10"));
+        assert_eq!(dump.ram[0x10], 5);
+        assert_eq!(dump.prg_ram.len(), 0x2000);
+        assert_eq!(
+            dump.prg_banks,
+            [Some(0), Some(0x2000), Some(0), Some(0x2000)]
+        );
+
+        let path = std::env::temp_dir().join("crashdump_test.json");
+        dump.save(&path).unwrap();
+        assert_eq!(CrashDump::load(&path).unwrap(), dump);
+        std::fs::remove_file(path).unwrap();
+        assert!(dump
+            .to_string()
+            .starts_with("test.nes crashed: Fell over at $8004"));
+    }
+}
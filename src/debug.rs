@@ -1,3 +1,36 @@
+use alloc::{format, string::String, vec::Vec};
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cpu::CPU;
+
+/**
+ * CPU registers between instructions, for debuggers.
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+pub struct Registers {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    pub cycles: u64,
+}
+
+/**
+ * PPU registers and where the beam is, for debuggers.
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+pub struct PpuRegisters {
+    pub ctrl: u8,
+    pub mask: u8,
+    pub status: u8,
+    pub scanline: usize,
+    pub dot: usize,
+}
+
 #[derive(Default, Debug, PartialEq)]
 pub struct CpuState {
     pub addr: u16,
@@ -26,3 +59,81 @@ impl CpuState {
         self.p = p & !(1 << 4)
     }
 }
+
+/**
+ * One line of a nestest.log style trace, everything as it was before the
+ * instruction ran:
+ *
+ * C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+ */
+#[derive(Debug, PartialEq)]
+pub struct TraceLine {
+    pub state: CpuState,
+    pub bytes: Vec<u8>,
+    // with the operands resolved, see disasm::trace_text
+    pub disasm: String,
+    pub scanline: usize,
+    pub dot: usize,
+}
+
+impl fmt::Display for TraceLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        let state = &self.state;
+        write!(
+            f,
+            "{:04X}  {:<8}  {:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+            state.addr,
+            bytes.join(" "),
+            self.disasm,
+            state.a,
+            state.x,
+            state.y,
+            state.p,
+            state.sp,
+            self.scanline,
+            self.dot,
+            state.cycles
+        )
+    }
+}
+
+/**
+ * A read or write the CPU made.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Access {
+    pub addr: u16,
+    pub value: u8,
+    pub write: bool,
+}
+
+/**
+ * Called by the CPU as it runs, for scripts and tools that want to watch or
+ * steer it. They get the CPU to themselves for the length of the call.
+ * Everything defaults to doing nothing.
+ */
+pub trait Hooks {
+    /**
+     * Before each instruction, after any interrupt has been taken.
+     */
+    fn instruction(&mut self, _cpu: &mut CPU) {}
+    /**
+     * After each instruction, with every access it made. Only called when
+     * `wants_accesses` says so, logging them isn't free.
+     */
+    fn accesses(&mut self, _cpu: &mut CPU, _accesses: &[Access]) {}
+    fn wants_accesses(&self) -> bool {
+        false
+    }
+    /**
+     * Once the PPU has finished a frame.
+     */
+    fn frame(&mut self, _cpu: &mut CPU) {}
+    /**
+     * Text to draw over the picture, x, y and the text.
+     */
+    fn overlay(&self) -> Vec<(usize, usize, String)> {
+        Vec::new()
+    }
+}
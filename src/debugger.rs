@@ -0,0 +1,657 @@
+use std::{collections::VecDeque, fmt};
+
+use crate::{
+    assertion::Assertion,
+    cpu::{Interrupt, IrqSource, Snapshot, CPU},
+    debug::Registers,
+    disasm::{self, Line},
+    ppu::{PpuAccess, PpuMemory},
+};
+
+// a snapshot every this many instructions, the last few kept: going back
+// is always possible (SNAPSHOTS - 1) * SNAPSHOT_INTERVAL instructions
+const SNAPSHOT_INTERVAL: usize = 512;
+const SNAPSHOTS: usize = 3;
+
+// how long `run` goes looking for a breakpoint before giving up
+pub const RUN_FRAMES: u64 = 60 * 60;
+
+const BRK: u8 = 0x00;
+
+// the PPU's dots a frame, scanline 261 being the pre-render line
+const DOTS: usize = 341;
+const SCANLINES: usize = 262;
+
+/**
+ * The instruction at `regs.pc` as memory is now, labeled, and the registers
+ * it starts with.
+ */
+pub fn instruction_line(cpu: &CPU, regs: &Registers) -> String {
+    let line = decode_at(cpu, regs.pc);
+    format!(
+        "{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+        line.to_string(),
+        regs.a,
+        regs.x,
+        regs.y,
+        regs.p,
+        regs.sp,
+        regs.cycles
+    )
+}
+
+// as much as the longest instruction, the rest of the window decodes
+// whatever of it it needs
+fn decode_at(cpu: &CPU, addr: u16) -> Line {
+    let bytes: Vec<u8> = (0..3).map(|n| cpu.peek(addr.wrapping_add(n))).collect();
+    disasm::disassemble_one_labeled(&bytes, addr, &|addr| cpu.label(addr).map(String::from))
+}
+
+/**
+ * Whether the branch `opcode` goes with the flags in `p`, None for anything
+ * that isn't a branch. They're all xxy10000, xx picking the flag and y what
+ * it has to be.
+ */
+fn branch_taken(opcode: u8, p: u8) -> Option<bool> {
+    if opcode & 0x1f != 0x10 {
+        return None;
+    }
+    // N, V, C, Z
+    let flag = [0x80, 0x40, 0x01, 0x02][(opcode >> 6) as usize];
+    Some((p & flag != 0) == (opcode & 0x20 != 0))
+}
+
+/**
+ * A row of the disassembly around PC, for the REPL and the GUI alike.
+ */
+#[derive(Debug, PartialEq)]
+pub struct ViewLine {
+    pub line: Line,
+    pub label: Option<String>,
+    // PC is here
+    pub current: bool,
+    // where a branch, JMP or JSR goes
+    pub target: Option<u16>,
+    // whether the current instruction, if it's a branch, will take it
+    pub taken: Option<bool>,
+}
+
+/**
+ * "> $C003  D0 FB     BNE $C000   ; ^ taken", a line to itself for a label.
+ */
+impl fmt::Display for ViewLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(label) = &self.label {
+            writeln!(f, "{}:", label)?
+        }
+        let marker = if self.current { '>' } else { ' ' };
+        let line = format!("{} {}", marker, self.line);
+        let Some(target) = self.target else {
+            return write!(f, "{}", line);
+        };
+        // which way it goes, backwards usually being a loop
+        let direction = if target <= self.line.addr { '^' } else { 'v' };
+        match self.taken {
+            Some(true) => write!(f, "{:<34}; {} taken", line, direction),
+            Some(false) => write!(f, "{:<34}; {} not taken", line, direction),
+            None => write!(f, "{:<34}; {}", line, direction),
+        }
+    }
+}
+
+/**
+ * The disassembly from `before` instructions before PC to `after` after it.
+ * Decoding backwards is guesswork, instructions being 1 to 3 bytes: it goes
+ * with the furthest start that lines up with PC, so can be wrong in data.
+ */
+pub fn disassembly_view(cpu: &CPU, before: usize, after: usize) -> Vec<ViewLine> {
+    let regs = cpu.registers();
+    let pc = regs.pc;
+    let mut lines = Vec::new();
+    for back in (1..=before as u16 * 3).rev() {
+        let mut addr = pc.wrapping_sub(back);
+        let mut sweep = Vec::new();
+        while addr != pc {
+            let line = decode_at(cpu, addr);
+            // ran past PC, it's in the middle of this one
+            if line.bytes.len() as u16 > pc.wrapping_sub(addr) {
+                break;
+            }
+            addr = addr.wrapping_add(line.bytes.len() as u16);
+            sweep.push(line)
+        }
+        if addr == pc && sweep.len() >= before {
+            lines = sweep.split_off(sweep.len() - before);
+            break;
+        }
+    }
+    let mut addr = pc;
+    for _ in 0..=after {
+        let line = decode_at(cpu, addr);
+        addr = addr.wrapping_add(line.bytes.len() as u16);
+        lines.push(line)
+    }
+    lines
+        .into_iter()
+        .map(|line| {
+            let current = line.addr == pc;
+            ViewLine {
+                label: cpu.label(line.addr).map(String::from),
+                target: disasm::jump_target(&line.bytes, line.addr),
+                taken: if current {
+                    branch_taken(line.bytes[0], regs.p)
+                } else {
+                    None
+                },
+                current,
+                line,
+            }
+        })
+        .collect()
+}
+
+/**
+ * `instruction_line` for where the machine is now, with the PPU's position
+ * for raster effects and NMI timing.
+ */
+pub fn status_line(cpu: &CPU) -> String {
+    let ppu = cpu.ppu();
+    format!(
+        "{} PPU:{:>3},{:>3}",
+        instruction_line(cpu, &cpu.registers()),
+        ppu.scanline(),
+        ppu.dot()
+    )
+}
+
+// dots since the start of the frame
+fn beam(cpu: &CPU) -> usize {
+    cpu.ppu().scanline() * DOTS + cpu.ppu().dot()
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Access {
+    Read,
+    Write,
+    Any,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Breakpoint {
+    // about to run the instruction at this address
+    Exec(u16),
+    // the PPU got to this scanline and dot
+    Beam {
+        scanline: usize,
+        dot: usize,
+    },
+    // at the top of the handler, for IRQs from any source without one
+    Nmi,
+    Irq(Option<IrqSource>),
+    Brk,
+    // the CPU went at PPU memory between start and end, both included
+    Watch {
+        memory: PpuMemory,
+        start: u16,
+        end: u16,
+        access: Access,
+    },
+}
+
+impl Breakpoint {
+    /**
+     * `from` is where the beam was before the instruction. The PPU runs
+     * three dots a cycle, so a position counts as hit once it's been passed.
+     * `accesses` are the instruction's to PPU memory.
+     */
+    fn hit(&self, cpu: &CPU, from: usize, accesses: &[PpuAccess]) -> bool {
+        match *self {
+            Breakpoint::Exec(addr) => cpu.registers().pc == addr,
+            Breakpoint::Beam { scanline, dot } => {
+                let frame = SCANLINES * DOTS;
+                let moved = (beam(cpu) + frame - from) % frame;
+                let to_target = (scanline * DOTS + dot + frame - from) % frame;
+                to_target != 0 && to_target <= moved
+            }
+            Breakpoint::Watch {
+                memory,
+                start,
+                end,
+                access,
+            } => accesses.iter().any(|a| {
+                let kind = match access {
+                    Access::Read => !a.write,
+                    Access::Write => a.write,
+                    Access::Any => true,
+                };
+                a.memory == memory && (start..=end).contains(&a.addr) && kind
+            }),
+            _ => false,
+        }
+    }
+
+    fn interrupted(&self, interrupt: Interrupt) -> bool {
+        match (*self, interrupt) {
+            (Breakpoint::Nmi, Interrupt::Nmi) | (Breakpoint::Brk, Interrupt::Brk) => true,
+            (Breakpoint::Irq(source), Interrupt::Irq(from)) => source.is_none_or(|s| s == from),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Breakpoint::Exec(addr) => write!(f, "exec ${:04X}", addr),
+            Breakpoint::Beam { scanline, dot } => write!(f, "scanline {} dot {}", scanline, dot),
+            Breakpoint::Nmi => write!(f, "nmi"),
+            Breakpoint::Irq(None) => write!(f, "irq"),
+            Breakpoint::Irq(Some(source)) => write!(f, "irq from {:?}", source),
+            Breakpoint::Brk => write!(f, "brk"),
+            Breakpoint::Watch {
+                memory,
+                start,
+                end,
+                access,
+            } => {
+                let access = match access {
+                    Access::Read => "read",
+                    Access::Write => "write",
+                    Access::Any => "access",
+                };
+                match memory {
+                    PpuMemory::Vram => write!(f, "{} vram ${:04X}-${:04X}", access, start, end),
+                    PpuMemory::Oam => write!(f, "{} oam ${:02X}-${:02X}", access, start, end),
+                }
+            }
+        }
+    }
+}
+
+/**
+ * Why the machine stopped.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Stop {
+    Done,
+    // the breakpoint's index
+    Breakpoint(usize),
+    // the assertion's index, it no longer holds
+    Assertion(usize),
+    // went through the frame limit without hitting anything
+    OutOfFrames,
+}
+
+/**
+ * Going back in time: snapshots taken as the machine runs, restored and run
+ * forward again to land on the instruction wanted. Emulation is
+ * deterministic and nothing's fed in while the debugger has it, so the
+ * replay comes out the same.
+ */
+pub struct Rewind {
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Rewind::new()
+    }
+}
+
+impl Rewind {
+    pub fn new() -> Rewind {
+        Rewind {
+            snapshots: VecDeque::with_capacity(SNAPSHOTS),
+        }
+    }
+
+    /**
+     * Call before every instruction run through the debugger.
+     */
+    pub fn record(&mut self, cpu: &CPU) {
+        let executed = cpu.executed();
+        let last = self.snapshots.back().map(|snapshot| snapshot.executed());
+        if !executed.is_multiple_of(SNAPSHOT_INTERVAL) || last == Some(executed) {
+            return;
+        }
+        if self.snapshots.len() == SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(cpu.snapshot())
+    }
+
+    /**
+     * Undo up to `instructions`, returning how many it could. Snapshots
+     * from after where it lands are dropped, running on from there is a
+     * different history.
+     */
+    pub fn step_back(&mut self, cpu: &mut CPU, instructions: usize) -> usize {
+        let now = cpu.executed();
+        let target = now.saturating_sub(instructions);
+        // the last one before the target, or the oldest there is
+        while self.snapshots.len() > 1
+            && self
+                .snapshots
+                .back()
+                .is_some_and(|snapshot| snapshot.executed() > target)
+        {
+            self.snapshots.pop_back();
+        }
+        let Some(snapshot) = self.snapshots.back() else {
+            return 0;
+        };
+        let target = target.max(snapshot.executed());
+        cpu.restore(snapshot);
+        cpu.replay(target - snapshot.executed());
+        now - target
+    }
+}
+
+/**
+ * Breakpoints and stepping, forwards and back, for the frontends to drive.
+ */
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    pub assertions: Vec<Assertion>,
+    rewind: Rewind,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: Vec::new(),
+            assertions: Vec::new(),
+            rewind: Rewind::new(),
+        }
+    }
+
+    pub fn step(&mut self, cpu: &mut CPU) -> bool {
+        self.rewind.record(cpu);
+        cpu.advance()
+    }
+
+    /**
+     * Run `instructions`, or less when a breakpoint comes up first.
+     */
+    pub fn step_over(&mut self, cpu: &mut CPU, instructions: usize) -> Stop {
+        for _ in 0..instructions {
+            if let (_, Some(stop)) = self.advance(cpu) {
+                return stop;
+            }
+        }
+        Stop::Done
+    }
+
+    /**
+     * Run until a breakpoint, for at most `frames` frames.
+     */
+    pub fn run(&mut self, cpu: &mut CPU, frames: u64) -> Stop {
+        let mut left = frames;
+        while left > 0 {
+            let (frame, hit) = self.advance(cpu);
+            if frame {
+                left -= 1
+            }
+            if let Some(stop) = hit {
+                return stop;
+            }
+        }
+        Stop::OutOfFrames
+    }
+
+    pub fn step_back(&mut self, cpu: &mut CPU, instructions: usize) -> usize {
+        self.rewind.step_back(cpu, instructions)
+    }
+
+    /**
+     * An instruction, or only the start of an interrupt handler when there's
+     * a breakpoint on it. Whether a frame finished, and the breakpoint hit or
+     * the assertion that failed.
+     */
+    fn advance(&mut self, cpu: &mut CPU) -> (bool, Option<Stop>) {
+        let from = beam(cpu);
+        if let Some(interrupt) = cpu.enter_interrupt() {
+            if let Some(idx) = self.interrupted(interrupt) {
+                return (false, Some(Stop::Breakpoint(idx)));
+            }
+        }
+        let watching = self
+            .breakpoints
+            .iter()
+            .any(|bp| matches!(bp, Breakpoint::Watch { .. }));
+        cpu.ppu_mut().set_watching(watching);
+        let brk = cpu.peek(cpu.registers().pc) == BRK;
+        let frame = self.step(cpu);
+        let accesses = cpu.ppu_mut().take_accesses();
+        let hit = self
+            .breakpoints
+            .iter()
+            .position(|bp| bp.hit(cpu, from, &accesses))
+            .or_else(|| self.interrupted(Interrupt::Brk).filter(|_| brk));
+        if let Some(idx) = hit {
+            return (frame, Some(Stop::Breakpoint(idx)));
+        }
+        let failed = self
+            .assertions
+            .iter()
+            .position(|assertion| (frame || !assertion.per_frame) && !assertion.holds(cpu));
+        (frame, failed.map(Stop::Assertion))
+    }
+
+    fn interrupted(&self, interrupt: Interrupt) -> Option<usize> {
+        self.breakpoints
+            .iter()
+            .position(|bp| bp.interrupted(interrupt))
+    }
+}
+
+#[cfg(test)]
+mod debugger_test {
+    use crate::{
+        assertion::Assertion,
+        cpu::{cpu_with_prg, cpu_with_program, CPU},
+        ppu::PpuMemory,
+    };
+
+    use super::{disassembly_view, Access, Breakpoint, Debugger, Stop, SNAPSHOT_INTERVAL};
+
+    fn counting_cpu() -> CPU {
+        let program = [
+            0xe8, // INX
+            0xf0, 0x03, // BEQ $8006
+            0x4c, 0x00, 0x80, // JMP $8000
+            0xc8, // INY
+            0x4c, 0x00, 0x80, // JMP $8000
+        ];
+        cpu_with_program(&program)
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut cpu = counting_cpu();
+        let mut debugger = Debugger::new();
+        debugger.breakpoints.push(Breakpoint::Exec(0x8006));
+        assert_eq!(debugger.run(&mut cpu, 10), Stop::Breakpoint(0));
+        assert_eq!(cpu.registers().x, 0);
+        // 255 times round INX, BEQ, JMP then the INX and BEQ that get out
+        assert_eq!(cpu.executed(), 767);
+        assert_eq!(debugger.step_over(&mut cpu, 3), Stop::Done);
+        assert_eq!(cpu.registers().y, 1);
+        debugger.breakpoints.clear();
+        assert_eq!(debugger.run(&mut cpu, 1), Stop::OutOfFrames);
+    }
+
+    #[test]
+    fn test_assertions() {
+        let mut cpu = counting_cpu();
+        let mut debugger = Debugger::new();
+        debugger.assertions = vec![
+            Assertion::parse("sp >= 20").unwrap(),
+            Assertion::parse("x < 10").unwrap(),
+        ];
+        assert_eq!(debugger.run(&mut cpu, 1), Stop::Assertion(1));
+        // right after the 16th INX
+        assert_eq!(cpu.registers().x, 0x10);
+        assert_eq!(cpu.executed(), 1 + 15 * 3);
+
+        // only looked at once the frame's done
+        let mut cpu = counting_cpu();
+        debugger.assertions = vec![Assertion::parse("x < 10 every frame").unwrap()];
+        assert_eq!(debugger.run(&mut cpu, 2), Stop::Assertion(0));
+        assert_eq!(cpu.ppu().scanline(), 241);
+    }
+
+    #[test]
+    fn test_beam_breakpoints() {
+        let mut cpu = counting_cpu();
+        let mut debugger = Debugger::new();
+        debugger.breakpoints.push(Breakpoint::Beam {
+            scanline: 241,
+            dot: 1,
+        });
+        assert_eq!(debugger.run(&mut cpu, 2), Stop::Breakpoint(0));
+        let ppu = cpu.ppu();
+        // somewhere in the instruction that went past it
+        assert_eq!(ppu.scanline(), 241);
+        assert!((1..1 + 7 * 3).contains(&ppu.dot()));
+
+        // round the end of the frame and back to the top
+        debugger.breakpoints[0] = Breakpoint::Beam {
+            scanline: 0,
+            dot: 0,
+        };
+        assert_eq!(debugger.run(&mut cpu, 2), Stop::Breakpoint(0));
+        assert!(cpu.ppu().scanline() == 0 && cpu.ppu().dot() < 7 * 3);
+    }
+
+    #[test]
+    fn test_interrupt_breakpoints() {
+        let program = [
+            0xa9, 0x80, // LDA #$80
+            0x8d, 0x00, 0x20, // STA $2000
+            0x4c, 0x05, 0x80, // JMP $8005
+            0x00, 0x00, // BRK at $8008, the NMI handler
+        ];
+        let mut prgrom = vec![0; 0x4000];
+        prgrom[..program.len()].copy_from_slice(&program);
+        prgrom[0x3ffa..0x3ffc].copy_from_slice(&[0x08, 0x80]);
+        prgrom[0x3ffc..0x3ffe].copy_from_slice(&[0x00, 0x80]);
+        prgrom[0x3ffe..].copy_from_slice(&[0x05, 0x80]);
+        let mut cpu = cpu_with_prg(prgrom);
+
+        let mut debugger = Debugger::new();
+        debugger.breakpoints = vec![Breakpoint::Irq(None), Breakpoint::Brk, Breakpoint::Nmi];
+        assert_eq!(debugger.run(&mut cpu, 2), Stop::Breakpoint(2));
+        // on the handler's first instruction, not yet run
+        assert_eq!(cpu.registers().pc, 0x8008);
+        assert_eq!(debugger.step_over(&mut cpu, 1), Stop::Breakpoint(1));
+        assert_eq!(cpu.registers().pc, 0x8005);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let program = [
+            0xa9, 0x3f, // LDA #$3F
+            0x8d, 0x06, 0x20, // STA $2006
+            0xa9, 0x00, // LDA #$00
+            0x8d, 0x06, 0x20, // STA $2006
+            0xa9, 0x0f, // LDA #$0F
+            0x8d, 0x07, 0x20, // STA $2007, the backdrop
+            0x8d, 0x04, 0x20, // STA $2004
+            0x4c, 0x12, 0x80, // JMP $8012
+        ];
+        let mut cpu = cpu_with_program(&program);
+
+        let mut debugger = Debugger::new();
+        debugger.breakpoints = vec![
+            Breakpoint::Watch {
+                memory: PpuMemory::Vram,
+                start: 0x2000,
+                end: 0x23ff,
+                access: Access::Write,
+            },
+            Breakpoint::Watch {
+                memory: PpuMemory::Vram,
+                start: 0x3f00,
+                end: 0x3fff,
+                access: Access::Any,
+            },
+            Breakpoint::Watch {
+                memory: PpuMemory::Oam,
+                start: 0,
+                end: 0xff,
+                access: Access::Write,
+            },
+        ];
+        assert_eq!(debugger.run(&mut cpu, 1), Stop::Breakpoint(1));
+        // stopped after the STA $2007
+        assert_eq!(cpu.registers().pc, 0x800f);
+        assert_eq!(debugger.run(&mut cpu, 1), Stop::Breakpoint(2));
+        assert_eq!(cpu.ppu().oam()[0], 0x0f);
+        assert_eq!(debugger.run(&mut cpu, 1), Stop::OutOfFrames);
+    }
+
+    #[test]
+    fn test_disassembly_view() {
+        let mut cpu = counting_cpu();
+        // onto the JMP after the BEQ
+        let mut debugger = Debugger::new();
+        debugger.step_over(&mut cpu, 2);
+        let lines: Vec<String> = disassembly_view(&cpu, 2, 1)
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "  $8000  E8        INX",
+                "  $8001  F0 03     BEQ $8006      ; v",
+                "> $8003  4C 00 80  JMP $8000      ; ^",
+                "  $8006  C8        INY",
+            ]
+        );
+        debugger.step_over(&mut cpu, 2);
+        let view = disassembly_view(&cpu, 0, 0);
+        assert_eq!(view[0].line.addr, 0x8001);
+        // X is 2, no zero flag
+        assert_eq!(view[0].taken, Some(false));
+        assert_eq!(super::branch_taken(0xf0, 0x02), Some(true));
+        assert_eq!(super::branch_taken(0x90, 0x00), Some(true));
+        assert_eq!(super::branch_taken(0x4c, 0x00), None);
+    }
+
+    #[test]
+    fn test_step_back() {
+        let mut cpu = counting_cpu();
+        let mut debugger = Debugger::new();
+        debugger.step_over(&mut cpu, 700);
+        let before = cpu.registers();
+        debugger.step_over(&mut cpu, 300);
+        assert_eq!(debugger.step_back(&mut cpu, 300), 300);
+        assert_eq!(cpu.registers(), before);
+        assert_eq!(cpu.executed(), 700);
+
+        // and forward again the same way
+        debugger.step_over(&mut cpu, 300);
+        let after = cpu.registers();
+        debugger.step_back(&mut cpu, 300);
+        debugger.step_over(&mut cpu, 300);
+        assert_eq!(cpu.registers(), after);
+
+        // no further than the oldest snapshot, the one at power on is gone
+        let mut cpu = counting_cpu();
+        let mut debugger = Debugger::new();
+        debugger.step_over(&mut cpu, 2000);
+        assert_eq!(
+            debugger.step_back(&mut cpu, 100_000),
+            2000 - SNAPSHOT_INTERVAL
+        );
+        assert_eq!(cpu.executed(), SNAPSHOT_INTERVAL);
+    }
+}
@@ -0,0 +1,30 @@
+use alloc::boxed::Box;
+
+/**
+ * Hardware on the CPU's bus: RAM, the PPU's registers, the controller ports,
+ * the cartridge, and anything plugged in on top with `Bus::attach`. The bus
+ * hands each device the reads and writes in the addresses it claims, `addr`
+ * being the CPU's address, mirrors and all.
+ */
+pub trait BusDevice {
+    /**
+     * A copy with state of its own, for snapshots of the whole machine.
+     */
+    fn box_clone(&self) -> Box<dyn BusDevice>;
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, data: u8);
+    /**
+     * What `read` would return but without the side effects, for debuggers
+     * and scripts. Registers that change when they're read read as 0.
+     */
+    fn peek(&self, addr: u16) -> u8 {
+        let _ = addr;
+        0
+    }
+}
+
+impl Clone for Box<dyn BusDevice> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
@@ -0,0 +1,486 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt;
+
+/**
+ * How an instruction finds its operand, decides how many bytes follow the
+ * opcode and how they're written out.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    Relative,
+}
+
+impl Mode {
+    pub fn operand_len(self) -> u16 {
+        match self {
+            Mode::Implied | Mode::Accumulator => 0,
+            Mode::Absolute | Mode::AbsoluteX | Mode::AbsoluteY | Mode::Indirect => 2,
+            _ => 1,
+        }
+    }
+}
+
+// the "cc = 01" group, aaabbb01
+const ALU: [&str; 8] = ["ORA", "AND", "EOR", "ADC", "STA", "LDA", "CMP", "SBC"];
+const ALU_MODES: [Mode; 8] = [
+    Mode::IndirectX,
+    Mode::ZeroPage,
+    Mode::Immediate,
+    Mode::Absolute,
+    Mode::IndirectY,
+    Mode::ZeroPageX,
+    Mode::AbsoluteY,
+    Mode::AbsoluteX,
+];
+
+/**
+ * Mnemonic and addressing mode of an official opcode, None for the rest.
+ */
+pub fn decode(opcode: u8) -> Option<(&'static str, Mode)> {
+    if opcode & 0b11 == 0b01 {
+        // no STA #imm
+        if opcode == 0x89 {
+            return None;
+        }
+        return Some((
+            ALU[(opcode >> 5) as usize],
+            ALU_MODES[((opcode >> 2) & 0b111) as usize],
+        ));
+    }
+    let decoded = match opcode {
+        0x06 => ("ASL", Mode::ZeroPage),
+        0x16 => ("ASL", Mode::ZeroPageX),
+        0x0e => ("ASL", Mode::Absolute),
+        0x1e => ("ASL", Mode::AbsoluteX),
+        0x0a => ("ASL", Mode::Accumulator),
+        0x26 => ("ROL", Mode::ZeroPage),
+        0x36 => ("ROL", Mode::ZeroPageX),
+        0x2e => ("ROL", Mode::Absolute),
+        0x3e => ("ROL", Mode::AbsoluteX),
+        0x2a => ("ROL", Mode::Accumulator),
+        0x46 => ("LSR", Mode::ZeroPage),
+        0x56 => ("LSR", Mode::ZeroPageX),
+        0x4e => ("LSR", Mode::Absolute),
+        0x5e => ("LSR", Mode::AbsoluteX),
+        0x4a => ("LSR", Mode::Accumulator),
+        0x66 => ("ROR", Mode::ZeroPage),
+        0x76 => ("ROR", Mode::ZeroPageX),
+        0x6e => ("ROR", Mode::Absolute),
+        0x7e => ("ROR", Mode::AbsoluteX),
+        0x6a => ("ROR", Mode::Accumulator),
+        0x86 => ("STX", Mode::ZeroPage),
+        0x96 => ("STX", Mode::ZeroPageY),
+        0x8e => ("STX", Mode::Absolute),
+        0xa2 => ("LDX", Mode::Immediate),
+        0xa6 => ("LDX", Mode::ZeroPage),
+        0xb6 => ("LDX", Mode::ZeroPageY),
+        0xae => ("LDX", Mode::Absolute),
+        0xbe => ("LDX", Mode::AbsoluteY),
+        0xc6 => ("DEC", Mode::ZeroPage),
+        0xd6 => ("DEC", Mode::ZeroPageX),
+        0xce => ("DEC", Mode::Absolute),
+        0xde => ("DEC", Mode::AbsoluteX),
+        0xe6 => ("INC", Mode::ZeroPage),
+        0xf6 => ("INC", Mode::ZeroPageX),
+        0xee => ("INC", Mode::Absolute),
+        0xfe => ("INC", Mode::AbsoluteX),
+        0x24 => ("BIT", Mode::ZeroPage),
+        0x2c => ("BIT", Mode::Absolute),
+        0x4c => ("JMP", Mode::Absolute),
+        0x6c => ("JMP", Mode::Indirect),
+        0x20 => ("JSR", Mode::Absolute),
+        0x84 => ("STY", Mode::ZeroPage),
+        0x94 => ("STY", Mode::ZeroPageX),
+        0x8c => ("STY", Mode::Absolute),
+        0xa0 => ("LDY", Mode::Immediate),
+        0xa4 => ("LDY", Mode::ZeroPage),
+        0xb4 => ("LDY", Mode::ZeroPageX),
+        0xac => ("LDY", Mode::Absolute),
+        0xbc => ("LDY", Mode::AbsoluteX),
+        0xc0 => ("CPY", Mode::Immediate),
+        0xc4 => ("CPY", Mode::ZeroPage),
+        0xcc => ("CPY", Mode::Absolute),
+        0xe0 => ("CPX", Mode::Immediate),
+        0xe4 => ("CPX", Mode::ZeroPage),
+        0xec => ("CPX", Mode::Absolute),
+        0x10 => ("BPL", Mode::Relative),
+        0x30 => ("BMI", Mode::Relative),
+        0x50 => ("BVC", Mode::Relative),
+        0x70 => ("BVS", Mode::Relative),
+        0x90 => ("BCC", Mode::Relative),
+        0xb0 => ("BCS", Mode::Relative),
+        0xd0 => ("BNE", Mode::Relative),
+        0xf0 => ("BEQ", Mode::Relative),
+        0x00 => ("BRK", Mode::Implied),
+        0x40 => ("RTI", Mode::Implied),
+        0x60 => ("RTS", Mode::Implied),
+        0x08 => ("PHP", Mode::Implied),
+        0x28 => ("PLP", Mode::Implied),
+        0x48 => ("PHA", Mode::Implied),
+        0x68 => ("PLA", Mode::Implied),
+        0x88 => ("DEY", Mode::Implied),
+        0xa8 => ("TAY", Mode::Implied),
+        0xc8 => ("INY", Mode::Implied),
+        0xe8 => ("INX", Mode::Implied),
+        0x18 => ("CLC", Mode::Implied),
+        0x38 => ("SEC", Mode::Implied),
+        0x58 => ("CLI", Mode::Implied),
+        0x78 => ("SEI", Mode::Implied),
+        0x98 => ("TYA", Mode::Implied),
+        0xb8 => ("CLV", Mode::Implied),
+        0xd8 => ("CLD", Mode::Implied),
+        0xf8 => ("SED", Mode::Implied),
+        0x8a => ("TXA", Mode::Implied),
+        0x9a => ("TXS", Mode::Implied),
+        0xaa => ("TAX", Mode::Implied),
+        0xba => ("TSX", Mode::Implied),
+        0xca => ("DEX", Mode::Implied),
+        0xea => ("NOP", Mode::Implied),
+        _ => return None,
+    };
+    Some(decoded)
+}
+
+/**
+ * One line of a listing. Bytes that don't decode are kept as a `.byte`.
+ */
+#[derive(Debug, PartialEq)]
+pub struct Line {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(
+            f,
+            "${:04X}  {:<8}  {}",
+            self.addr,
+            bytes.join(" "),
+            self.text
+        )
+    }
+}
+
+/**
+ * Names for addresses, see `Symbols`. None leaves the address as it is.
+ */
+pub type Labels<'a> = &'a dyn Fn(u16) -> Option<String>;
+
+pub fn no_labels(_addr: u16) -> Option<String> {
+    None
+}
+
+// "$10", or its name
+fn zero_page(byte: u8, label: Labels) -> String {
+    label(byte as u16).unwrap_or_else(|| format!("${:02X}", byte))
+}
+
+fn absolute(word: u16, label: Labels) -> String {
+    label(word).unwrap_or_else(|| format!("${:04X}", word))
+}
+
+fn operand(mode: Mode, addr: u16, bytes: &[u8], label: Labels) -> String {
+    let byte = bytes.first().copied().unwrap_or(0);
+    let word = u16::from_le_bytes([byte, bytes.get(1).copied().unwrap_or(0)]);
+    match mode {
+        Mode::Implied => String::new(),
+        Mode::Accumulator => " A".to_string(),
+        Mode::Immediate => format!(" #${:02X}", byte),
+        Mode::ZeroPage => format!(" {}", zero_page(byte, label)),
+        Mode::ZeroPageX => format!(" {},X", zero_page(byte, label)),
+        Mode::ZeroPageY => format!(" {},Y", zero_page(byte, label)),
+        Mode::Absolute => format!(" {}", absolute(word, label)),
+        Mode::AbsoluteX => format!(" {},X", absolute(word, label)),
+        Mode::AbsoluteY => format!(" {},Y", absolute(word, label)),
+        Mode::Indirect => format!(" ({})", absolute(word, label)),
+        Mode::IndirectX => format!(" ({},X)", zero_page(byte, label)),
+        Mode::IndirectY => format!(" ({}),Y", zero_page(byte, label)),
+        // the target rather than the offset, it's what you want to read
+        Mode::Relative => format!(
+            " {}",
+            absolute(addr.wrapping_add(2).wrapping_add(byte as i8 as u16), label)
+        ),
+    }
+}
+
+/**
+ * Where the branch, JMP or JSR in `bytes` at `addr` goes. None for anything
+ * else, and for JMP ($xxxx) since that depends on memory.
+ */
+pub fn jump_target(bytes: &[u8], addr: u16) -> Option<u16> {
+    let (mnemonic, mode) = decode(*bytes.first()?)?;
+    match mode {
+        Mode::Relative => Some(
+            addr.wrapping_add(2)
+                .wrapping_add(*bytes.get(1)? as i8 as u16),
+        ),
+        Mode::Absolute if mnemonic == "JMP" || mnemonic == "JSR" => {
+            Some(u16::from_le_bytes([*bytes.get(1)?, *bytes.get(2)?]))
+        }
+        _ => None,
+    }
+}
+
+/**
+ * The instruction in `bytes` at `addr` the way nestest.log writes it, with
+ * where each operand ends up and what's there before it runs:
+ * "LDA ($80,X) @ 80 = 0200 = 5A". `peek` reads memory without side effects,
+ * `x` and `y` are the index registers. Operands with a label get the label
+ * instead, which won't match nestest.log any more.
+ */
+pub fn trace_text(
+    bytes: &[u8],
+    addr: u16,
+    x: u8,
+    y: u8,
+    peek: impl Fn(u16) -> u8,
+    label: Labels,
+) -> String {
+    let Some((mnemonic, mode)) = decode(bytes[0]) else {
+        return format!(".byte ${:02X}", bytes[0]);
+    };
+    let byte = bytes.get(1).copied().unwrap_or(0);
+    let word = u16::from_le_bytes([byte, bytes.get(2).copied().unwrap_or(0)]);
+    // pointers in zero page wrap around inside it
+    let zp_word =
+        |ptr: u8| u16::from_le_bytes([peek(ptr as u16), peek(ptr.wrapping_add(1) as u16)]);
+    let resolved = match mode {
+        Mode::ZeroPage => format!(" {} = {:02X}", zero_page(byte, label), peek(byte as u16)),
+        Mode::ZeroPageX | Mode::ZeroPageY => {
+            let (index, name) = if mode == Mode::ZeroPageX {
+                (x, 'X')
+            } else {
+                (y, 'Y')
+            };
+            let target = byte.wrapping_add(index);
+            format!(
+                " {},{} @ {:02X} = {:02X}",
+                zero_page(byte, label),
+                name,
+                target,
+                peek(target as u16)
+            )
+        }
+        // the jump target, not data
+        Mode::Absolute if mnemonic == "JMP" || mnemonic == "JSR" => {
+            operand(mode, addr, &bytes[1..], label)
+        }
+        Mode::Absolute => format!(" {} = {:02X}", absolute(word, label), peek(word)),
+        Mode::AbsoluteX | Mode::AbsoluteY => {
+            let (index, name) = if mode == Mode::AbsoluteX {
+                (x, 'X')
+            } else {
+                (y, 'Y')
+            };
+            let target = word.wrapping_add(index as u16);
+            format!(
+                " {},{} @ {:04X} = {:02X}",
+                absolute(word, label),
+                name,
+                target,
+                peek(target)
+            )
+        }
+        // with the 6502's bug, the high byte doesn't cross the page
+        Mode::Indirect => {
+            let hi = (word & 0xff00) | (word.wrapping_add(1) & 0xff);
+            let target = u16::from_le_bytes([peek(word), peek(hi)]);
+            format!(" ({}) = {:04X}", absolute(word, label), target)
+        }
+        Mode::IndirectX => {
+            let ptr = byte.wrapping_add(x);
+            let target = zp_word(ptr);
+            format!(
+                " ({},X) @ {:02X} = {:04X} = {:02X}",
+                zero_page(byte, label),
+                ptr,
+                target,
+                peek(target)
+            )
+        }
+        Mode::IndirectY => {
+            let base = zp_word(byte);
+            let target = base.wrapping_add(y as u16);
+            format!(
+                " ({}),Y = {:04X} @ {:04X} = {:02X}",
+                zero_page(byte, label),
+                base,
+                target,
+                peek(target)
+            )
+        }
+        _ => operand(mode, addr, &bytes[1..], label),
+    };
+    format!("{}{}", mnemonic, resolved)
+}
+
+/**
+ * Decode the instruction at the start of `bytes`, which sits at `addr`.
+ */
+pub fn disassemble_one(bytes: &[u8], addr: u16) -> Line {
+    disassemble_one_labeled(bytes, addr, &no_labels)
+}
+
+pub fn disassemble_one_labeled(bytes: &[u8], addr: u16, label: Labels) -> Line {
+    let opcode = bytes[0];
+    match decode(opcode) {
+        Some((mnemonic, mode)) if bytes.len() > mode.operand_len() as usize => {
+            let len = 1 + mode.operand_len() as usize;
+            Line {
+                addr,
+                bytes: bytes[..len].to_vec(),
+                text: format!("{}{}", mnemonic, operand(mode, addr, &bytes[1..len], label)),
+            }
+        }
+        // unofficial, or cut off by the end of the data
+        _ => Line {
+            addr,
+            bytes: vec![opcode],
+            text: format!(".byte ${:02X}", opcode),
+        },
+    }
+}
+
+/**
+ * Linear sweep over `bytes` loaded at `origin`. Data gets decoded as code
+ * too, there's no telling them apart without running the game.
+ */
+pub fn disassemble(bytes: &[u8], origin: u16) -> Vec<Line> {
+    disassemble_labeled(bytes, origin, &no_labels)
+}
+
+pub fn disassemble_labeled(bytes: &[u8], origin: u16, label: Labels) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let addr = origin.wrapping_add(offset as u16);
+        let line = disassemble_one_labeled(&bytes[offset..], addr, label);
+        offset += line.bytes.len();
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod disasm_test {
+    use super::{
+        decode, disassemble, disassemble_labeled, jump_target, no_labels, trace_text, Mode,
+    };
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(0xa9), Some(("LDA", Mode::Immediate)));
+        assert_eq!(decode(0x91), Some(("STA", Mode::IndirectY)));
+        assert_eq!(decode(0x7d), Some(("ADC", Mode::AbsoluteX)));
+        assert_eq!(decode(0xbe), Some(("LDX", Mode::AbsoluteY)));
+        assert_eq!(decode(0x89), None);
+        assert_eq!(decode(0x02), None);
+        // every official opcode
+        assert_eq!((0..=255).filter_map(decode).count(), 151);
+    }
+
+    #[test]
+    fn test_jump_target() {
+        assert_eq!(jump_target(&[0xd0, 0xfb], 0xc003), Some(0xc000));
+        assert_eq!(jump_target(&[0x10, 0x04], 0xc003), Some(0xc009));
+        assert_eq!(jump_target(&[0x20, 0xf5, 0xc5], 0xc000), Some(0xc5f5));
+        assert_eq!(jump_target(&[0x6c, 0xff, 0x02], 0xc000), None);
+        assert_eq!(jump_target(&[0xad, 0x00, 0x02], 0xc000), None);
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let program = [
+            0x4c, 0xf5, 0xc5, // JMP $C5F5
+            0xd0, 0xfb, // BNE back to the JMP
+            0xb1, 0x10, // LDA ($10),Y
+            0x0a, // ASL A
+            0x02, // not an instruction
+            0xad, 0x00, // cut off
+        ];
+        let lines: Vec<String> = disassemble(&program, 0xc000)
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "$C000  4C F5 C5  JMP $C5F5",
+                "$C003  D0 FB     BNE $C000",
+                "$C005  B1 10     LDA ($10),Y",
+                "$C007  0A        ASL A",
+                "$C008  02        .byte $02",
+                "$C009  AD        .byte $AD",
+                "$C00A  00        BRK",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trace_text() {
+        let mut memory = [0u8; 0x10000];
+        memory[0x80..0x82].copy_from_slice(&[0x00, 0x02]);
+        memory[0x0200] = 0x5a;
+        memory[0x0205] = 0x89;
+        memory[0x15] = 0x33;
+        // JMP ($02FF) reads its high byte from $0200
+        memory[0x02ff] = 0x7e;
+        let peek = |addr: u16| memory[addr as usize];
+        let trace = |bytes: &[u8]| trace_text(bytes, 0xc000, 0x05, 0x05, peek, &no_labels);
+        assert_eq!(trace(&[0xa9, 0x10]), "LDA #$10");
+        assert_eq!(trace(&[0x85, 0x80]), "STA $80 = 00");
+        assert_eq!(trace(&[0xb5, 0x10]), "LDA $10,X @ 15 = 33");
+        assert_eq!(trace(&[0xad, 0x00, 0x02]), "LDA $0200 = 5A");
+        assert_eq!(trace(&[0xbd, 0x00, 0x02]), "LDA $0200,X @ 0205 = 89");
+        assert_eq!(trace(&[0xa1, 0x7b]), "LDA ($7B,X) @ 80 = 0200 = 5A");
+        assert_eq!(trace(&[0xb1, 0x80]), "LDA ($80),Y = 0200 @ 0205 = 89");
+        assert_eq!(trace(&[0x6c, 0xff, 0x02]), "JMP ($02FF) = 5A7E");
+        assert_eq!(trace(&[0x20, 0xf5, 0xc5]), "JSR $C5F5");
+        assert_eq!(trace(&[0x4a]), "LSR A");
+    }
+
+    #[test]
+    fn test_labels() {
+        let label = |addr: u16| match addr {
+            0x10 => Some("ptr".to_string()),
+            0x0200 => Some("buffer".to_string()),
+            0xc000 => Some("Reset".to_string()),
+            _ => None,
+        };
+        let program = [
+            0xb1, 0x10, // LDA (ptr),Y
+            0x8d, 0x00, 0x02, // STA buffer
+            0xd0, 0xf9, // BNE Reset
+        ];
+        let lines: Vec<String> = disassemble_labeled(&program, 0xc000, &label)
+            .iter()
+            .map(|line| line.text.clone())
+            .collect();
+        assert_eq!(lines, ["LDA (ptr),Y", "STA buffer", "BNE Reset"]);
+        let peek = |addr: u16| if addr == 0x0200 { 0x5a } else { 0 };
+        assert_eq!(
+            trace_text(&[0xbd, 0x00, 0x02], 0xc000, 0, 0, peek, &label),
+            "LDA buffer,X @ 0200 = 5A"
+        );
+    }
+}
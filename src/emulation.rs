@@ -0,0 +1,284 @@
+use std::{
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender, TryRecvError},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{controller::Buttons, machine::Nes, ppu::Frame};
+
+// frames waiting for the frontend, past that they're dropped
+const FRAME_QUEUE: usize = 2;
+
+/**
+ * Something for the emulation thread to do. They're picked up between
+ * frames, never in the middle of one.
+ */
+pub enum Command {
+    SetController(usize, Buttons),
+    // stops between two frames until `Resume`
+    Pause,
+    Resume,
+    // a frame while paused
+    Step,
+    PowerCycle,
+    SoftReset,
+    // frames a second as a multiple of the console's, None is as fast as it
+    // goes
+    SetSpeed(Option<f64>),
+    // anything else, on the emulation thread with the machine to itself
+    Run(Box<dyn FnOnce(&mut Nes) + Send>),
+    Quit,
+}
+
+/**
+ * The console running on a thread of its own, so a window that's slow to
+ * present or busy with events doesn't hold up the game or its sound. Input
+ * and commands go in over one channel and frames come out of another.
+ *
+ * The machine is built on the thread and never leaves it, the sinks it's
+ * given there (sound especially) are fed from there too. Frames the frontend
+ * doesn't take in time are dropped, only the newest matter.
+ *
+ * Only embedders use this so far, the bundled frontends still run the
+ * machine on the window's thread (see the readme).
+ */
+pub struct EmulationThread {
+    commands: Sender<Command>,
+    frames: Receiver<Frame>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl EmulationThread {
+    /**
+     * Start running what `build` makes at normal speed.
+     */
+    pub fn spawn<F: FnOnce() -> Nes + Send + 'static>(build: F) -> EmulationThread {
+        let (commands, incoming) = mpsc::channel();
+        let (sender, frames) = mpsc::sync_channel(FRAME_QUEUE);
+        let thread = thread::spawn(move || Emulation::new(build(), incoming, sender).run());
+        EmulationThread {
+            commands,
+            frames,
+            thread: Some(thread),
+        }
+    }
+
+    /**
+     * Nothing happens once the thread's gone, the machine went with it.
+     */
+    pub fn send(&self, command: Command) {
+        let _ = self.commands.send(command);
+    }
+
+    pub fn set_controller(&self, player: usize, buttons: Buttons) {
+        self.send(Command::SetController(player, buttons))
+    }
+
+    /**
+     * Call `f` on the emulation thread between frames and wait for what it
+     * returns, for a debugger to look around. None when the thread's gone.
+     */
+    pub fn with_nes<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Nes) -> T + Send + 'static,
+    ) -> Option<T> {
+        let (reply, result) = mpsc::channel();
+        self.send(Command::Run(Box::new(move |nes| {
+            let _ = reply.send(f(nes));
+        })));
+        result.recv().ok()
+    }
+
+    /**
+     * The frames as they're finished.
+     */
+    pub fn frames(&self) -> &Receiver<Frame> {
+        &self.frames
+    }
+
+    /**
+     * The newest frame since the last call, if there's been one.
+     */
+    pub fn latest_frame(&self) -> Option<Frame> {
+        self.frames.try_iter().last()
+    }
+}
+
+impl Drop for EmulationThread {
+    fn drop(&mut self) {
+        self.send(Command::Quit);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// the thread's side
+struct Emulation {
+    nes: Nes,
+    commands: Receiver<Command>,
+    frames: SyncSender<Frame>,
+    paused: bool,
+    steps: usize,
+    speed: Option<f64>,
+    next: Instant,
+}
+
+impl Emulation {
+    fn new(nes: Nes, commands: Receiver<Command>, frames: SyncSender<Frame>) -> Emulation {
+        Emulation {
+            nes,
+            commands,
+            frames,
+            paused: false,
+            steps: 0,
+            speed: Some(1.0),
+            next: Instant::now(),
+        }
+    }
+
+    fn run(mut self) {
+        loop {
+            // everything that's come in, waiting for more while paused
+            loop {
+                let command = if self.paused && self.steps == 0 {
+                    self.commands.recv().map_err(|_| TryRecvError::Disconnected)
+                } else {
+                    self.commands.try_recv()
+                };
+                match command {
+                    Ok(Command::Quit) | Err(TryRecvError::Disconnected) => return,
+                    Ok(command) => self.handle(command),
+                    Err(TryRecvError::Empty) => break,
+                }
+            }
+            self.steps = self.steps.saturating_sub(1);
+            // halted on an opcode it doesn't have, the frames go on and it's
+            // for the frontend to ask (`Nes::halted`)
+            let _ = self.nes.run_frame();
+            let frame = self.nes.frame();
+            if let Err(mpsc::TrySendError::Disconnected(_)) = self.frames.try_send(frame.clone()) {
+                return;
+            }
+            if !self.wait() {
+                return;
+            }
+        }
+    }
+
+    fn handle(&mut self, command: Command) {
+        match command {
+            Command::SetController(player, buttons) => self.nes.set_controller(player, buttons),
+            Command::Pause => self.paused = true,
+            Command::Resume => {
+                self.paused = false;
+                self.next = Instant::now()
+            }
+            Command::Step => self.steps += 1,
+            Command::PowerCycle => self.nes.power_cycle(),
+            Command::SoftReset => self.nes.soft_reset(),
+            Command::SetSpeed(speed) => {
+                self.speed = speed;
+                self.next = Instant::now()
+            }
+            Command::Run(f) => f(&mut self.nes),
+            Command::Quit => (),
+        }
+    }
+
+    /**
+     * Until it's time for the next frame, listening for commands meanwhile.
+     * The schedule starts over when it falls more than a frame behind rather
+     * than running fast to catch up. False once it's time to stop.
+     */
+    fn wait(&mut self) -> bool {
+        let Some(speed) = self.speed else {
+            return true;
+        };
+        // the region can change between frames
+        let frame_time = Duration::from_secs_f64(1.0 / (self.nes.region().frame_rate() * speed));
+        if self.paused {
+            return true;
+        }
+        self.next += frame_time;
+        let now = Instant::now();
+        if now > self.next + frame_time {
+            self.next = now;
+            return true;
+        }
+        while let Some(timeout) = self.next.checked_duration_since(Instant::now()) {
+            match self.commands.recv_timeout(timeout) {
+                Ok(Command::Quit) | Err(RecvTimeoutError::Disconnected) => return false,
+                Ok(command) => self.handle(command),
+                Err(RecvTimeoutError::Timeout) => break,
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod emulation_test {
+    use std::{sync::mpsc::TryRecvError, thread, time::Duration};
+
+    use crate::{
+        cartridge::{Cartridge, Mirroring},
+        controller::Buttons,
+        machine::Nes,
+    };
+
+    use super::{Command, EmulationThread};
+
+    // keeps whatever's held on controller 1 at $10, each frame
+    fn nes() -> Nes {
+        let mut prgrom = vec![0; 0x4000];
+        let program = [
+            0xa9, 0x80, 0x8d, 0x00, 0x20, // $8000: LDA #$80, STA $2000
+            0x4c, 0x05, 0x80, // $8005: JMP $8005
+            0xa9, 0x01, 0x8d, 0x16, 0x40, // $8008 NMI: LDA #$01, STA $4016
+            0xa9, 0x00, 0x8d, 0x16, 0x40, // LDA #$00, STA $4016
+            0xad, 0x16, 0x40, 0x29, 0x01, // LDA $4016, AND #$01
+            0x85, 0x10, 0x40, // STA $10, RTI
+        ];
+        prgrom[..program.len()].copy_from_slice(&program);
+        prgrom[0x3ffa..].copy_from_slice(&[0x08, 0x80, 0x00, 0x80, 0x00, 0x80]);
+        let mut nes = Nes::new();
+        nes.insert_cartridge(Cartridge {
+            prgrom,
+            mirroring: Mirroring::Horizontal,
+            ..Default::default()
+        })
+        .unwrap();
+        nes
+    }
+
+    #[test]
+    fn test_emulation_thread() {
+        let emulation = EmulationThread::spawn(nes);
+        emulation.send(Command::SetSpeed(None));
+        emulation.set_controller(0, Buttons::A);
+        for _ in 0..3 {
+            emulation.frames().recv().unwrap();
+        }
+        assert_eq!(emulation.with_nes(|nes| nes.cpu().peek(0x10)), Some(1));
+
+        // nothing more once it's paused and what was made before is taken
+        emulation.send(Command::Pause);
+        emulation.with_nes(|_| ());
+        while emulation.latest_frame().is_some() {}
+        thread::sleep(Duration::from_millis(50));
+        assert!(matches!(
+            emulation.frames().try_recv(),
+            Err(TryRecvError::Empty)
+        ));
+
+        emulation.set_controller(0, Buttons::empty());
+        emulation.send(Command::Step);
+        emulation.frames().recv().unwrap();
+        assert_eq!(emulation.with_nes(|nes| nes.cpu().peek(0x10)), Some(0));
+        assert!(emulation.latest_frame().is_none());
+
+        emulation.send(Command::Resume);
+        emulation.frames().recv().unwrap();
+    }
+}
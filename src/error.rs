@@ -0,0 +1,37 @@
+use thiserror::Error;
+
+use crate::{
+    cartridge::CartridgeError, cpu::UnsupportedOpcode, fds::FdsError, machine::BuildError,
+    mapper::UnsupportedMapper, savestate::StateError,
+};
+#[cfg(feature = "std")]
+use crate::{config::ConfigError, movie::MovieError};
+
+/**
+ * Everything the library can fail with, for a frontend that would rather
+ * handle one type than one a module. The modules' own errors are kept as
+ * they are and say what went wrong, this just wraps them.
+ */
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Cartridge(#[from] CartridgeError),
+    #[error(transparent)]
+    Mapper(#[from] UnsupportedMapper),
+    #[error(transparent)]
+    Opcode(#[from] UnsupportedOpcode),
+    #[error(transparent)]
+    Disk(#[from] FdsError),
+    #[error(transparent)]
+    State(#[from] StateError),
+    #[error(transparent)]
+    Build(#[from] BuildError),
+    #[cfg(feature = "std")]
+    #[error(transparent)]
+    Movie(#[from] MovieError),
+    #[cfg(feature = "std")]
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
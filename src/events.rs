@@ -0,0 +1,185 @@
+use alloc::{vec, vec::Vec};
+
+use crate::ppu::Frame;
+
+// a dot for every PPU cycle, a row for every scanline, pre-render included
+pub const WIDTH: usize = 341;
+pub const HEIGHT: usize = 262;
+
+// the picture underneath is dimmed to this much so the events stand out
+const DIM: u16 = 3;
+
+/**
+ * Which register an event hit, for sorting and coloring them.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EventKind {
+    // $2000 to $2007, mirrors folded down
+    Ppu(u8),
+    OamDma,
+    Apu,
+    Controller,
+    // writes to cartridge space outside PRG-RAM
+    Mapper,
+}
+
+impl EventKind {
+    /**
+     * What counts as an event: PPU, APU and controller registers, and
+     * writes that go to the mapper. None for everything else.
+     */
+    pub fn of(addr: u16, write: bool) -> Option<EventKind> {
+        match addr {
+            0x2000..=0x3fff => Some(EventKind::Ppu((addr & 0x07) as u8)),
+            0x4014 if write => Some(EventKind::OamDma),
+            0x4016 => Some(EventKind::Controller),
+            0x4017 if !write => Some(EventKind::Controller),
+            0x4000..=0x4017 if write => Some(EventKind::Apu),
+            0x4015 => Some(EventKind::Apu),
+            0x4020..=0x5fff | 0x8000..=0xffff if write => Some(EventKind::Mapper),
+            _ => None,
+        }
+    }
+
+    pub fn color(self) -> (u8, u8, u8) {
+        match self {
+            EventKind::Ppu(0) => (0xff, 0x40, 0x40),
+            EventKind::Ppu(1) => (0xff, 0xa0, 0x20),
+            EventKind::Ppu(2) => (0xff, 0xff, 0x40),
+            EventKind::Ppu(3 | 4) => (0x40, 0xff, 0xff),
+            EventKind::Ppu(5) => (0x40, 0xff, 0x40),
+            EventKind::Ppu(6) => (0x40, 0x80, 0xff),
+            EventKind::Ppu(_) => (0xff, 0x40, 0xff),
+            EventKind::OamDma => (0xff, 0xff, 0xff),
+            EventKind::Apu => (0xa0, 0xa0, 0xa0),
+            EventKind::Controller => (0xc0, 0x80, 0x40),
+            EventKind::Mapper => (0xff, 0x90, 0xc0),
+        }
+    }
+}
+
+/**
+ * A register access and where the PPU was when it happened. The PPU catches
+ * up after each instruction, so that's as of the start of the instruction.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Event {
+    pub addr: u16,
+    pub value: u8,
+    pub write: bool,
+    pub scanline: usize,
+    pub dot: usize,
+    pub kind: EventKind,
+}
+
+/**
+ * Register accesses for the frame being run and the last one finished.
+ */
+#[derive(Clone)]
+pub struct EventLog {
+    current: Vec<Event>,
+    last: Vec<Event>,
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        EventLog::new()
+    }
+}
+
+impl EventLog {
+    pub fn new() -> EventLog {
+        EventLog {
+            current: Vec::new(),
+            last: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, addr: u16, value: u8, write: bool, scanline: usize, dot: usize) {
+        if let Some(kind) = EventKind::of(addr, write) {
+            self.current.push(Event {
+                addr,
+                value,
+                write,
+                scanline,
+                dot,
+                kind,
+            })
+        }
+    }
+
+    pub fn start_frame(&mut self) {
+        self.last = core::mem::take(&mut self.current)
+    }
+
+    /**
+     * The last whole frame's events, in the order they happened.
+     */
+    pub fn events(&self) -> &[Event] {
+        &self.last
+    }
+
+    /**
+     * The last frame as a WIDTH x HEIGHT RGB24 image: the picture, dimmed,
+     * where it's drawn and a dot in the event's color for every event.
+     */
+    pub fn render(&self, frame: &Frame) -> Vec<u8> {
+        let mut pixels = vec![0; WIDTH * HEIGHT * 3];
+        let picture = frame.data();
+        for y in 0..Frame::HEIGHT {
+            for x in 0..Frame::WIDTH {
+                // dot 0 is idle, pixels come out from dot 1
+                let to = (y * WIDTH + x + 1) * 3;
+                let from = (y * Frame::WIDTH + x) * 3;
+                for c in 0..3 {
+                    pixels[to + c] = (picture[from + c] as u16 / DIM) as u8
+                }
+            }
+        }
+        for event in &self.last {
+            let (r, g, b) = event.kind.color();
+            let at = (event.scanline.min(HEIGHT - 1) * WIDTH + event.dot.min(WIDTH - 1)) * 3;
+            pixels[at..at + 3].copy_from_slice(&[r, g, b]);
+        }
+        pixels
+    }
+}
+
+#[cfg(test)]
+mod events_test {
+    use crate::ppu::Frame;
+
+    use super::{EventKind, EventLog, WIDTH};
+
+    #[test]
+    fn test_kinds() {
+        assert_eq!(EventKind::of(0x2005, true), Some(EventKind::Ppu(5)));
+        // mirrors
+        assert_eq!(EventKind::of(0x3ffe, true), Some(EventKind::Ppu(6)));
+        assert_eq!(EventKind::of(0x4014, true), Some(EventKind::OamDma));
+        assert_eq!(EventKind::of(0x4017, true), Some(EventKind::Apu));
+        assert_eq!(EventKind::of(0x4017, false), Some(EventKind::Controller));
+        assert_eq!(EventKind::of(0x8000, true), Some(EventKind::Mapper));
+        // code and data aren't events
+        assert_eq!(EventKind::of(0x8000, false), None);
+        assert_eq!(EventKind::of(0x6000, true), None);
+        assert_eq!(EventKind::of(0x0300, true), None);
+    }
+
+    #[test]
+    fn test_log() {
+        let mut log = EventLog::new();
+        log.record(0x2006, 0x20, true, 100, 250);
+        log.record(0x0000, 0x00, true, 100, 260);
+        assert!(log.events().is_empty());
+        log.start_frame();
+        assert_eq!(log.events().len(), 1);
+        assert_eq!(log.events()[0].kind, EventKind::Ppu(6));
+
+        let pixels = log.render(&Frame::new());
+        let at = (100 * WIDTH + 250) * 3;
+        assert_eq!(pixels[at..at + 3], [0x40, 0x80, 0xff]);
+        log.start_frame();
+        assert!(log.events().is_empty());
+    }
+}
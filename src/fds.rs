@@ -0,0 +1,237 @@
+use alloc::{vec, vec::Vec};
+use core::fmt;
+#[cfg(feature = "std")]
+use std::{fs, io, path::Path};
+
+// fwNES header, "FDS" followed by MS-DOS end of file
+const FDS_TAG: [u8; 4] = [0x46, 0x44, 0x53, 0x1a];
+const FDS_HEADER_SIZE: usize = 16;
+pub const DISK_SIDE_SIZE: usize = 65500;
+// every side starts with a disk info block reading "*NINTENDO-HVC*"
+const DISK_VERIFICATION: &[u8] = b"\x01*NINTENDO-HVC*";
+pub const BIOS_SIZE: usize = 0x2000;
+
+// Gaps the drive sees between blocks that the .fds format leaves out
+const LEADING_GAP: usize = 28300 / 8;
+const BLOCK_GAP: usize = 976 / 8;
+const GAP_END: u8 = 0x80;
+// .fds images don't store CRCs, the drive reports them as good regardless
+const FAKE_CRC: [u8; 2] = [0x4d, 0x62];
+
+#[derive(Debug)]
+pub enum FdsError {
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    // size isn't a multiple of a disk side or a side is missing its header block
+    BadImage,
+    BadBios {
+        size: usize,
+    },
+}
+
+impl fmt::Display for FdsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            FdsError::Io(e) => write!(f, "Unable to read disk: {}", e),
+            FdsError::BadImage => write!(f, "File is not a Famicom Disk System image."),
+            FdsError::BadBios { size } => write!(
+                f,
+                "Disk System BIOS should be {} bytes but is {}.",
+                BIOS_SIZE, size
+            ),
+        }
+    }
+}
+
+impl core::error::Error for FdsError {}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for FdsError {
+    fn from(e: io::Error) -> Self {
+        FdsError::Io(e)
+    }
+}
+
+/**
+ * A .fds disk image: one or more 65500 byte sides of raw block data.
+ */
+pub struct DiskImage {
+    pub sides: Vec<Vec<u8>>,
+}
+
+impl DiskImage {
+    #[cfg(feature = "std")]
+    pub fn load(path: &str) -> Result<DiskImage, FdsError> {
+        DiskImage::from_bytes(&fs::read(path)?)
+    }
+    /**
+     * Prefers the save file written by `save` over the original image so
+     * anything the game wrote to disk is still there next time.
+     */
+    #[cfg(feature = "std")]
+    pub fn load_with_save(path: &str) -> Result<DiskImage, FdsError> {
+        let save = save_path(path);
+        if Path::new(&save).exists() {
+            DiskImage::load(&save)
+        } else {
+            DiskImage::load(path)
+        }
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Result<DiskImage, FdsError> {
+        let data = if bytes.starts_with(&FDS_TAG) {
+            &bytes[FDS_HEADER_SIZE.min(bytes.len())..]
+        } else {
+            bytes
+        };
+        if data.is_empty() || data.len() % DISK_SIDE_SIZE != 0 {
+            return Err(FdsError::BadImage);
+        }
+        let sides: Vec<Vec<u8>> = data.chunks(DISK_SIDE_SIZE).map(|s| s.to_vec()).collect();
+        if sides.iter().any(|s| !s.starts_with(DISK_VERIFICATION)) {
+            return Err(FdsError::BadImage);
+        }
+        Ok(DiskImage { sides })
+    }
+    // Headerless .fds, which is what most tools expect
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.sides.concat()
+    }
+}
+
+#[cfg(feature = "std")]
+pub fn save_path(rom_path: &str) -> String {
+    format!("{}.sav", rom_path)
+}
+
+/**
+ * Write a disk the game has modified next to the rom, `load_with_save` picks
+ * it back up.
+ */
+#[cfg(feature = "std")]
+pub fn write_save(rom_path: &str, data: &[u8]) -> io::Result<()> {
+    fs::write(save_path(rom_path), data)
+}
+
+#[cfg(feature = "std")]
+pub fn load_bios(path: &str) -> Result<Vec<u8>, FdsError> {
+    let bios = fs::read(path)?;
+    check_bios(&bios)?;
+    Ok(bios)
+}
+
+/**
+ * A BIOS is all of the Disk System's 8KB boot ROM, nothing else will do.
+ */
+pub fn check_bios(bios: &[u8]) -> Result<(), FdsError> {
+    if bios.len() != BIOS_SIZE {
+        return Err(FdsError::BadBios { size: bios.len() });
+    }
+    Ok(())
+}
+
+/**
+ * Length of the block starting at `side[idx]`, None once we run off the end
+ * of the valid blocks.
+ */
+fn block_len(side: &[u8], idx: usize) -> Option<usize> {
+    match side.get(idx)? {
+        // disk info
+        1 => Some(56),
+        // file amount
+        2 => Some(2),
+        // file header
+        3 => Some(16),
+        // file data, size comes from the preceding file header
+        4 if idx >= 3 => Some(1 + side[idx - 3] as usize + ((side[idx - 2] as usize) << 8)),
+        _ => None,
+    }
+}
+
+/**
+ * Lay a side out the way the drive head sees it, gaps and CRCs included.
+ */
+pub fn add_gaps(side: &[u8]) -> Vec<u8> {
+    let mut gapped = vec![0; LEADING_GAP];
+    let mut idx = 0;
+    while let Some(len) = block_len(side, idx) {
+        if idx + len > side.len() {
+            break;
+        }
+        gapped.push(GAP_END);
+        gapped.extend_from_slice(&side[idx..idx + len]);
+        gapped.extend_from_slice(&FAKE_CRC);
+        gapped.extend(core::iter::repeat_n(0, BLOCK_GAP));
+        idx += len;
+    }
+    gapped.resize(gapped.len().max(DISK_SIDE_SIZE + LEADING_GAP), 0);
+    gapped
+}
+
+/**
+ * Inverse of `add_gaps`, walks the blocks the game may have rewritten.
+ */
+pub fn strip_gaps(gapped: &[u8]) -> Vec<u8> {
+    let mut side = Vec::with_capacity(DISK_SIDE_SIZE);
+    let mut idx = 0;
+    // skip the gap up to and including its end marker
+    while let Some(offset) = gapped[idx..].iter().position(|b| *b == GAP_END) {
+        idx += offset + 1;
+        // block length depends on what we've already copied for file data
+        let start = side.len();
+        side.push(gapped.get(idx).copied().unwrap_or(0));
+        let len = match block_len(&side, start) {
+            Some(len) if idx + len <= gapped.len() => len,
+            _ => {
+                side.pop();
+                break;
+            }
+        };
+        side.extend_from_slice(&gapped[idx + 1..idx + len]);
+        idx += len + FAKE_CRC.len();
+    }
+    side.resize(DISK_SIDE_SIZE, 0);
+    side
+}
+
+#[cfg(test)]
+mod fds_test {
+    use super::{add_gaps, strip_gaps, DiskImage, DISK_SIDE_SIZE, DISK_VERIFICATION};
+
+    fn make_side() -> Vec<u8> {
+        let mut side = DISK_VERIFICATION.to_vec();
+        side.resize(56, 0);
+        // one file of 3 bytes
+        side.extend([2, 1]);
+        let mut header = vec![3, 0, 0];
+        header.extend(b"FILENAME");
+        header.extend([0x00, 0x60, 3, 0, 0]);
+        side.extend(header);
+        side.extend([4, 0xaa, 0xbb, 0xcc]);
+        side.resize(DISK_SIDE_SIZE, 0);
+        side
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let mut image = vec![0x46, 0x44, 0x53, 0x1a, 2];
+        image.resize(16, 0);
+        image.extend(make_side());
+        image.extend(make_side());
+        let disk = DiskImage::from_bytes(&image).unwrap();
+        assert_eq!(disk.sides.len(), 2);
+        assert_eq!(disk.to_bytes().len(), 2 * DISK_SIDE_SIZE);
+
+        assert!(DiskImage::from_bytes(&image[..1000]).is_err());
+    }
+
+    #[test]
+    fn test_gaps_round_trip() {
+        let side = make_side();
+        let gapped = add_gaps(&side);
+        // gap, marker, then the disk info block
+        let start = gapped.iter().position(|b| *b == 0x80).unwrap();
+        assert_eq!(&gapped[start + 1..start + 16], DISK_VERIFICATION);
+        assert_eq!(strip_gaps(&gapped), side);
+    }
+}
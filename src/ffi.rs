@@ -0,0 +1,357 @@
+/*
+ C API over `Nes`, see include/nes.h. Everything goes through a pointer from
+ `nes_new`, which stays valid until `nes_free`. A null one is ignored by
+ everything that takes it. No panic gets out into C, where unwinding would
+ abort the host: calls that can fail return -1 and `nes_last_error` says the
+ emulator panicked, the others return what they would for a null pointer.
+*/
+use std::{
+    ffi::{c_char, c_int, CString},
+    panic::{self, AssertUnwindSafe},
+    ptr, slice,
+};
+
+use crate::{
+    cartridge::Cartridge, controller::Buttons, crashdump::panic_message, savestate::SaveState, Nes,
+};
+
+// spelled out for the header, the same as `Frame`'s and `machine::SAMPLE_RATE`
+pub const NES_WIDTH: usize = 256;
+pub const NES_HEIGHT: usize = 240;
+pub const NES_SAMPLE_RATE: u32 = 44100;
+
+/**
+ * The console and what the last call that failed said about it.
+ */
+pub struct NesHandle {
+    nes: Nes,
+    error: Option<CString>,
+}
+
+impl NesHandle {
+    fn failed(&mut self, message: String) -> c_int {
+        self.error = CString::new(message).ok();
+        -1
+    }
+}
+
+/**
+ * `f` on the console behind `handle`: 0 when it worked, -1 when it failed
+ * or panicked (or `handle` is null) with `nes_last_error` set.
+ */
+unsafe fn with_nes(handle: *mut NesHandle, f: impl FnOnce(&mut Nes) -> crate::Result<()>) -> c_int {
+    let Some(handle) = handle.as_mut() else {
+        return -1;
+    };
+    match panic::catch_unwind(AssertUnwindSafe(|| f(&mut handle.nes))) {
+        Ok(Ok(())) => 0,
+        Ok(Err(e)) => handle.failed(e.to_string()),
+        Err(panic) => handle.failed(format!(
+            "The emulator panicked: {}",
+            panic_message(panic.as_ref())
+        )),
+    }
+}
+
+// `f`, or `otherwise` when it panics
+fn guarded<T>(otherwise: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(otherwise)
+}
+
+/**
+ * A console with nothing in it, null if it couldn't be made. Free it with
+ * `nes_free`.
+ */
+#[no_mangle]
+pub extern "C" fn nes_new() -> *mut NesHandle {
+    guarded(ptr::null_mut(), || {
+        Box::into_raw(Box::new(NesHandle {
+            nes: Nes::new(),
+            error: None,
+        }))
+    })
+}
+
+/**
+ * # Safety
+ * `handle` is from `nes_new` and isn't used again.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn nes_free(handle: *mut NesHandle) {
+    if !handle.is_null() {
+        guarded((), || drop(Box::from_raw(handle)))
+    }
+}
+
+/**
+ * Put in the iNES or UNIF image in `data` and turn the power on. 0 when it
+ * worked, -1 when it didn't and `nes_last_error` says why.
+ *
+ * # Safety
+ * `data` points at `len` bytes, it isn't kept.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn nes_load_rom(
+    handle: *mut NesHandle,
+    data: *const u8,
+    len: usize,
+) -> c_int {
+    if data.is_null() {
+        return -1;
+    }
+    with_nes(handle, |nes| {
+        let cartridge = Cartridge::from_bytes(slice::from_raw_parts(data, len))?;
+        nes.insert_cartridge(cartridge)
+    })
+}
+
+/**
+ * Why the last call that returned -1 failed, null before any did. Good
+ * until the next call that fails or `nes_free`.
+ *
+ * # Safety
+ * `handle` is from `nes_new`.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn nes_last_error(handle: *const NesHandle) -> *const c_char {
+    match handle.as_ref().and_then(|handle| handle.error.as_ref()) {
+        Some(error) => error.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/**
+ * The reset button, RAM is kept. 0, or -1 if it panicked.
+ *
+ * # Safety
+ * `handle` is from `nes_new`.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn nes_reset(handle: *mut NesHandle) -> c_int {
+    with_nes(handle, |nes| {
+        nes.soft_reset();
+        Ok(())
+    })
+}
+
+/**
+ * Off and on again with the same game in. 0, or -1 if it panicked.
+ *
+ * # Safety
+ * `handle` is from `nes_new`.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn nes_power_cycle(handle: *mut NesHandle) -> c_int {
+    with_nes(handle, |nes| {
+        nes.power_cycle();
+        Ok(())
+    })
+}
+
+/**
+ * Run until the next picture's drawn. 0, or -1 when the CPU's halted on an
+ * opcode it doesn't have (the picture's still there) or the emulator
+ * panicked, `nes_last_error` says which.
+ *
+ * # Safety
+ * `handle` is from `nes_new`.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn nes_run_frame(handle: *mut NesHandle) -> c_int {
+    with_nes(handle, |nes| nes.run_frame().map(|_| ()))
+}
+
+/**
+ * The last picture, `NES_WIDTH` x `NES_HEIGHT` RGB24 pixels row by row.
+ * `len` gets the size in bytes if it isn't null. Good until the next
+ * `nes_run_frame`.
+ *
+ * # Safety
+ * `handle` is from `nes_new`, `len` is null or can be written.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn nes_framebuffer(handle: *const NesHandle, len: *mut usize) -> *const u8 {
+    let Some(handle) = handle.as_ref() else {
+        return ptr::null();
+    };
+    guarded(ptr::null(), || {
+        let data = handle.nes.frame().data();
+        if let Some(len) = len.as_mut() {
+            *len = data.len()
+        }
+        data.as_ptr()
+    })
+}
+
+/**
+ * Hold down `buttons` on controller `player` (0 is the first) until they're
+ * set again. A bit each, from the lowest: A, B, Select, Start, Up, Down,
+ * Left, Right. 0, or -1 if it panicked.
+ *
+ * # Safety
+ * `handle` is from `nes_new`.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn nes_set_input(handle: *mut NesHandle, player: u32, buttons: u8) -> c_int {
+    with_nes(handle, |nes| {
+        if player < 4 {
+            let buttons = Buttons::from_bits_truncate(buttons);
+            nes.set_controller(player as usize, buttons)
+        }
+        Ok(())
+    })
+}
+
+/**
+ * Copy up to `max` samples of the last frame's sound (mono, signed 16 bit
+ * at `NES_SAMPLE_RATE`) into `out`, returning how many there were.
+ *
+ * # Safety
+ * `handle` is from `nes_new`, `out` has room for `max` samples.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn nes_audio(handle: *const NesHandle, out: *mut i16, max: usize) -> usize {
+    let Some(handle) = handle.as_ref() else {
+        return 0;
+    };
+    if out.is_null() {
+        return 0;
+    }
+    guarded(0, || {
+        let samples = handle.nes.audio_samples();
+        let count = samples.len().min(max);
+        ptr::copy_nonoverlapping(samples.as_ptr(), out, count);
+        count
+    })
+}
+
+/**
+ * The machine as a save state, `len` bytes to hand back to
+ * `nes_load_state`, null if it panicked. Free it with `nes_free_state`.
+ *
+ * # Safety
+ * `handle` is from `nes_new`, `len` can be written.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn nes_save_state(handle: *const NesHandle, len: *mut usize) -> *mut u8 {
+    let (Some(handle), Some(len)) = (handle.as_ref(), len.as_mut()) else {
+        return ptr::null_mut();
+    };
+    guarded(ptr::null_mut(), || {
+        let bytes = handle.nes.save_state().to_bytes().into_boxed_slice();
+        *len = bytes.len();
+        Box::into_raw(bytes) as *mut u8
+    })
+}
+
+/**
+ * # Safety
+ * `state` and `len` are from `nes_save_state`, and `state` isn't used again.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn nes_free_state(state: *mut u8, len: usize) {
+    if !state.is_null() {
+        guarded((), || {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(state, len)))
+        })
+    }
+}
+
+/**
+ * 0 when the machine's back where `state` was taken, -1 with
+ * `nes_last_error` set when it's not from this game or not a save state.
+ *
+ * # Safety
+ * `handle` is from `nes_new`, `state` points at `len` bytes.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn nes_load_state(
+    handle: *mut NesHandle,
+    state: *const u8,
+    len: usize,
+) -> c_int {
+    if state.is_null() {
+        return -1;
+    }
+    with_nes(handle, |nes| {
+        nes.load_state(&SaveState::from_bytes(slice::from_raw_parts(state, len))?)
+    })
+}
+
+#[cfg(test)]
+mod ffi_test {
+    use std::{ffi::CStr, ptr};
+
+    use crate::{machine::SAMPLE_RATE, ppu::Frame};
+
+    use super::{
+        nes_audio, nes_framebuffer, nes_free, nes_free_state, nes_last_error, nes_load_rom,
+        nes_load_state, nes_new, nes_run_frame, nes_save_state, nes_set_input, with_nes,
+        NES_HEIGHT, NES_SAMPLE_RATE, NES_WIDTH,
+    };
+
+    fn rom() -> Vec<u8> {
+        let mut rom = b"NES\x1a\x01\x00\x00\x00".to_vec();
+        rom.resize(16, 0);
+        let mut prgrom = vec![0; 0x4000];
+        // $8000: JMP $8000
+        prgrom[..3].copy_from_slice(&[0x4c, 0x00, 0x80]);
+        prgrom[0x3ffc..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80]);
+        rom.extend(prgrom);
+        rom
+    }
+
+    #[test]
+    fn test_ffi() {
+        assert_eq!((NES_WIDTH, NES_HEIGHT), (Frame::WIDTH, Frame::HEIGHT));
+        assert_eq!(NES_SAMPLE_RATE, SAMPLE_RATE);
+        unsafe {
+            let nes = nes_new();
+            assert!(nes_last_error(nes).is_null());
+            assert_eq!(nes_load_rom(nes, b"junk".as_ptr(), 4), -1);
+            let error = CStr::from_ptr(nes_last_error(nes)).to_str().unwrap();
+            assert_eq!(error, "File is not in the iNES or UNIF file format.");
+
+            let rom = rom();
+            assert_eq!(nes_load_rom(nes, rom.as_ptr(), rom.len()), 0);
+            assert_eq!(nes_set_input(nes, 0, 0x09), 0);
+            assert_eq!(nes_run_frame(nes), 0);
+            let mut len = 0;
+            assert!(!nes_framebuffer(nes, &mut len).is_null());
+            assert_eq!(len, NES_WIDTH * NES_HEIGHT * 3);
+            let mut samples = [1; 1000];
+            let count = nes_audio(nes, samples.as_mut_ptr(), samples.len());
+            assert!((733..=734).contains(&count));
+            assert!(samples[..count].iter().all(|&sample| sample == 0));
+
+            let state = nes_save_state(nes, &mut len);
+            assert_eq!(nes_load_state(nes, state, len), 0);
+            assert_eq!(nes_load_state(nes, state, 3), -1);
+            nes_free_state(state, len);
+            nes_free(nes);
+            nes_free(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_failures() {
+        let error = |nes| unsafe { CStr::from_ptr(nes_last_error(nes)).to_str().unwrap() };
+        unsafe {
+            let nes = nes_new();
+            assert_eq!(nes_run_frame(ptr::null_mut()), -1);
+            // SLO in place of the JMP
+            let mut rom = rom();
+            rom[16] = 0x03;
+            assert_eq!(nes_load_rom(nes, rom.as_ptr(), rom.len()), 0);
+            assert_eq!(nes_run_frame(nes), -1);
+            assert_eq!(
+                error(nes),
+                "Opcode $03 at $8000 is not supported, the CPU has halted."
+            );
+            // a panic stays on this side
+            assert_eq!(with_nes(nes, |_| panic!("Fell over")), -1);
+            assert_eq!(error(nes), "The emulator panicked: Fell over");
+            nes_free(nes);
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+
+use crate::movie::{buttons_from_text, FrameInput, Movie, MovieError, MovieStart};
+
+pub const FM2_TAG: &str = "version 3";
+
+// FM2 input commands, the first column of every frame
+const CMD_SOFT_RESET: u32 = 1;
+const CMD_HARD_RESET: u32 = 2;
+
+// FM2 port0/port1 device types
+const SI_NONE: &str = "0";
+const SI_GAMEPAD: &str = "1";
+
+/**
+ * What's plugged into the two ports, from the header.
+ */
+struct Ports {
+    four_score: bool,
+    gamepads: [bool; 2],
+}
+
+/**
+ * Parse an FCEUX .fm2 movie. Only text input logs of standard controllers
+ * (optionally through a Four Score) starting from power on are supported.
+ *
+ * Each frame is `|commands|port0|port1|port2|`, or
+ * `|commands|p1|p2|p3|p4|port2|` with a Four Score. port2 is the Famicom
+ * expansion port which we ignore.
+ */
+pub fn parse(text: &str) -> Result<Movie, MovieError> {
+    let mut lines = text.lines().enumerate();
+    match lines.next() {
+        Some((_, line)) if line.trim() == FM2_TAG => (),
+        _ => return Err(MovieError::BadMagic),
+    }
+    let mut ports = Ports {
+        four_score: false,
+        gamepads: [true, true],
+    };
+    // FM2 identifies the rom by MD5, which we don't compute
+    let mut movie = Movie {
+        rom_crc32: None,
+        start: MovieStart::PowerOn,
+        frames: Vec::new(),
+        checksums: BTreeMap::new(),
+    };
+    for (idx, line) in lines {
+        let bad = |reason| MovieError::BadLine {
+            line: idx + 1,
+            reason,
+        };
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('|') {
+            let first_frame = movie.frames.is_empty();
+            movie
+                .frames
+                .push(parse_frame(line, &ports, first_frame).map_err(bad)?);
+            continue;
+        }
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match (key, value.trim()) {
+            ("binary", "1") => return Err(MovieError::Unsupported("a binary input log")),
+            ("palFlag", "1") => return Err(MovieError::Unsupported("PAL timing")),
+            ("savestate", _) => return Err(MovieError::Unsupported("a savestate start")),
+            ("FDS", "1") => return Err(MovieError::Unsupported("the Disk System")),
+            ("fourscore", four_score) => ports.four_score = four_score == "1",
+            ("port0", device) | ("port1", device) => {
+                let port = if key == "port0" { 0 } else { 1 };
+                ports.gamepads[port] = match device {
+                    SI_NONE => false,
+                    SI_GAMEPAD => true,
+                    _ => return Err(MovieError::Unsupported("a Zapper")),
+                }
+            }
+            // comments, subtitles, rom name/checksum, rerecord count...
+            _ => (),
+        }
+    }
+    Ok(movie)
+}
+
+fn parse_frame(line: &str, ports: &Ports, first_frame: bool) -> Result<FrameInput, &'static str> {
+    let mut columns = line.strip_prefix('|').unwrap_or(line).split('|');
+    let commands: u32 = columns
+        .next()
+        .and_then(|c| c.trim().parse().ok())
+        .ok_or("bad command column")?;
+    let mut input = FrameInput {
+        soft_reset: commands & CMD_SOFT_RESET != 0,
+        // a power cycle on the very first frame is where we start anyway
+        power_cycle: commands & CMD_HARD_RESET != 0 && !first_frame,
+        ..FrameInput::default()
+    };
+    if commands & !(CMD_SOFT_RESET | CMD_HARD_RESET) != 0 {
+        return Err("unsupported command");
+    }
+
+    let players = if ports.four_score { 4 } else { 2 };
+    for (port, buttons) in input.buttons.iter_mut().take(players).enumerate() {
+        let column = columns.next().ok_or("missing controller column")?;
+        // nothing plugged in leaves the column empty
+        if column.is_empty() || (!ports.four_score && !ports.gamepads[port]) {
+            continue;
+        }
+        *buttons = buttons_from_text(column).ok_or("bad controller column")?
+    }
+    Ok(input)
+}
+
+#[cfg(test)]
+mod fm2_test {
+    use crate::{controller::Buttons, movie::MovieError};
+
+    use super::parse;
+
+    #[test]
+    fn test_parse() {
+        let movie = parse(
+            "version 3\n\
+             emuVersion 22020\n\
+             romFilename smb\n\
+             romChecksum base64:jjYwGG411HcjG/j9UOVM3Q==\n\
+             fourscore 0\n\
+             port0 1\n\
+             port1 0\n\
+             port2 0\n\
+             comment author someone\n\
+             |2|........|||\n\
+             |0|....T...|||\n\
+             |1|R......A|||\n\
+             |2|........|||\n",
+        )
+        .unwrap();
+        assert_eq!(movie.rom_crc32, None);
+        assert_eq!(movie.frames.len(), 4);
+        assert!(!movie.frames[0].soft_reset);
+        assert!(!movie.frames[0].power_cycle);
+        assert!(movie.frames[3].power_cycle);
+        assert_eq!(movie.frames[1].buttons[0], Buttons::START);
+        assert!(movie.frames[2].soft_reset);
+        assert_eq!(movie.frames[2].buttons[0], Buttons::RIGHT | Buttons::A);
+    }
+
+    #[test]
+    fn test_four_score() {
+        let movie =
+            parse("version 3\nfourscore 1\n|0|.......A|......B.|.....S..|....T...||\n").unwrap();
+        assert_eq!(
+            movie.frames[0].buttons,
+            [Buttons::A, Buttons::B, Buttons::SELECT, Buttons::START]
+        );
+    }
+
+    #[test]
+    fn test_unsupported() {
+        assert!(matches!(
+            parse("version 3\nport1 2\n"),
+            Err(MovieError::Unsupported(_))
+        ));
+        assert!(matches!(
+            parse("version 3\n|0|........|||\n|4|........|||\n"),
+            Err(MovieError::BadLine { line: 3, .. })
+        ));
+    }
+}
@@ -0,0 +1,112 @@
+use sdl2::{
+    audio::{AudioQueue, AudioSpecDesired},
+    AudioSubsystem,
+};
+
+use nes::{config::AudioConfig, sink::AudioSink};
+
+// queue more than this many device buffers and we're running ahead
+const MAX_BUFFERS: u32 = 4;
+
+/**
+ * Names of the playback devices SDL can see, for the `device` setting.
+ */
+pub fn audio_devices() -> Result<Vec<String>, String> {
+    let audio = sdl2::init()?.audio()?;
+    let count = audio.num_audio_playback_devices().unwrap_or(0);
+    (0..count)
+        .map(|idx| audio.audio_playback_device_name(idx))
+        .collect()
+}
+
+/**
+ * Dropouts since the device was opened. An underrun is the device running
+ * dry (a crackle), an overrun is us getting too far ahead and throwing
+ * samples away (latency creeping up otherwise).
+ */
+#[derive(Clone, Copy, Default, Debug)]
+pub struct AudioStats {
+    pub underruns: u64,
+    pub overruns: u64,
+}
+
+pub struct AudioOutput {
+    queue: AudioQueue<i16>,
+    samples_per_frame: f64,
+    // leftover fraction of a sample carried to the next frame
+    credit: f64,
+    max_queued: u32,
+    started: bool,
+    stats: AudioStats,
+    samples: Vec<i16>,
+}
+
+impl AudioOutput {
+    /**
+     * `frame_rate` is the console's, for how much sound a frame makes.
+     */
+    pub fn open(
+        audio: &AudioSubsystem,
+        config: &AudioConfig,
+        frame_rate: f64,
+    ) -> Result<AudioOutput, String> {
+        let desired = AudioSpecDesired {
+            freq: Some(config.sample_rate as i32),
+            channels: Some(1),
+            samples: Some(config.buffer_frames),
+        };
+        let queue: AudioQueue<i16> = audio.open_queue(config.device.as_deref(), &desired)?;
+        let spec = queue.spec();
+        let max_queued = spec.samples as u32 * MAX_BUFFERS * std::mem::size_of::<i16>() as u32;
+        let samples_per_frame = spec.freq as f64 / frame_rate;
+        queue.resume();
+        Ok(AudioOutput {
+            queue,
+            samples_per_frame,
+            credit: 0.0,
+            max_queued,
+            started: false,
+            stats: AudioStats::default(),
+            samples: Vec::new(),
+        })
+    }
+    /**
+     * Queue the sound for `frames` emulated frames. Silence until there's an
+     * APU to make some.
+     */
+    pub fn push_frames(&mut self, frames: u32) {
+        // paused, the queue running dry isn't a dropout
+        if frames == 0 {
+            self.started = false;
+            return;
+        }
+        self.credit += self.samples_per_frame * frames as f64;
+        let count = self.credit.floor();
+        self.credit -= count;
+        let mut samples = std::mem::take(&mut self.samples);
+        samples.clear();
+        samples.resize(count as usize, 0);
+        self.samples(&samples);
+        self.samples = samples
+    }
+    pub fn stats(&self) -> AudioStats {
+        self.stats
+    }
+}
+
+impl AudioSink for AudioOutput {
+    fn samples(&mut self, samples: &[i16]) {
+        let queued = self.queue.size();
+        if self.started && queued == 0 {
+            self.stats.underruns += 1
+        }
+        if queued > self.max_queued {
+            self.stats.overruns += 1;
+            return;
+        }
+        if let Err(e) = self.queue.queue_audio(samples) {
+            eprintln!("Unable to queue audio: {}", e)
+        }
+        self.started = true
+    }
+}
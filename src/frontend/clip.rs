@@ -0,0 +1,109 @@
+use std::{collections::HashMap, collections::VecDeque, fs::File, io::BufWriter};
+
+use gif::{Encoder, EncodingError, Repeat};
+
+use nes::ppu::Frame;
+
+// keep every other frame, 30fps is plenty for a clip and halves the memory
+const FRAME_STEP: usize = 2;
+// GIF delays are in 1/100s, alternating 3 and 4 averages out to 30fps
+const DELAYS: [u16; 2] = [3, 4];
+
+/**
+ * The last few seconds of what was on screen, ready to be dumped as a GIF
+ * when something interesting (or broken) happens.
+ */
+pub struct ClipRecorder {
+    frames: VecDeque<Vec<u8>>,
+    capacity: usize,
+    skipped: usize,
+}
+
+impl ClipRecorder {
+    pub fn new(seconds: u32) -> ClipRecorder {
+        let capacity = seconds as usize * 60 / FRAME_STEP;
+        ClipRecorder {
+            frames: VecDeque::with_capacity(capacity),
+            capacity,
+            skipped: 0,
+        }
+    }
+    /**
+     * An RGB24 frame as shown, the oldest one drops off once we're full.
+     */
+    pub fn push(&mut self, pixels: &[u8]) {
+        self.skipped = (self.skipped + 1) % FRAME_STEP;
+        if self.skipped != 0 || self.capacity == 0 {
+            return;
+        }
+        let mut frame = if self.frames.len() == self.capacity {
+            self.frames.pop_front().unwrap()
+        } else {
+            Vec::with_capacity(pixels.len())
+        };
+        frame.clear();
+        frame.extend_from_slice(pixels);
+        self.frames.push_back(frame)
+    }
+    pub fn write_gif(&self, path: &str) -> Result<(), EncodingError> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = Encoder::new(file, Frame::WIDTH as u16, Frame::HEIGHT as u16, &[])?;
+        encoder.set_repeat(Repeat::Infinite)?;
+        for (idx, pixels) in self.frames.iter().enumerate() {
+            let mut frame = gif_frame(pixels);
+            frame.delay = DELAYS[idx % DELAYS.len()];
+            encoder.write_frame(&frame)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * NES frames only use a handful of colors so an exact palette nearly always
+ * fits, anything busier gets quantized.
+ */
+fn gif_frame(pixels: &[u8]) -> gif::Frame<'static> {
+    let (width, height) = (Frame::WIDTH as u16, Frame::HEIGHT as u16);
+    let mut palette: HashMap<&[u8], u8> = HashMap::new();
+    let mut indexed = Vec::with_capacity(pixels.len() / 3);
+    for rgb in pixels.chunks_exact(3) {
+        let next = palette.len();
+        if next == 256 && !palette.contains_key(rgb) {
+            return gif::Frame::from_rgb(width, height, pixels);
+        }
+        indexed.push(*palette.entry(rgb).or_insert(next as u8));
+    }
+    let mut colors = vec![0; palette.len() * 3];
+    for (rgb, idx) in palette {
+        colors[idx as usize * 3..idx as usize * 3 + 3].copy_from_slice(rgb)
+    }
+    gif::Frame::from_palette_pixels(width, height, indexed, colors, None)
+}
+
+#[cfg(test)]
+mod clip_test {
+    use nes::ppu::Frame;
+
+    use super::{gif_frame, ClipRecorder};
+
+    #[test]
+    fn test_ring_buffer() {
+        let mut clip = ClipRecorder::new(1);
+        for n in 0..100u8 {
+            clip.push(&[n; 3])
+        }
+        // 30 kept out of the last 60
+        assert_eq!(clip.frames.len(), 30);
+        assert_eq!(clip.frames.back().unwrap(), &vec![99; 3]);
+        assert_eq!(clip.frames.front().unwrap(), &vec![41; 3]);
+    }
+
+    #[test]
+    fn test_gif_frame() {
+        let mut pixels = vec![0; Frame::WIDTH * Frame::HEIGHT * 3];
+        pixels[3..6].copy_from_slice(&[0xff, 0x00, 0x00]);
+        let frame = gif_frame(&pixels);
+        assert_eq!(frame.palette.as_deref(), Some(&[0, 0, 0, 0xff, 0, 0][..]));
+        assert_eq!(&frame.buffer[..3], &[0, 1, 0]);
+    }
+}
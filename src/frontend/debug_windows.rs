@@ -0,0 +1,136 @@
+use sdl2::{
+    pixels::PixelFormatEnum,
+    render::{TextureCreator, WindowCanvas},
+    video::WindowContext,
+    VideoSubsystem,
+};
+
+use nes::{cpu::CPU, events};
+
+/**
+ * PPU viewers that get a window of their own.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DebugView {
+    Nametables,
+    Patterns,
+    Sprites,
+    Events,
+}
+
+impl DebugView {
+    fn title(self) -> &'static str {
+        match self {
+            DebugView::Nametables => "Nametables",
+            DebugView::Patterns => "Pattern tables",
+            DebugView::Sprites => "Sprites",
+            DebugView::Events => "Events",
+        }
+    }
+    // image size, then how much it's blown up on screen
+    fn size(self) -> ((u32, u32), u32) {
+        match self {
+            DebugView::Nametables => ((512, 480), 1),
+            DebugView::Patterns => ((256, 128), 2),
+            DebugView::Sprites => ((64, 128), 3),
+            DebugView::Events => ((events::WIDTH as u32, events::HEIGHT as u32), 2),
+        }
+    }
+    fn render(self, cpu: &CPU) -> Vec<u8> {
+        let ppu = cpu.ppu();
+        match self {
+            DebugView::Nametables => ppu.nametables(),
+            // side by side, the sprite palettes are more telling than the
+            // background ones for most games
+            DebugView::Patterns => {
+                let tables = [ppu.pattern_table(0, 4), ppu.pattern_table(1, 4)];
+                (0..128)
+                    .flat_map(|row| {
+                        tables
+                            .iter()
+                            .flat_map(move |table| &table[row * 128 * 3..(row + 1) * 128 * 3])
+                    })
+                    .copied()
+                    .collect()
+            }
+            DebugView::Sprites => ppu.sprites(),
+            DebugView::Events => match cpu.events() {
+                Some(events) => events.render(cpu.frame()),
+                // the first frame after opening
+                None => vec![0; events::WIDTH * events::HEIGHT * 3],
+            },
+        }
+    }
+}
+
+struct DebugWindow {
+    view: DebugView,
+    canvas: WindowCanvas,
+    creator: TextureCreator<WindowContext>,
+}
+
+/**
+ * The open viewers, redrawn once a frame.
+ */
+pub struct DebugWindows {
+    video: VideoSubsystem,
+    windows: Vec<DebugWindow>,
+}
+
+impl DebugWindows {
+    pub fn new(video: VideoSubsystem) -> DebugWindows {
+        DebugWindows {
+            video,
+            windows: Vec::new(),
+        }
+    }
+
+    pub fn toggle(&mut self, view: DebugView) -> Result<(), String> {
+        if let Some(idx) = self.windows.iter().position(|w| w.view == view) {
+            self.windows.remove(idx);
+            return Ok(());
+        }
+        let ((width, height), scale) = view.size();
+        let window = self
+            .video
+            .window(view.title(), width * scale, height * scale)
+            .build()
+            .map_err(|e| e.to_string())?;
+        let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+        let creator = canvas.texture_creator();
+        self.windows.push(DebugWindow {
+            view,
+            canvas,
+            creator,
+        });
+        Ok(())
+    }
+
+    /**
+     * False if the window isn't one of ours.
+     */
+    pub fn close(&mut self, window_id: u32) -> bool {
+        let before = self.windows.len();
+        self.windows.retain(|w| w.canvas.window().id() != window_id);
+        self.windows.len() != before
+    }
+
+    pub fn update(&mut self, cpu: &mut CPU) -> Result<(), String> {
+        // only kept while someone's looking
+        cpu.set_event_logging(self.windows.iter().any(|w| w.view == DebugView::Events));
+        for window in &mut self.windows {
+            let ((width, height), _) = window.view.size();
+            // small and only while debugging, not worth keeping around
+            let mut texture = window
+                .creator
+                .create_texture_streaming(PixelFormatEnum::RGB24, width, height)
+                .map_err(|e| e.to_string())?;
+            texture
+                .update(None, &window.view.render(cpu), width as usize * 3)
+                .map_err(|e| e.to_string())?;
+            window.canvas.copy(&texture, None, None)?;
+            window.canvas.present();
+        }
+        Ok(())
+    }
+}
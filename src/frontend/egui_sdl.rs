@@ -0,0 +1,184 @@
+use std::{collections::HashMap, time::Instant};
+
+use egui::{epaint::Primitive, ImageData, PointerButton, Pos2, RawInput, TextureId};
+use sdl2::{
+    event::Event,
+    mouse::MouseButton,
+    pixels::PixelFormatEnum,
+    rect::Rect,
+    render::{BlendMode, Texture, TextureCreator, WindowCanvas},
+    sys,
+    video::WindowContext,
+};
+
+fn pointer_button(button: MouseButton) -> Option<PointerButton> {
+    match button {
+        MouseButton::Left => Some(PointerButton::Primary),
+        MouseButton::Right => Some(PointerButton::Secondary),
+        MouseButton::Middle => Some(PointerButton::Middle),
+        _ => None,
+    }
+}
+
+/**
+ * egui drawn with the SDL renderer. Only the mouse goes to egui, the
+ * keyboard stays with the game.
+ */
+pub struct EguiSdl<'r> {
+    pub ctx: egui::Context,
+    creator: &'r TextureCreator<WindowContext>,
+    textures: HashMap<TextureId, Texture<'r>>,
+    events: Vec<egui::Event>,
+    pointer: Pos2,
+    start: Instant,
+}
+
+impl<'r> EguiSdl<'r> {
+    pub fn new(creator: &'r TextureCreator<WindowContext>) -> EguiSdl<'r> {
+        EguiSdl {
+            ctx: egui::Context::default(),
+            creator,
+            textures: HashMap::new(),
+            events: Vec::new(),
+            pointer: Pos2::ZERO,
+            start: Instant::now(),
+        }
+    }
+
+    /**
+     * Returns true when the event is egui's, a click on a menu shouldn't also
+     * fire the Zapper.
+     */
+    pub fn handle_event(&mut self, event: &Event) -> bool {
+        let modifiers = egui::Modifiers::default();
+        match *event {
+            Event::MouseMotion { x, y, .. } => {
+                self.pointer = Pos2::new(x as f32, y as f32);
+                self.events.push(egui::Event::PointerMoved(self.pointer))
+            }
+            Event::MouseButtonDown { mouse_btn, .. } | Event::MouseButtonUp { mouse_btn, .. } => {
+                if let Some(button) = pointer_button(mouse_btn) {
+                    self.events.push(egui::Event::PointerButton {
+                        pos: self.pointer,
+                        button,
+                        pressed: matches!(event, Event::MouseButtonDown { .. }),
+                        modifiers,
+                    })
+                }
+            }
+            Event::MouseWheel {
+                precise_x,
+                precise_y,
+                ..
+            } => self.events.push(egui::Event::MouseWheel {
+                unit: egui::MouseWheelUnit::Line,
+                delta: egui::vec2(precise_x, precise_y),
+                modifiers,
+            }),
+            _ => return false,
+        }
+        self.ctx.is_pointer_over_area() || self.ctx.is_using_pointer()
+    }
+
+    /**
+     * Run `ui` and draw the result over whatever is on the canvas.
+     */
+    pub fn draw(
+        &mut self,
+        canvas: &mut WindowCanvas,
+        ui: impl FnMut(&egui::Context),
+    ) -> Result<(), String> {
+        let (width, height) = canvas.output_size()?;
+        let input = RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                Pos2::ZERO,
+                egui::vec2(width as f32, height as f32),
+            )),
+            time: Some(self.start.elapsed().as_secs_f64()),
+            events: std::mem::take(&mut self.events),
+            ..Default::default()
+        };
+        let output = self.ctx.run(input, ui);
+
+        for (id, delta) in &output.textures_delta.set {
+            let ImageData::Color(image) = &delta.image;
+            let [w, h] = image.size;
+            // SDL blends straight alpha, egui hands out premultiplied
+            let bytes: Vec<u8> = image
+                .pixels
+                .iter()
+                .flat_map(|color| color.to_srgba_unmultiplied())
+                .collect();
+            let (x, y) = match delta.pos {
+                Some([x, y]) => (x as i32, y as i32),
+                None => {
+                    let mut texture = self
+                        .creator
+                        .create_texture_static(PixelFormatEnum::RGBA32, w as u32, h as u32)
+                        .map_err(|e| e.to_string())?;
+                    texture.set_blend_mode(BlendMode::Blend);
+                    self.textures.insert(*id, texture);
+                    (0, 0)
+                }
+            };
+            if let Some(texture) = self.textures.get_mut(id) {
+                texture
+                    .update(Rect::new(x, y, w as u32, h as u32), &bytes, w * 4)
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+
+        for clipped in self.ctx.tessellate(output.shapes, output.pixels_per_point) {
+            let Primitive::Mesh(mesh) = clipped.primitive else {
+                continue;
+            };
+            let Some(texture) = self.textures.get(&mesh.texture_id) else {
+                continue;
+            };
+            let clip = clipped.clip_rect;
+            canvas.set_clip_rect(Rect::new(
+                clip.min.x as i32,
+                clip.min.y as i32,
+                clip.width().max(1.0) as u32,
+                clip.height().max(1.0) as u32,
+            ));
+            let vertices: Vec<sys::SDL_Vertex> = mesh
+                .vertices
+                .iter()
+                .map(|vertex| {
+                    let [r, g, b, a] = vertex.color.to_srgba_unmultiplied();
+                    sys::SDL_Vertex {
+                        position: sys::SDL_FPoint {
+                            x: vertex.pos.x,
+                            y: vertex.pos.y,
+                        },
+                        color: sys::SDL_Color { r, g, b, a },
+                        tex_coord: sys::SDL_FPoint {
+                            x: vertex.uv.x,
+                            y: vertex.uv.y,
+                        },
+                    }
+                })
+                .collect();
+            let indices: Vec<i32> = mesh.indices.iter().map(|&idx| idx as i32).collect();
+            // SAFETY: the renderer and texture are alive for the call and the
+            // counts match the buffers, sdl2 has no safe wrapper for this yet
+            unsafe {
+                sys::SDL_RenderGeometry(
+                    canvas.raw(),
+                    texture.raw(),
+                    vertices.as_ptr(),
+                    vertices.len() as i32,
+                    indices.as_ptr(),
+                    indices.len() as i32,
+                );
+            }
+        }
+        canvas.set_clip_rect(None);
+
+        for id in &output.textures_delta.free {
+            self.textures.remove(id);
+        }
+        Ok(())
+    }
+}
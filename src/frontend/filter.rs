@@ -0,0 +1,147 @@
+use nes::{config::Filter, ppu::Frame};
+
+// every NES pixel becomes a FACTOR x FACTOR block, enough rows for a visible
+// gap between lines and enough columns for some bleed between pixels
+pub const FACTOR: usize = 3;
+const WIDTH: usize = Frame::WIDTH * FACTOR;
+const HEIGHT: usize = Frame::HEIGHT * FACTOR;
+
+// brightness of the last row of every block, in percent
+const SCANLINE: u16 = 45;
+// how far the corners are pulled in, 0 is flat
+const CURVATURE: f64 = 0.04;
+
+fn pixel(pixels: &[u8], width: usize, x: usize, y: usize) -> [u8; 3] {
+    let addr = (y * width + x) * 3;
+    [pixels[addr], pixels[addr + 1], pixels[addr + 2]]
+}
+
+/**
+ * Runs a `Filter` over RGB24 frames, keeping its buffers between frames.
+ */
+pub struct PostProcess {
+    scaled: Vec<u8>,
+    curved: Vec<u8>,
+}
+
+impl PostProcess {
+    pub fn new() -> PostProcess {
+        PostProcess {
+            scaled: Vec::new(),
+            curved: Vec::new(),
+        }
+    }
+    /**
+     * Filters a NES sized frame. Returns the picture and its width and
+     * height, which is FACTOR times the NES resolution for anything but
+     * `Filter::None`.
+     */
+    pub fn apply<'a>(&'a mut self, filter: Filter, frame: &'a [u8]) -> (&'a [u8], usize, usize) {
+        if filter == Filter::None {
+            return (frame, Frame::WIDTH, Frame::HEIGHT);
+        }
+        let blur = matches!(filter, Filter::Phosphor | Filter::Crt);
+        let scanlines = matches!(filter, Filter::Scanlines | Filter::Crt);
+        self.scaled.clear();
+        self.scaled.reserve(WIDTH * HEIGHT * 3);
+        for y in 0..HEIGHT {
+            let src_y = y / FACTOR;
+            for x in 0..WIDTH {
+                let src_x = x / FACTOR;
+                let mut rgb = pixel(frame, Frame::WIDTH, src_x, src_y);
+                // the outer columns of a block pick up half of the pixel
+                // beside them
+                if blur {
+                    let neighbour = match x % FACTOR {
+                        0 => src_x.saturating_sub(1),
+                        sub if sub == FACTOR - 1 => (src_x + 1).min(Frame::WIDTH - 1),
+                        _ => src_x,
+                    };
+                    let other = pixel(frame, Frame::WIDTH, neighbour, src_y);
+                    for (c, o) in rgb.iter_mut().zip(other) {
+                        *c = ((*c as u16 + o as u16) / 2) as u8
+                    }
+                }
+                if scanlines && y % FACTOR == FACTOR - 1 {
+                    for c in rgb.iter_mut() {
+                        *c = (*c as u16 * SCANLINE / 100) as u8
+                    }
+                }
+                self.scaled.extend_from_slice(&rgb);
+            }
+        }
+        if filter != Filter::Crt {
+            return (&self.scaled, WIDTH, HEIGHT);
+        }
+        // barrel distortion: the further from the middle, the further out
+        // the picture is sampled from, corners fall off into black
+        self.curved.clear();
+        self.curved.resize(WIDTH * HEIGHT * 3, 0);
+        for y in 0..HEIGHT {
+            let ny = y as f64 / HEIGHT as f64 * 2.0 - 1.0;
+            for x in 0..WIDTH {
+                let nx = x as f64 / WIDTH as f64 * 2.0 - 1.0;
+                let sx = nx * (1.0 + CURVATURE * ny * ny);
+                let sy = ny * (1.0 + CURVATURE * nx * nx);
+                let src_x = ((sx + 1.0) / 2.0 * WIDTH as f64).floor();
+                let src_y = ((sy + 1.0) / 2.0 * HEIGHT as f64).floor();
+                if src_x < 0.0 || src_y < 0.0 || src_x >= WIDTH as f64 || src_y >= HEIGHT as f64 {
+                    continue;
+                }
+                let rgb = pixel(&self.scaled, WIDTH, src_x as usize, src_y as usize);
+                let addr = (y * WIDTH + x) * 3;
+                self.curved[addr..addr + 3].copy_from_slice(&rgb);
+            }
+        }
+        (&self.curved, WIDTH, HEIGHT)
+    }
+}
+
+#[cfg(test)]
+mod filter_test {
+    use nes::{config::Filter, ppu::Frame};
+
+    use super::{pixel, PostProcess, FACTOR, HEIGHT, WIDTH};
+
+    #[test]
+    fn test_scanlines() {
+        let frame = vec![200; Frame::WIDTH * Frame::HEIGHT * 3];
+        let mut post = PostProcess::new();
+        let (pixels, width, height) = post.apply(Filter::Scanlines, &frame);
+        assert_eq!((width, height), (WIDTH, HEIGHT));
+        assert_eq!(pixel(pixels, width, 10, 0), [200; 3]);
+        assert_eq!(pixel(pixels, width, 10, FACTOR - 1), [90; 3]);
+    }
+
+    #[test]
+    fn test_phosphor() {
+        // a white pixel at 1, 0 on black
+        let mut frame = vec![0; Frame::WIDTH * Frame::HEIGHT * 3];
+        frame[3..6].copy_from_slice(&[0xff; 3]);
+        let mut post = PostProcess::new();
+        let (pixels, width, _) = post.apply(Filter::Phosphor, &frame);
+        // bleeds into the edges of the blocks either side, the middle is intact
+        assert_eq!(pixel(pixels, width, FACTOR - 1, 0), [0x7f; 3]);
+        assert_eq!(pixel(pixels, width, FACTOR + 1, 0), [0xff; 3]);
+        assert_eq!(pixel(pixels, width, FACTOR * 2, 0), [0x7f; 3]);
+        assert_eq!(pixel(pixels, width, FACTOR * 3 - 1, 0), [0; 3]);
+    }
+
+    #[test]
+    fn test_crt_corners() {
+        let frame = vec![0xff; Frame::WIDTH * Frame::HEIGHT * 3];
+        let mut post = PostProcess::new();
+        let (pixels, width, height) = post.apply(Filter::Crt, &frame);
+        assert_eq!(pixel(pixels, width, 0, 0), [0; 3]);
+        assert_eq!(pixel(pixels, width, width / 2, height / 2), [0xff; 3]);
+    }
+
+    #[test]
+    fn test_none_passes_through() {
+        let frame = vec![7; Frame::WIDTH * Frame::HEIGHT * 3];
+        let mut post = PostProcess::new();
+        let (pixels, width, height) = post.apply(Filter::None, &frame);
+        assert_eq!((width, height), (Frame::WIDTH, Frame::HEIGHT));
+        assert_eq!(pixels, &frame[..]);
+    }
+}
@@ -0,0 +1,603 @@
+use egui::{
+    text::LayoutJob, Color32, ColorImage, ComboBox, Context, FontId, RichText, TextFormat,
+    TextureHandle, TextureOptions,
+};
+
+use nes::{
+    cheats::CheatEdit,
+    config::{Aspect, Config, DisplayConfig, Filter},
+    cpu::CPU,
+    debugger::disassembly_view,
+    hexdump::{MemoryView, Space, ROW_BYTES},
+    movie::BUTTON_CHARS,
+    ramsearch::{Comparison, RamSearch},
+    savestate::SLOTS,
+    tas::MovieEdit,
+};
+
+use crate::binding::{Binder, Device};
+
+use super::{keymap::Hotkey, scaling::MAX_SCALE, session::Session};
+
+const COMPARISONS: [Comparison; 6] = [
+    Comparison::Equal(0),
+    Comparison::Greater,
+    Comparison::Less,
+    Comparison::ChangedBy(1),
+    Comparison::Changed,
+    Comparison::Unchanged,
+];
+
+// instructions the disassembly shows before and after PC
+const DISASSEMBLY_BEFORE: usize = 8;
+const DISASSEMBLY_AFTER: usize = 16;
+
+const FILTERS: [Filter; 4] = [
+    Filter::None,
+    Filter::Scanlines,
+    Filter::Phosphor,
+    Filter::Crt,
+];
+
+/**
+ * What the frontend has to do after a GUI frame.
+ */
+#[derive(Default)]
+pub struct GuiActions {
+    pub quit: bool,
+    // the picture size changed
+    pub resize: bool,
+}
+
+/**
+ * Writes the display settings into config.toml, leaving the rest of the
+ * file as it is.
+ */
+fn save_display(display: &DisplayConfig) -> Result<(), String> {
+    let path = Config::default_path();
+    let mut config = Config::load_or_create(&path).map_err(|e| e.to_string())?;
+    config.display = display.clone();
+    config.save(&path).map_err(|e| e.to_string())
+}
+
+/**
+ * One line of the hexdump, bytes the last frame changed in yellow.
+ */
+fn hex_row(view: &MemoryView, row: usize) -> LayoutJob {
+    let font_id = FontId::monospace(12.0);
+    let format = |color| TextFormat {
+        font_id: font_id.clone(),
+        color,
+        ..Default::default()
+    };
+    let start = row * ROW_BYTES;
+    let mut job = LayoutJob::default();
+    job.append(&format!("${:04X} ", start), 0.0, format(Color32::GRAY));
+    for (idx, byte) in view.row_bytes(row).iter().enumerate() {
+        let color = if view.changed(start + idx) {
+            Color32::YELLOW
+        } else {
+            Color32::LIGHT_GRAY
+        };
+        job.append(&format!(" {:02X}", byte), 0.0, format(color));
+    }
+    job.append(
+        &format!("  {}", view.ascii(row)),
+        0.0,
+        format(Color32::GRAY),
+    );
+    job
+}
+
+/**
+ * Menu bar, settings and debug windows. Hidden with the toggle_gui hotkey.
+ */
+pub struct Gui {
+    pub visible: bool,
+    settings: bool,
+    input: bool,
+    // asking for keys, the frontend feeds it key presses
+    pub binding: Option<Binder>,
+    cpu: bool,
+    disassembly: bool,
+    ppu: bool,
+    apu: bool,
+    memory: bool,
+    memory_view: MemoryView,
+    ram_search: bool,
+    // None until the first search
+    search: Option<RamSearch>,
+    comparison: Comparison,
+    // palette the pattern tables are drawn with, 0..=7
+    pattern_palette: u16,
+    patterns: Option<TextureHandle>,
+    cheats: bool,
+    // the cheat being typed in, and what was wrong with the last one
+    cheat_name: String,
+    cheat_code: String,
+    cheat_error: Option<String>,
+    tas: bool,
+    // the controller the piano roll shows
+    tas_port: usize,
+}
+
+impl Gui {
+    pub fn new() -> Gui {
+        Gui {
+            visible: true,
+            settings: false,
+            input: false,
+            binding: None,
+            cpu: false,
+            disassembly: false,
+            ppu: false,
+            apu: false,
+            memory: false,
+            memory_view: MemoryView::new(Space::Cpu),
+            ram_search: false,
+            search: None,
+            comparison: Comparison::Changed,
+            pattern_palette: 0,
+            patterns: None,
+            cheats: false,
+            cheat_name: String::new(),
+            cheat_code: String::new(),
+            cheat_error: None,
+            tas: false,
+            tas_port: 0,
+        }
+    }
+
+    pub fn ui(&mut self, ctx: &Context, session: &mut Session, cpu: &mut CPU) -> GuiActions {
+        let mut actions = GuiActions::default();
+        if !self.visible {
+            return actions;
+        }
+        egui::TopBottomPanel::top("menu").show(ctx, |ui| {
+            egui::MenuBar::new().ui(ui, |ui| {
+                ui.menu_button("File", |ui| {
+                    if ui.button("Save clip").clicked() {
+                        session.hotkey_down(cpu, Hotkey::SaveClip, false);
+                    }
+                    if ui.button("Quit").clicked() {
+                        actions.quit = true
+                    }
+                });
+                ui.menu_button("Emulation", |ui| {
+                    if ui.button("Pause").clicked() {
+                        session.hotkey_down(cpu, Hotkey::Pause, false);
+                    }
+                    if ui.button("Frame advance").clicked() {
+                        session.hotkey_down(cpu, Hotkey::FrameAdvance, false);
+                    }
+                    if ui.button("Reset").clicked() {
+                        cpu.soft_reset()
+                    }
+                    if ui.button("Power cycle").clicked() {
+                        cpu.power_cycle()
+                    }
+                    if cpu.disk_sides() > 0 && ui.button("Swap disk").clicked() {
+                        session.hotkey_down(cpu, Hotkey::SwapDisk, false);
+                    }
+                    ui.separator();
+                    let mut slot = session.slot();
+                    if ui
+                        .add(egui::Slider::new(&mut slot, 0..=SLOTS - 1).text("Slot"))
+                        .changed()
+                    {
+                        session.set_slot(slot)
+                    }
+                    if ui.button("Save state").clicked() {
+                        session.hotkey_down(cpu, Hotkey::SaveState, false);
+                    }
+                    if ui.button("Load state").clicked() {
+                        session.hotkey_down(cpu, Hotkey::LoadState, false);
+                    }
+                    ui.separator();
+                    ui.checkbox(&mut self.cheats, "Cheats...");
+                    ui.checkbox(&mut self.tas, "TAS editor...");
+                });
+                ui.menu_button("Settings", |ui| {
+                    ui.checkbox(&mut self.settings, "Display...");
+                    ui.checkbox(&mut self.input, "Input...");
+                });
+                ui.menu_button("Debug", |ui| {
+                    ui.checkbox(&mut self.cpu, "CPU");
+                    ui.checkbox(&mut self.disassembly, "Disassembly");
+                    ui.checkbox(&mut self.ppu, "PPU");
+                    ui.checkbox(&mut self.apu, "APU");
+                    ui.checkbox(&mut self.memory, "Memory");
+                    ui.checkbox(&mut self.ram_search, "RAM search");
+                });
+            });
+        });
+
+        let mut settings = self.settings;
+        egui::Window::new("Display")
+            .open(&mut settings)
+            .show(ctx, |ui| {
+                let display = &mut session.display;
+                let before = (display.scale, display.aspect);
+                ui.add(egui::Slider::new(&mut display.scale, 1..=MAX_SCALE).text("Scale"));
+                ui.horizontal(|ui| {
+                    ui.label("Aspect");
+                    ui.radio_value(&mut display.aspect, Aspect::Square, "1:1");
+                    ui.radio_value(&mut display.aspect, Aspect::Ntsc, "8:7");
+                });
+                ui.checkbox(&mut display.fit, "Fit to window");
+                ui.checkbox(&mut display.integer_scale, "Integer scaling");
+                ui.checkbox(&mut display.borderless, "Borderless");
+                ComboBox::from_label("Filter")
+                    .selected_text(display.filter.name())
+                    .show_ui(ui, |ui| {
+                        for filter in FILTERS {
+                            ui.selectable_value(&mut display.filter, filter, filter.name());
+                        }
+                    });
+                ui.checkbox(&mut display.input_display, "Input display");
+                ui.checkbox(&mut display.osd, "On-screen messages");
+                ui.checkbox(&mut display.show_fps, "Show FPS");
+                ui.checkbox(&mut display.show_profile, "Show profile");
+                actions.resize |= !display.fit && before != (display.scale, display.aspect);
+                if ui.button("Save").clicked() {
+                    if let Err(e) = save_display(display) {
+                        eprintln!("{}", e)
+                    }
+                }
+            });
+        self.settings = settings;
+
+        let mut input = self.input;
+        egui::Window::new("Input")
+            .open(&mut input)
+            .show(ctx, |ui| match &self.binding {
+                Some(binder) => {
+                    ui.label(binder.prompt());
+                    if ui.button("Cancel").clicked() {
+                        self.binding = None
+                    }
+                }
+                None => {
+                    ui.label("Saved to config.toml once every button has a key.");
+                    let devices = (0..4).map(Device::Controller).chain([Device::PowerPad]);
+                    for device in devices {
+                        if ui.button(format!("Bind {}", device.name())).clicked() {
+                            self.binding = Some(Binder::new(device))
+                        }
+                    }
+                }
+            });
+        // closing the window gives up on the keys
+        if !input {
+            self.binding = None
+        }
+        self.input = input;
+
+        egui::Window::new("CPU")
+            .open(&mut self.cpu)
+            .show(ctx, |ui| {
+                let regs = cpu.registers();
+                let ppu = cpu.ppu().registers();
+                ui.monospace(format!(
+                    "PC:{:04X}  A:{:02X}  X:{:02X}  Y:{:02X}\nP:{:02X}  SP:{:02X}\nCycles: {}\n\
+                     Scanline: {}  Dot: {}",
+                    regs.pc,
+                    regs.a,
+                    regs.x,
+                    regs.y,
+                    regs.p,
+                    regs.sp,
+                    regs.cycles,
+                    ppu.scanline,
+                    ppu.dot
+                ));
+                // NV-BDIZC
+                let flags: String = "NV-BDIZC"
+                    .chars()
+                    .enumerate()
+                    .map(|(bit, c)| if regs.p & (0x80 >> bit) != 0 { c } else { '.' })
+                    .collect();
+                ui.monospace(flags);
+            });
+
+        // follows PC, best read paused or a frame at a time
+        egui::Window::new("Disassembly")
+            .open(&mut self.disassembly)
+            .show(ctx, |ui| {
+                for line in disassembly_view(cpu, DISASSEMBLY_BEFORE, DISASSEMBLY_AFTER) {
+                    let color = if line.current {
+                        Color32::YELLOW
+                    } else {
+                        Color32::LIGHT_GRAY
+                    };
+                    ui.label(RichText::new(line.to_string()).monospace().color(color));
+                }
+            });
+
+        let mut cheats = self.cheats;
+        egui::Window::new("Cheats")
+            .open(&mut cheats)
+            .show(ctx, |ui| self.cheats_ui(ui, cpu));
+        self.cheats = cheats;
+
+        let mut tas = self.tas;
+        egui::Window::new("TAS editor")
+            .open(&mut tas)
+            .show(ctx, |ui| self.tas_ui(ui, session, cpu));
+        self.tas = tas;
+
+        let mut ppu = self.ppu;
+        egui::Window::new("PPU").open(&mut ppu).show(ctx, |ui| {
+            ui.add(egui::Slider::new(&mut self.pattern_palette, 0..=7).text("Palette"));
+            let mut pixels = Vec::with_capacity(256 * 128);
+            let tables = [
+                cpu.ppu().pattern_table(0, self.pattern_palette),
+                cpu.ppu().pattern_table(1, self.pattern_palette),
+            ];
+            for row in 0..128 {
+                for table in &tables {
+                    pixels.extend(
+                        table[row * 128 * 3..(row + 1) * 128 * 3]
+                            .chunks_exact(3)
+                            .map(|rgb| Color32::from_rgb(rgb[0], rgb[1], rgb[2])),
+                    )
+                }
+            }
+            let image = ColorImage::new([256, 128], pixels);
+            let texture = self.patterns.get_or_insert_with(|| {
+                ctx.load_texture("patterns", image.clone(), TextureOptions::NEAREST)
+            });
+            texture.set(image, TextureOptions::NEAREST);
+            ui.image((texture.id(), egui::vec2(512.0, 256.0)));
+            ui.label("Palette RAM");
+            let colors = cpu.ppu().palette_ram();
+            for half in colors.chunks(16) {
+                ui.horizontal(|ui| {
+                    for &(r, g, b) in half {
+                        let (rect, _) =
+                            ui.allocate_exact_size(egui::vec2(16.0, 16.0), egui::Sense::hover());
+                        ui.painter()
+                            .rect_filled(rect, 0.0, Color32::from_rgb(r, g, b));
+                    }
+                });
+            }
+        });
+        self.ppu = ppu;
+
+        let mut memory = self.memory;
+        egui::Window::new("Memory")
+            .open(&mut memory)
+            .show(ctx, |ui| {
+                let view = &mut self.memory_view;
+                let mut space = view.space;
+                ComboBox::from_label("Space")
+                    .selected_text(space.name())
+                    .show_ui(ui, |ui| {
+                        for option in Space::ALL {
+                            ui.selectable_value(&mut space, option, option.name());
+                        }
+                    });
+                view.set_space(space);
+                // ui runs once a frame, so this is what the last frame changed
+                view.update(space.read(cpu));
+                let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+                egui::ScrollArea::vertical().show_rows(ui, row_height, view.rows(), |ui, rows| {
+                    for row in rows {
+                        ui.label(hex_row(view, row));
+                    }
+                });
+            });
+        self.memory = memory;
+
+        let mut ram_search = self.ram_search;
+        egui::Window::new("RAM search")
+            .open(&mut ram_search)
+            .show(ctx, |ui| self.ram_search_ui(ui, cpu));
+        self.ram_search = ram_search;
+
+        egui::Window::new("APU")
+            .open(&mut self.apu)
+            .show(ctx, |ui| {
+                ui.label("There's no APU yet.");
+            });
+        actions
+    }
+    fn cheats_ui(&mut self, ui: &mut egui::Ui, cpu: &mut CPU) {
+        let mut edit = None;
+        for (idx, cheat) in cpu.cheats().list().iter().enumerate() {
+            ui.horizontal(|ui| {
+                let mut enabled = cheat.enabled;
+                if ui.checkbox(&mut enabled, "").changed() {
+                    edit = Some(if enabled {
+                        CheatEdit::Enable(idx)
+                    } else {
+                        CheatEdit::Disable(idx)
+                    })
+                }
+                ui.monospace(&cheat.code);
+                ui.label(&cheat.name);
+                if ui.small_button("Remove").clicked() {
+                    edit = Some(CheatEdit::Remove(idx))
+                }
+            });
+        }
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(&mut self.cheat_code).hint_text("Code"));
+            ui.add(egui::TextEdit::singleline(&mut self.cheat_name).hint_text("Name"));
+            if ui.button("Add").clicked() {
+                edit = Some(CheatEdit::Add {
+                    code: self.cheat_code.clone(),
+                    name: self.cheat_name.clone(),
+                })
+            }
+        });
+        ui.label("Game Genie, AAAA:VV or AAAA?CC:VV");
+        if let Some(edit) = edit {
+            // saved as soon as they change
+            let result = cpu.edit_cheats(|cheats| cheats.apply(&edit).and_then(|_| cheats.save()));
+            self.cheat_error = result.err().map(|e| e.to_string());
+            if self.cheat_error.is_none() && matches!(edit, CheatEdit::Add { .. }) {
+                self.cheat_code.clear();
+                self.cheat_name.clear()
+            }
+        }
+        if let Some(e) = &self.cheat_error {
+            ui.colored_label(Color32::LIGHT_RED, e);
+        }
+    }
+    /**
+     * Piano roll: a row per frame of the movie, a column per button. Lag
+     * frames are in red, the frame about to run in yellow. Clicking a frame
+     * goes back (or forward) to it, right clicking has the rest.
+     */
+    fn tas_ui(&mut self, ui: &mut egui::Ui, session: &mut Session, cpu: &mut CPU) {
+        let Some(movie) = session.movie() else {
+            ui.label("Start a movie with --record or --play to edit its input.");
+            return;
+        };
+        let tas = session.tas();
+        let current = movie.frame();
+        let frames = &movie.movie.frames;
+        let mut edit = None;
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "Frame {} of {}, {} lag frames",
+                current,
+                frames.len(),
+                tas.lag_frames()
+            ));
+            ComboBox::from_id_salt("tas_port")
+                .selected_text(format!("Player {}", self.tas_port + 1))
+                .show_ui(ui, |ui| {
+                    for port in 0..4 {
+                        ui.selectable_value(
+                            &mut self.tas_port,
+                            port,
+                            format!("Player {}", port + 1),
+                        );
+                    }
+                });
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Frame advance").clicked() {
+                session.hotkey_down(cpu, Hotkey::FrameAdvance, false);
+            }
+            if ui.button("Re-record from here").clicked() {
+                edit = Some(MovieEdit::Rerecord(current))
+            }
+        });
+        let Some(movie) = session.movie() else {
+            return;
+        };
+        let tas = session.tas();
+        let frames = &movie.movie.frames;
+        let row_height = ui.spacing().interact_size.y;
+        egui::ScrollArea::vertical().show_rows(ui, row_height, frames.len(), |ui, rows| {
+            for frame in rows {
+                ui.horizontal(|ui| {
+                    let color = match tas.lag(frame) {
+                        _ if frame == current => Color32::YELLOW,
+                        Some(true) => Color32::LIGHT_RED,
+                        Some(false) => Color32::LIGHT_GRAY,
+                        // not run since it changed
+                        None => Color32::GRAY,
+                    };
+                    let label = RichText::new(format!("{:>6}", frame))
+                        .monospace()
+                        .color(color);
+                    let row = ui.add(egui::Label::new(label).sense(egui::Sense::click()));
+                    if row.clicked() {
+                        edit = Some(MovieEdit::Seek(frame))
+                    }
+                    row.context_menu(|ui| {
+                        if ui.button("Re-record from here").clicked() {
+                            edit = Some(MovieEdit::Rerecord(frame))
+                        }
+                        if ui.button("Insert a frame").clicked() {
+                            edit = Some(MovieEdit::Insert(frame))
+                        }
+                        if ui.button("Remove the frame").clicked() {
+                            edit = Some(MovieEdit::Remove(frame))
+                        }
+                    });
+                    let buttons = frames[frame].buttons[self.tas_port];
+                    for (button, c) in BUTTON_CHARS {
+                        let text = RichText::new(c.to_string()).monospace();
+                        if ui
+                            .selectable_label(buttons.contains(button), text)
+                            .clicked()
+                        {
+                            edit = Some(MovieEdit::Toggle {
+                                frame,
+                                port: self.tas_port,
+                                button,
+                            })
+                        }
+                    }
+                });
+            }
+        });
+        if let Some(edit) = edit {
+            session.edit_movie(cpu, edit)
+        }
+    }
+    fn ram_search_ui(&mut self, ui: &mut egui::Ui, cpu: &CPU) {
+        ui.horizontal(|ui| {
+            let mut comparison = self.comparison;
+            ComboBox::from_id_salt("comparison")
+                .selected_text(comparison.name())
+                .show_ui(ui, |ui| {
+                    for option in COMPARISONS {
+                        let selected =
+                            std::mem::discriminant(&comparison) == std::mem::discriminant(&option);
+                        if ui.selectable_label(selected, option.name()).clicked() && !selected {
+                            comparison = option
+                        }
+                    }
+                });
+            match &mut comparison {
+                Comparison::Equal(value) => {
+                    ui.add(egui::DragValue::new(value).hexadecimal(2, false, true));
+                }
+                Comparison::ChangedBy(by) => {
+                    ui.add(egui::DragValue::new(by).range(-255..=255));
+                }
+                _ => (),
+            }
+            self.comparison = comparison;
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Search").clicked() {
+                self.search
+                    .get_or_insert_with(|| RamSearch::new(cpu))
+                    .search(cpu, self.comparison)
+            }
+            // starts over from the current values
+            if ui.button("Reset").clicked() {
+                self.search = Some(RamSearch::new(cpu))
+            }
+        });
+        let Some(search) = &self.search else {
+            ui.label("Play until the value changes, then search.");
+            return;
+        };
+        let candidates = search.candidates();
+        ui.label(format!(
+            "{} addresses after {} searches",
+            candidates.len(),
+            search.searches()
+        ));
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        egui::ScrollArea::vertical().show_rows(ui, row_height, candidates.len(), |ui, rows| {
+            for &addr in &candidates[rows] {
+                ui.monospace(format!(
+                    "${:04X}  {:02X} -> {:02X}",
+                    addr,
+                    search.value(addr),
+                    cpu.peek(addr)
+                ));
+            }
+        });
+    }
+}
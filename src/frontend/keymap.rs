@@ -0,0 +1,150 @@
+use std::{collections::HashMap, hash::Hash};
+
+use nes::{
+    config::{Hotkeys, InputConfig},
+    controller::Buttons,
+};
+
+/**
+ * What a key drives: a controller button or a Power Pad sensor.
+ */
+#[derive(Clone, Copy)]
+pub enum Input {
+    // controller port and button
+    Controller(usize, Buttons),
+    // sensor number, 1 to 12
+    PowerPad(usize),
+}
+
+/**
+ * Emulator controls rather than game input.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Hotkey {
+    SwapDisk,
+    TakeOver,
+    ToggleInputDisplay,
+    SaveClip,
+    ScaleUp,
+    ScaleDown,
+    ToggleAspect,
+    FastForward,
+    SlowMotion,
+    Pause,
+    FrameAdvance,
+    CycleFilter,
+    CyclePalette,
+    ToggleGui,
+    ToggleNametables,
+    TogglePatterns,
+    ToggleSprites,
+    ToggleEvents,
+    SaveState,
+    LoadState,
+    NextSlot,
+    PreviousSlot,
+}
+
+impl Hotkey {
+    fn bindings(hotkeys: &Hotkeys) -> [(Hotkey, &String); 22] {
+        [
+            (Hotkey::SwapDisk, &hotkeys.swap_disk),
+            (Hotkey::TakeOver, &hotkeys.take_over),
+            (Hotkey::ToggleInputDisplay, &hotkeys.toggle_input_display),
+            (Hotkey::SaveClip, &hotkeys.save_clip),
+            (Hotkey::ScaleUp, &hotkeys.scale_up),
+            (Hotkey::ScaleDown, &hotkeys.scale_down),
+            (Hotkey::ToggleAspect, &hotkeys.toggle_aspect),
+            (Hotkey::FastForward, &hotkeys.fast_forward),
+            (Hotkey::SlowMotion, &hotkeys.slow_motion),
+            (Hotkey::Pause, &hotkeys.pause),
+            (Hotkey::FrameAdvance, &hotkeys.frame_advance),
+            (Hotkey::CycleFilter, &hotkeys.cycle_filter),
+            (Hotkey::CyclePalette, &hotkeys.cycle_palette),
+            (Hotkey::ToggleGui, &hotkeys.toggle_gui),
+            (Hotkey::ToggleNametables, &hotkeys.toggle_nametables),
+            (Hotkey::TogglePatterns, &hotkeys.toggle_patterns),
+            (Hotkey::ToggleSprites, &hotkeys.toggle_sprites),
+            (Hotkey::ToggleEvents, &hotkeys.toggle_events),
+            (Hotkey::SaveState, &hotkeys.save_state),
+            (Hotkey::LoadState, &hotkeys.load_state),
+            (Hotkey::NextSlot, &hotkeys.next_slot),
+            (Hotkey::PreviousSlot, &hotkeys.previous_slot),
+        ]
+    }
+}
+
+/**
+ * Key to the input or hotkey it drives, built from the bindings in the config.
+ * `K` is whatever the window library calls a key, `parse` turns the SDL style
+ * names in the config into one.
+ */
+pub struct KeyMap<K> {
+    keys: HashMap<K, Input>,
+    hotkeys: HashMap<K, Hotkey>,
+}
+
+impl<K: Eq + Hash> KeyMap<K> {
+    pub fn new(
+        input: &InputConfig,
+        hotkeys: &Hotkeys,
+        parse: impl Fn(&str) -> Option<K>,
+    ) -> KeyMap<K> {
+        let mut keys = HashMap::new();
+        for (port, bindings) in input.players().into_iter().enumerate() {
+            for (button, name) in bindings.bindings() {
+                match parse(name) {
+                    Some(key) => {
+                        keys.insert(key, Input::Controller(port, button));
+                    }
+                    None => eprintln!(
+                        "Unknown key \"{}\" bound to player {} {:?}, ignoring it.",
+                        name,
+                        port + 1,
+                        button
+                    ),
+                }
+            }
+        }
+        // the mat wins over any controller sharing its keys
+        if input.power_pad {
+            for (idx, name) in input.power_pad_keys.iter().enumerate() {
+                match parse(name) {
+                    Some(key) => {
+                        keys.insert(key, Input::PowerPad(idx + 1));
+                    }
+                    None => eprintln!(
+                        "Unknown key \"{}\" bound to Power Pad button {}, ignoring it.",
+                        name,
+                        idx + 1
+                    ),
+                }
+            }
+        }
+        let mut hotkey_map = HashMap::new();
+        for (hotkey, name) in Hotkey::bindings(hotkeys) {
+            match parse(name) {
+                Some(key) => {
+                    hotkey_map.insert(key, hotkey);
+                }
+                None => eprintln!(
+                    "Unknown key \"{}\" bound to {:?}, ignoring it.",
+                    name, hotkey
+                ),
+            }
+        }
+        KeyMap {
+            keys,
+            hotkeys: hotkey_map,
+        }
+    }
+    pub fn get(&self, key: &K) -> Option<Input> {
+        self.keys.get(key).copied()
+    }
+    /**
+     * Hotkeys take priority over game input on the same key.
+     */
+    pub fn hotkey(&self, key: &K) -> Option<Hotkey> {
+        self.hotkeys.get(key).copied()
+    }
+}
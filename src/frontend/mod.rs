@@ -0,0 +1,68 @@
+// SDL is the default, winit + softbuffer is there for when the SDL
+// development libraries aren't. With both built SDL wins. With neither
+// there's only --headless.
+#[cfg(any(feature = "sdl", feature = "winit"))]
+mod clip;
+#[cfg(any(feature = "sdl", feature = "winit"))]
+mod filter;
+#[cfg(any(feature = "sdl", feature = "winit"))]
+mod keymap;
+#[cfg(any(feature = "sdl", feature = "winit"))]
+mod osd;
+#[cfg(any(feature = "sdl", feature = "winit"))]
+mod overlay;
+#[cfg(any(feature = "sdl", feature = "winit"))]
+mod pacer;
+#[cfg(any(feature = "sdl", feature = "winit"))]
+mod scaling;
+#[cfg(any(feature = "sdl", feature = "winit"))]
+mod session;
+
+#[cfg(feature = "sdl")]
+mod audio;
+#[cfg(feature = "sdl")]
+mod debug_windows;
+#[cfg(feature = "gui")]
+mod egui_sdl;
+#[cfg(feature = "gui")]
+mod gui;
+#[cfg(feature = "sdl")]
+mod sdl;
+#[cfg(all(feature = "winit", not(feature = "sdl")))]
+mod winit;
+
+#[cfg(feature = "sdl")]
+pub use audio::audio_devices;
+#[cfg(feature = "sdl")]
+pub use sdl::{bind, run};
+#[cfg(all(feature = "winit", not(feature = "sdl")))]
+pub use winit::run;
+
+#[cfg(not(any(feature = "sdl", feature = "winit")))]
+pub fn run(
+    _: &mut nes::cpu::CPU,
+    _: &nes::config::Config,
+    _: &str,
+    _: u32,
+    _: Option<&mut nes::movie::MovieSession>,
+    _: Option<&mut nes::netplay::Netplay>,
+) -> Result<(), String> {
+    Err("Built without a frontend (the \"sdl\" or \"winit\" feature), use --headless".to_string())
+}
+
+/**
+ * Binding reads key presses through SDL, which also names them the way the
+ * config does.
+ */
+#[cfg(not(feature = "sdl"))]
+pub fn bind(_: crate::binding::Device) -> Result<Option<crate::binding::Binder>, String> {
+    Err("Built without the \"sdl\" feature, edit the keys in config.toml".to_string())
+}
+
+/**
+ * Sound goes through SDL, the other frontends are silent.
+ */
+#[cfg(not(feature = "sdl"))]
+pub fn audio_devices() -> Result<Vec<String>, String> {
+    Err("Built without the \"sdl\" feature, there's no audio".to_string())
+}
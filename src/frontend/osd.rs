@@ -0,0 +1,226 @@
+use std::time::{Duration, Instant};
+
+use nes::ppu::Frame;
+
+// 3x5 glyphs, one byte per row, bit 2 is the leftmost pixel
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+// a pixel of space between characters and lines
+const ADVANCE: usize = GLYPH_WIDTH + 1;
+const LINE_HEIGHT: usize = GLYPH_HEIGHT + 1;
+const MARGIN: usize = 8;
+
+const TEXT: (u8, u8, u8) = (0xff, 0xff, 0xff);
+const SHADOW: (u8, u8, u8) = (0x00, 0x00, 0x00);
+
+// how long a message stays up, in frames shown
+const MESSAGE_FRAMES: u32 = 120;
+
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        ' ' => [0; GLYPH_HEIGHT],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '(' => [0b010, 0b100, 0b100, 0b100, 0b010],
+        ')' => [0b010, 0b001, 0b001, 0b001, 0b010],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        _ => [0b110, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+fn set_pixel(pixels: &mut [u8], x: usize, y: usize, rgb: (u8, u8, u8)) {
+    if x < Frame::WIDTH && y < Frame::HEIGHT {
+        let addr = (y * Frame::WIDTH + x) * 3;
+        pixels[addr..addr + 3].copy_from_slice(&[rgb.0, rgb.1, rgb.2]);
+    }
+}
+
+pub fn text_width(text: &str) -> usize {
+    text.chars().count() * ADVANCE
+}
+
+/**
+ * Draw `text` with its top left corner at x, y on an RGB24 frame. Every lit
+ * pixel gets a shadow below and to the right so it reads on any background.
+ */
+pub fn draw_text(pixels: &mut [u8], x: usize, y: usize, text: &str) {
+    for (idx, c) in text.chars().enumerate() {
+        let left = x + idx * ADVANCE;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> col) != 0 {
+                    set_pixel(pixels, left + col + 1, y + row + 1, SHADOW);
+                }
+            }
+        }
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> col) != 0 {
+                    set_pixel(pixels, left + col, y + row, TEXT);
+                }
+            }
+        }
+    }
+}
+
+/**
+ * Emulated frames per second, updated once a second.
+ */
+pub struct FpsCounter {
+    frames: u32,
+    since: Instant,
+    fps: f64,
+}
+
+impl FpsCounter {
+    pub fn new() -> FpsCounter {
+        FpsCounter {
+            frames: 0,
+            since: Instant::now(),
+            fps: 0.0,
+        }
+    }
+    /**
+     * Returns true when the FPS has just been updated.
+     */
+    pub fn add_frames(&mut self, frames: u32) -> bool {
+        self.frames += frames;
+        let elapsed = self.since.elapsed();
+        if elapsed < Duration::from_secs(1) {
+            return false;
+        }
+        self.fps = self.frames as f64 / elapsed.as_secs_f64();
+        self.frames = 0;
+        self.since = Instant::now();
+        true
+    }
+    pub fn fps(&self) -> f64 {
+        self.fps
+    }
+}
+
+/**
+ * On-screen display: the latest message for a couple of seconds top left,
+ * plus status (paused, fast forward...) and the FPS top right. Turned off,
+ * messages go to stdout instead.
+ */
+pub struct Osd {
+    enabled: bool,
+    message: Option<(String, u32)>,
+}
+
+impl Osd {
+    pub fn new(enabled: bool) -> Osd {
+        Osd {
+            enabled,
+            message: None,
+        }
+    }
+    /**
+     * Replaces whatever message is up.
+     */
+    pub fn show(&mut self, text: impl Into<String>) {
+        let text = text.into();
+        if self.enabled {
+            self.message = Some((text, MESSAGE_FRAMES))
+        } else {
+            println!("{}", text)
+        }
+    }
+    /**
+     * Call once per frame shown, messages time out as they're drawn.
+     */
+    pub fn draw(&mut self, pixels: &mut [u8], status: &[String]) {
+        if !self.enabled {
+            return;
+        }
+        if let Some((text, frames)) = &mut self.message {
+            draw_text(pixels, MARGIN, MARGIN, text);
+            *frames -= 1;
+            if *frames == 0 {
+                self.message = None
+            }
+        }
+        for (line, text) in status.iter().enumerate() {
+            let x = Frame::WIDTH.saturating_sub(MARGIN + text_width(text));
+            draw_text(pixels, x, MARGIN + line * LINE_HEIGHT, text)
+        }
+    }
+}
+
+#[cfg(test)]
+mod osd_test {
+    use nes::ppu::Frame;
+
+    use super::{draw_text, Osd, MESSAGE_FRAMES};
+
+    fn pixel(pixels: &[u8], x: usize, y: usize) -> (u8, u8, u8) {
+        let addr = (y * Frame::WIDTH + x) * 3;
+        (pixels[addr], pixels[addr + 1], pixels[addr + 2])
+    }
+
+    #[test]
+    fn test_draw_text() {
+        let mut pixels = vec![0x80; Frame::WIDTH * Frame::HEIGHT * 3];
+        draw_text(&mut pixels, 10, 10, "1");
+        // top middle of the 1, its shadow, and the unlit corner
+        assert_eq!(pixel(&pixels, 11, 10), (0xff, 0xff, 0xff));
+        assert_eq!(pixel(&pixels, 13, 15), (0, 0, 0));
+        assert_eq!(pixel(&pixels, 10, 10), (0x80, 0x80, 0x80));
+        // off the edge is clipped
+        draw_text(&mut pixels, Frame::WIDTH - 2, Frame::HEIGHT - 2, "W");
+    }
+
+    #[test]
+    fn test_message_expires() {
+        let mut pixels = vec![0; Frame::WIDTH * Frame::HEIGHT * 3];
+        let mut osd = Osd::new(true);
+        osd.show("State 1 saved");
+        for _ in 0..MESSAGE_FRAMES {
+            assert!(osd.message.is_some());
+            osd.draw(&mut pixels, &[]);
+        }
+        assert!(osd.message.is_none());
+    }
+}
@@ -0,0 +1,81 @@
+use nes::{controller::Buttons, ppu::Frame};
+
+type Rgb = (u8, u8, u8);
+
+const BACKGROUND: Rgb = (0x00, 0x00, 0x00);
+const RELEASED: Rgb = (0x50, 0x50, 0x50);
+const PRESSED: Rgb = (0xf8, 0x38, 0x00);
+
+// one controller, in NES pixels
+const PAD_WIDTH: usize = 32;
+const PAD_HEIGHT: usize = 11;
+const PAD_SPACING: usize = 4;
+// most TVs hide the bottom 8 lines
+const BOTTOM_MARGIN: usize = 8;
+
+// x, y, width, height inside the pad
+const BUTTON_RECTS: [(Buttons, (usize, usize, usize, usize)); 8] = [
+    (Buttons::UP, (4, 1, 3, 3)),
+    (Buttons::DOWN, (4, 7, 3, 3)),
+    (Buttons::LEFT, (1, 4, 3, 3)),
+    (Buttons::RIGHT, (7, 4, 3, 3)),
+    (Buttons::SELECT, (11, 5, 4, 2)),
+    (Buttons::START, (16, 5, 4, 2)),
+    (Buttons::B, (22, 4, 3, 3)),
+    (Buttons::A, (27, 4, 3, 3)),
+];
+
+/**
+ * `pixels` is an RGB24 frame, same layout as `Frame::data`.
+ */
+fn fill_rect(pixels: &mut [u8], (x, y, width, height): (usize, usize, usize, usize), rgb: Rgb) {
+    for row in y..(y + height).min(Frame::HEIGHT) {
+        for col in x..(x + width).min(Frame::WIDTH) {
+            let addr = (row * Frame::WIDTH + col) * 3;
+            pixels[addr..addr + 3].copy_from_slice(&[rgb.0, rgb.1, rgb.2]);
+        }
+    }
+}
+
+/**
+ * Draw a little controller per player along the bottom left of the picture,
+ * held buttons lit up. Drawn over a copy of the frame, the emulated one is
+ * what the Zapper looks at.
+ */
+pub fn draw_input_display(pixels: &mut [u8], players: &[Buttons]) {
+    let y = Frame::HEIGHT - BOTTOM_MARGIN - PAD_HEIGHT;
+    for (player, buttons) in players.iter().enumerate() {
+        let x = PAD_SPACING + player * (PAD_WIDTH + PAD_SPACING);
+        fill_rect(pixels, (x, y, PAD_WIDTH, PAD_HEIGHT), BACKGROUND);
+        for (button, (bx, by, width, height)) in BUTTON_RECTS {
+            let rgb = if buttons.contains(button) {
+                PRESSED
+            } else {
+                RELEASED
+            };
+            fill_rect(pixels, (x + bx, y + by, width, height), rgb)
+        }
+    }
+}
+
+#[cfg(test)]
+mod overlay_test {
+    use nes::{controller::Buttons, ppu::Frame};
+
+    use super::{draw_input_display, PRESSED, RELEASED};
+
+    #[test]
+    fn test_draw_input_display() {
+        let mut pixels = vec![0xff; Frame::WIDTH * Frame::HEIGHT * 3];
+        draw_input_display(&mut pixels, &[Buttons::A, Buttons::empty()]);
+        let pixel = |x: usize, y: usize| {
+            let addr = (y * Frame::WIDTH + x) * 3;
+            (pixels[addr], pixels[addr + 1], pixels[addr + 2])
+        };
+        // player 1's A, then player 2's
+        assert_eq!(pixel(4 + 27, 240 - 19 + 4), PRESSED);
+        assert_eq!(pixel(40 + 27, 240 - 19 + 4), RELEASED);
+        // the rest of the picture is untouched
+        assert_eq!(pixel(200, 10), (0xff, 0xff, 0xff));
+    }
+}
@@ -0,0 +1,87 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+// sleep is only good to a millisecond or so, spin for the rest
+const SPIN_MARGIN: Duration = Duration::from_millis(1);
+
+/**
+ * Frame limiter: `wait` returns once it's time for the next frame.
+ *
+ * Deadlines are kept on an absolute schedule so rounding doesn't drift, but if
+ * we fall more than a frame behind (slow machine, window dragged, debugger)
+ * the schedule restarts from now instead of running fast to catch up.
+ */
+pub struct FramePacer {
+    frame_time: Duration,
+    next: Instant,
+}
+
+impl FramePacer {
+    pub fn new(frame_rate: f64) -> FramePacer {
+        FramePacer {
+            frame_time: Duration::from_secs_f64(1.0 / frame_rate),
+            next: Instant::now(),
+        }
+    }
+    pub fn wait(&mut self) {
+        self.next += self.frame_time;
+        let now = Instant::now();
+        if now > self.next + self.frame_time {
+            self.next = now;
+            return;
+        }
+        if let Some(sleep) = self.next.checked_duration_since(now + SPIN_MARGIN) {
+            thread::sleep(sleep)
+        }
+        while Instant::now() < self.next {
+            std::hint::spin_loop()
+        }
+    }
+}
+
+/**
+ * How many frames to emulate per frame shown for a speed multiplier: 4 at
+ * 4x, every other time at 0.5x. Leftover fractions carry over.
+ */
+pub struct FrameBudget {
+    credit: f64,
+}
+
+impl FrameBudget {
+    pub fn new() -> FrameBudget {
+        FrameBudget { credit: 0.0 }
+    }
+    pub fn frames(&mut self, speed: f64) -> u32 {
+        self.credit += speed;
+        let frames = self.credit.floor();
+        self.credit -= frames;
+        frames as u32
+    }
+}
+
+#[cfg(test)]
+mod pacer_test {
+    use std::time::{Duration, Instant};
+
+    use super::{FrameBudget, FramePacer};
+
+    #[test]
+    fn test_wait() {
+        let start = Instant::now();
+        let mut pacer = FramePacer::new(100.0);
+        for _ in 0..5 {
+            pacer.wait()
+        }
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_frame_budget() {
+        let mut budget = FrameBudget::new();
+        assert_eq!(budget.frames(4.0), 4);
+        let slow: Vec<u32> = (0..8).map(|_| budget.frames(0.25)).collect();
+        assert_eq!(slow, vec![0, 0, 0, 1, 0, 0, 0, 1]);
+    }
+}
@@ -0,0 +1,116 @@
+use nes::{config::Aspect, ppu::Frame};
+
+pub const MAX_SCALE: u32 = 4;
+
+/**
+ * Where in the window the picture goes: x, y, width, height.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Viewport {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Viewport {
+    /**
+     * Window coordinates to the NES pixel under them, None outside the picture.
+     */
+    pub fn nes_pixel(&self, x: i32, y: i32) -> Option<(usize, usize)> {
+        let (x, y) = (x - self.x, y - self.y);
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return None;
+        }
+        Some((
+            x as usize * Frame::WIDTH / self.width as usize,
+            y as usize * Frame::HEIGHT / self.height as usize,
+        ))
+    }
+}
+
+/**
+ * Size of the picture at an integer `scale`, stretched horizontally for 8:7.
+ */
+pub fn picture_size(scale: u32, aspect: Aspect) -> (u32, u32) {
+    let width = (Frame::WIDTH as f64 * scale as f64 * aspect.pixel_ratio()).round();
+    (width as u32, Frame::HEIGHT as u32 * scale)
+}
+
+/**
+ * Place the picture in a `window` sized window. `fit` grows it as big as the
+ * window allows, in whole steps with `integer`, otherwise it stays at
+ * `scale`. Either way it's centered with black bars around whatever is left.
+ */
+pub fn viewport(
+    window: (u32, u32),
+    scale: u32,
+    aspect: Aspect,
+    fit: bool,
+    integer: bool,
+) -> Viewport {
+    let (width, height) = if fit {
+        let (native_width, native_height) = picture_size(1, aspect);
+        let mut zoom =
+            (window.0 as f64 / native_width as f64).min(window.1 as f64 / native_height as f64);
+        // still 1x in a window that's too small for it
+        if integer {
+            zoom = zoom.floor().max(1.0)
+        }
+        (
+            (native_width as f64 * zoom) as u32,
+            (native_height as f64 * zoom) as u32,
+        )
+    } else {
+        picture_size(scale, aspect)
+    };
+    Viewport {
+        x: (window.0 as i32 - width as i32) / 2,
+        y: (window.1 as i32 - height as i32) / 2,
+        width,
+        height,
+    }
+}
+
+#[cfg(test)]
+mod scaling_test {
+    use nes::config::Aspect;
+
+    use super::{picture_size, viewport, Viewport};
+
+    #[test]
+    fn test_picture_size() {
+        assert_eq!(picture_size(1, Aspect::Square), (256, 240));
+        assert_eq!(picture_size(3, Aspect::Square), (768, 720));
+        assert_eq!(picture_size(2, Aspect::Ntsc), (585, 480));
+    }
+
+    #[test]
+    fn test_viewport() {
+        // window taller than the picture, bars top and bottom
+        assert_eq!(
+            viewport((512, 600), 2, Aspect::Square, true, false),
+            Viewport {
+                x: 0,
+                y: 60,
+                width: 512,
+                height: 480
+            }
+        );
+        // 3.33x is 3x in whole steps
+        assert_eq!(
+            viewport((1000, 800), 1, Aspect::Square, true, true),
+            Viewport {
+                x: 116,
+                y: 40,
+                width: 768,
+                height: 720
+            }
+        );
+        let centered = viewport((1000, 800), 2, Aspect::Square, false, false);
+        assert_eq!((centered.x, centered.y), (244, 160));
+        assert_eq!(centered.nes_pixel(244, 160), Some((0, 0)));
+        assert_eq!(centered.nes_pixel(244 + 511, 160 + 479), Some((255, 239)));
+        assert_eq!(centered.nes_pixel(10, 10), None);
+    }
+}
@@ -0,0 +1,405 @@
+use sdl2::{
+    event::{Event, WindowEvent},
+    keyboard::Keycode,
+    mouse::MouseButton,
+    pixels::Color,
+    pixels::PixelFormatEnum,
+    rect::Rect,
+    render::{Texture, TextureCreator, WindowCanvas},
+    video::WindowContext,
+};
+
+use nes::{
+    config::{Config, DisplayConfig, Pacing, WindowGeometry},
+    cpu::CPU,
+    movie::MovieSession,
+    netplay::Netplay,
+    ppu::Frame,
+    present::Presenter,
+};
+
+use crate::binding::{Binder, Device};
+
+use super::{
+    audio::AudioOutput,
+    debug_windows::{DebugView, DebugWindows},
+    keymap::{Hotkey, KeyMap},
+    pacer::FramePacer,
+    scaling::{picture_size, viewport},
+    session::Session,
+};
+#[cfg(feature = "gui")]
+use super::{
+    egui_sdl::EguiSdl,
+    gui::{Gui, GuiActions},
+};
+
+fn fit_window(canvas: &mut WindowCanvas, display: &DisplayConfig) -> Result<(), String> {
+    let (width, height) = picture_size(display.scale, display.aspect);
+    canvas
+        .window_mut()
+        .set_size(width, height)
+        .map_err(|e| e.to_string())
+}
+
+/**
+ * The picture on the main window, scaled into `view`. It's drawn on the
+ * canvas, which shows it along with anything drawn over it at the next
+ * `present`.
+ */
+struct SdlPresenter<'a> {
+    canvas: WindowCanvas,
+    creator: &'a TextureCreator<WindowContext>,
+    // sized to whatever the filter puts out, remade when that changes
+    texture: Texture<'a>,
+    size: (usize, usize),
+    view: Rect,
+}
+
+impl<'a> SdlPresenter<'a> {
+    fn new(
+        canvas: WindowCanvas,
+        creator: &'a TextureCreator<WindowContext>,
+    ) -> Result<SdlPresenter<'a>, String> {
+        let size = (Frame::WIDTH, Frame::HEIGHT);
+        Ok(SdlPresenter {
+            canvas,
+            creator,
+            texture: texture(creator, size)?,
+            size,
+            view: Rect::new(0, 0, size.0 as u32, size.1 as u32),
+        })
+    }
+}
+
+fn texture<'a>(
+    creator: &'a TextureCreator<WindowContext>,
+    (width, height): (usize, usize),
+) -> Result<Texture<'a>, String> {
+    creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
+        .map_err(|e| e.to_string())
+}
+
+impl Presenter for SdlPresenter<'_> {
+    fn present(&mut self, pixels: &[u8], width: usize, height: usize) -> Result<(), String> {
+        if (width, height) != self.size {
+            self.size = (width, height);
+            self.texture = texture(self.creator, self.size)?;
+        }
+        self.texture
+            .update(None, pixels, width * 3)
+            .map_err(|e| e.to_string())?;
+        self.canvas.set_draw_color(Color::BLACK);
+        self.canvas.clear();
+        self.canvas.copy(&self.texture, None, self.view)
+    }
+}
+
+/**
+ * SDL window + event loop. Runs until the window is closed or Escape is hit.
+ * With a movie session the controllers are recorded to or played back from
+ * it every frame, with netplay they come from both players.
+ */
+pub fn run(
+    cpu: &mut CPU,
+    config: &Config,
+    title: &str,
+    crc32: u32,
+    movie: Option<&mut MovieSession>,
+    netplay: Option<&mut Netplay>,
+) -> Result<(), String> {
+    let mut session = Session::new(cpu, config, title, crc32, movie, netplay);
+    let sdl = sdl2::init()?;
+    let video = sdl.video()?;
+    // where it was last time, unless --scale asked for a size
+    let mut builder = match session.display.window {
+        Some(geometry) => {
+            let mut builder = video.window(title, geometry.width, geometry.height);
+            builder.position(geometry.x, geometry.y);
+            builder
+        }
+        None => {
+            let (width, height) = picture_size(session.display.scale, session.display.aspect);
+            let mut builder = video.window(title, width, height);
+            builder.position_centered();
+            builder
+        }
+    };
+    builder.resizable();
+    if session.display.borderless {
+        builder.borderless();
+    }
+    let window = builder.build().map_err(|e| e.to_string())?;
+    let mut canvas = window.into_canvas();
+    if session.display.pacing == Pacing::Vsync {
+        canvas = canvas.present_vsync()
+    }
+    let canvas = canvas.build().map_err(|e| e.to_string())?;
+    let texture_creator = canvas.texture_creator();
+    let mut presenter = SdlPresenter::new(canvas, &texture_creator)?;
+    let main_window = presenter.canvas.window().id();
+    let mut debug_windows = DebugWindows::new(video.clone());
+    let mut events = sdl.event_pump()?;
+    // carry on without sound rather than not at all
+    let mut audio = if config.audio.enabled {
+        sdl.audio()
+            .and_then(|audio| AudioOutput::open(&audio, &config.audio, session.frame_rate()))
+            .map_err(|e| eprintln!("Unable to open audio: {}", e))
+            .ok()
+    } else {
+        None
+    };
+
+    // rebound from the gui without a restart
+    #[cfg(feature = "gui")]
+    let mut input = config.input.clone();
+    #[cfg_attr(not(feature = "gui"), allow(unused_mut))]
+    let mut keymap = KeyMap::new(&config.input, &config.hotkeys, Keycode::from_name);
+    let mut pacer = FramePacer::new(session.frame_rate());
+    #[cfg(feature = "gui")]
+    let mut egui = EguiSdl::new(&texture_creator);
+    #[cfg(feature = "gui")]
+    let mut gui = Gui::new();
+
+    'running: loop {
+        let display = &session.display;
+        let mut view = viewport(
+            presenter.canvas.output_size()?,
+            display.scale,
+            display.aspect,
+            display.fit,
+            display.integer_scale,
+        );
+        let mut resize = false;
+        for event in events.poll_iter() {
+            // keys go to the binding dialog while it's asking for them
+            #[cfg(feature = "gui")]
+            if let Some(binder) = &mut gui.binding {
+                match event {
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Escape),
+                        ..
+                    } => {
+                        gui.binding = None;
+                        continue;
+                    }
+                    Event::KeyDown {
+                        keycode: Some(key),
+                        repeat: false,
+                        ..
+                    } => {
+                        if binder.press(&key.name()) {
+                            binder.apply(&mut input);
+                            if let Err(e) = binder.save(&Config::default_path()) {
+                                eprintln!("{}", e)
+                            }
+                            keymap = KeyMap::new(&input, &config.hotkeys, Keycode::from_name);
+                            gui.binding = None;
+                        }
+                        continue;
+                    }
+                    _ => (),
+                }
+            }
+            // clicks on the menus aren't shots
+            #[cfg(feature = "gui")]
+            if gui.visible && egui.handle_event(&event) {
+                continue;
+            }
+            // the zapper only points at the game
+            if event.is_mouse() && event.get_window_id() != Some(main_window) {
+                continue;
+            }
+            match event {
+                // with more than one window open there's no Quit for the
+                // main one, just this
+                Event::Window {
+                    win_event: WindowEvent::Close,
+                    window_id,
+                    ..
+                } if !debug_windows.close(window_id) => break 'running,
+                // so clicks later in this batch land in the right place
+                Event::Window {
+                    win_event: WindowEvent::SizeChanged(width, height),
+                    window_id,
+                    ..
+                } if window_id == main_window => {
+                    let display = &session.display;
+                    view = viewport(
+                        (width as u32, height as u32),
+                        display.scale,
+                        display.aspect,
+                        display.fit,
+                        display.integer_scale,
+                    )
+                }
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(key),
+                    repeat,
+                    ..
+                } => match keymap.hotkey(&key) {
+                    Some(hotkey) => {
+                        let view = match hotkey {
+                            Hotkey::ToggleNametables => Some(DebugView::Nametables),
+                            Hotkey::TogglePatterns => Some(DebugView::Patterns),
+                            Hotkey::ToggleSprites => Some(DebugView::Sprites),
+                            Hotkey::ToggleEvents => Some(DebugView::Events),
+                            _ => None,
+                        };
+                        if let (Some(view), false) = (view, repeat) {
+                            debug_windows.toggle(view)?
+                        }
+                        #[cfg(feature = "gui")]
+                        if hotkey == Hotkey::ToggleGui && !repeat {
+                            gui.visible = !gui.visible
+                        }
+                        resize |= session.hotkey_down(cpu, hotkey, repeat)
+                    }
+                    None => {
+                        if let Some(input) = keymap.get(&key) {
+                            session.press(cpu, input, true)
+                        }
+                    }
+                },
+                Event::KeyUp {
+                    keycode: Some(key), ..
+                } => match keymap.hotkey(&key) {
+                    Some(hotkey) => session.hotkey_up(hotkey),
+                    None => {
+                        if let Some(input) = keymap.get(&key) {
+                            session.press(cpu, input, false)
+                        }
+                    }
+                },
+                Event::MouseMotion { x, y, .. } => session.aim(cpu, &view, x, y),
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    ..
+                } => session.trigger(cpu, true),
+                Event::MouseButtonUp {
+                    mouse_btn: MouseButton::Left,
+                    ..
+                } => session.trigger(cpu, false),
+                _ => (),
+            }
+        }
+
+        if resize {
+            fit_window(&mut presenter.canvas, &session.display)?;
+        }
+
+        let frames = session.run_frames(cpu);
+        if let Some(audio) = &mut audio {
+            audio.push_frames(frames)
+        }
+        if let Some(title) = session.title() {
+            presenter
+                .canvas
+                .window_mut()
+                .set_title(&title)
+                .map_err(|e| e.to_string())?;
+        }
+        let (screen, width, height) = session.screen(cpu);
+        presenter.view = Rect::new(view.x, view.y, view.width, view.height);
+        presenter.present(screen, width, height)?;
+        #[cfg(feature = "gui")]
+        if gui.visible {
+            let mut actions = GuiActions::default();
+            egui.draw(&mut presenter.canvas, |ctx| {
+                actions = gui.ui(ctx, &mut session, cpu)
+            })?;
+            if actions.quit {
+                break 'running;
+            }
+            if actions.resize {
+                fit_window(&mut presenter.canvas, &session.display)?;
+            }
+            // does nothing unless it was flipped in the settings
+            presenter
+                .canvas
+                .window_mut()
+                .set_bordered(!session.display.borderless);
+        }
+        debug_windows.update(cpu)?;
+        session.frame_drawn(cpu);
+        if session.needs_pacing() {
+            pacer.wait()
+        }
+        presenter.canvas.present();
+    }
+    let window = presenter.canvas.window();
+    let (x, y) = window.position();
+    let (width, height) = window.size();
+    let geometry = WindowGeometry {
+        x,
+        y,
+        width,
+        height,
+    };
+    if let Err(e) = Config::save_window(&Config::default_path(), geometry) {
+        eprintln!("{}", e)
+    }
+    if let Some(audio) = &audio {
+        let stats = audio.stats();
+        println!(
+            "Audio: {} underruns, {} overruns",
+            stats.underruns, stats.overruns
+        )
+    }
+    Ok(())
+}
+
+/**
+ * Small window asking for one key at a time, its title says which. None when
+ * it's closed or Escape is hit before the last one.
+ */
+pub fn bind(device: Device) -> Result<Option<Binder>, String> {
+    let mut binder = Binder::new(device);
+    let sdl = sdl2::init()?;
+    let video = sdl.video()?;
+    let window = video
+        .window(&binder.prompt(), 480, 64)
+        .position_centered()
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    canvas.set_draw_color(Color::BLACK);
+    canvas.clear();
+    canvas.present();
+    let mut events = sdl.event_pump()?;
+    eprintln!("{}", binder.prompt());
+    loop {
+        match events.wait_event() {
+            Event::Quit { .. }
+            | Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            } => return Ok(None),
+            Event::KeyDown {
+                keycode: Some(key),
+                repeat: false,
+                ..
+            } => {
+                let prompt = binder.prompt();
+                if binder.press(&key.name()) {
+                    return Ok(Some(binder));
+                }
+                // the same again if the key was already used
+                if binder.prompt() != prompt {
+                    eprintln!("{}", binder.prompt());
+                    canvas
+                        .window_mut()
+                        .set_title(&binder.prompt())
+                        .map_err(|e| e.to_string())?;
+                }
+            }
+            _ => (),
+        }
+    }
+}
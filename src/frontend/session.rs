@@ -0,0 +1,532 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(feature = "gui")]
+use nes::tas::MovieEdit;
+use nes::{
+    config::{Config, DisplayConfig, Pacing},
+    controller::Buttons,
+    cpu::CPU,
+    movie::{MovieMode, MovieSession},
+    netplay::{Netplay, NetplayError},
+    ppu::{self, Frame, Palette},
+    profiler::Subsystem,
+    savestate::{SaveState, SLOTS},
+    tas::TasEditor,
+};
+
+use super::{
+    clip::ClipRecorder,
+    filter::PostProcess,
+    keymap::{Hotkey, Input},
+    osd::{self, FpsCounter, Osd},
+    overlay,
+    pacer::FrameBudget,
+    scaling::{Viewport, MAX_SCALE},
+};
+
+// for naming captures
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/**
+ * The built in palettes followed by every .pal file that loads, by name.
+ */
+fn load_palettes(files: &[String]) -> Vec<(String, Palette)> {
+    let mut palettes = vec![
+        ("default".to_string(), ppu::SYSTEM_PALLETE),
+        (
+            "grayscale".to_string(),
+            ppu::grayscale(&ppu::SYSTEM_PALLETE),
+        ),
+    ];
+    for file in files {
+        match ppu::load_palette(file) {
+            Ok(palette) => {
+                let name = Path::new(file)
+                    .file_stem()
+                    .map_or(file.clone(), |stem| stem.to_string_lossy().into_owned());
+                palettes.push((name, palette))
+            }
+            Err(e) => eprintln!("{} ({}), skipping it.", e, file),
+        }
+    }
+    palettes
+}
+
+/**
+ * Everything between the window and the emulator that doesn't care which
+ * window library is underneath: hotkeys, speed, pause, the movie, the OSD,
+ * clips and the picture that ends up on screen. A frontend feeds it input and
+ * calls `run_frames` + `screen` once per frame shown.
+ */
+pub struct Session<'a> {
+    config: &'a Config,
+    // rom name, mapper and region, the FPS goes on the end
+    title: &'a str,
+    title_stale: bool,
+    pub display: DisplayConfig,
+    movie: Option<&'a mut MovieSession>,
+    // anchors and lag frames for the movie, there's always one
+    tas: TasEditor,
+    netplay: Option<&'a mut Netplay>,
+    // the other player's input for the next frame hasn't come yet
+    waiting: bool,
+    clip: ClipRecorder,
+    osd: Osd,
+    fps: FpsCounter,
+    // the console's, what 100% speed is
+    frame_rate: f64,
+    fast_forward: bool,
+    slow_motion: f64,
+    paused: bool,
+    frame_advance: bool,
+    budget: FrameBudget,
+    disk_side: usize,
+    // for save state slots
+    config_path: PathBuf,
+    crc32: u32,
+    slot: usize,
+    // None is uncapped: one frame per loop and no waiting
+    speed: Option<f64>,
+    // the frame plus overlays, before filtering
+    screen: Vec<u8>,
+    post: PostProcess,
+    palettes: Vec<(String, Palette)>,
+    palette: usize,
+    // when the last frames were done, the frontend's time starts there
+    emulated: Instant,
+}
+
+impl<'a> Session<'a> {
+    /**
+     * Plugs in the devices from the config too.
+     */
+    pub fn new(
+        cpu: &mut CPU,
+        config: &'a Config,
+        title: &'a str,
+        crc32: u32,
+        movie: Option<&'a mut MovieSession>,
+        netplay: Option<&'a mut Netplay>,
+    ) -> Self {
+        config.input.plug_into(cpu);
+        let mut display = config.display.clone();
+        display.scale = display.scale.clamp(1, MAX_SCALE);
+        let palettes = load_palettes(&config.display.palette_files);
+        let palette = palettes
+            .iter()
+            .position(|(name, _)| *name == config.display.palette)
+            .unwrap_or_else(|| {
+                eprintln!(
+                    "Unknown palette \"{}\", using the default.",
+                    config.display.palette
+                );
+                0
+            });
+        cpu.set_palette(&palettes[palette].1);
+        cpu.set_profiling(display.show_profile);
+        Session {
+            config,
+            title,
+            title_stale: false,
+            display,
+            movie,
+            tas: TasEditor::new(cpu),
+            netplay,
+            waiting: false,
+            clip: ClipRecorder::new(config.capture.clip_seconds),
+            osd: Osd::new(config.display.osd),
+            fps: FpsCounter::new(),
+            frame_rate: cpu.region().frame_rate(),
+            fast_forward: false,
+            slow_motion: 1.0,
+            paused: false,
+            frame_advance: false,
+            budget: FrameBudget::new(),
+            disk_side: 0,
+            config_path: Config::default_path(),
+            crc32,
+            slot: 0,
+            speed: Some(1.0),
+            screen: Vec::with_capacity(Frame::WIDTH * Frame::HEIGHT * 3),
+            post: PostProcess::new(),
+            palettes,
+            palette,
+            emulated: Instant::now(),
+        }
+    }
+
+    /**
+     * Returns true when the picture size changed and the window should follow.
+     * `repeat` is a key held down, only frame advance acts on those.
+     */
+    pub fn hotkey_down(&mut self, cpu: &mut CPU, hotkey: Hotkey, repeat: bool) -> bool {
+        if repeat && hotkey != Hotkey::FrameAdvance {
+            return false;
+        }
+        match hotkey {
+            Hotkey::SwapDisk if cpu.disk_sides() > 0 => {
+                self.disk_side = (self.disk_side + 1) % cpu.disk_sides();
+                cpu.insert_disk(Some(self.disk_side))
+            }
+            Hotkey::SwapDisk => (),
+            Hotkey::TakeOver => {
+                if let Some(session) = self.movie.as_deref_mut() {
+                    session.take_over(cpu);
+                    self.tas.invalidate(session.frame());
+                    self.osd
+                        .show(format!("Took over at frame {}", session.frame()))
+                }
+            }
+            Hotkey::ToggleInputDisplay => self.display.input_display = !self.display.input_display,
+            Hotkey::SaveClip => {
+                let path = format!("clip-{}.gif", unix_time());
+                match self.clip.write_gif(&path) {
+                    Ok(()) => self.osd.show(format!("Saved {}", path)),
+                    Err(e) => {
+                        eprintln!("Unable to save clip: {}", e);
+                        self.osd.show("Unable to save clip")
+                    }
+                }
+            }
+            Hotkey::ScaleUp | Hotkey::ScaleDown => {
+                self.display.scale = if hotkey == Hotkey::ScaleUp {
+                    self.display.scale + 1
+                } else {
+                    self.display.scale - 1
+                }
+                .clamp(1, MAX_SCALE);
+                // in fit mode the window is the user's to size
+                return !self.display.fit;
+            }
+            Hotkey::ToggleAspect => {
+                self.display.aspect = self.display.aspect.toggle();
+                return !self.display.fit;
+            }
+            Hotkey::FastForward => self.fast_forward = true,
+            Hotkey::SlowMotion => {
+                self.slow_motion = if self.slow_motion > 0.25 {
+                    self.slow_motion / 2.0
+                } else {
+                    1.0
+                };
+                self.osd
+                    .show(format!("Speed {}%", self.slow_motion * 100.0))
+            }
+            Hotkey::Pause => self.paused = !self.paused,
+            // holding it down steps repeatedly
+            Hotkey::FrameAdvance => {
+                self.paused = true;
+                self.frame_advance = true
+            }
+            // shows up from the next frame rendered
+            Hotkey::CyclePalette => {
+                self.palette = (self.palette + 1) % self.palettes.len();
+                let (name, palette) = &self.palettes[self.palette];
+                cpu.set_palette(palette);
+                self.osd.show(format!("Palette: {}", name))
+            }
+            Hotkey::CycleFilter => {
+                self.display.filter = self.display.filter.next();
+                self.osd
+                    .show(format!("Filter: {}", self.display.filter.name()))
+            }
+            Hotkey::SaveState => self.save_state(cpu),
+            Hotkey::LoadState => self.load_state(cpu),
+            Hotkey::NextSlot | Hotkey::PreviousSlot => {
+                let step = if hotkey == Hotkey::NextSlot {
+                    1
+                } else {
+                    SLOTS - 1
+                };
+                self.set_slot((self.slot + step) % SLOTS)
+            }
+            // the frontend's business
+            Hotkey::ToggleGui
+            | Hotkey::ToggleNametables
+            | Hotkey::TogglePatterns
+            | Hotkey::ToggleSprites
+            | Hotkey::ToggleEvents => (),
+        }
+        false
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+
+    pub fn set_slot(&mut self, slot: usize) {
+        self.slot = slot;
+        let path = SaveState::slot_path(&self.config_path, self.crc32, slot);
+        let used = if path.exists() { "" } else { " (empty)" };
+        self.osd.show(format!("Slot {}{}", slot, used))
+    }
+
+    fn save_state(&mut self, cpu: &CPU) {
+        let path = SaveState::slot_path(&self.config_path, self.crc32, self.slot);
+        match cpu.save_state().save(&path) {
+            Ok(()) => self.osd.show(format!("Saved state {}", self.slot)),
+            Err(e) => {
+                eprintln!("{} ({})", e, path.display());
+                self.osd.show(format!("Unable to save state {}", self.slot))
+            }
+        }
+    }
+
+    fn load_state(&mut self, cpu: &mut CPU) {
+        let path = SaveState::slot_path(&self.config_path, self.crc32, self.slot);
+        if !path.exists() {
+            return self.osd.show(format!("State {} is empty", self.slot));
+        }
+        if self.netplay.as_ref().is_some_and(|n| !n.is_host()) {
+            return self.osd.show("Only the host can load states");
+        }
+        match SaveState::load(&path).and_then(|state| cpu.load_state(&state)) {
+            Ok(()) => {
+                self.osd.show(format!("Loaded state {}", self.slot));
+                // the other player carries on from here too
+                if let Some(Err(e)) = self.netplay.as_deref_mut().map(|n| n.resync(cpu)) {
+                    self.end_netplay(e)
+                }
+            }
+            Err(e) => {
+                eprintln!("{} ({})", e, path.display());
+                self.osd.show(format!("Unable to load state {}", self.slot))
+            }
+        }
+    }
+
+    // the piano roll in the gui is what reads and edits the movie
+    #[cfg(feature = "gui")]
+    pub fn movie(&self) -> Option<&MovieSession> {
+        self.movie.as_deref()
+    }
+
+    #[cfg(feature = "gui")]
+    pub fn tas(&self) -> &TasEditor {
+        &self.tas
+    }
+
+    /**
+     * Change the movie from the TAS editor. Pauses, from there it goes a
+     * frame at a time.
+     */
+    #[cfg(feature = "gui")]
+    pub fn edit_movie(&mut self, cpu: &mut CPU, edit: MovieEdit) {
+        if self.netplay.is_some() {
+            return self.osd.show("The movie can't be edited during netplay");
+        }
+        let Some(session) = self.movie.as_deref_mut() else {
+            return;
+        };
+        self.paused = true;
+        if let Err(e) = self.tas.apply(cpu, session, edit) {
+            eprintln!("{}", e);
+            self.osd.show("Unable to go back in the movie")
+        }
+    }
+
+    pub fn hotkey_up(&mut self, hotkey: Hotkey) {
+        if hotkey == Hotkey::FastForward {
+            self.fast_forward = false
+        }
+    }
+
+    fn end_netplay(&mut self, e: NetplayError) {
+        eprintln!("{}", e);
+        self.osd.show(e.to_string());
+        self.netplay = None;
+        self.waiting = false
+    }
+
+    /**
+     * A bound key went down or up. The movie owns the controllers while it
+     * plays, so this is ignored then. In netplay the first controller's keys
+     * are the local player's, whichever port that is.
+     */
+    pub fn press(&mut self, cpu: &mut CPU, input: Input, pressed: bool) {
+        if self.movie.as_ref().map(|m| m.mode()) == Some(MovieMode::Playing) {
+            return;
+        }
+        match input {
+            Input::Controller(0, button) if self.netplay.is_some() => {
+                if let Some(netplay) = self.netplay.as_deref_mut() {
+                    netplay.set_button(button, pressed)
+                }
+            }
+            Input::Controller(_, _) if self.netplay.is_some() => (),
+            Input::Controller(port, button) => cpu.controller(port).set_button(button, pressed),
+            Input::PowerPad(button) => {
+                if let Some(power_pad) = cpu.power_pad() {
+                    power_pad.set_button(button, pressed)
+                }
+            }
+        }
+    }
+
+    /**
+     * The mouse moved to x, y in window coordinates, `view` being where the
+     * picture is.
+     */
+    pub fn aim(&mut self, cpu: &mut CPU, view: &Viewport, x: i32, y: i32) {
+        if let Some(zapper) = cpu.zapper() {
+            zapper.aim(view.nes_pixel(x, y))
+        }
+    }
+
+    pub fn trigger(&mut self, cpu: &mut CPU, pulled: bool) {
+        if let Some(zapper) = cpu.zapper() {
+            zapper.set_trigger(pulled)
+        }
+    }
+
+    /**
+     * Emulate however many frames this one shown is worth at the current
+     * speed, none when paused. Returns how many that was. Netplay runs at
+     * normal speed only, and stops short while the other player catches up.
+     */
+    pub fn run_frames(&mut self, cpu: &mut CPU) -> u32 {
+        cpu.set_profiling(self.display.show_profile);
+        self.speed = match (self.fast_forward, self.display.pacing) {
+            (true, _) => self.config.speed.fast_forward.multiplier(),
+            (false, Pacing::Unlimited) => None,
+            (false, _) => Some(self.slow_motion),
+        };
+        let frames = if self.netplay.is_some() {
+            self.budget.frames(1.0)
+        } else if self.paused {
+            std::mem::take(&mut self.frame_advance) as u32
+        } else {
+            self.speed.map_or(1, |speed| self.budget.frames(speed))
+        };
+        let mut run = 0;
+        for idx in 0..frames {
+            // fast forwarding, only the frame that's shown needs drawing
+            cpu.ppu_mut()
+                .set_skip_rendering(self.fast_forward && idx + 1 < frames);
+            if let Some(netplay) = self.netplay.as_deref_mut() {
+                let ready = netplay.advance(cpu);
+                for message in netplay.take_messages() {
+                    self.osd.show(message)
+                }
+                match ready {
+                    Ok(ready) => self.waiting = !ready,
+                    Err(e) => self.end_netplay(e),
+                }
+                if self.waiting {
+                    break;
+                }
+            }
+            match self.movie.as_deref_mut() {
+                Some(session) => {
+                    let synced = session.desync().is_none();
+                    if self.tas.run_frame(cpu, session) {
+                        self.osd
+                            .show(format!("Movie finished after {} frames", session.frame()))
+                    }
+                    if let (true, Some((matched, frame))) = (synced, session.desync()) {
+                        self.osd.show(format!(
+                            "Movie desynced between frames {} and {}",
+                            matched, frame
+                        ))
+                    }
+                }
+                None => cpu.run_frame(),
+            }
+            run += 1
+        }
+        cpu.ppu_mut().set_skip_rendering(false);
+        self.title_stale |= self.fps.add_frames(run);
+        self.emulated = Instant::now();
+        run
+    }
+
+    /**
+     * Call once the picture's been handed over, before any waiting for the
+     * next frame, for the profiler.
+     */
+    pub fn frame_drawn(&self, cpu: &mut CPU) {
+        if let Some(profiler) = cpu.profiler() {
+            profiler.add(Subsystem::Frontend, self.emulated.elapsed())
+        }
+    }
+
+    /**
+     * Window title with the FPS and speed, Some about once a second when it
+     * needs updating.
+     */
+    pub fn title(&mut self) -> Option<String> {
+        if !std::mem::take(&mut self.title_stale) {
+            return None;
+        }
+        let fps = self.fps.fps();
+        Some(format!(
+            "{} - {:.1} FPS ({:.0}%)",
+            self.title,
+            fps,
+            fps / self.frame_rate * 100.0
+        ))
+    }
+
+    /**
+     * Frames a second at normal speed, for the pacer and the sound.
+     */
+    pub fn frame_rate(&self) -> f64 {
+        self.frame_rate
+    }
+
+    /**
+     * Whether the frontend should hold this frame to the console's speed. With
+     * vsync the present blocks until the next refresh, speeding up or slowing
+     * down is done by running more or fewer frames per present.
+     */
+    pub fn needs_pacing(&self) -> bool {
+        self.display.pacing == Pacing::Limiter && (self.speed.is_some() || self.paused)
+    }
+
+    /**
+     * The picture to show as RGB24, with its width and height: the frame plus
+     * whatever overlays are on, through the filter.
+     */
+    pub fn screen(&mut self, cpu: &mut CPU) -> (&[u8], usize, usize) {
+        self.screen.clear();
+        self.screen.extend_from_slice(cpu.frame().data());
+        if self.display.input_display {
+            let players = if self.config.input.four_score { 4 } else { 2 };
+            let buttons: Vec<Buttons> = (0..players).map(|p| cpu.controller(p).buttons()).collect();
+            overlay::draw_input_display(&mut self.screen, &buttons)
+        }
+        for (x, y, text) in cpu.overlay() {
+            osd::draw_text(&mut self.screen, x, y, &text)
+        }
+        // clips get the game, not the OSD
+        self.clip.push(&self.screen);
+        let mut status = Vec::new();
+        if self.display.show_fps {
+            status.push(format!("{:.1} FPS", self.fps.fps()))
+        }
+        if let Some(profiler) = cpu.profiler() {
+            status.extend(profiler.stats().lines())
+        }
+        if let (true, Some(netplay)) = (self.waiting, &self.netplay) {
+            status.push(format!("Waiting for player {}", 2 - netplay.port()))
+        } else if let Some(unsupported) = cpu.unsupported_opcode() {
+            status.push(unsupported.to_string())
+        } else if let Some(pc) = cpu.halted() {
+            status.push(format!("CPU halted at ${:04X}, reset to carry on", pc))
+        } else if self.paused {
+            status.push("Paused".to_string())
+        } else if self.fast_forward {
+            status.push("Fast-forward".to_string())
+        }
+        self.osd.draw(&mut self.screen, &status);
+        self.post.apply(self.display.filter, &self.screen)
+    }
+}
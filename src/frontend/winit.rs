@@ -0,0 +1,421 @@
+use std::{num::NonZeroU32, rc::Rc};
+
+use softbuffer::{Context, Surface};
+use winit::{
+    application::ApplicationHandler,
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{ElementState, MouseButton, WindowEvent},
+    event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
+    keyboard::{KeyCode, PhysicalKey},
+    window::{Window, WindowId},
+};
+
+use nes::{
+    config::{Config, Pacing, WindowGeometry},
+    cpu::CPU,
+    movie::MovieSession,
+    netplay::Netplay,
+};
+
+use super::{
+    keymap::KeyMap,
+    pacer::FramePacer,
+    scaling::{picture_size, viewport, Viewport},
+    session::Session,
+};
+
+/**
+ * The config names keys the way SDL does, this maps those names onto winit's
+ * physical keys. Keys that aren't here can't be bound in this frontend.
+ */
+fn key_code(name: &str) -> Option<KeyCode> {
+    let letters = [
+        KeyCode::KeyA,
+        KeyCode::KeyB,
+        KeyCode::KeyC,
+        KeyCode::KeyD,
+        KeyCode::KeyE,
+        KeyCode::KeyF,
+        KeyCode::KeyG,
+        KeyCode::KeyH,
+        KeyCode::KeyI,
+        KeyCode::KeyJ,
+        KeyCode::KeyK,
+        KeyCode::KeyL,
+        KeyCode::KeyM,
+        KeyCode::KeyN,
+        KeyCode::KeyO,
+        KeyCode::KeyP,
+        KeyCode::KeyQ,
+        KeyCode::KeyR,
+        KeyCode::KeyS,
+        KeyCode::KeyT,
+        KeyCode::KeyU,
+        KeyCode::KeyV,
+        KeyCode::KeyW,
+        KeyCode::KeyX,
+        KeyCode::KeyY,
+        KeyCode::KeyZ,
+    ];
+    let digits = [
+        KeyCode::Digit0,
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+        KeyCode::Digit5,
+        KeyCode::Digit6,
+        KeyCode::Digit7,
+        KeyCode::Digit8,
+        KeyCode::Digit9,
+    ];
+    let keypad = [
+        KeyCode::Numpad0,
+        KeyCode::Numpad1,
+        KeyCode::Numpad2,
+        KeyCode::Numpad3,
+        KeyCode::Numpad4,
+        KeyCode::Numpad5,
+        KeyCode::Numpad6,
+        KeyCode::Numpad7,
+        KeyCode::Numpad8,
+        KeyCode::Numpad9,
+    ];
+    let function = [
+        KeyCode::F1,
+        KeyCode::F2,
+        KeyCode::F3,
+        KeyCode::F4,
+        KeyCode::F5,
+        KeyCode::F6,
+        KeyCode::F7,
+        KeyCode::F8,
+        KeyCode::F9,
+        KeyCode::F10,
+        KeyCode::F11,
+        KeyCode::F12,
+    ];
+    let name = name.to_ascii_lowercase();
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        match c {
+            'a'..='z' => return Some(letters[c as usize - 'a' as usize]),
+            '0'..='9' => return Some(digits[c as usize - '0' as usize]),
+            _ => (),
+        }
+    }
+    if let Some(digit) = name.strip_prefix("keypad ") {
+        return digit
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| keypad.get(n).copied());
+    }
+    if let Some(n) = name.strip_prefix('f') {
+        if let Ok(n) = n.parse::<usize>() {
+            return function.get(n.wrapping_sub(1)).copied();
+        }
+    }
+    let key = match name.as_str() {
+        "up" => KeyCode::ArrowUp,
+        "down" => KeyCode::ArrowDown,
+        "left" => KeyCode::ArrowLeft,
+        "right" => KeyCode::ArrowRight,
+        "return" => KeyCode::Enter,
+        "keypad enter" => KeyCode::NumpadEnter,
+        "space" => KeyCode::Space,
+        "tab" => KeyCode::Tab,
+        "backspace" => KeyCode::Backspace,
+        "escape" => KeyCode::Escape,
+        "left shift" => KeyCode::ShiftLeft,
+        "right shift" => KeyCode::ShiftRight,
+        "left ctrl" => KeyCode::ControlLeft,
+        "right ctrl" => KeyCode::ControlRight,
+        "left alt" => KeyCode::AltLeft,
+        "right alt" => KeyCode::AltRight,
+        "insert" => KeyCode::Insert,
+        "delete" => KeyCode::Delete,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "=" => KeyCode::Equal,
+        "-" => KeyCode::Minus,
+        "," => KeyCode::Comma,
+        "." => KeyCode::Period,
+        "/" => KeyCode::Slash,
+        "\\" => KeyCode::Backslash,
+        ";" => KeyCode::Semicolon,
+        "'" => KeyCode::Quote,
+        "[" => KeyCode::BracketLeft,
+        "]" => KeyCode::BracketRight,
+        "`" => KeyCode::Backquote,
+        _ => return None,
+    };
+    Some(key)
+}
+
+/**
+ * Nearest neighbour scale of an RGB24 picture `src_size` big into `view` of a
+ * 0RGB window buffer `width` pixels wide, black around it.
+ */
+fn blit(
+    screen: &[u8],
+    src_size: (usize, usize),
+    buffer: &mut [u32],
+    width: usize,
+    view: &Viewport,
+) {
+    buffer.fill(0);
+    let height = buffer.len() / width;
+    for y in view.y.max(0)..(view.y + view.height as i32).min(height as i32) {
+        let src_y = (y - view.y) as usize * src_size.1 / view.height as usize;
+        for x in view.x.max(0)..(view.x + view.width as i32).min(width as i32) {
+            let src_x = (x - view.x) as usize * src_size.0 / view.width as usize;
+            let addr = (src_y * src_size.0 + src_x) * 3;
+            let rgb = &screen[addr..addr + 3];
+            buffer[y as usize * width + x as usize] =
+                (rgb[0] as u32) << 16 | (rgb[1] as u32) << 8 | rgb[2] as u32;
+        }
+    }
+}
+
+struct Gfx {
+    window: Rc<Window>,
+    surface: Surface<Rc<Window>, Rc<Window>>,
+}
+
+struct App<'a, 'c> {
+    cpu: &'c mut CPU,
+    session: Session<'a>,
+    keymap: KeyMap<KeyCode>,
+    title: &'c str,
+    gfx: Option<Gfx>,
+    pacer: FramePacer,
+    error: Option<String>,
+}
+
+impl App<'_, '_> {
+    fn view(&self, window: &Window) -> Viewport {
+        let size = window.inner_size();
+        let display = &self.session.display;
+        viewport(
+            (size.width, size.height),
+            display.scale,
+            display.aspect,
+            display.fit,
+            display.integer_scale,
+        )
+    }
+
+    fn open(&mut self, event_loop: &ActiveEventLoop) -> Result<Gfx, String> {
+        let display = &self.session.display;
+        let mut attributes = Window::default_attributes()
+            .with_title(self.title)
+            .with_decorations(!display.borderless);
+        attributes = match display.window {
+            Some(geometry) => attributes
+                .with_inner_size(PhysicalSize::new(geometry.width, geometry.height))
+                .with_position(PhysicalPosition::new(geometry.x, geometry.y)),
+            None => {
+                let (width, height) = picture_size(display.scale, display.aspect);
+                attributes.with_inner_size(PhysicalSize::new(width, height))
+            }
+        };
+        let window = Rc::new(
+            event_loop
+                .create_window(attributes)
+                .map_err(|e| e.to_string())?,
+        );
+        let context = Context::new(window.clone()).map_err(|e| e.to_string())?;
+        let surface = Surface::new(&context, window.clone()).map_err(|e| e.to_string())?;
+        Ok(Gfx { window, surface })
+    }
+
+    fn frame(&mut self) -> Result<(), String> {
+        let Some(gfx) = self.gfx.as_mut() else {
+            return Ok(());
+        };
+        self.session.run_frames(self.cpu);
+        if let Some(title) = self.session.title() {
+            gfx.window.set_title(&title)
+        }
+        let size = gfx.window.inner_size();
+        let (Some(width), Some(height)) =
+            (NonZeroU32::new(size.width), NonZeroU32::new(size.height))
+        else {
+            // minimized
+            return Ok(());
+        };
+        let display = &self.session.display;
+        let view = viewport(
+            (size.width, size.height),
+            display.scale,
+            display.aspect,
+            display.fit,
+            display.integer_scale,
+        );
+        gfx.surface
+            .resize(width, height)
+            .map_err(|e| e.to_string())?;
+        let mut buffer = gfx.surface.buffer_mut().map_err(|e| e.to_string())?;
+        let (screen, src_width, src_height) = self.session.screen(self.cpu);
+        blit(
+            screen,
+            (src_width, src_height),
+            &mut buffer,
+            size.width as usize,
+            &view,
+        );
+        self.session.frame_drawn(self.cpu);
+        if self.session.needs_pacing() {
+            self.pacer.wait()
+        }
+        buffer.present().map_err(|e| e.to_string())
+    }
+
+    fn fail(&mut self, event_loop: &ActiveEventLoop, error: String) {
+        self.error = Some(error);
+        event_loop.exit()
+    }
+}
+
+impl ApplicationHandler for App<'_, '_> {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.gfx.is_none() {
+            match self.open(event_loop) {
+                Ok(gfx) => self.gfx = Some(gfx),
+                Err(e) => self.fail(event_loop, e),
+            }
+        }
+    }
+
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, _: WindowId, event: WindowEvent) {
+        match event {
+            WindowEvent::CloseRequested => event_loop.exit(),
+            WindowEvent::KeyboardInput { event, .. } => {
+                let PhysicalKey::Code(key) = event.physical_key else {
+                    return;
+                };
+                let pressed = event.state == ElementState::Pressed;
+                if key == KeyCode::Escape && pressed {
+                    return event_loop.exit();
+                }
+                match (self.keymap.hotkey(&key), pressed) {
+                    (Some(hotkey), true) => {
+                        if self.session.hotkey_down(self.cpu, hotkey, event.repeat) {
+                            let (width, height) = picture_size(
+                                self.session.display.scale,
+                                self.session.display.aspect,
+                            );
+                            if let Some(gfx) = &self.gfx {
+                                let _ = gfx
+                                    .window
+                                    .request_inner_size(PhysicalSize::new(width, height));
+                            }
+                        }
+                    }
+                    (Some(hotkey), false) => self.session.hotkey_up(hotkey),
+                    (None, _) if !event.repeat => {
+                        if let Some(input) = self.keymap.get(&key) {
+                            self.session.press(self.cpu, input, pressed)
+                        }
+                    }
+                    (None, _) => (),
+                }
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                if let Some(gfx) = &self.gfx {
+                    let view = self.view(&gfx.window);
+                    self.session
+                        .aim(self.cpu, &view, position.x as i32, position.y as i32)
+                }
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => self
+                .session
+                .trigger(self.cpu, state == ElementState::Pressed),
+            WindowEvent::RedrawRequested => {
+                if let Err(e) = self.frame() {
+                    self.fail(event_loop, e)
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn about_to_wait(&mut self, _: &ActiveEventLoop) {
+        if let Some(gfx) = &self.gfx {
+            gfx.window.request_redraw()
+        }
+    }
+}
+
+/**
+ * winit window drawn into with softbuffer, for when SDL isn't around. Same
+ * controls as the SDL frontend, runs until the window is closed or Escape is
+ * hit.
+ */
+pub fn run(
+    cpu: &mut CPU,
+    config: &Config,
+    title: &str,
+    crc32: u32,
+    movie: Option<&mut MovieSession>,
+    netplay: Option<&mut Netplay>,
+) -> Result<(), String> {
+    let mut session = Session::new(cpu, config, title, crc32, movie, netplay);
+    // softbuffer has no way to wait for vsync, the limiter is the next best thing
+    if session.display.pacing == Pacing::Vsync {
+        session.display.pacing = Pacing::Limiter
+    }
+    let pacer = FramePacer::new(session.frame_rate());
+    let event_loop = EventLoop::new().map_err(|e| e.to_string())?;
+    event_loop.set_control_flow(ControlFlow::Poll);
+    let mut app = App {
+        cpu,
+        session,
+        keymap: KeyMap::new(&config.input, &config.hotkeys, key_code),
+        title,
+        gfx: None,
+        pacer,
+        error: None,
+    };
+    event_loop.run_app(&mut app).map_err(|e| e.to_string())?;
+    // not every platform will say where the window is
+    if let Some(gfx) = &app.gfx {
+        if let Ok(position) = gfx.window.outer_position() {
+            let size = gfx.window.inner_size();
+            let geometry = WindowGeometry {
+                x: position.x,
+                y: position.y,
+                width: size.width,
+                height: size.height,
+            };
+            if let Err(e) = Config::save_window(&Config::default_path(), geometry) {
+                eprintln!("{}", e)
+            }
+        }
+    }
+    app.error.map_or(Ok(()), Err)
+}
+
+#[cfg(test)]
+mod winit_test {
+    use winit::keyboard::KeyCode;
+
+    use super::key_code;
+
+    #[test]
+    fn test_key_code() {
+        assert_eq!(key_code("Z"), Some(KeyCode::KeyZ));
+        assert_eq!(key_code("right shift"), Some(KeyCode::ShiftRight));
+        assert_eq!(key_code("Keypad 7"), Some(KeyCode::Numpad7));
+        assert_eq!(key_code("F12"), Some(KeyCode::F12));
+        assert_eq!(key_code("F13"), None);
+        assert_eq!(key_code("\\"), Some(KeyCode::Backslash));
+        assert_eq!(key_code("Menu"), None);
+    }
+}
@@ -0,0 +1,267 @@
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::{
+    cpu::CPU,
+    debug::{PpuRegisters, Registers},
+    debugger::{Debugger, Stop},
+    movie::MovieSession,
+    ppu::Frame,
+    profiler::ProfileStats,
+};
+
+/**
+ * How a headless run went.
+ */
+pub struct HeadlessReport {
+    pub frames: u64,
+    pub elapsed: Duration,
+    // where a movie being played stopped matching, see `MovieSession::desync`
+    pub desync: Option<(usize, usize)>,
+}
+
+impl HeadlessReport {
+    pub fn fps(&self) -> f64 {
+        self.frames as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/**
+ * CRC32 of the picture, two runs showing the same thing hash the same.
+ */
+pub fn frame_hash(frame: &Frame) -> u32 {
+    crc32fast::hash(frame.data())
+}
+
+/**
+ * Where a headless run ended up, for `--report json`.
+ */
+#[derive(Serialize)]
+pub struct MachineReport {
+    pub frames: u64,
+    pub elapsed_secs: f64,
+    pub cpu: Registers,
+    pub ppu: PpuRegisters,
+    pub frame_hash: String,
+    // blargg's status byte, for test roms that have one
+    pub test_status: Option<u8>,
+    // with --profile
+    pub profile: Option<ProfileStats>,
+    // the first frame a movie didn't match its checksum on
+    pub desync_frame: Option<usize>,
+}
+
+impl MachineReport {
+    pub fn new(cpu: &CPU, report: &HeadlessReport) -> MachineReport {
+        MachineReport {
+            frames: report.frames,
+            elapsed_secs: report.elapsed.as_secs_f64(),
+            cpu: cpu.registers(),
+            ppu: cpu.ppu().registers(),
+            frame_hash: format!("{:08x}", frame_hash(cpu.frame())),
+            test_status: test_status(cpu),
+            profile: cpu.profile_stats(),
+            desync_frame: report.desync.map(|(_, frame)| frame),
+        }
+    }
+}
+
+/**
+ * Emulate as fast as possible with no window, audio or input: for CI,
+ * benchmarks and ROM tests. Runs `frames` frames, or without a limit until
+ * the movie being played runs out (forever if there isn't one).
+ */
+pub fn run(
+    cpu: &mut CPU,
+    frames: Option<u64>,
+    mut movie: Option<&mut MovieSession>,
+) -> HeadlessReport {
+    let start = Instant::now();
+    let mut done = 0;
+    while frames.is_none_or(|frames| done < frames) {
+        if let Some(session) = movie.as_deref_mut() {
+            if session.advance(cpu) && frames.is_none() {
+                break;
+            }
+        }
+        cpu.run_frame();
+        done += 1;
+    }
+    if let Some(profiler) = cpu.profiler() {
+        profiler.flush()
+    }
+    HeadlessReport {
+        frames: done,
+        elapsed: start.elapsed(),
+        desync: movie.and_then(|session| session.desync()),
+    }
+}
+
+/**
+ * `run`, but through the debugger so its assertions are checked as it goes.
+ * Stops at the first one that fails, returning its index.
+ */
+pub fn run_asserting(
+    cpu: &mut CPU,
+    debugger: &mut Debugger,
+    frames: Option<u64>,
+    mut movie: Option<&mut MovieSession>,
+) -> (HeadlessReport, Option<usize>) {
+    let start = Instant::now();
+    let mut done = 0;
+    let mut failed = None;
+    while frames.is_none_or(|frames| done < frames) {
+        if let Some(session) = movie.as_deref_mut() {
+            if session.advance(cpu) && frames.is_none() {
+                break;
+            }
+        }
+        if let Stop::Assertion(idx) = debugger.run(cpu, 1) {
+            failed = Some(idx);
+            break;
+        }
+        done += 1;
+    }
+    let report = HeadlessReport {
+        frames: done,
+        elapsed: start.elapsed(),
+        desync: movie.and_then(|session| session.desync()),
+    };
+    (report, failed)
+}
+
+// blargg's test roms put their result in PRG RAM, vouched for by a signature
+const TEST_STATUS: u16 = 0x6000;
+const TEST_SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+// zero terminated, what it's printed on screen
+const TEST_MESSAGE: u16 = 0x6004;
+const TEST_RUNNING: u8 = 0x80;
+const TEST_NEEDS_RESET: u8 = 0x81;
+// the roms want the reset button held for at least 100ms
+const RESET_DELAY: u64 = 6;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TestResult {
+    Passed,
+    // the rom's result code, what it means is up to the rom
+    Failed(u8),
+    TimedOut,
+}
+
+pub fn test_status(cpu: &CPU) -> Option<u8> {
+    let signature = [
+        cpu.peek(TEST_STATUS + 1),
+        cpu.peek(TEST_STATUS + 2),
+        cpu.peek(TEST_STATUS + 3),
+    ];
+    (signature == TEST_SIGNATURE).then(|| cpu.peek(TEST_STATUS))
+}
+
+/**
+ * The text a test rom has written so far, the reason it failed when it has.
+ * Empty without the signature.
+ */
+pub fn test_message(cpu: &CPU) -> String {
+    if test_status(cpu).is_none() {
+        return String::new();
+    }
+    let bytes: Vec<u8> = (TEST_MESSAGE..0x8000)
+        .map(|addr| cpu.peek(addr))
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/**
+ * Run a test rom for up to `frames` frames, pressing reset when it asks.
+ * Returns how it went and the frames it took.
+ */
+pub fn run_test(cpu: &mut CPU, frames: u64) -> (TestResult, u64) {
+    let mut reset_at = None;
+    for frame in 0..frames {
+        cpu.run_frame();
+        match test_status(cpu) {
+            None | Some(TEST_RUNNING) => (),
+            Some(TEST_NEEDS_RESET) => match reset_at {
+                None => reset_at = Some(frame + RESET_DELAY),
+                Some(at) if frame >= at => {
+                    reset_at = None;
+                    cpu.soft_reset()
+                }
+                Some(_) => (),
+            },
+            Some(0) => return (TestResult::Passed, frame + 1),
+            Some(code) => return (TestResult::Failed(code), frame + 1),
+        }
+    }
+    (TestResult::TimedOut, frames)
+}
+
+#[cfg(test)]
+mod headless_test {
+    use crate::{
+        cpu::{cpu_with_program, CPU},
+        movie::{FrameInput, Movie, MovieSession},
+    };
+
+    use super::{MachineReport, TestResult};
+
+    fn make_cpu() -> CPU {
+        // JMP $8000
+        cpu_with_program(&[0x4c, 0x00, 0x80])
+    }
+
+    #[test]
+    fn test_fixed_frames() {
+        let mut cpu = make_cpu();
+        assert_eq!(super::run(&mut cpu, Some(5), None).frames, 5);
+    }
+
+    #[test]
+    fn test_until_movie_ends() {
+        let mut cpu = make_cpu();
+        let mut movie = Movie::new(0);
+        movie.frames = vec![FrameInput::default(); 3];
+        let mut session = MovieSession::play(movie);
+        assert_eq!(super::run(&mut cpu, None, Some(&mut session)).frames, 3);
+    }
+
+    #[test]
+    fn test_run_test() {
+        // writes the signature and a result code, then spins
+        let program = |code: u8| {
+            [
+                0xa9, 0xde, 0x8d, 0x01, 0x60, // LDA #$de, STA $6001
+                0xa9, 0xb0, 0x8d, 0x02, 0x60, // LDA #$b0, STA $6002
+                0xa9, 0x61, 0x8d, 0x03, 0x60, // LDA #$61, STA $6003
+                0xa9, code, 0x8d, 0x00, 0x60, // LDA #code, STA $6000
+                0xa9, 0x4f, 0x8d, 0x04, 0x60, // LDA #'O', STA $6004
+                0xa9, 0x4b, 0x8d, 0x05, 0x60, // LDA #'K', STA $6005
+                0x4c, 0x1e, 0x80, // JMP $801e
+            ]
+        };
+        let mut cpu = cpu_with_program(&program(0));
+        assert_eq!(super::run_test(&mut cpu, 10), (TestResult::Passed, 1));
+        assert_eq!(super::test_message(&cpu), "OK");
+        let mut cpu = cpu_with_program(&program(3));
+        assert_eq!(super::run_test(&mut cpu, 10), (TestResult::Failed(3), 1));
+        let mut cpu = cpu_with_program(&program(0x80));
+        assert_eq!(super::run_test(&mut cpu, 10), (TestResult::TimedOut, 10));
+        let mut cpu = make_cpu();
+        assert_eq!(super::run_test(&mut cpu, 10), (TestResult::TimedOut, 10));
+        assert_eq!(super::test_message(&cpu), "");
+    }
+
+    #[test]
+    fn test_machine_report() {
+        let mut cpu = make_cpu();
+        let report = super::run(&mut cpu, Some(2), None);
+        let json = serde_json::to_value(MachineReport::new(&cpu, &report)).unwrap();
+        assert_eq!(json["frames"], 2);
+        assert_eq!(json["cpu"]["pc"], 0x8000);
+        assert_eq!(json["frame_hash"].as_str().unwrap().len(), 8);
+        assert!(json["ppu"]["scanline"].is_u64());
+        assert!(json["test_status"].is_null());
+    }
+}
@@ -0,0 +1,173 @@
+use crate::cpu::CPU;
+
+pub const ROW_BYTES: usize = 16;
+
+/**
+ * Memory a hexdump can look at. The CPU space covers internal RAM and
+ * whatever the cartridge maps in, PRG-RAM included.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Space {
+    Cpu,
+    Ppu,
+    Oam,
+}
+
+impl Space {
+    pub const ALL: [Space; 3] = [Space::Cpu, Space::Ppu, Space::Oam];
+
+    /**
+     * "cpu", "ppu" or "oam", as on the command line.
+     */
+    pub fn parse(text: &str) -> Option<Space> {
+        Space::ALL
+            .into_iter()
+            .find(|space| space.name().eq_ignore_ascii_case(text))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Space::Cpu => "CPU",
+            Space::Ppu => "PPU",
+            Space::Oam => "OAM",
+        }
+    }
+
+    /**
+     * Every byte in the space, read without side effects. The PPU and APU
+     * registers read as 0.
+     */
+    pub fn read(self, cpu: &CPU) -> Vec<u8> {
+        match self {
+            Space::Cpu => cpu.read_range(0..=0xffff),
+            Space::Ppu => (0..0x4000).map(|addr| cpu.ppu().peek(addr)).collect(),
+            Space::Oam => cpu.ppu().oam().to_vec(),
+        }
+    }
+}
+
+/**
+ * The last two reads of a space, to show which bytes a frame changed.
+ */
+pub struct MemoryView {
+    pub space: Space,
+    previous: Vec<u8>,
+    current: Vec<u8>,
+}
+
+impl MemoryView {
+    pub fn new(space: Space) -> MemoryView {
+        MemoryView {
+            space,
+            previous: Vec::new(),
+            current: Vec::new(),
+        }
+    }
+
+    /**
+     * Switching spaces starts over, nothing is changed on the first read.
+     */
+    pub fn set_space(&mut self, space: Space) {
+        if space != self.space {
+            *self = MemoryView::new(space)
+        }
+    }
+
+    /**
+     * Once a frame with `Space::read`.
+     */
+    pub fn update(&mut self, bytes: Vec<u8>) {
+        self.previous = std::mem::replace(&mut self.current, bytes);
+        if self.previous.len() != self.current.len() {
+            self.previous = self.current.clone()
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.current.len().div_ceil(ROW_BYTES)
+    }
+
+    pub fn changed(&self, addr: usize) -> bool {
+        self.previous.get(addr) != self.current.get(addr)
+    }
+
+    /**
+     * Printable ASCII of a row, dots for the rest.
+     */
+    pub fn ascii(&self, row: usize) -> String {
+        self.row_bytes(row)
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect()
+    }
+
+    /**
+     * "$0010  00  01* 02 ...  ................", a * after the bytes the
+     * last frame changed.
+     */
+    pub fn row_text(&self, row: usize) -> String {
+        let start = row * ROW_BYTES;
+        let hex: String = self
+            .row_bytes(row)
+            .iter()
+            .enumerate()
+            .map(|(idx, b)| {
+                let mark = if self.changed(start + idx) { '*' } else { ' ' };
+                format!(" {:02X}{}", b, mark)
+            })
+            .collect();
+        format!("${:04X} {} {}", start, hex, self.ascii(row))
+    }
+
+    pub fn row_bytes(&self, row: usize) -> &[u8] {
+        let start = row * ROW_BYTES;
+        &self.current[start..(start + ROW_BYTES).min(self.current.len())]
+    }
+}
+
+#[cfg(test)]
+mod hexdump_test {
+    use super::{MemoryView, Space};
+
+    #[test]
+    fn test_changed() {
+        let mut view = MemoryView::new(Space::Oam);
+        view.update(vec![0; 256]);
+        assert!(!(0..256).any(|addr| view.changed(addr)));
+        let mut bytes = vec![0; 256];
+        bytes[0x42] = 7;
+        view.update(bytes.clone());
+        assert!(view.changed(0x42));
+        assert!(!view.changed(0x43));
+        // not changed any more once a frame leaves it alone
+        view.update(bytes);
+        assert!(!view.changed(0x42));
+        view.set_space(Space::Ppu);
+        assert_eq!(view.rows(), 0);
+    }
+
+    #[test]
+    fn test_rows() {
+        let mut view = MemoryView::new(Space::Cpu);
+        let mut bytes: Vec<u8> = (0x40..0x64).chain([0, 0x7f]).collect();
+        view.update(bytes.clone());
+        assert_eq!(view.rows(), 3);
+        assert_eq!(view.row_bytes(1), (0x50..0x60).collect::<Vec<u8>>());
+        assert_eq!(view.ascii(1), "PQRSTUVWXYZ[\\]^_");
+        // the last row is short, unprintables are dots
+        assert_eq!(view.ascii(2), "`abc..");
+        bytes[1] = 0;
+        view.update(bytes);
+        assert_eq!(
+            view.row_text(0),
+            "$0000  40  00* 42  43  44  45  46  47  48  49  4A  4B  4C  4D  4E  4F  @.BCDEFGHIJKLMNO"
+        );
+        assert_eq!(Space::parse("ppu"), Some(Space::Ppu));
+    }
+}
@@ -0,0 +1,71 @@
+// the core only needs alloc, anything with files, threads or the clock is std
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod assertion;
+pub mod bus;
+pub mod cartridge;
+pub mod cdl;
+pub mod cheats;
+#[cfg(feature = "std")]
+pub mod config;
+pub mod controller;
+#[cfg(feature = "std")]
+pub mod coverage;
+pub mod cpu;
+#[cfg(feature = "std")]
+pub mod crashdump;
+pub mod debug;
+#[cfg(feature = "std")]
+pub mod debugger;
+pub mod device;
+pub mod disasm;
+#[cfg(feature = "std")]
+pub mod emulation;
+pub mod error;
+pub mod events;
+pub mod fds;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "std")]
+pub mod fm2;
+#[cfg(feature = "std")]
+pub mod headless;
+#[cfg(feature = "std")]
+pub mod hexdump;
+pub mod machine;
+pub mod mapper;
+#[cfg(feature = "std")]
+pub mod movie;
+#[cfg(feature = "std")]
+pub mod netplay;
+pub mod power_pad;
+pub mod ppu;
+pub mod present;
+#[cfg(feature = "std")]
+pub mod profiler;
+#[cfg(feature = "std")]
+pub mod ramsearch;
+#[cfg(feature = "std")]
+pub mod repl;
+pub mod romdb;
+pub mod savestate;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod sink;
+#[cfg(feature = "std")]
+pub mod symbols;
+#[cfg(feature = "std")]
+pub mod tas;
+#[cfg(feature = "std")]
+pub mod testrunner;
+#[cfg(feature = "std")]
+pub mod tracediff;
+pub mod unif;
+pub mod utils;
+pub mod zapper;
+
+pub use error::{Error, Result};
+pub use machine::{Nes, NesBuilder};
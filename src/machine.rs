@@ -0,0 +1,791 @@
+use alloc::{boxed::Box, vec::Vec};
+use core::{error::Error, fmt, ops::RangeInclusive};
+
+#[cfg(feature = "std")]
+use crate::savestate::SaveState;
+use crate::{
+    bus::{Bus, PowerOnRam},
+    cartridge::{Cartridge, Region},
+    controller::Buttons,
+    cpu::CPU,
+    error::Result,
+    fds::DiskImage,
+    ppu::{Frame, Palette, PPU},
+    sink::{AudioSink, VideoSink},
+};
+
+// what `Nes::audio_samples` is at unless the builder says otherwise, mono
+pub const SAMPLE_RATE: u32 = 44100;
+// what `NesBuilder::sample_rate` takes, about what sound devices do
+pub const MIN_SAMPLE_RATE: u32 = 8000;
+pub const MAX_SAMPLE_RATE: u32 = 192000;
+
+/**
+ * How closely the machine follows the hardware where being exact is more
+ * than most games need.
+ */
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Accuracy {
+    #[default]
+    Standard,
+    // the hardware's warts too: DMC DMA clocking the controller ports
+    // twice, which games that read them once get wrong
+    Exact,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BuildError {
+    SampleRate(u32),
+    // a sample rate for a console that's not making sound
+    HeadlessSound,
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::SampleRate(rate) => write!(
+                f,
+                "Sample rate {}Hz is out of range ({}-{}Hz).",
+                rate, MIN_SAMPLE_RATE, MAX_SAMPLE_RATE
+            ),
+            BuildError::HeadlessSound => {
+                write!(
+                    f,
+                    "A headless console makes no sound to set a sample rate for."
+                )
+            }
+        }
+    }
+}
+
+impl Error for BuildError {}
+
+/**
+ * A `Nes` set up the way the embedder wants it, checked all at once by
+ * `build` rather than when something goes wrong later. The settings stick
+ * for every cartridge put in the console it builds.
+ */
+#[derive(Clone, Default)]
+pub struct NesBuilder {
+    region: Option<Region>,
+    palette: Option<Palette>,
+    accuracy: Accuracy,
+    power_on_ram: PowerOnRam,
+    sample_rate: Option<u32>,
+    headless: bool,
+    skip_rendering: bool,
+}
+
+impl NesBuilder {
+    pub fn new() -> NesBuilder {
+        NesBuilder::default()
+    }
+    /**
+     * NTSC, PAL or Dendy whatever the cartridge says.
+     */
+    pub fn region(mut self, region: Region) -> NesBuilder {
+        self.region = Some(region);
+        self
+    }
+    /**
+     * The colors the frames are drawn in, see `ppu::parse_palette`.
+     */
+    pub fn palette(mut self, palette: Palette) -> NesBuilder {
+        self.palette = Some(palette);
+        self
+    }
+    pub fn accuracy(mut self, accuracy: Accuracy) -> NesBuilder {
+        self.accuracy = accuracy;
+        self
+    }
+    pub fn power_on_ram(mut self, fill: PowerOnRam) -> NesBuilder {
+        self.power_on_ram = fill;
+        self
+    }
+    /**
+     * What `Nes::audio_samples` is at, `SAMPLE_RATE` when it's not set.
+     */
+    pub fn sample_rate(mut self, rate: u32) -> NesBuilder {
+        self.sample_rate = Some(rate);
+        self
+    }
+    /**
+     * Nobody's listening: no sound is made and the audio sink isn't called.
+     */
+    pub fn headless(mut self, headless: bool) -> NesBuilder {
+        self.headless = headless;
+        self
+    }
+    /**
+     * Nobody's watching either: the PPU keeps time but draws nothing, see
+     * `Nes::set_skip_rendering`.
+     */
+    pub fn skip_rendering(mut self, skip: bool) -> NesBuilder {
+        self.skip_rendering = skip;
+        self
+    }
+    pub fn build(self) -> Result<Nes> {
+        match self.sample_rate {
+            Some(_) if self.headless => Err(BuildError::HeadlessSound)?,
+            Some(rate) if !(MIN_SAMPLE_RATE..=MAX_SAMPLE_RATE).contains(&rate) => {
+                Err(BuildError::SampleRate(rate))?
+            }
+            _ => (),
+        }
+        let cpu = self.cpu();
+        Ok(Nes {
+            cpu,
+            samples: Vec::new(),
+            credit: 0.0,
+            video: None,
+            audio: None,
+            options: self,
+            frames: 0,
+        })
+    }
+    // an empty console to these settings, before anything's loaded
+    fn cpu(&self) -> CPU {
+        let mut ppu = PPU::new();
+        if let Some(palette) = &self.palette {
+            ppu.set_palette(palette)
+        }
+        ppu.set_skip_rendering(self.skip_rendering);
+        let mut cpu = CPU::new(Bus::new(ppu));
+        cpu.fill_ram(self.power_on_ram);
+        cpu.set_dmc_conflict(self.accuracy == Accuracy::Exact);
+        cpu
+    }
+}
+
+/**
+ * Running totals since the cartridge went in, for telling how fast the
+ * emulator's going: take two and see `per_second`.
+ */
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Counters {
+    pub frames: u64,
+    pub instructions: u64,
+    pub cpu_cycles: u64,
+    pub ppu_dots: u64,
+}
+
+impl Counters {
+    /**
+     * How many of each a second between `earlier` and these, `seconds`
+     * apart. Loading a save state can wind them back, which counts as none.
+     */
+    pub fn per_second(&self, earlier: &Counters, seconds: f64) -> Rates {
+        let rate = |now: u64, then: u64| now.saturating_sub(then) as f64 / seconds;
+        Rates {
+            frames: rate(self.frames, earlier.frames),
+            instructions: rate(self.instructions, earlier.instructions),
+            cpu_cycles: rate(self.cpu_cycles, earlier.cpu_cycles),
+            ppu_dots: rate(self.ppu_dots, earlier.ppu_dots),
+        }
+    }
+}
+
+/**
+ * `Counters` a second. A real NTSC console does 60.1 frames, about 1.79
+ * million cycles and 5.37 million dots.
+ */
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Rates {
+    pub frames: f64,
+    pub instructions: f64,
+    pub cpu_cycles: f64,
+    pub ppu_dots: f64,
+}
+
+/**
+ * The whole console, for embedding it: put a cartridge in, set the buttons,
+ * run a frame and take the picture and sound it made, or have them handed
+ * to sinks as they're made. For anything more (debugging, movies, cheats)
+ * there's the CPU underneath, which owns the rest of the machine.
+ */
+pub struct Nes {
+    cpu: CPU,
+    samples: Vec<i16>,
+    // leftover fraction of a sample carried to the next frame
+    credit: f64,
+    video: Option<Box<dyn VideoSink>>,
+    audio: Option<Box<dyn AudioSink>>,
+    // what it was built with, for every cartridge that goes in
+    options: NesBuilder,
+    // run since the cartridge went in
+    frames: u64,
+}
+
+impl Default for Nes {
+    fn default() -> Self {
+        Nes::new()
+    }
+}
+
+impl Nes {
+    /**
+     * A console with nothing in it, it runs but doesn't do much. See
+     * `NesBuilder` for one that's set up differently.
+     */
+    pub fn new() -> Nes {
+        NesBuilder::new().build().expect("the defaults are valid")
+    }
+    /**
+     * Hand every frame to `sink` as it's finished from here on, None stops.
+     * Sinks stay put when the cartridge is swapped.
+     */
+    pub fn set_video_sink(&mut self, sink: Option<Box<dyn VideoSink>>) {
+        self.video = sink
+    }
+    /**
+     * The same for the sound, once a frame after the picture.
+     */
+    pub fn set_audio_sink(&mut self, sink: Option<Box<dyn AudioSink>>) {
+        self.audio = sink
+    }
+    /**
+     * Swap `cartridge` in and turn the power on, anything from the last one
+     * is gone. Unsupported boards leave the console as it was.
+     */
+    pub fn insert_cartridge(&mut self, cartridge: Cartridge) -> Result<()> {
+        let mut cpu = self.options.cpu();
+        cpu.load_cartridge(cartridge)?;
+        self.replace_cpu(cpu);
+        Ok(())
+    }
+    /**
+     * A Famicom Disk System with `disk` in it, `bios` being its boot ROM. A
+     * BIOS that isn't 8KB leaves the console as it was.
+     */
+    pub fn insert_disk(&mut self, bios: Vec<u8>, disk: DiskImage) -> Result<()> {
+        let mut cpu = self.options.cpu();
+        cpu.load_disk(bios, disk)?;
+        self.replace_cpu(cpu);
+        Ok(())
+    }
+    // the region the builder was given beats the game's
+    fn replace_cpu(&mut self, mut cpu: CPU) {
+        if let Some(region) = self.options.region {
+            cpu.set_region(region)
+        }
+        self.cpu = cpu;
+        self.samples.clear();
+        self.credit = 0.0;
+        self.frames = 0
+    }
+    /**
+     * Off and on again with the same cartridge in. RAM and the registers
+     * start over, the cartridge's battery backed RAM doesn't.
+     */
+    pub fn power_cycle(&mut self) {
+        self.cpu.power_cycle()
+    }
+    /**
+     * The reset button. RAM is left as it was, games tell it apart from a
+     * power cycle that way.
+     */
+    pub fn soft_reset(&mut self) {
+        self.cpu.soft_reset()
+    }
+    /**
+     * Run as an NTSC, PAL or Dendy console, in place of what the cartridge
+     * said. Inserting another one goes back to its own (or the builder's).
+     */
+    pub fn set_region(&mut self, region: Region) {
+        self.cpu.set_region(region)
+    }
+    pub fn region(&self) -> Region {
+        self.cpu.region()
+    }
+    /**
+     * Run without drawing, for checking movies or test roms and for fast
+     * forwarding. Everything a game can notice (timing, NMIs, sprite 0 hits
+     * and overflow) stays exact, only the picture stops: `frame` is the last
+     * one drawn and the video sink isn't called. It sticks for the next
+     * cartridge too.
+     */
+    pub fn set_skip_rendering(&mut self, skip: bool) {
+        self.options.skip_rendering = skip;
+        self.cpu.ppu_mut().set_skip_rendering(skip)
+    }
+    /**
+     * A frame's worth, the finished picture. Once the CPU's halted on an
+     * opcode it doesn't have, frames still come but each run says so (see
+     * `CPU::unsupported_opcode`), `frame` has the picture.
+     */
+    pub fn run_frame(&mut self) -> Result<&Frame> {
+        self.cpu.run_frame();
+        self.frames += 1;
+        if let Some(video) = self.video.as_mut().filter(|_| !self.options.skip_rendering) {
+            video.frame(self.cpu.frame())
+        }
+        if !self.options.headless {
+            self.make_samples()
+        }
+        match self.cpu.unsupported_opcode() {
+            Some(unsupported) => Err(unsupported.into()),
+            None => Ok(self.cpu.frame()),
+        }
+    }
+    // silence until there's an APU to make some
+    fn make_samples(&mut self) {
+        self.credit += self.sample_rate() as f64 / self.region().frame_rate();
+        // whole samples, it's never negative
+        let count = self.credit as usize;
+        self.credit -= count as f64;
+        self.samples.clear();
+        self.samples.resize(count, 0);
+        if let Some(audio) = &mut self.audio {
+            audio.samples(&self.samples)
+        }
+    }
+    /**
+     * The last frame drawn, black before the first.
+     */
+    pub fn frame(&self) -> &Frame {
+        self.cpu.frame()
+    }
+    /**
+     * The sound of the last frame at `sample_rate`, a little over 733
+     * samples a frame at 44.1kHz (882 on PAL and Dendy). Nothing when it's
+     * headless.
+     */
+    pub fn audio_samples(&self) -> &[i16] {
+        &self.samples
+    }
+    /**
+     * Where the game locked the CPU up with a KIL opcode (or ran into one
+     * the CPU doesn't have, which `run_frame` reports), if it has. Frames
+     * keep coming (the picture stays as the game left it) until
+     * `soft_reset` or `power_cycle`.
+     */
+    pub fn halted(&self) -> Option<u16> {
+        self.cpu.halted()
+    }
+    /**
+     * What's been run so far, see `Counters`.
+     */
+    pub fn counters(&self) -> Counters {
+        let cycles = self.cpu.registers().cycles;
+        Counters {
+            frames: self.frames,
+            instructions: self.cpu.executed() as u64,
+            cpu_cycles: cycles,
+            ppu_dots: self.region().ppu_dots(cycles),
+        }
+    }
+    pub fn sample_rate(&self) -> u32 {
+        self.options.sample_rate.unwrap_or(SAMPLE_RATE)
+    }
+    /**
+     * Hold down exactly `buttons` on controller `player` (0 is the first)
+     * until they're set again.
+     */
+    pub fn set_controller(&mut self, player: usize, buttons: Buttons) {
+        self.cpu.set_controller_state(player, buttons)
+    }
+    /**
+     * What the CPU would read at `addr`, without the side effects a read
+     * can have (see `BusDevice::peek`), for tools, scripts and tests.
+     */
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.cpu.peek(addr)
+    }
+    /**
+     * Write `value` to `addr` as the CPU would, registers and all, without
+     * it taking any time.
+     */
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        self.cpu.poke(addr, value)
+    }
+    /**
+     * `peek` over `addrs`, a page of RAM or the whole bus at once.
+     */
+    pub fn read_range(&self, addrs: RangeInclusive<u16>) -> Vec<u8> {
+        self.cpu.read_range(addrs)
+    }
+    #[cfg(feature = "std")]
+    pub fn save_state(&self) -> SaveState {
+        self.cpu.save_state()
+    }
+    /**
+     * Back to `state`, which has to be from the cartridge that's in.
+     */
+    #[cfg(feature = "std")]
+    pub fn load_state(&mut self, state: &SaveState) -> Result<()> {
+        Ok(self.cpu.load_state(state)?)
+    }
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+    pub fn into_cpu(self) -> CPU {
+        self.cpu
+    }
+}
+
+#[cfg(test)]
+mod machine_test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::{
+        bus::PowerOnRam,
+        cartridge::{Cartridge, Mirroring, Region},
+        controller::Buttons,
+        cpu::UnsupportedOpcode,
+        error::Error,
+        fds::{DiskImage, FdsError, BIOS_SIZE},
+        headless::frame_hash,
+        ppu::Frame,
+        savestate::SaveState,
+    };
+
+    use super::{Accuracy, BuildError, Nes, NesBuilder};
+
+    // keeps whatever's held on controller 1 at $10, each frame
+    fn cartridge() -> Cartridge {
+        let mut prgrom = vec![0; 0x4000];
+        let program = [
+            0xa9, 0x80, 0x8d, 0x00, 0x20, // $8000: LDA #$80, STA $2000
+            0x4c, 0x05, 0x80, // $8005: JMP $8005
+            0xa9, 0x01, 0x8d, 0x16, 0x40, // $8008 NMI: LDA #$01, STA $4016
+            0xa9, 0x00, 0x8d, 0x16, 0x40, // LDA #$00, STA $4016
+            0xad, 0x16, 0x40, 0x29, 0x01, // LDA $4016, AND #$01
+            0x85, 0x10, 0x40, // STA $10, RTI
+        ];
+        prgrom[..program.len()].copy_from_slice(&program);
+        prgrom[0x3ffa..].copy_from_slice(&[0x08, 0x80, 0x00, 0x80, 0x00, 0x80]);
+        Cartridge {
+            prgrom,
+            mirroring: Mirroring::Horizontal,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_nes() {
+        let mut nes = Nes::new();
+        nes.insert_cartridge(cartridge()).unwrap();
+        nes.set_controller(0, Buttons::A);
+        let samples: usize = (0..60)
+            .map(|_| {
+                nes.run_frame().unwrap();
+                nes.audio_samples().len()
+            })
+            .sum();
+        assert!((44000..=44100).contains(&samples));
+        assert_eq!(nes.cpu().peek(0x10), 1);
+
+        let state = SaveState::from_bytes(&nes.save_state().to_bytes()).unwrap();
+        nes.set_controller(0, Buttons::empty());
+        nes.run_frame().unwrap();
+        assert_eq!(nes.cpu().peek(0x10), 0);
+        nes.load_state(&state).unwrap();
+        assert_eq!(nes.cpu().peek(0x10), 1);
+
+        nes.insert_cartridge(cartridge()).unwrap();
+        assert_eq!(nes.cpu().peek(0x10), 0);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut nes = Nes::new();
+        nes.insert_cartridge(cartridge()).unwrap();
+        nes.run_frame().unwrap();
+        let state = nes.save_state();
+        let unsupported = Cartridge {
+            mapper: 255,
+            ..cartridge()
+        };
+        let e = nes.insert_cartridge(unsupported).unwrap_err();
+        assert!(matches!(e, Error::Mapper(_)));
+        assert_eq!(e.to_string(), "Mapper 255 (unknown) is not supported.");
+        // still the first one
+        nes.load_state(&state).unwrap();
+
+        let mut other = Nes::new();
+        other
+            .insert_cartridge(Cartridge {
+                prgrom: vec![0; 0x8000],
+                ..cartridge()
+            })
+            .unwrap();
+        assert!(matches!(other.load_state(&state), Err(Error::State(_))));
+    }
+
+    #[test]
+    fn test_regions() {
+        // CPU cycles in two frames, give or take where the NMI lands
+        for (region, cycles, samples) in [
+            (Region::Ntsc, 59561, 733..=734),
+            (Region::Pal, 66495, 881..=882),
+            (Region::Dendy, 70928, 881..=882),
+        ] {
+            let mut nes = Nes::new();
+            nes.insert_cartridge(Cartridge {
+                region,
+                ..cartridge()
+            })
+            .unwrap();
+            assert_eq!(nes.region(), region);
+            nes.run_frame().unwrap();
+            let start = nes.cpu().registers().cycles;
+            nes.run_frame().unwrap();
+            assert!(samples.contains(&nes.audio_samples().len()));
+            nes.run_frame().unwrap();
+            let run = nes.cpu().registers().cycles - start;
+            assert!(run.abs_diff(cycles) < 20, "{} ran {} cycles", region, run);
+        }
+
+        // forced, until the next cartridge
+        let mut nes = Nes::new();
+        nes.insert_cartridge(cartridge()).unwrap();
+        nes.set_region(Region::Dendy);
+        nes.run_frame().unwrap();
+        assert_eq!(nes.region(), Region::Dendy);
+        nes.insert_cartridge(cartridge()).unwrap();
+        assert_eq!(nes.region(), Region::Ntsc);
+    }
+
+    #[test]
+    fn test_memory() {
+        let mut nes = Nes::new();
+        nes.insert_cartridge(cartridge()).unwrap();
+        nes.poke(0x0812, 0x42);
+        assert_eq!(nes.peek(0x12), 0x42);
+        let ram = nes.read_range(0..=0x7ff);
+        assert_eq!((ram.len(), ram[0x12]), (0x800, 0x42));
+        assert_eq!(
+            nes.read_range(0xfffa..=0xffff),
+            [0x08, 0x80, 0x00, 0x80, 0x00, 0x80]
+        );
+
+        // writes reach the PPU, its registers peek as 0
+        for byte in [0x3f, 0x00] {
+            nes.poke(0x2006, byte)
+        }
+        nes.poke(0x2007, 0x21);
+        assert_eq!(nes.cpu().ppu().peek(0x3f00), 0x21);
+        assert_eq!(nes.peek(0x2007), 0);
+    }
+
+    #[test]
+    fn test_resets() {
+        let mut nes = Nes::new();
+        nes.insert_cartridge(cartridge()).unwrap();
+        nes.cpu_mut().fill_ram(PowerOnRam::Ones);
+        nes.set_controller(0, Buttons::A);
+        nes.run_frame().unwrap();
+        nes.run_frame().unwrap();
+        let sp = nes.cpu().registers().sp;
+        assert_eq!(nes.cpu().peek(0x10), 1);
+        assert_eq!(nes.cpu().ppu().registers().ctrl, 0x80);
+
+        // RAM stays, the PPU's control register doesn't
+        nes.soft_reset();
+        assert_eq!(nes.cpu().registers().pc, 0x8000);
+        assert_eq!(nes.cpu().registers().sp, sp.wrapping_sub(3));
+        assert_eq!(nes.cpu().peek(0x10), 1);
+        assert_eq!(nes.cpu().ppu().registers().ctrl, 0);
+
+        nes.run_frame().unwrap();
+        nes.power_cycle();
+        let registers = nes.cpu().registers();
+        assert_eq!((registers.pc, registers.sp), (0x8000, 0xfd));
+        assert_eq!(nes.cpu().peek(0x10), 0xff);
+        assert_eq!(nes.cpu().ppu().registers().ctrl, 0);
+        nes.run_frame().unwrap();
+        nes.run_frame().unwrap();
+        assert_eq!(nes.cpu().peek(0x10), 1);
+    }
+
+    #[test]
+    fn test_builder() {
+        let mut nes = NesBuilder::new()
+            .region(Region::Pal)
+            .power_on_ram(PowerOnRam::Ones)
+            .sample_rate(48000)
+            .accuracy(Accuracy::Exact)
+            .build()
+            .unwrap();
+        nes.insert_cartridge(cartridge()).unwrap();
+        nes.run_frame().unwrap();
+        assert_eq!(nes.region(), Region::Pal);
+        assert_eq!(nes.peek(0x700), 0xff);
+        assert_eq!(nes.audio_samples().len(), 959);
+
+        let mut nes = NesBuilder::new().headless(true).build().unwrap();
+        nes.insert_cartridge(cartridge()).unwrap();
+        nes.run_frame().unwrap();
+        assert!(nes.audio_samples().is_empty());
+
+        for (builder, error) in [
+            (
+                NesBuilder::new().sample_rate(100),
+                BuildError::SampleRate(100),
+            ),
+            (
+                NesBuilder::new().headless(true).sample_rate(44100),
+                BuildError::HeadlessSound,
+            ),
+        ] {
+            match builder.build() {
+                Err(Error::Build(e)) => assert_eq!(e, error),
+                _ => panic!("expected {}", error),
+            }
+        }
+        assert_eq!(
+            BuildError::SampleRate(100).to_string(),
+            "Sample rate 100Hz is out of range (8000-192000Hz)."
+        );
+    }
+
+    #[test]
+    fn test_insert_disk() {
+        let mut nes = Nes::new();
+        nes.insert_cartridge(cartridge()).unwrap();
+        let disk = || DiskImage { sides: Vec::new() };
+        match nes.insert_disk(vec![0; 0x1000], disk()) {
+            Err(Error::Disk(FdsError::BadBios { size })) => assert_eq!(size, 0x1000),
+            _ => panic!("expected a bad BIOS"),
+        }
+        // the cartridge's still in
+        nes.set_controller(0, Buttons::A);
+        nes.run_frame().unwrap();
+        nes.run_frame().unwrap();
+        assert_eq!(nes.peek(0x10), 1);
+
+        // $E000: JMP $E000
+        let mut bios = vec![0; BIOS_SIZE];
+        bios[..3].copy_from_slice(&[0x4c, 0x00, 0xe0]);
+        bios[0x1ffc..0x1ffe].copy_from_slice(&[0x00, 0xe0]);
+        nes.insert_disk(bios, disk()).unwrap();
+        nes.run_frame().unwrap();
+        assert_eq!(nes.cpu().registers().pc, 0xe000);
+    }
+
+    #[test]
+    fn test_unsupported_opcode() {
+        // SLO in place of the JMP, the frame still comes
+        let mut slo = cartridge();
+        slo.prgrom[5] = 0x03;
+        let mut nes = Nes::new();
+        nes.insert_cartridge(slo).unwrap();
+        let unsupported = UnsupportedOpcode {
+            opcode: 0x03,
+            pc: 0x8005,
+        };
+        for _ in 0..2 {
+            match nes.run_frame() {
+                Err(Error::Opcode(e)) => assert_eq!(e, unsupported),
+                _ => panic!("expected {}", unsupported),
+            }
+        }
+        assert_eq!(nes.halted(), Some(0x8005));
+        assert_eq!(nes.counters().frames, 2);
+        assert_eq!(
+            unsupported.to_string(),
+            "Opcode $03 at $8005 is not supported, the CPU has halted."
+        );
+
+        // a KIL's the game's doing, not an error
+        let mut kil = cartridge();
+        kil.prgrom[5] = 0x02;
+        nes.insert_cartridge(kil).unwrap();
+        nes.run_frame().unwrap();
+        assert_eq!(nes.halted(), Some(0x8005));
+    }
+
+    #[test]
+    fn test_counters() {
+        let mut nes = Nes::new();
+        nes.insert_cartridge(cartridge()).unwrap();
+        let start = nes.counters();
+        for _ in 0..10 {
+            nes.run_frame().unwrap();
+        }
+        let counters = nes.counters();
+        assert_eq!(counters.frames, 10);
+        assert_eq!(counters.ppu_dots, counters.cpu_cycles * 3);
+        // mostly the 3 cycle JMP
+        assert!(counters.instructions > counters.cpu_cycles / 4);
+        let rates = counters.per_second(&start, 0.5);
+        assert_eq!(rates.frames, 20.0);
+        assert_eq!(
+            rates.cpu_cycles,
+            (counters.cpu_cycles - start.cpu_cycles) as f64 * 2.0
+        );
+        assert_eq!(start.per_second(&counters, 1.0).instructions, 0.0);
+
+        nes.insert_cartridge(cartridge()).unwrap();
+        assert_eq!(nes.counters().frames, 0);
+    }
+
+    #[test]
+    fn test_skip_rendering() {
+        // everything on with solid tiles, sprite 0 hits the background at the
+        // top left and the NMI keeps PPUSTATUS at $10
+        let mut prgrom = vec![0; 0x4000];
+        let program = [
+            0xa9, 0x80, 0x8d, 0x00, 0x20, // $8000: LDA #$80, STA $2000
+            0xa9, 0x1e, 0x8d, 0x01, 0x20, // LDA #$1E, STA $2001
+            0x4c, 0x0a, 0x80, // $800A: JMP $800A
+            0xad, 0x02, 0x20, 0x85, 0x10, 0x40, // $800D NMI: LDA $2002, STA $10, RTI
+        ];
+        prgrom[..program.len()].copy_from_slice(&program);
+        prgrom[0x3ffa..].copy_from_slice(&[0x0d, 0x80, 0x00, 0x80, 0x00, 0x80]);
+        let cartridge = || Cartridge {
+            prgrom: prgrom.clone(),
+            chrrom: vec![0xff; 0x2000],
+            mirroring: Mirroring::Horizontal,
+            ..Default::default()
+        };
+        let mut drawn = Nes::new();
+        drawn.insert_cartridge(cartridge()).unwrap();
+        let mut skipped = NesBuilder::new().skip_rendering(true).build().unwrap();
+        skipped.insert_cartridge(cartridge()).unwrap();
+        for _ in 0..3 {
+            drawn.run_frame().unwrap();
+            skipped.run_frame().unwrap();
+        }
+        assert_eq!(skipped.peek(0x10) & 0x40, 0x40);
+        assert_eq!(skipped.peek(0x10), drawn.peek(0x10));
+        assert_eq!(skipped.counters(), drawn.counters());
+        assert_ne!(frame_hash(drawn.frame()), frame_hash(&Frame::new()));
+        assert_eq!(frame_hash(skipped.frame()), frame_hash(&Frame::new()));
+
+        // drawing again from the next frame
+        skipped.set_skip_rendering(false);
+        skipped.run_frame().unwrap();
+        drawn.run_frame().unwrap();
+        assert_eq!(frame_hash(skipped.frame()), frame_hash(drawn.frame()));
+    }
+
+    #[test]
+    fn test_sinks() {
+        let mut nes = Nes::new();
+        nes.insert_cartridge(cartridge()).unwrap();
+        let hashes = Rc::new(RefCell::new(Vec::new()));
+        let samples = Rc::new(RefCell::new(0));
+        let sink = hashes.clone();
+        nes.set_video_sink(Some(Box::new(move |frame: &Frame| {
+            sink.borrow_mut().push(frame_hash(frame))
+        })));
+        let sink = samples.clone();
+        nes.set_audio_sink(Some(Box::new(move |samples: &[i16]| {
+            *sink.borrow_mut() += samples.len()
+        })));
+        for _ in 0..3 {
+            let hash = frame_hash(nes.run_frame().unwrap());
+            assert_eq!(hashes.borrow().last(), Some(&hash));
+        }
+        assert_eq!(hashes.borrow().len(), 3);
+        assert_eq!(*samples.borrow(), 3 * 733 + 2);
+
+        nes.set_video_sink(None);
+        nes.run_frame().unwrap();
+        assert_eq!(hashes.borrow().len(), 3);
+    }
+}
@@ -1,24 +1,922 @@
-use bus::Bus;
-use cartridge::Cartridge;
-use cpu::CPU;
-use ppu::PPU;
+use std::{
+    any::Any,
+    fs::{self, File},
+    io::{self, BufWriter, IsTerminal, Write},
+    net::TcpListener,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+};
 
-extern crate sdl2;
+use nes::cartridge::Cartridge;
+use nes::cdl::CodeDataLog;
+use nes::cheats::{CheatEdit, Cheats};
+use nes::config::{Config, Resume};
+use nes::coverage::Coverage;
+use nes::cpu::CPU;
+use nes::crashdump::{panic_message, CrashDump};
+use nes::debugger::{Debugger, Stop};
+use nes::headless::MachineReport;
+use nes::hexdump::{MemoryView, Space};
+use nes::movie::{Movie, MovieMode, MovieSession, MovieStart};
+use nes::netplay::Netplay;
+use nes::savestate::SaveState;
+use nes::symbols::Symbols;
+use nes::testrunner::Verdict;
+use nes::tracediff::DiffResult;
+use nes::{disasm, fds, headless, mapper, netplay, repl, symbols, testrunner, tracediff, Nes};
 
-mod bus;
-mod cartridge;
-mod cpu;
-mod debug;
-mod ppu;
-mod utils;
+use binding::Device;
+use cli::{Command, ReportFormat, RunOptions};
+use overrides::GameOverrides;
+
+mod binding;
+mod cli;
+mod frontend;
+mod overrides;
+
+// Disk System BIOS, not something we can ship
+const FDS_BIOS_PATH: &str = "./disksys.rom";
+
+/**
+ * File name without the directory or extension, for games we don't know.
+ */
+fn file_stem(file_path: &str) -> String {
+    Path::new(file_path)
+        .file_stem()
+        .map_or(file_path.to_string(), |stem| {
+            stem.to_string_lossy().into_owned()
+        })
+}
+
+/**
+ * The game's own settings, if it has any. A file that doesn't parse is
+ * reported and ignored.
+ */
+fn game_overrides(config_path: &Path, crc32: u32) -> Option<GameOverrides> {
+    let path = GameOverrides::path(config_path, crc32);
+    match GameOverrides::load(&path) {
+        Ok(Some(overrides)) => {
+            eprintln!("Using the settings in {}", path.display());
+            Some(overrides)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            eprintln!("{} ({}), ignoring it.", e, path.display());
+            None
+        }
+    }
+}
+
+/**
+ * The game's cheats, none if the file won't load.
+ */
+fn load_cheats(config_path: &Path, crc32: u32) -> Cheats {
+    let path = Cheats::path(config_path, crc32);
+    Cheats::load(&path).unwrap_or_else(|e| {
+        eprintln!("{} ({}), ignoring it.", e, path.display());
+        Cheats::new()
+    })
+}
+
+/**
+ * Say why the game couldn't be loaded and give up.
+ */
+fn load_failed(e: nes::Error) -> ! {
+    eprintln!("{}", e);
+    if let nes::Error::Mapper(_) = e {
+        let supported: Vec<String> = mapper::supported_mappers()
+            .iter()
+            .map(|m| format!("{} ({})", m.number, m.name))
+            .collect();
+        eprintln!("Supported mappers: {}", supported.join(", "));
+    }
+    std::process::exit(1)
+}
+
+/**
+ * Disk images go through the Disk System BIOS instead of a cartridge mapper.
+ * Returns the CRC32 of the disk as loaded, save included, the start of a
+ * window title and the game's overrides.
+ */
+fn load_disk(file_path: &str, config_path: &Path) -> (CPU, u32, String, Option<GameOverrides>) {
+    let (bios, disk) = fds::DiskImage::load_with_save(file_path)
+        .and_then(|disk| Ok((fds::load_bios(FDS_BIOS_PATH)?, disk)))
+        .unwrap_or_else(|e| load_failed(e.into()));
+    eprintln!("Loaded {} ({} disk sides)", file_path, disk.sides.len());
+    let crc32 = crc32fast::hash(&disk.to_bytes());
+    let mut nes = Nes::new();
+    nes.insert_disk(bios, disk).unwrap_or_else(|e| load_failed(e));
+    let title = format!("{} - FDS", file_stem(file_path));
+    let overrides = game_overrides(config_path, crc32);
+    (nes.into_cpu(), crc32, title, overrides)
+}
+
+fn load_cartridge(
+    file_path: &str,
+    config_path: &Path,
+) -> (CPU, u32, String, Option<GameOverrides>) {
+    let mut cartridge = Cartridge::load(file_path).unwrap_or_else(|e| load_failed(e.into()));
+    // stdout is kept for reports
+    eprintln!(
+        "Loaded {} (CRC32 {:08x})",
+        cartridge.title.as_deref().unwrap_or(file_path),
+        cartridge.hashes.crc32
+    );
+    let crc32 = cartridge.hashes.crc32;
+    let overrides = game_overrides(config_path, crc32);
+    if let Some(overrides) = &overrides {
+        overrides.apply_to_cartridge(&mut cartridge)
+    }
+    let name = match &cartridge.title {
+        Some(title) => title.clone(),
+        None => file_stem(file_path),
+    };
+    let title = format!("{} - mapper {}", name, cartridge.mapper);
+    let mut nes = Nes::new();
+    if let Err(e) = nes.insert_cartridge(cartridge) {
+        load_failed(e)
+    }
+    (nes.into_cpu(), crc32, title, overrides)
+}
+
+/**
+ * Movie to play back, put `cpu` where it starts, or an empty one to record
+ * into when only --record is given.
+ */
+fn start_movie(
+    cpu: &mut CPU,
+    play_path: Option<&str>,
+    record: bool,
+    from_state: bool,
+    crc32: u32,
+) -> Option<MovieSession> {
+    let Some(path) = play_path else {
+        // from power on, right after the rom is loaded, or the --load-state
+        // the machine is in, which goes in the movie
+        return record.then(|| {
+            let mut movie = Movie::new(crc32);
+            if from_state {
+                movie.start = MovieStart::Embedded(cpu.save_state().to_bytes())
+            }
+            MovieSession::record(movie)
+        });
+    };
+    let movie = match Movie::load(path) {
+        Ok(movie) => movie,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1)
+        }
+    };
+    match movie.rom_crc32 {
+        Some(movie_crc32) if movie_crc32 != crc32 => eprintln!(
+            "Movie was recorded against CRC32 {:08x}, this rom is {:08x}; it will probably desync.",
+            movie_crc32, crc32
+        ),
+        _ => (),
+    }
+    let started = movie.start_state(path).and_then(|state| match state {
+        Some(state) => Ok(cpu.load_state(&state)?),
+        None => Ok(()),
+    });
+    if let Err(e) = started {
+        eprintln!("{}", e);
+        std::process::exit(1)
+    }
+    Some(MovieSession::play(movie))
+}
+
+/**
+ * Load the state the game was left in last time, asking first with
+ * `Resume::Ask`. Without a terminal to ask in the answer is yes.
+ */
+fn resume(cpu: &mut CPU, path: &Path, resume: Resume) {
+    if resume == Resume::Ask && io::stdin().is_terminal() {
+        print!("Resume where the game was left? [Y/n] ");
+        let _ = io::stdout().flush();
+        let mut answer = String::new();
+        let _ = io::stdin().read_line(&mut answer);
+        if answer.trim().to_ascii_lowercase().starts_with('n') {
+            return;
+        }
+    }
+    match SaveState::load(path).and_then(|s| cpu.load_state(&s)) {
+        Ok(()) => println!("Resumed where the game was left, --fresh starts over"),
+        Err(e) => eprintln!("{} ({}), starting from power on.", e, path.display()),
+    }
+}
+
+/**
+ * Wait for the other player with --host, or join them with --join. Either way
+ * both machines are the host's from here.
+ */
+fn start_netplay(
+    cpu: &mut CPU,
+    crc32: u32,
+    host: Option<u16>,
+    join: Option<&str>,
+    delay: u8,
+) -> Option<Netplay> {
+    let netplay = match (host, join) {
+        (None, None) => return None,
+        (Some(_), Some(_)) => Err("Either --host or --join, not both".to_string()),
+        (Some(port), None) => TcpListener::bind(("0.0.0.0", port))
+            .map_err(|e| format!("Unable to listen on port {}: {}", port, e))
+            .and_then(|listener| {
+                println!("Waiting for player 2 on port {}", port);
+                Netplay::host(&listener, cpu, crc32, delay).map_err(|e| e.to_string())
+            }),
+        (None, Some(addr)) => {
+            println!("Joining {}", addr);
+            Netplay::join(addr, cpu, crc32).map_err(|e| e.to_string())
+        }
+    };
+    match netplay {
+        Ok(netplay) => Some(netplay),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1)
+        }
+    }
+}
+
+#[cfg(feature = "scripting")]
+fn load_script(cpu: &mut CPU, path: &str) {
+    match nes::script::Script::load(path) {
+        Ok(script) => cpu.set_hooks(Some(Box::new(script))),
+        Err(e) => {
+            eprintln!("{} ({})", e, path);
+            std::process::exit(1)
+        }
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+fn load_script(_cpu: &mut CPU, path: &str) {
+    eprintln!(
+        "Unable to run {}, built without the scripting feature",
+        path
+    );
+    std::process::exit(1)
+}
+
+/**
+ * Text, or json when the file ends in .json.
+ */
+fn save_coverage(coverage: &Coverage, path: &str) {
+    let text = if path.to_ascii_lowercase().ends_with(".json") {
+        // plain data, this can't fail
+        serde_json::to_string_pretty(coverage).unwrap()
+    } else {
+        coverage.to_string()
+    };
+    match fs::write(path, text + "\n") {
+        Ok(()) => eprintln!(
+            "Executed {:.1}% of PRG ROM, written to {}",
+            coverage.percent(),
+            path
+        ),
+        Err(e) => eprintln!("Unable to save {}: {}", path, e),
+    }
+}
+
+fn run(options: RunOptions) {
+    let RunOptions {
+        rom: mut file_path,
+        record: record_path,
+        play: play_path,
+        scale,
+        aspect,
+        region,
+        fit,
+        borderless,
+        headless,
+        frames,
+        report,
+        profile,
+        trace,
+        symbols: symbol_files,
+        script,
+        cdl,
+        coverage,
+        assertions,
+        load_state,
+        save_state,
+        host,
+        join,
+        input_delay,
+        fresh,
+        recent,
+        audio_device,
+        audio_buffer,
+    } = options;
+
+    let config_path = Config::default_path();
+    let (mut config, config_loaded) = match Config::load_or_create(&config_path) {
+        Ok(config) => (config, true),
+        Err(e) => {
+            eprintln!("{} ({}), using the defaults.", e, config_path.display());
+            (Config::default(), false)
+        }
+    };
+    match recent {
+        Some(None) => {
+            for (idx, rom) in config.recent.roms.iter().enumerate() {
+                println!("{:>2}: {}", idx + 1, rom)
+            }
+            return;
+        }
+        Some(Some(n)) => match config.recent.roms.get(n.wrapping_sub(1)) {
+            Some(rom) => file_path = Some(rom.clone()),
+            None => {
+                eprintln!("There's no recent rom {}", n);
+                std::process::exit(1)
+            }
+        },
+        None => (),
+    }
+
+    let file_path = file_path.unwrap_or_else(|| "./test_roms/cpu/nestest.nes".to_string());
+    let (mut cpu, crc32, name, overrides) = if file_path.to_ascii_lowercase().ends_with(".fds") {
+        load_disk(&file_path, &config_path)
+    } else {
+        load_cartridge(&file_path, &config_path)
+    };
+    // saved before the command line options below, those are just for this run.
+    // a config that didn't parse is left alone for the user to fix
+    if config_loaded {
+        config.recent.add(&file_path);
+        if let Err(e) = config.save(&config_path) {
+            eprintln!("{}", e)
+        }
+    }
+    let netplaying = host.is_some() || join.is_some();
+    if netplaying && (headless || play_path.is_some()) {
+        eprintln!("Netplay needs a window and both players' controllers, not --headless or --play");
+        std::process::exit(1)
+    }
+    // they'd only be on one side, the other player's game would go its own way
+    if !netplaying {
+        cpu.set_cheats(load_cheats(&config_path, crc32))
+    }
+    // then the game's settings, and the command line wins over both
+    if let Some(overrides) = &overrides {
+        overrides.apply_to_config(&mut config)
+    }
+    config.display.scale = scale.unwrap_or(config.display.scale);
+    config.display.aspect = aspect.unwrap_or(config.display.aspect);
+    config.display.fit |= fit;
+    config.display.show_profile |= profile;
+    config.display.borderless |= borderless;
+    // a size asked for beats the one remembered from last time
+    if scale.is_some() {
+        config.display.window = None
+    }
+    config.audio.device = audio_device.or(config.audio.device);
+    config.audio.buffer_frames = audio_buffer.unwrap_or(config.audio.buffer_frames);
+    cpu.fill_ram(config.emulation.power_on_ram);
+    if let Some(region) = region.or(config.emulation.region) {
+        cpu.set_region(region)
+    }
+    let title = format!("{} - {}", name, cpu.region());
+
+    // buffered, a trace runs to gigabytes quickly
+    if let Some(path) = &trace {
+        match File::create(path) {
+            Ok(file) => cpu.set_trace(Some(Box::new(BufWriter::new(file)))),
+            Err(e) => {
+                eprintln!("Unable to create {}: {}", path, e);
+                std::process::exit(1)
+            }
+        }
+    }
+    if trace.is_some() || !symbol_files.is_empty() {
+        let banks = cpu.rom_sizes().0 / symbols::BANK_SIZE;
+        let symbols = load_symbols(&file_path, banks, &symbol_files);
+        cpu.set_symbols((!symbols.is_empty()).then_some(symbols))
+    }
+    if let Some(path) = &script {
+        load_script(&mut cpu, path)
+    }
+    // added to the one from last time, coverage is counted from the log too
+    if cdl.is_some() || coverage.is_some() {
+        let (prg, chr) = cpu.rom_sizes();
+        if prg == 0 {
+            eprintln!("There's no PRG ROM to log in {}", file_path);
+            std::process::exit(1)
+        }
+        let log = match &cdl {
+            Some(path) => CodeDataLog::load_or_new(Path::new(path), prg, chr).unwrap_or_else(|e| {
+                eprintln!("Unable to load {}: {}", path, e);
+                std::process::exit(1)
+            }),
+            None => CodeDataLog::new(prg, chr),
+        };
+        cpu.set_cdl(Some(log));
+    }
+    if let Some(path) = &load_state {
+        if let Err(e) = SaveState::load(Path::new(path)).and_then(|s| cpu.load_state(&s)) {
+            eprintln!("{} ({})", e, path);
+            std::process::exit(1)
+        }
+    }
+    // only windowed play on its own, anything else has its own idea of where
+    // the game starts
+    let resume_path = SaveState::resume_path(&config_path, crc32);
+    let resuming = config.recent.resume != Resume::Off
+        && !headless
+        && !netplaying
+        && play_path.is_none()
+        && record_path.is_none();
+    if resuming && !fresh && load_state.is_none() && resume_path.exists() {
+        resume(&mut cpu, &resume_path, config.recent.resume)
+    }
+    let delay = input_delay.unwrap_or(netplay::DEFAULT_DELAY);
+    let mut netplay = start_netplay(&mut cpu, crc32, host, join.as_deref(), delay);
+    let mut movie = start_movie(
+        &mut cpu,
+        play_path.as_deref(),
+        record_path.is_some(),
+        load_state.is_some(),
+        crc32,
+    );
+    let mut debugger = Debugger::new();
+    debugger.assertions = assertions;
+    let mut failed = None;
+    let crashed = panic::catch_unwind(AssertUnwindSafe(|| {
+        if headless {
+            config.input.plug_into(&mut cpu);
+            cpu.set_profiling(profile);
+            // only the JSON report has a look at the picture
+            cpu.ppu_mut().set_skip_rendering(report == ReportFormat::Text);
+            let run = if debugger.assertions.is_empty() {
+                headless::run(&mut cpu, frames, movie.as_mut())
+            } else {
+                let (run, assertion) =
+                    headless::run_asserting(&mut cpu, &mut debugger, frames, movie.as_mut());
+                failed = assertion;
+                run
+            };
+            // into the debugger where it went wrong, straight out again
+            // without a terminal
+            if let Some(idx) = failed {
+                let stop = Stop::Assertion(idx);
+                let stdin = io::stdin().lock();
+                if let Err(e) = repl::run(&mut cpu, &mut debugger, stop, stdin, io::stdout()) {
+                    eprintln!("{}", e)
+                }
+            }
+            match report {
+                ReportFormat::Text => {
+                    println!(
+                        "Ran {} frames in {:.2}s ({:.1} FPS)",
+                        run.frames,
+                        run.elapsed.as_secs_f64(),
+                        run.fps()
+                    );
+                    if let Some(unsupported) = cpu.unsupported_opcode() {
+                        println!("{}", unsupported)
+                    } else if let Some(pc) = cpu.halted() {
+                        println!("CPU halted at ${:04X}", pc)
+                    }
+                    for line in cpu.profile_stats().iter().flat_map(|stats| stats.lines()) {
+                        println!("{}", line)
+                    }
+                }
+                ReportFormat::Json => {
+                    let report = MachineReport::new(&cpu, &run);
+                    // plain data, this can't fail
+                    println!("{}", serde_json::to_string_pretty(&report).unwrap())
+                }
+            }
+            match run.desync {
+                Some((matched, frame)) => Err(format!(
+                    "Movie desynced between frames {} and {}",
+                    matched, frame
+                )),
+                None => Ok(()),
+            }
+        } else {
+            frontend::run(
+                &mut cpu,
+                &config,
+                &title,
+                crc32,
+                movie.as_mut(),
+                netplay.as_mut(),
+            )
+        }
+    }));
+    let result = match crashed {
+        Ok(result) => result,
+        Err(panic) => {
+            write_crash_dump(&cpu, &file_path, panic.as_ref());
+            panic::resume_unwind(panic)
+        }
+    };
+    // a played back movie is only written out once it's been taken over
+    if let (Some(path), Some(session)) = (&record_path, &movie) {
+        if session.mode() == MovieMode::Recording {
+            match session.movie.save(path) {
+                Ok(()) => eprintln!("Recorded {} frames to {}", session.frame(), path),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    }
+    // flushes it
+    cpu.set_trace(None);
+    let log = cpu.set_cdl(None);
+    if let (Some(path), Some(log)) = (&cdl, &log) {
+        let (code, data) = log.counts();
+        match log.save(Path::new(path)) {
+            Ok(()) => eprintln!(
+                "Logged {} bytes of code and {} of data out of {} to {}",
+                code,
+                data,
+                cpu.rom_sizes().0,
+                path
+            ),
+            Err(e) => eprintln!("Unable to save {}: {}", path, e),
+        }
+    }
+    if let (Some(path), Some(log)) = (&coverage, &log) {
+        save_coverage(&Coverage::new(log), path)
+    }
+    if let Some(path) = &save_state {
+        match cpu.save_state().save(Path::new(path)) {
+            Ok(()) => eprintln!("Saved the state to {}", path),
+            Err(e) => eprintln!("{} ({})", e, path),
+        }
+    }
+    // a frontend that didn't start has nothing worth keeping
+    if resuming && result.is_ok() {
+        if let Err(e) = cpu.save_state().save(&resume_path) {
+            eprintln!("{} ({})", e, resume_path.display())
+        }
+    }
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1)
+    }
+
+    // whatever the game wrote to disk outlives the session
+    if let Some(data) = cpu.save_data() {
+        if let Err(e) = fds::write_save(&file_path, &data) {
+            eprintln!("Unable to write save: {}", e)
+        }
+    }
+    // for scripts, a failed assertion fails the run
+    if failed.is_some() {
+        std::process::exit(1)
+    }
+}
+
+/**
+ * The machine as it was when it fell over, next to where we were run from.
+ */
+fn write_crash_dump(cpu: &CPU, file_path: &str, panic: &(dyn Any + Send)) {
+    let dump = CrashDump::capture(cpu, &panic_message(panic), file_path);
+    let path = format!("{}-crash.json", file_stem(file_path));
+    match dump.save(Path::new(&path)) {
+        Ok(()) => eprintln!("Wrote a crash dump to {}", path),
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+/**
+ * A crash dump read back for a post-mortem.
+ */
+fn crash_dump(path: &str) {
+    match CrashDump::load(Path::new(path)) {
+        // piped into head more often than not
+        Ok(dump) => {
+            let _ = write!(io::stdout().lock(), "{}", dump);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1)
+        }
+    }
+}
+
+/**
+ * Header details, for working out why a rom won't boot.
+ */
+fn rominfo(file_path: &str) {
+    if file_path.to_ascii_lowercase().ends_with(".fds") {
+        match fds::DiskImage::load(file_path) {
+            Ok(disk) => {
+                println!("File:       {}", file_path);
+                println!("Format:     FDS");
+                println!("Disk sides: {}", disk.sides.len());
+                println!("CRC32:      {:08x}", crc32fast::hash(&disk.to_bytes()))
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1)
+            }
+        }
+        return;
+    }
+    let cartridge = match Cartridge::load(file_path) {
+        Ok(cartridge) => cartridge,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1)
+        }
+    };
+    let yes_no = |flag: bool| if flag { "yes" } else { "no" };
+    let supported = mapper::supported_mappers()
+        .iter()
+        .any(|m| m.number == cartridge.mapper);
+    println!("File:       {}", file_path);
+    println!("Format:     {}", cartridge.format);
+    if let Some(title) = &cartridge.title {
+        println!("Title:      {}", title)
+    }
+    println!(
+        "Mapper:     {} ({}){}",
+        cartridge.mapper,
+        mapper::mapper_name(cartridge.mapper),
+        if supported { "" } else { ", not supported" }
+    );
+    if cartridge.submapper != 0 {
+        println!("Submapper:  {}", cartridge.submapper)
+    }
+    println!("Console:    {:?}", cartridge.console);
+    println!("PRG ROM:    {}KB", cartridge.prgrom.len() / 1024);
+    // no CHR ROM means the board has CHR RAM instead
+    match cartridge.chrrom.len() {
+        0 => println!("CHR ROM:    none (CHR RAM)"),
+        len => println!("CHR ROM:    {}KB", len / 1024),
+    }
+    println!("Mirroring:  {:?}", cartridge.mirroring);
+    println!("Battery:    {}", yes_no(cartridge.battery));
+    println!("Trainer:    {}", yes_no(cartridge.trainer.is_some()));
+    println!("Region:     {}", cartridge.region);
+    println!("CRC32:      {:08x}", cartridge.hashes.crc32);
+    println!("SHA-1:      {}", cartridge.hashes.sha1_hex());
+}
+
+/**
+ * Listing of the whole PRG ROM, 16KB bank by bank. Where a bank really
+ * sits is up to the mapper, so this goes with the usual layout: the last
+ * bank fixed at $C000 and the rest switched in at $8000.
+ */
+/**
+ * FCEUX's .nl files next to the rom if there are any, then `files`.
+ */
+fn load_symbols(file_path: &str, banks: usize, files: &[String]) -> Symbols {
+    let mut symbols = Symbols::new();
+    let loaded = symbols
+        .load_fceux(Path::new(file_path), banks)
+        .and_then(|_| {
+            files
+                .iter()
+                .try_for_each(|file| symbols.load(Path::new(file)))
+        });
+    if let Err(e) = loaded {
+        eprintln!("{}", e);
+        std::process::exit(1)
+    }
+    symbols
+}
+
+fn disasm(file_path: &str, symbol_files: &[String]) {
+    let cartridge = match Cartridge::load(file_path) {
+        Ok(cartridge) => cartridge,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1)
+        }
+    };
+    let banks: Vec<&[u8]> = cartridge.prgrom.chunks(symbols::BANK_SIZE).collect();
+    let symbols = load_symbols(file_path, banks.len(), symbol_files);
+    // piped into head and the like, stop quietly once the reader's gone
+    let mut out = io::stdout().lock();
+    for (idx, bank) in banks.iter().enumerate() {
+        let origin = if idx == banks.len() - 1 {
+            0xc000
+        } else {
+            0x8000
+        };
+        // the bank being listed and the fixed last one are known, the
+        // other half of the address space could be anything
+        let bank_at = |addr: u16| match addr {
+            0xc000.. => Some(banks.len() - 1),
+            0x8000.. if origin == 0x8000 => Some(idx),
+            _ => None,
+        };
+        let label = |addr: u16| symbols.label(addr, bank_at(addr)).map(String::from);
+        let lines = disasm::disassemble_labeled(bank, origin, &label);
+        let written = writeln!(out, "; bank {}", idx).and_then(|_| {
+            lines.iter().try_for_each(|line| match label(line.addr) {
+                Some(name) => writeln!(out, "{}:\n{}", name, line),
+                None => writeln!(out, "{}", line),
+            })
+        });
+        if written.is_err() {
+            return;
+        }
+    }
+}
+
+/**
+ * Run `frames` frames without a window, then dump `space` with the bytes the
+ * last of them changed marked.
+ */
+fn hexdump(file_path: &str, space: Space, frames: u64) {
+    let (mut cpu, _, _, _) = load_cartridge(file_path, &Config::default_path());
+    let mut view = MemoryView::new(space);
+    // memory's all that's shown
+    cpu.ppu_mut().set_skip_rendering(true);
+    headless::run(&mut cpu, Some(frames.saturating_sub(1)), None);
+    view.update(space.read(&cpu));
+    headless::run(&mut cpu, Some(1), None);
+    view.update(space.read(&cpu));
+    let mut out = io::stdout().lock();
+    for row in 0..view.rows() {
+        if writeln!(out, "{}", view.row_text(row)).is_err() {
+            return;
+        }
+    }
+}
+
+fn debug(file_path: &str, symbol_files: &[String]) {
+    let config_path = Config::default_path();
+    let (mut cpu, crc32, _, _) = load_cartridge(file_path, &config_path);
+    cpu.set_cheats(load_cheats(&config_path, crc32));
+    let banks = cpu.rom_sizes().0 / symbols::BANK_SIZE;
+    let symbols = load_symbols(file_path, banks, symbol_files);
+    cpu.set_symbols((!symbols.is_empty()).then_some(symbols));
+    let result = repl::run(
+        &mut cpu,
+        &mut Debugger::new(),
+        Stop::Done,
+        io::stdin().lock(),
+        io::stdout().lock(),
+    );
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1)
+    }
+}
+
+/**
+ * Run the rom against a trace from another emulator, exiting nonzero where
+ * they part ways.
+ */
+fn trace_diff(file_path: &str, log_path: &str, start: Option<u16>, context: usize) {
+    let (mut cpu, _, _, _) = load_cartridge(file_path, &Config::default_path());
+    if let Some(pc) = start {
+        cpu.set_pc(pc)
+    }
+    let log = match File::open(log_path) {
+        Ok(file) => io::BufReader::new(file),
+        Err(e) => {
+            eprintln!("Unable to open {}: {}", log_path, e);
+            std::process::exit(1)
+        }
+    };
+    match tracediff::diff(&mut cpu, log, context) {
+        Ok(DiffResult::Matched(lines)) => println!("All {} lines match", lines),
+        Ok(DiffResult::Diverged(divergence)) => {
+            println!("{}", divergence);
+            std::process::exit(1)
+        }
+        Err(e) => {
+            eprintln!("Unable to read {}: {}", log_path, e);
+            std::process::exit(1)
+        }
+    }
+}
+
+/**
+ * Write down the frame hashes the manifests' roms end on now.
+ */
+fn bless_manifests(manifests: &[String], frames: u64) {
+    for manifest in manifests {
+        match testrunner::bless(Path::new(manifest), frames) {
+            Ok(count) => println!("{}: {} frame hashes updated", manifest, count),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1)
+            }
+        }
+    }
+}
+
+/**
+ * Run each test rom headlessly, exiting nonzero if any of them didn't pass.
+ */
+fn test_roms(paths: &[String], frames: u64) {
+    let cases = match testrunner::collect(paths, frames) {
+        Ok(cases) => cases,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1)
+        }
+    };
+    let results: Vec<_> = cases
+        .into_iter()
+        .map(|case| {
+            let (verdict, frames) = testrunner::run_case(&case);
+            (case, verdict, frames)
+        })
+        .collect();
+    println!("{}", testrunner::summary(&results));
+    if results
+        .iter()
+        .any(|(_, verdict, _)| *verdict != Verdict::Passed)
+    {
+        std::process::exit(1)
+    }
+}
+
+/**
+ * Change the game's cheats, if asked to, and list them.
+ */
+fn cheats(file_path: &str, edit: Option<CheatEdit>) {
+    let config_path = Config::default_path();
+    let (_, crc32, _, _) = if file_path.to_ascii_lowercase().ends_with(".fds") {
+        load_disk(file_path, &config_path)
+    } else {
+        load_cartridge(file_path, &config_path)
+    };
+    let path = Cheats::path(&config_path, crc32);
+    let result = Cheats::load(&path).and_then(|mut cheats| {
+        if let Some(edit) = &edit {
+            cheats.apply(edit)?;
+            cheats.save()?
+        }
+        Ok(cheats)
+    });
+    match result {
+        Ok(cheats) => println!("{}", cheats),
+        Err(e) => {
+            eprintln!("{} ({})", e, path.display());
+            std::process::exit(1)
+        }
+    }
+}
+
+/**
+ * Ask for every button of `device` in a small window, then save the keys.
+ */
+fn bind(device: Device) {
+    match frontend::bind(device) {
+        Ok(Some(binder)) => {
+            let path = Config::default_path();
+            match binder.save(&path) {
+                Ok(()) => eprintln!("Saved the {} keys to {}", device.name(), path.display()),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+        Ok(None) => eprintln!("Cancelled, nothing was saved"),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1)
+        }
+    }
+}
 
 fn main() {
-    let file_path = "./test_roms/cpu/nestest.nes";
-    let cartridge = Cartridge::load(file_path).expect("Error loading file");
-    let ppu = PPU::new();
-    let bus: Bus = Bus::new(ppu);
-    let mut cpu = CPU::new(bus);
-
-    cpu.load_cartridge(cartridge);
-    todo!()
+    let command = match cli::parse(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(1)
+        }
+    };
+    match command {
+        Command::Run(options) => run(options),
+        Command::RomInfo(path) => rominfo(&path),
+        Command::CrashDump(path) => crash_dump(&path),
+        Command::Disasm { rom, symbols } => disasm(&rom, &symbols),
+        Command::Debug { rom, symbols } => debug(&rom, &symbols),
+        Command::TraceDiff {
+            rom,
+            log,
+            start,
+            context,
+        } => trace_diff(&rom, &log, start, context),
+        Command::Hexdump { rom, space, frames } => hexdump(&rom, space, frames),
+        Command::Test {
+            roms,
+            frames,
+            bless: false,
+        } => test_roms(&roms, frames),
+        Command::Test {
+            roms,
+            frames,
+            bless: true,
+        } => bless_manifests(&roms, frames),
+        Command::Bind(device) => bind(device),
+        Command::Cheats { rom, edit } => cheats(&rom, edit),
+        Command::AudioDevices => match frontend::audio_devices() {
+            Ok(devices) => devices.iter().for_each(|device| println!("{}", device)),
+            Err(e) => eprintln!("{}", e),
+        },
+        Command::Help => println!("{}", cli::USAGE),
+    }
 }
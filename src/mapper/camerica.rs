@@ -0,0 +1,185 @@
+use alloc::{boxed::Box, vec::Vec};
+#[cfg(feature = "std")]
+use core::mem;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cartridge::{Cartridge, Mirroring};
+#[cfg(feature = "std")]
+use crate::savestate::StateError;
+
+use super::{bank_offset, chr_or_ram, last_bank, Mapper};
+#[cfg(feature = "std")]
+use super::{load_with_chr, save_with_chr};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+
+/**
+ * Camerica/Codemasters, mapper 71 (Micro Machines, Bee 52, Fire Hawk).
+ *
+ * UxROM-like: 0x8000..=0xbfff switchable 16KB bank selected by writing to
+ * 0xc000..=0xffff, 0xc000..=0xffff fixed to the last bank. CHR is always RAM.
+ *
+ * Fire Hawk (submapper 1) uses writes to 0x8000..=0x9fff to pick the single
+ * screen nametable. iNES 1 dumps can't say which board they are so only writes
+ * to 0x9000..=0x9fff are treated as mirroring control, which the other games
+ * never touch.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Camerica {
+    #[serde(skip)]
+    prgrom: Vec<u8>,
+    #[serde(skip)]
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_bank: u8,
+    fire_hawk: bool,
+    mirroring: Mirroring,
+}
+
+impl Camerica {
+    pub fn new(cartridge: Cartridge) -> Camerica {
+        let (chr, chr_is_ram) = chr_or_ram(cartridge.chrrom);
+        Camerica {
+            prgrom: cartridge.prgrom,
+            chr,
+            chr_is_ram,
+            prg_bank: 0,
+            fire_hawk: cartridge.submapper == 1,
+            mirroring: cartridge.mirroring,
+        }
+    }
+    fn write_mirroring(&mut self, data: u8) {
+        self.mirroring = if data & 0x10 == 0 {
+            Mirroring::SingleScreenLower
+        } else {
+            Mirroring::SingleScreenUpper
+        }
+    }
+}
+
+impl Mapper for Camerica {
+    fn box_clone(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+    #[cfg(feature = "std")]
+    fn save_state(&self) -> Vec<u8> {
+        save_with_chr(self, &self.chr, self.chr_is_ram)
+    }
+    #[cfg(feature = "std")]
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let state: Camerica = load_with_chr(state, &mut self.chr, self.chr_is_ram)?;
+        *self = Camerica {
+            prgrom: mem::take(&mut self.prgrom),
+            chr: mem::take(&mut self.chr),
+            ..state
+        };
+        Ok(())
+    }
+    fn power_cycle(&mut self) {
+        self.prg_bank = 0
+    }
+    fn peek_prg(&self, addr: u16) -> u8 {
+        self.prg_rom_offset(addr)
+            .map_or(0, |offset| self.prgrom[offset])
+    }
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        let bank = match addr {
+            0x8000..=0xbfff => self.prg_bank as usize,
+            0xc000..=0xffff => last_bank(&self.prgrom, PRG_BANK_SIZE),
+            _ => return None,
+        };
+        Some(bank_offset(
+            &self.prgrom,
+            PRG_BANK_SIZE,
+            bank,
+            addr as usize,
+        ))
+    }
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x8000..=0x8fff if self.fire_hawk => self.write_mirroring(data),
+            0x9000..=0x9fff => self.write_mirroring(data),
+            0xc000..=0xffff => self.prg_bank = data & 0x0f,
+            _ => (),
+        }
+    }
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr[addr as usize % self.chr.len()]
+    }
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let len = self.chr.len();
+            self.chr[addr as usize % len] = data
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod camerica_test {
+    use crate::cartridge::{Cartridge, Mirroring};
+
+    use super::{Camerica, Mapper};
+
+    fn make_camerica(submapper: u8) -> Camerica {
+        // 8 PRG banks, each filled with its bank number
+        let prgrom = (0..8).flat_map(|b| vec![b as u8; 0x4000]).collect();
+        Camerica::new(Cartridge {
+            prgrom,
+            mirroring: Mirroring::Horizontal,
+            mapper: 71,
+            submapper,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_prg_banking() {
+        let mut camerica = make_camerica(0);
+        assert_eq!(camerica.read_prg(0x8000), 0);
+        assert_eq!(camerica.read_prg(0xc000), 7);
+
+        // the bank register is up in the fixed bank
+        camerica.write_prg(0xc000, 5);
+        assert_eq!(camerica.read_prg(0xbfff), 5);
+        assert_eq!(camerica.read_prg(0xc000), 7);
+        assert_eq!(camerica.read_prg(0xffff), 7);
+        camerica.write_prg(0xf123, 0x12);
+        assert_eq!(camerica.read_prg(0x8000), 2);
+        // only $C000 and up
+        camerica.write_prg(0xa000, 3);
+        assert_eq!(camerica.read_prg(0x8000), 2);
+    }
+
+    #[test]
+    fn test_fire_hawk_mirroring() {
+        let mut fire_hawk = make_camerica(1);
+        assert_eq!(fire_hawk.mirroring(), Mirroring::Horizontal);
+        fire_hawk.write_prg(0x8000, 0x10);
+        assert_eq!(fire_hawk.mirroring(), Mirroring::SingleScreenUpper);
+        fire_hawk.write_prg(0x9fff, 0x00);
+        assert_eq!(fire_hawk.mirroring(), Mirroring::SingleScreenLower);
+
+        // other boards leave $8000..=$8FFF alone, $9000..=$9FFF is always taken
+        let mut camerica = make_camerica(0);
+        camerica.write_prg(0x8000, 0x10);
+        assert_eq!(camerica.mirroring(), Mirroring::Horizontal);
+        camerica.write_prg(0x9000, 0x10);
+        assert_eq!(camerica.mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn test_small_prg() {
+        let mut camerica = Camerica::new(Cartridge {
+            prgrom: (0..0x2000).map(|i| (i / 0x1000) as u8).collect(),
+            mapper: 71,
+            ..Default::default()
+        });
+        assert_eq!(camerica.read_prg(0xc000), 0);
+        assert_eq!(camerica.read_prg(0xd000), 1);
+        assert_eq!(camerica.read_prg(0xffff), 1);
+    }
+}
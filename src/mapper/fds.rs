@@ -0,0 +1,464 @@
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::mem;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "std")]
+use crate::savestate::{self, StateError};
+use crate::{
+    cartridge::Mirroring,
+    fds::{add_gaps, strip_gaps, DiskImage},
+};
+
+use super::{Mapper, CHR_RAM_SIZE};
+
+const RAM_SIZE: usize = 0x8000;
+// cpu cycles the drive takes to move one byte under the head
+const BYTE_DELAY: u32 = 150;
+// cpu cycles for the head to get back to the start of the disk
+const REWIND_DELAY: u32 = 50000;
+// how long a disk stays out during a swap, long enough for the BIOS to notice
+const SWAP_DELAY: u32 = 1_000_000;
+
+/**
+ * Famicom Disk System RAM adapter + drive.
+ *
+ * 0x6000..=0xdfff is 32KB of RAM the BIOS loads files into, 0xe000..=0xffff
+ * the 8KB BIOS, CHR is 8KB of RAM. The drive is a serial device: once the
+ * motor is on a byte passes under the head every ~150 cpu cycles and the game
+ * (well, the BIOS) has to read or write it through 0x4024/0x4031 before the
+ * next one comes along.
+ *
+ * Sides are kept with the gaps and CRCs the real disk has so the BIOS sees
+ * exactly what it expects, see `fds::add_gaps`.
+ */
+// a save state leaves the disks alone, they're save data as much as the
+// game's battery backed RAM is
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Fds {
+    #[serde(skip)]
+    bios: Vec<u8>,
+    ram: Vec<u8>,
+    chr: Vec<u8>,
+    #[serde(skip)]
+    sides: Vec<Vec<u8>>,
+    side: Option<usize>,
+    // side to insert once SWAP_DELAY is up
+    next_side: Option<usize>,
+    swap_delay: u32,
+    modified: bool,
+    mirroring: Mirroring,
+
+    // 0x4020..=0x4023
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_repeat: bool,
+    irq_enabled: bool,
+    timer_irq: bool,
+    disk_regs_enabled: bool,
+
+    // 0x4025
+    motor_on: bool,
+    reset_transfer: bool,
+    read_mode: bool,
+    crc_control: bool,
+    transfer_enabled: bool,
+    disk_irq_enabled: bool,
+
+    // drive
+    position: usize,
+    delay: u32,
+    end_of_head: bool,
+    scanning: bool,
+    gap_ended: bool,
+    read_data: u8,
+    write_data: u8,
+    transfer_complete: bool,
+    disk_irq: bool,
+    external: u8,
+}
+
+impl Fds {
+    pub fn new(bios: Vec<u8>, disk: DiskImage) -> Fds {
+        Fds {
+            bios,
+            ram: vec![0; RAM_SIZE],
+            chr: vec![0; CHR_RAM_SIZE],
+            sides: disk.sides.iter().map(|s| add_gaps(s)).collect(),
+            side: Some(0),
+            next_side: None,
+            swap_delay: 0,
+            modified: false,
+            mirroring: Mirroring::Horizontal,
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_repeat: false,
+            irq_enabled: false,
+            timer_irq: false,
+            disk_regs_enabled: true,
+            motor_on: false,
+            reset_transfer: false,
+            read_mode: true,
+            crc_control: false,
+            transfer_enabled: false,
+            disk_irq_enabled: false,
+            position: 0,
+            delay: 0,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            read_data: 0,
+            write_data: 0,
+            transfer_complete: false,
+            disk_irq: false,
+            external: 0,
+        }
+    }
+
+    fn write_control(&mut self, data: u8) {
+        self.motor_on = data & 0x01 != 0;
+        self.reset_transfer = data & 0x02 != 0;
+        self.read_mode = data & 0x04 != 0;
+        self.mirroring = if data & 0x08 != 0 {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        };
+        self.crc_control = data & 0x10 != 0;
+        self.transfer_enabled = data & 0x40 != 0;
+        self.disk_irq_enabled = data & 0x80 != 0;
+        self.disk_irq = false;
+    }
+
+    fn read_status(&mut self) -> u8 {
+        let status = self.status();
+        self.transfer_complete = false;
+        self.timer_irq = false;
+        self.disk_irq = false;
+        status
+    }
+
+    fn status(&self) -> u8 {
+        // CRCs are faked so they always check out, bit 4 stays clear
+        self.timer_irq as u8 | (self.transfer_complete as u8) << 1
+    }
+
+    fn read_drive_status(&self) -> u8 {
+        let no_disk = self.side.is_none();
+        // bit 6 is open bus, usually set
+        0x40 | no_disk as u8 | ((no_disk || !self.scanning) as u8) << 1 | (no_disk as u8) << 2
+    }
+
+    fn clock_timer(&mut self) {
+        if !self.irq_enabled || !self.disk_regs_enabled {
+            return;
+        }
+        if self.irq_counter == 0 {
+            self.timer_irq = true;
+            if self.irq_repeat {
+                self.irq_counter = self.irq_reload
+            } else {
+                self.irq_enabled = false
+            }
+        } else {
+            self.irq_counter -= 1
+        }
+    }
+
+    fn clock_swap(&mut self) {
+        if self.swap_delay > 0 {
+            self.swap_delay -= 1;
+            if self.swap_delay == 0 {
+                self.side = self.next_side.take()
+            }
+        }
+    }
+
+    fn clock_drive(&mut self) {
+        let side = match self.side {
+            Some(side) if self.motor_on => side,
+            _ => {
+                self.end_of_head = true;
+                self.scanning = false;
+                return;
+            }
+        };
+        if self.reset_transfer && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            self.delay = REWIND_DELAY;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        let mut need_irq = self.disk_irq_enabled;
+        if self.read_mode {
+            let data = self.sides[side][self.position];
+            if !self.transfer_enabled {
+                self.gap_ended = false
+            } else if data != 0 && !self.gap_ended {
+                // the 0x80 ending the gap comes through but doesn't raise an IRQ
+                self.gap_ended = true;
+                need_irq = false;
+            }
+            if self.gap_ended {
+                self.transfer_complete = true;
+                self.read_data = data;
+                self.disk_irq |= need_irq;
+            }
+        } else {
+            if !self.crc_control {
+                self.transfer_complete = true;
+                self.disk_irq |= need_irq;
+                // gap bytes until the BIOS says the data is ready to go out
+                self.sides[side][self.position] = if self.transfer_enabled {
+                    self.write_data
+                } else {
+                    0
+                };
+                self.modified = true;
+            }
+            // while the CRC goes out the fake one already on disk is left alone
+            self.gap_ended = false;
+        }
+
+        self.position += 1;
+        if self.position >= self.sides[side].len() {
+            self.motor_on = false;
+            self.end_of_head = true;
+        } else {
+            self.delay = BYTE_DELAY;
+        }
+    }
+}
+
+impl Mapper for Fds {
+    fn box_clone(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+    #[cfg(feature = "std")]
+    fn save_state(&self) -> Vec<u8> {
+        savestate::encode(self)
+    }
+    #[cfg(feature = "std")]
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let state: Fds = savestate::decode(state)?;
+        let sides = self.sides.len();
+        if [state.side, state.next_side]
+            .iter()
+            .flatten()
+            .any(|&s| s >= sides)
+        {
+            return Err(StateError::Mismatch("disk sides"));
+        }
+        if state.ram.len() != RAM_SIZE || state.chr.len() != CHR_RAM_SIZE {
+            return Err(StateError::Mismatch("RAM size"));
+        }
+        *self = Fds {
+            bios: mem::take(&mut self.bios),
+            sides: mem::take(&mut self.sides),
+            modified: self.modified,
+            ..state
+        };
+        Ok(())
+    }
+    fn power_cycle(&mut self) {
+        let side = self.side;
+        *self = Fds {
+            bios: mem::take(&mut self.bios),
+            ram: mem::take(&mut self.ram),
+            chr: mem::take(&mut self.chr),
+            sides: mem::take(&mut self.sides),
+            side,
+            modified: self.modified,
+            ..Fds::new(Vec::new(), DiskImage { sides: Vec::new() })
+        }
+    }
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x4030 if self.disk_regs_enabled => self.read_status(),
+            0x4031 if self.disk_regs_enabled => {
+                self.transfer_complete = false;
+                self.disk_irq = false;
+                self.read_data
+            }
+            _ => self.peek_prg(addr),
+        }
+    }
+    fn peek_prg(&self, addr: u16) -> u8 {
+        match addr {
+            0x4030 if self.disk_regs_enabled => self.status(),
+            0x4031 if self.disk_regs_enabled => self.read_data,
+            0x4032 if self.disk_regs_enabled => self.read_drive_status(),
+            // bit 7 is the battery check, always good
+            0x4033 if self.disk_regs_enabled => 0x80 | (self.external & 0x7f),
+            0x6000..=0xdfff => self.ram[(addr - 0x6000) as usize],
+            0xe000..=0xffff => self.bios[(addr - 0xe000) as usize],
+            _ => 0,
+        }
+    }
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4020 => self.irq_reload = self.irq_reload & 0xff00 | data as u16,
+            0x4021 => self.irq_reload = self.irq_reload & 0x00ff | (data as u16) << 8,
+            0x4022 => {
+                self.irq_repeat = data & 0x01 != 0;
+                self.irq_enabled = data & 0x02 != 0 && self.disk_regs_enabled;
+                if self.irq_enabled {
+                    self.irq_counter = self.irq_reload
+                } else {
+                    self.timer_irq = false
+                }
+            }
+            0x4023 => {
+                // bit 1 enables the sound registers, there's no audio yet
+                self.disk_regs_enabled = data & 0x01 != 0;
+                if !self.disk_regs_enabled {
+                    self.irq_enabled = false;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            }
+            0x4024 if self.disk_regs_enabled => {
+                self.write_data = data;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            0x4025 if self.disk_regs_enabled => self.write_control(data),
+            0x4026 if self.disk_regs_enabled => self.external = data,
+            0x6000..=0xdfff => self.ram[(addr - 0x6000) as usize] = data,
+            _ => (),
+        }
+    }
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr[addr as usize % CHR_RAM_SIZE]
+    }
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        self.chr[addr as usize % CHR_RAM_SIZE] = data
+    }
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+    fn tick(&mut self, cpu_cycles: u64) {
+        for _ in 0..cpu_cycles {
+            self.clock_timer();
+            self.clock_swap();
+            self.clock_drive();
+        }
+    }
+    fn irq_pending(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+    fn disk_sides(&self) -> usize {
+        self.sides.len()
+    }
+    fn insert_disk(&mut self, side: Option<usize>) {
+        let side = side.filter(|s| *s < self.sides.len());
+        // pull the current disk out first, the BIOS won't notice a swap otherwise
+        if self.side.is_some() && side.is_some() {
+            self.side = None;
+            self.next_side = side;
+            self.swap_delay = SWAP_DELAY;
+        } else {
+            self.side = side;
+            self.next_side = None;
+            self.swap_delay = 0;
+        }
+    }
+    fn save_data(&self) -> Option<Vec<u8>> {
+        if !self.modified {
+            return None;
+        }
+        Some(self.sides.iter().flat_map(|s| strip_gaps(s)).collect())
+    }
+}
+
+#[cfg(test)]
+mod fds_mapper_test {
+    use crate::fds::{DiskImage, BIOS_SIZE, DISK_SIDE_SIZE};
+
+    use super::{Fds, Mapper, SWAP_DELAY};
+
+    fn make_fds(sides: usize) -> Fds {
+        let mut side = b"\x01*NINTENDO-HVC*".to_vec();
+        side.resize(DISK_SIDE_SIZE, 0);
+        let disk = DiskImage {
+            sides: vec![side; sides],
+        };
+        Fds::new(vec![0xea; BIOS_SIZE], disk)
+    }
+
+    #[test]
+    fn test_memory_map() {
+        let mut fds = make_fds(1);
+        fds.write_prg(0x6000, 0x12);
+        fds.write_prg(0xdfff, 0x34);
+        assert_eq!(fds.read_prg(0x6000), 0x12);
+        assert_eq!(fds.read_prg(0xdfff), 0x34);
+        // bios is read only
+        fds.write_prg(0xe000, 0);
+        assert_eq!(fds.read_prg(0xe000), 0xea);
+    }
+
+    #[test]
+    fn test_timer_irq() {
+        let mut fds = make_fds(1);
+        fds.write_prg(0x4020, 10);
+        fds.write_prg(0x4021, 0);
+        fds.write_prg(0x4022, 0b10);
+        fds.tick(10);
+        assert!(!fds.irq_pending());
+        fds.tick(1);
+        assert!(fds.irq_pending());
+        // peeking at it doesn't, reading the status does
+        assert_eq!(fds.peek_prg(0x4030) & 0x01, 1);
+        assert!(fds.irq_pending());
+        assert_eq!(fds.read_prg(0x4030) & 0x01, 1);
+        assert!(!fds.irq_pending());
+    }
+
+    #[test]
+    fn test_read_disk() {
+        let mut fds = make_fds(1);
+        // motor on, read mode, transfer enabled
+        fds.write_prg(0x4025, 0x45);
+        assert_eq!(fds.read_prg(0x4032) & 0b10, 0b10);
+        // skip the rewind and the leading gap, the first byte handed over is
+        // the gap end marker followed by the disk info block
+        let mut data = Vec::new();
+        while data.len() < 16 {
+            fds.tick(1);
+            if fds.peek_prg(0x4030) & 0b10 != 0 {
+                // peeking acknowledges nothing
+                fds.peek_prg(0x4031);
+                assert_eq!(fds.read_prg(0x4030) & 0b10, 0b10);
+                data.push(fds.read_prg(0x4031));
+            }
+        }
+        assert_eq!(&data, b"\x80\x01*NINTENDO-HVC*");
+        assert_eq!(fds.read_prg(0x4032) & 0b10, 0);
+    }
+
+    #[test]
+    fn test_swap_disk() {
+        let mut fds = make_fds(2);
+        assert_eq!(fds.disk_sides(), 2);
+        fds.insert_disk(Some(1));
+        assert_eq!(fds.read_prg(0x4032) & 0b1, 1);
+        fds.tick(SWAP_DELAY as u64);
+        assert_eq!(fds.read_prg(0x4032) & 0b1, 0);
+        assert_eq!(fds.side, Some(1));
+
+        fds.insert_disk(None);
+        assert_eq!(fds.read_prg(0x4032) & 0b1, 1);
+        assert!(fds.save_data().is_none());
+    }
+}
@@ -0,0 +1,257 @@
+use alloc::{boxed::Box, vec::Vec};
+#[cfg(feature = "std")]
+use core::mem;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "std")]
+use crate::savestate::StateError;
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    savestate,
+};
+
+use super::{bank_offset, chr_or_ram, copy_trainer, last_bank, Mapper, PRG_RAM_SIZE};
+#[cfg(feature = "std")]
+use super::{load_with_chr, save_with_chr};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub(crate) enum Latch {
+    FD,
+    FE,
+}
+
+/**
+ * The MMC2/MMC4 fetch latches. Each 4KB pattern table has its own latch which
+ * flips whenever the PPU fetches tile $FD or $FE from it, choosing which of the
+ * two CHR bank registers is used for that half of the pattern table.
+ *
+ * The MMC2 only reacts to the exact addresses $0FD8/$0FE8 for the lower table,
+ * while the MMC4 reacts to the whole $0FD8..=$0FDF/$0FE8..=$0FEF range. Both
+ * react to the full ranges for the upper table.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct FetchLatch {
+    latches: [Latch; 2],
+    exact_lower: bool,
+}
+
+impl FetchLatch {
+    pub fn new(exact_lower: bool) -> FetchLatch {
+        FetchLatch {
+            latches: [Latch::FE, Latch::FE],
+            exact_lower,
+        }
+    }
+    pub fn get(&self, table: usize) -> Latch {
+        self.latches[table]
+    }
+    // The switch takes effect on the fetch _after_ the one that triggered it
+    pub fn notify(&mut self, addr: u16) {
+        match addr {
+            0x0fd8 => self.latches[0] = Latch::FD,
+            0x0fe8 => self.latches[0] = Latch::FE,
+            0x0fd9..=0x0fdf if !self.exact_lower => self.latches[0] = Latch::FD,
+            0x0fe9..=0x0fef if !self.exact_lower => self.latches[0] = Latch::FE,
+            0x1fd8..=0x1fdf => self.latches[1] = Latch::FD,
+            0x1fe8..=0x1fef => self.latches[1] = Latch::FE,
+            _ => (),
+        }
+    }
+}
+
+/**
+ * Mapper 10 (Fire Emblem, Famicom Wars).
+ *
+ * PRG: 0x8000..=0xbfff switchable 16KB bank, 0xc000..=0xffff fixed to the last bank.
+ * CHR: two 4KB windows, each picking between an FD and FE bank based on its latch.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Mmc4 {
+    #[serde(skip)]
+    prgrom: Vec<u8>,
+    #[serde(with = "savestate::array")]
+    prgram: [u8; PRG_RAM_SIZE],
+    #[serde(skip)]
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_bank: u8,
+    // [0 FD, 0 FE, 1 FD, 1 FE]
+    chr_banks: [u8; 4],
+    latch: FetchLatch,
+    mirroring: Mirroring,
+}
+
+impl Mmc4 {
+    pub fn new(cartridge: Cartridge) -> Mmc4 {
+        let (chr, chr_is_ram) = chr_or_ram(cartridge.chrrom);
+        Mmc4 {
+            prgrom: cartridge.prgrom,
+            prgram: [0; PRG_RAM_SIZE],
+            chr,
+            chr_is_ram,
+            prg_bank: 0,
+            chr_banks: [0; 4],
+            latch: FetchLatch::new(false),
+            mirroring: cartridge.mirroring,
+        }
+    }
+    fn chr_offset(&self, addr: u16) -> usize {
+        let table = (addr / CHR_BANK_SIZE as u16) as usize;
+        let bank = match self.latch.get(table) {
+            Latch::FD => self.chr_banks[table * 2],
+            Latch::FE => self.chr_banks[table * 2 + 1],
+        };
+        bank_offset(&self.chr, CHR_BANK_SIZE, bank as usize, addr as usize)
+    }
+}
+
+impl Mapper for Mmc4 {
+    fn box_clone(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+    fn load_trainer(&mut self, trainer: &[u8]) -> bool {
+        copy_trainer(&mut self.prgram, trainer);
+        true
+    }
+    #[cfg(feature = "std")]
+    fn save_state(&self) -> Vec<u8> {
+        save_with_chr(self, &self.chr, self.chr_is_ram)
+    }
+    #[cfg(feature = "std")]
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let state: Mmc4 = load_with_chr(state, &mut self.chr, self.chr_is_ram)?;
+        *self = Mmc4 {
+            prgrom: mem::take(&mut self.prgrom),
+            chr: mem::take(&mut self.chr),
+            ..state
+        };
+        Ok(())
+    }
+    fn power_cycle(&mut self) {
+        self.prg_bank = 0;
+        self.chr_banks = [0; 4];
+        self.latch = FetchLatch::new(false)
+    }
+    fn peek_prg(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff => self.prgram[(addr - 0x6000) as usize],
+            _ => self
+                .prg_rom_offset(addr)
+                .map_or(0, |offset| self.prgrom[offset]),
+        }
+    }
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        let bank = match addr {
+            0x8000..=0xbfff => self.prg_bank as usize,
+            0xc000..=0xffff => last_bank(&self.prgrom, PRG_BANK_SIZE),
+            _ => return None,
+        };
+        Some(bank_offset(
+            &self.prgrom,
+            PRG_BANK_SIZE,
+            bank,
+            addr as usize,
+        ))
+    }
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7fff => self.prgram[(addr - 0x6000) as usize] = data,
+            0xa000..=0xafff => self.prg_bank = data & 0x0f,
+            0xb000..=0xbfff => self.chr_banks[0] = data & 0x1f,
+            0xc000..=0xcfff => self.chr_banks[1] = data & 0x1f,
+            0xd000..=0xdfff => self.chr_banks[2] = data & 0x1f,
+            0xe000..=0xefff => self.chr_banks[3] = data & 0x1f,
+            0xf000..=0xffff => {
+                self.mirroring = if data & 0x01 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                }
+            }
+            _ => (),
+        }
+    }
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = data
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+    fn notify_ppu_fetch(&mut self, addr: u16) {
+        self.latch.notify(addr)
+    }
+    fn watches_ppu_fetches(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod mmc4_test {
+    use crate::cartridge::{Cartridge, Mirroring};
+
+    use super::{Mapper, Mmc4};
+
+    fn make_mmc4() -> Mmc4 {
+        // 4 PRG banks and 4 CHR banks, each filled with its bank number
+        let prgrom = (0..4).flat_map(|b| vec![b as u8; 0x4000]).collect();
+        let chrrom = (0..4).flat_map(|b| vec![b as u8; 0x1000]).collect();
+        Mmc4::new(Cartridge {
+            prgrom,
+            chrrom,
+            mirroring: Mirroring::Vertical,
+            mapper: 10,
+            submapper: 0,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_prg_banking() {
+        let mut mmc4 = make_mmc4();
+        assert_eq!(mmc4.read_prg(0xc000), 3);
+        mmc4.write_prg(0xa000, 2);
+        assert_eq!(mmc4.read_prg(0x8000), 2);
+        assert_eq!(mmc4.read_prg(0xffff), 3);
+    }
+
+    #[test]
+    fn test_small_prg() {
+        // NES 2.0 can describe PRG smaller than a bank, it's mirrored through it
+        for size in [0x2000, 0x1000] {
+            let mut mmc4 = Mmc4::new(Cartridge {
+                prgrom: (0..size).map(|i| (i / 0x800) as u8).collect(),
+                chrrom: vec![0; 0x2000],
+                mapper: 10,
+                ..Default::default()
+            });
+            assert_eq!(mmc4.read_prg(0xc000), 0);
+            assert_eq!(mmc4.read_prg(0xc800), 1);
+            assert_eq!(mmc4.read_prg(0xc000 + size as u16), 0);
+            assert_eq!(mmc4.read_prg(0xffff), ((size - 1) / 0x800) as u8);
+        }
+    }
+
+    #[test]
+    fn test_fetch_latch() {
+        let mut mmc4 = make_mmc4();
+        mmc4.write_prg(0xb000, 1);
+        mmc4.write_prg(0xc000, 2);
+
+        assert_eq!(mmc4.read_chr(0x0000), 2);
+        // MMC4 latches on the whole $0FD8..=$0FDF range
+        mmc4.notify_ppu_fetch(0x0fdb);
+        assert_eq!(mmc4.read_chr(0x0000), 1);
+        mmc4.notify_ppu_fetch(0x0fe8);
+        assert_eq!(mmc4.read_chr(0x0000), 2);
+    }
+}
@@ -0,0 +1,232 @@
+use alloc::{boxed::Box, rc::Rc, vec, vec::Vec};
+use core::cell::RefCell;
+
+#[cfg(feature = "std")]
+use serde::{de::DeserializeOwned, Serialize};
+
+#[cfg(feature = "std")]
+use crate::savestate::{self, StateError};
+use crate::{
+    cartridge::{Mirroring, TRAINER_ADDR},
+    device::BusDevice,
+};
+
+pub use camerica::Camerica;
+pub use fds::Fds;
+pub use mmc4::Mmc4;
+pub use namco108::Namco108;
+pub use namco163::Namco163;
+pub use nrom::Nrom;
+pub use registry::{
+    board_mapper, from_cartridge, mapper_name, supported_mappers, UnsupportedMapper,
+};
+pub use uxrom::Uxrom;
+pub use vrc4::Vrc4;
+pub use vrc6::Vrc6;
+
+mod camerica;
+mod fds;
+mod mmc4;
+mod namco108;
+mod namco163;
+mod nrom;
+mod registry;
+mod uxrom;
+mod vrc4;
+mod vrc6;
+mod vrc_irq;
+
+pub const PRG_RAM_SIZE: usize = 0x2000;
+pub const CHR_RAM_SIZE: usize = 0x2000;
+
+/**
+ * The mapper is shared between the cpu bus (PRG) and the ppu bus (CHR + mirroring).
+ */
+pub type SharedMapper = Rc<RefCell<Box<dyn Mapper>>>;
+
+/**
+ * Cartridge hardware as seen from both the CPU and the PPU.
+ *
+ * CPU addresses are passed through as-is (0x4020..=0xffff) and
+ * PPU addresses are pattern table addresses (0x0000..=0x1fff).
+ */
+pub trait Mapper {
+    /**
+     * A copy with memory of its own, for snapshots of the whole machine.
+     */
+    fn box_clone(&self) -> Box<dyn Mapper>;
+    /**
+     * What the CPU reads at `addr`. Mappers with registers that change when
+     * they're read override it, for the rest it's `peek_prg`.
+     */
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        self.peek_prg(addr)
+    }
+    /**
+     * What `read_prg` would return, without changing anything, for
+     * debuggers and `BusDevice::peek`.
+     */
+    fn peek_prg(&self, addr: u16) -> u8;
+    fn write_prg(&mut self, addr: u16, data: u8);
+    fn read_chr(&self, addr: u16) -> u8;
+    fn write_chr(&mut self, addr: u16, data: u8);
+    fn mirroring(&self) -> Mirroring;
+    /**
+     * Called by the PPU after every pattern table fetch. Mappers that switch
+     * banks based on what the PPU is reading (MMC2/MMC4 latches) hook in here.
+     */
+    fn notify_ppu_fetch(&mut self, _addr: u16) {}
+    /**
+     * Put the rom's trainer in PRG RAM at $7000, where it expects to be on
+     * power up. False for boards that have no PRG RAM to put it in.
+     */
+    fn load_trainer(&mut self, _trainer: &[u8]) -> bool {
+        false
+    }
+    /**
+     * True for the mappers that do something with `notify_ppu_fetch`, the
+     * PPU can't leave out any fetches for them.
+     */
+    fn watches_ppu_fetches(&self) -> bool {
+        false
+    }
+    /**
+     * Where in PRG ROM a CPU address is mapped right now, None for RAM,
+     * registers and anything else that isn't ROM.
+     */
+    fn prg_rom_offset(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+    /**
+     * Mappers that take over the nametables (0x2000..=0x3eff) return `Some` here,
+     * otherwise the PPU's own 2KB of vram is used with `mirroring`.
+     */
+    fn read_nametable(&self, _addr: u16) -> Option<u8> {
+        None
+    }
+    fn write_nametable(&mut self, _addr: u16, _data: u8) -> bool {
+        false
+    }
+    /**
+     * Advance mapper hardware clocked by the CPU (e.g. cycle counting IRQs).
+     */
+    fn tick(&mut self, _cpu_cycles: u64) {}
+    fn irq_pending(&self) -> bool {
+        false
+    }
+    /**
+     * Registers back to how they come up at power on. What's in the board's
+     * memory (PRG and CHR RAM, the disk) stays, the game's save is in there.
+     */
+    fn power_cycle(&mut self) {}
+    /**
+     * Disk based hardware (the FDS) lets the player flip or change disks while
+     * running. `None` ejects the disk.
+     */
+    fn disk_sides(&self) -> usize {
+        0
+    }
+    fn insert_disk(&mut self, _side: Option<usize>) {}
+    /**
+     * Anything the game wrote that should outlive the session, `None` when
+     * there's nothing new to save.
+     */
+    fn save_data(&self) -> Option<Vec<u8>> {
+        None
+    }
+    /**
+     * Registers and RAM for a save state, not ROM. `load_state` gets back
+     * what the same game's mapper saved, and leaves it be if it can't.
+     * Save states need std, there's no encoding for them without it.
+     */
+    #[cfg(feature = "std")]
+    fn save_state(&self) -> Vec<u8>;
+    #[cfg(feature = "std")]
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError>;
+}
+
+/**
+ * `load_trainer` for the boards with 8KB of PRG RAM at $6000. It goes in
+ * whether or not the game has enabled the RAM yet.
+ */
+pub(crate) fn copy_trainer(prgram: &mut [u8; PRG_RAM_SIZE], trainer: &[u8]) {
+    let start = (TRAINER_ADDR - 0x6000) as usize;
+    let len = trainer.len().min(PRG_RAM_SIZE - start);
+    prgram[start..start + len].copy_from_slice(&trainer[..len]);
+}
+
+pub fn share(mapper: Box<dyn Mapper>) -> SharedMapper {
+    Rc::new(RefCell::new(mapper))
+}
+
+// the cartridge's side of the CPU bus, $4020-$FFFF
+impl BusDevice for SharedMapper {
+    fn box_clone(&self) -> Box<dyn BusDevice> {
+        Box::new(share(self.borrow().box_clone()))
+    }
+    fn read(&mut self, addr: u16) -> u8 {
+        self.borrow_mut().read_prg(addr)
+    }
+    fn write(&mut self, addr: u16, data: u8) {
+        self.borrow_mut().write_prg(addr, data)
+    }
+    fn peek(&self, addr: u16) -> u8 {
+        self.borrow().peek_prg(addr)
+    }
+}
+
+/**
+ * Index into `banks` for a bank of `bank_size` bytes. The bank number wraps
+ * around the number of banks actually present on the cartridge, and a ROM
+ * smaller than one bank is mirrored through it.
+ */
+pub(crate) fn bank_offset(banks: &[u8], bank_size: usize, bank: usize, addr: usize) -> usize {
+    let bank_count = (banks.len() / bank_size).max(1);
+    ((bank % bank_count) * bank_size + (addr % bank_size)) % banks.len().max(1)
+}
+
+/**
+ * The bank fixed at the top of the address space on most boards, the first
+ * one for a ROM smaller than one bank.
+ */
+pub(crate) fn last_bank(banks: &[u8], bank_size: usize) -> usize {
+    (banks.len() / bank_size).saturating_sub(1)
+}
+
+/**
+ * `mapper`'s state, with its CHR if that's RAM. The mapper leaves its ROM
+ * out with `#[serde(skip)]`.
+ */
+#[cfg(feature = "std")]
+pub(crate) fn save_with_chr<T: Serialize>(mapper: &T, chr: &[u8], chr_is_ram: bool) -> Vec<u8> {
+    let chr_ram = if chr_is_ram { chr } else { &[] };
+    savestate::encode(&(mapper, chr_ram))
+}
+
+/**
+ * Back from `save_with_chr`, CHR RAM goes straight into `chr`. What comes
+ * back has no ROM, the caller moves its own over.
+ */
+#[cfg(feature = "std")]
+pub(crate) fn load_with_chr<T: DeserializeOwned>(
+    state: &[u8],
+    chr: &mut [u8],
+    chr_is_ram: bool,
+) -> Result<T, StateError> {
+    let (mapper, chr_ram): (T, Vec<u8>) = savestate::decode(state)?;
+    if chr_is_ram {
+        savestate::fill(chr, &chr_ram, "CHR RAM")?
+    }
+    Ok(mapper)
+}
+
+/**
+ * Cartridges without CHR ROM come with 8KB of CHR RAM instead.
+ */
+pub(crate) fn chr_or_ram(chrrom: Vec<u8>) -> (Vec<u8>, bool) {
+    if chrrom.is_empty() {
+        (vec![0; CHR_RAM_SIZE], true)
+    } else {
+        (chrrom, false)
+    }
+}
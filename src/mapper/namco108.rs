@@ -0,0 +1,235 @@
+use alloc::{boxed::Box, vec::Vec};
+#[cfg(feature = "std")]
+use core::mem;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cartridge::{Cartridge, Mirroring};
+#[cfg(feature = "std")]
+use crate::savestate::StateError;
+
+use super::{bank_offset, chr_or_ram, Mapper};
+#[cfg(feature = "std")]
+use super::{load_with_chr, save_with_chr};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
+
+/**
+ * The bank select/bank data register pair of the Namco 108. Its successor
+ * the MMC3 has the same pair with PRG/CHR mode bits, mirroring, PRG RAM
+ * protect and the scanline IRQ on top, so it's kept apart from the 108 as
+ * groundwork for an MMC3 to build on.
+ *
+ * R0, R1: 2KB CHR banks
+ * R2..=R5: 1KB CHR banks
+ * R6, R7: 8KB PRG banks
+ */
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub(crate) struct BankRegisters {
+    select: u8,
+    regs: [u8; 8],
+}
+
+impl BankRegisters {
+    pub fn write_select(&mut self, data: u8) {
+        self.select = data
+    }
+    pub fn write_data(&mut self, data: u8) {
+        self.regs[(self.select & 0b111) as usize] = data
+    }
+    fn prg_mode(&self) -> bool {
+        self.select & 0x40 != 0
+    }
+    fn chr_inversion(&self) -> bool {
+        self.select & 0x80 != 0
+    }
+    /**
+     * 8KB PRG bank mapped at `addr` given the number of 8KB banks on the cart.
+     * Mode 0: R6, R7, second last, last
+     * Mode 1: second last, R7, R6, last
+     */
+    pub fn prg_bank(&self, addr: u16, bank_count: usize) -> usize {
+        // PRG smaller than 16KB has no second last bank, bank_offset wraps
+        let second_last = bank_count.saturating_sub(2);
+        match (addr, self.prg_mode()) {
+            (0x8000..=0x9fff, false) | (0xc000..=0xdfff, true) => self.regs[6] as usize,
+            (0x8000..=0x9fff, true) | (0xc000..=0xdfff, false) => second_last,
+            (0xa000..=0xbfff, _) => self.regs[7] as usize,
+            _ => bank_count.saturating_sub(1),
+        }
+    }
+    /**
+     * 1KB CHR bank mapped at `addr`. With inversion the 2KB banks move to 0x1000.
+     */
+    pub fn chr_bank(&self, addr: u16) -> usize {
+        let slot = addr as usize / CHR_BANK_SIZE;
+        let slot = if self.chr_inversion() {
+            slot ^ 0b100
+        } else {
+            slot
+        };
+        match slot {
+            0 | 1 => (self.regs[0] & 0xfe) as usize + slot,
+            2 | 3 => (self.regs[1] & 0xfe) as usize + slot - 2,
+            _ => self.regs[slot - 2] as usize,
+        }
+    }
+}
+
+/**
+ * Namco 108 / DxROM, mapper 206 (Babel no Tou, Dragon Spirit, Family Circuit
+ * and many Tengen releases).
+ *
+ * The chip the MMC3 grew out of, without its mode bits, mirroring control or
+ * IRQ: mirroring is fixed by the board, PRG is 4 bits and CHR 6 bits wide.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Namco108 {
+    #[serde(skip)]
+    prgrom: Vec<u8>,
+    #[serde(skip)]
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    banks: BankRegisters,
+    mirroring: Mirroring,
+}
+
+impl Namco108 {
+    pub fn new(cartridge: Cartridge) -> Namco108 {
+        let (chr, chr_is_ram) = chr_or_ram(cartridge.chrrom);
+        Namco108 {
+            prgrom: cartridge.prgrom,
+            chr,
+            chr_is_ram,
+            banks: Default::default(),
+            mirroring: cartridge.mirroring,
+        }
+    }
+    fn chr_offset(&self, addr: u16) -> usize {
+        bank_offset(
+            &self.chr,
+            CHR_BANK_SIZE,
+            self.banks.chr_bank(addr),
+            addr as usize,
+        )
+    }
+}
+
+impl Mapper for Namco108 {
+    fn box_clone(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+    #[cfg(feature = "std")]
+    fn save_state(&self) -> Vec<u8> {
+        save_with_chr(self, &self.chr, self.chr_is_ram)
+    }
+    #[cfg(feature = "std")]
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let state: Namco108 = load_with_chr(state, &mut self.chr, self.chr_is_ram)?;
+        *self = Namco108 {
+            prgrom: mem::take(&mut self.prgrom),
+            chr: mem::take(&mut self.chr),
+            ..state
+        };
+        Ok(())
+    }
+    fn power_cycle(&mut self) {
+        self.banks = Default::default()
+    }
+    fn peek_prg(&self, addr: u16) -> u8 {
+        self.prg_rom_offset(addr)
+            .map_or(0, |offset| self.prgrom[offset])
+    }
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xffff => {
+                let bank = self.banks.prg_bank(addr, self.prgrom.len() / PRG_BANK_SIZE);
+                Some(bank_offset(
+                    &self.prgrom,
+                    PRG_BANK_SIZE,
+                    bank,
+                    addr as usize,
+                ))
+            }
+            _ => None,
+        }
+    }
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            // no mode bits on the 108
+            0x8000..=0x9fff if addr & 0x01 == 0 => self.banks.write_select(data & 0b111),
+            0x8000..=0x9fff => {
+                let mask = if self.banks.select >= 6 { 0x0f } else { 0x3f };
+                self.banks.write_data(data & mask)
+            }
+            _ => (),
+        }
+    }
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = data
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod namco108_test {
+    use crate::cartridge::{Cartridge, Mirroring};
+
+    use super::{Mapper, Namco108};
+
+    #[test]
+    fn test_banking() {
+        let prgrom = (0..8).flat_map(|b| vec![b as u8; 0x2000]).collect();
+        let chrrom = (0..16).flat_map(|b| vec![b as u8; 0x400]).collect();
+        let mut n108 = Namco108::new(Cartridge {
+            prgrom,
+            chrrom,
+            mirroring: Mirroring::Vertical,
+            mapper: 206,
+            submapper: 0,
+            ..Default::default()
+        });
+
+        // R0 = 5, the low bit is ignored for the 2KB banks
+        n108.write_prg(0x8000, 0);
+        n108.write_prg(0x8001, 5);
+        assert_eq!(n108.read_chr(0x0000), 4);
+        assert_eq!(n108.read_chr(0x0400), 5);
+
+        // R6 = 3, setting the PRG mode bit has no effect on the 108
+        n108.write_prg(0x8000, 0x40 | 6);
+        n108.write_prg(0x8001, 3);
+        assert_eq!(n108.read_prg(0x8000), 3);
+        assert_eq!(n108.read_prg(0xc000), 6);
+        assert_eq!(n108.read_prg(0xe000), 7);
+
+        // only 4 bits of PRG bank, 6 of CHR
+        n108.write_prg(0x8000, 7);
+        n108.write_prg(0x8001, 0x15);
+        assert_eq!(n108.read_prg(0xa000), 5);
+        n108.write_prg(0x8000, 2);
+        n108.write_prg(0x8001, 0x4e);
+        assert_eq!(n108.read_chr(0x1000), 14);
+    }
+
+    #[test]
+    fn test_small_prg() {
+        let mut n108 = Namco108::new(Cartridge {
+            prgrom: (0..0x2000).map(|i| (i / 0x1000) as u8).collect(),
+            mapper: 206,
+            ..Default::default()
+        });
+        assert_eq!(n108.read_prg(0xc000), 0);
+        assert_eq!(n108.read_prg(0xf000), 1);
+        assert_eq!(n108.read_prg(0x9000), 1);
+    }
+}
@@ -0,0 +1,338 @@
+use alloc::{boxed::Box, vec::Vec};
+#[cfg(feature = "std")]
+use core::mem;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "std")]
+use crate::savestate::StateError;
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    savestate,
+};
+
+use super::{bank_offset, chr_or_ram, copy_trainer, last_bank, Mapper, PRG_RAM_SIZE};
+#[cfg(feature = "std")]
+use super::{load_with_chr, save_with_chr};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
+// bank values at or above this select a page of nametable ram instead of CHR ROM
+const CIRAM_SELECT: u8 = 0xe0;
+const IRQ_MAX: u16 = 0x7fff;
+
+/**
+ * Namco 163, mapper 19 (Megami Tensei II, Rolling Thunder, Final Lap).
+ *
+ * PRG: three switchable 8KB banks and the last bank fixed at 0xe000.
+ * CHR: eight 1KB banks for the pattern tables and four for the nametables,
+ * any of which can point at one of the two 1KB pages of nametable ram.
+ *
+ * Since the chip decides what the nametables are, it owns the console's
+ * nametable ram here rather than the PPU bus.
+ *
+ * The 128 bytes of internal ram are also where the expansion audio keeps
+ * its waveforms and channel registers, the sound itself isn't emulated.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Namco163 {
+    #[serde(skip)]
+    prgrom: Vec<u8>,
+    #[serde(with = "savestate::array")]
+    prgram: [u8; PRG_RAM_SIZE],
+    #[serde(skip)]
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    #[serde(with = "savestate::array")]
+    ciram: [u8; 0x800],
+    #[serde(with = "savestate::array")]
+    internal_ram: [u8; 0x80],
+    // bits 0-6 address, bit 7 auto increment
+    ram_addr: u8,
+    prg_banks: [u8; 3],
+    // 0..=7 pattern tables, 8..=11 nametables
+    chr_banks: [u8; 12],
+    // $E800 bits 6/7, when set the lower/upper pattern table can't select ciram
+    ciram_disabled: [bool; 2],
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+enum ChrTarget {
+    Rom(usize),
+    Ciram(usize),
+}
+
+impl Namco163 {
+    pub fn new(cartridge: Cartridge) -> Namco163 {
+        let (chr, chr_is_ram) = chr_or_ram(cartridge.chrrom);
+        Namco163 {
+            prgrom: cartridge.prgrom,
+            prgram: [0; PRG_RAM_SIZE],
+            chr,
+            chr_is_ram,
+            ciram: [0; 0x800],
+            internal_ram: [0; 0x80],
+            ram_addr: 0,
+            prg_banks: [0; 3],
+            // default to vertical mirroring until the game sets things up
+            chr_banks: [0, 0, 0, 0, 0, 0, 0, 0, 0xe0, 0xe1, 0xe0, 0xe1],
+            ciram_disabled: [false; 2],
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+    fn target(&self, slot: usize, addr: u16) -> ChrTarget {
+        let bank = self.chr_banks[slot];
+        let ciram_allowed = slot >= 8 || !self.ciram_disabled[slot / 4];
+        if bank >= CIRAM_SELECT && ciram_allowed {
+            ChrTarget::Ciram((bank as usize & 0x01) * 0x400 + (addr as usize & 0x3ff))
+        } else {
+            ChrTarget::Rom(bank_offset(
+                &self.chr,
+                CHR_BANK_SIZE,
+                bank as usize,
+                addr as usize,
+            ))
+        }
+    }
+    fn read_target(&self, target: ChrTarget) -> u8 {
+        match target {
+            ChrTarget::Rom(offset) => self.chr[offset],
+            ChrTarget::Ciram(offset) => self.ciram[offset],
+        }
+    }
+    fn write_target(&mut self, target: ChrTarget, data: u8) {
+        match target {
+            ChrTarget::Rom(offset) if self.chr_is_ram => self.chr[offset] = data,
+            ChrTarget::Rom(_) => (),
+            ChrTarget::Ciram(offset) => self.ciram[offset] = data,
+        }
+    }
+    fn nametable_slot(addr: u16) -> usize {
+        8 + ((addr as usize - 0x2000) / 0x400) % 4
+    }
+    fn access_internal_ram(&mut self) -> usize {
+        let addr = (self.ram_addr & 0x7f) as usize;
+        if self.ram_addr & 0x80 != 0 {
+            self.ram_addr = 0x80 | (self.ram_addr.wrapping_add(1) & 0x7f)
+        }
+        addr
+    }
+}
+
+impl Mapper for Namco163 {
+    fn box_clone(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+    fn load_trainer(&mut self, trainer: &[u8]) -> bool {
+        copy_trainer(&mut self.prgram, trainer);
+        true
+    }
+    #[cfg(feature = "std")]
+    fn save_state(&self) -> Vec<u8> {
+        save_with_chr(self, &self.chr, self.chr_is_ram)
+    }
+    #[cfg(feature = "std")]
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let state: Namco163 = load_with_chr(state, &mut self.chr, self.chr_is_ram)?;
+        *self = Namco163 {
+            prgrom: mem::take(&mut self.prgrom),
+            chr: mem::take(&mut self.chr),
+            ..state
+        };
+        Ok(())
+    }
+    fn power_cycle(&mut self) {
+        self.ram_addr = 0;
+        self.prg_banks = [0; 3];
+        self.chr_banks = [0, 0, 0, 0, 0, 0, 0, 0, 0xe0, 0xe1, 0xe0, 0xe1];
+        self.ciram_disabled = [false; 2];
+        self.irq_counter = 0;
+        self.irq_enabled = false;
+        self.irq_pending = false
+    }
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x4800..=0x4fff => {
+                let addr = self.access_internal_ram();
+                self.internal_ram[addr]
+            }
+            _ => self.peek_prg(addr),
+        }
+    }
+    fn peek_prg(&self, addr: u16) -> u8 {
+        match addr {
+            // without moving the address on
+            0x4800..=0x4fff => self.internal_ram[(self.ram_addr & 0x7f) as usize],
+            0x5000..=0x57ff => (self.irq_counter & 0xff) as u8,
+            0x5800..=0x5fff => (self.irq_counter >> 8) as u8 | (self.irq_enabled as u8) << 7,
+            0x6000..=0x7fff => self.prgram[(addr - 0x6000) as usize],
+            _ => self
+                .prg_rom_offset(addr)
+                .map_or(0, |offset| self.prgrom[offset]),
+        }
+    }
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        let bank = match addr {
+            0x8000..=0xdfff => self.prg_banks[(addr as usize - 0x8000) / PRG_BANK_SIZE] as usize,
+            0xe000..=0xffff => last_bank(&self.prgrom, PRG_BANK_SIZE),
+            _ => return None,
+        };
+        Some(bank_offset(
+            &self.prgrom,
+            PRG_BANK_SIZE,
+            bank,
+            addr as usize,
+        ))
+    }
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4800..=0x4fff => {
+                let addr = self.access_internal_ram();
+                self.internal_ram[addr] = data
+            }
+            0x5000..=0x57ff => {
+                self.irq_counter = (self.irq_counter & 0x7f00) | data as u16;
+                self.irq_pending = false
+            }
+            0x5800..=0x5fff => {
+                self.irq_counter = (self.irq_counter & 0x00ff) | ((data & 0x7f) as u16) << 8;
+                self.irq_enabled = data & 0x80 != 0;
+                self.irq_pending = false
+            }
+            0x6000..=0x7fff => self.prgram[(addr - 0x6000) as usize] = data,
+            0x8000..=0xdfff => self.chr_banks[(addr as usize - 0x8000) / 0x800] = data,
+            // bit 6 disables the expansion sound
+            0xe000..=0xe7ff => self.prg_banks[0] = data & 0x3f,
+            0xe800..=0xefff => {
+                self.prg_banks[1] = data & 0x3f;
+                self.ciram_disabled = [data & 0x40 != 0, data & 0x80 != 0]
+            }
+            0xf000..=0xf7ff => self.prg_banks[2] = data & 0x3f,
+            0xf800..=0xffff => self.ram_addr = data,
+            _ => (),
+        }
+    }
+    fn read_chr(&self, addr: u16) -> u8 {
+        let target = self.target(addr as usize / CHR_BANK_SIZE, addr);
+        self.read_target(target)
+    }
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        let target = self.target(addr as usize / CHR_BANK_SIZE, addr);
+        self.write_target(target, data)
+    }
+    // Not consulted since the nametables are handled below
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Vertical
+    }
+    fn read_nametable(&self, addr: u16) -> Option<u8> {
+        let target = self.target(Namco163::nametable_slot(addr), addr);
+        Some(self.read_target(target))
+    }
+    fn write_nametable(&mut self, addr: u16, data: u8) -> bool {
+        let target = self.target(Namco163::nametable_slot(addr), addr);
+        self.write_target(target, data);
+        true
+    }
+    fn tick(&mut self, cpu_cycles: u64) {
+        if !self.irq_enabled || self.irq_counter == IRQ_MAX {
+            return;
+        }
+        let next = self.irq_counter as u64 + cpu_cycles;
+        if next >= IRQ_MAX as u64 {
+            self.irq_counter = IRQ_MAX;
+            self.irq_pending = true
+        } else {
+            self.irq_counter = next as u16
+        }
+    }
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+}
+
+#[cfg(test)]
+mod namco163_test {
+    use crate::cartridge::{Cartridge, Mirroring};
+
+    use super::{Mapper, Namco163};
+
+    fn make_namco163() -> Namco163 {
+        let prgrom = (0..8).flat_map(|b| vec![b as u8; 0x2000]).collect();
+        let chrrom = (0..16).flat_map(|b| vec![b as u8; 0x400]).collect();
+        Namco163::new(Cartridge {
+            prgrom,
+            chrrom,
+            mirroring: Mirroring::Horizontal,
+            mapper: 19,
+            submapper: 0,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_small_prg() {
+        // 4KB, mirrored through the fixed bank
+        let mut n163 = Namco163::new(Cartridge {
+            prgrom: (0..0x1000).map(|i| (i / 0x800) as u8).collect(),
+            mapper: 19,
+            ..Default::default()
+        });
+        assert_eq!(n163.read_prg(0xe000), 0);
+        assert_eq!(n163.read_prg(0xe800), 1);
+        assert_eq!(n163.read_prg(0xf000), 0);
+        assert_eq!(n163.read_prg(0xffff), 1);
+    }
+
+    #[test]
+    fn test_chr_in_nametable_space() {
+        let mut n163 = make_namco163();
+        // $2000 -> ciram page 0, $2400 -> CHR ROM bank 5
+        n163.write_prg(0xc000, 0xe0);
+        n163.write_prg(0xc800, 0x05);
+        assert!(n163.write_nametable(0x2000, 0x42));
+        assert_eq!(n163.read_nametable(0x2000), Some(0x42));
+        assert_eq!(n163.read_nametable(0x2400), Some(5));
+
+        // pattern tables can point at ciram too, unless disabled via $E800
+        n163.write_prg(0x8000, 0xe0);
+        assert_eq!(n163.read_chr(0x0000), 0x42);
+        n163.write_prg(0xe800, 0x40);
+        assert_eq!(n163.read_chr(0x0000), 0xe0 % 16);
+    }
+
+    #[test]
+    fn test_internal_ram_auto_increment() {
+        let mut n163 = make_namco163();
+        n163.write_prg(0xf800, 0x80 | 0x7f);
+        n163.write_prg(0x4800, 1);
+        n163.write_prg(0x4800, 2);
+        n163.write_prg(0xf800, 0x7f);
+        assert_eq!(n163.read_prg(0x4800), 1);
+        n163.write_prg(0xf800, 0x00);
+        assert_eq!(n163.read_prg(0x4800), 2);
+
+        // peeking leaves the address where it is
+        n163.write_prg(0xf800, 0x80 | 0x7f);
+        assert_eq!(n163.peek_prg(0x4800), 1);
+        assert_eq!(n163.peek_prg(0x4800), 1);
+        assert_eq!(n163.read_prg(0x4800), 1);
+        assert_eq!(n163.peek_prg(0x4800), 2);
+    }
+
+    #[test]
+    fn test_irq_counter() {
+        let mut n163 = make_namco163();
+        n163.write_prg(0x5000, 0xfd);
+        n163.write_prg(0x5800, 0x80 | 0x7f);
+        n163.tick(1);
+        assert!(!n163.irq_pending());
+        n163.tick(1);
+        assert!(n163.irq_pending());
+        n163.write_prg(0x5000, 0x00);
+        assert!(!n163.irq_pending());
+    }
+}
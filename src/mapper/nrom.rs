@@ -0,0 +1,99 @@
+use alloc::{boxed::Box, vec::Vec};
+#[cfg(feature = "std")]
+use core::mem;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "std")]
+use crate::savestate::StateError;
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    savestate,
+};
+
+use super::{chr_or_ram, copy_trainer, Mapper, PRG_RAM_SIZE};
+#[cfg(feature = "std")]
+use super::{load_with_chr, save_with_chr};
+
+/**
+ * Mapper 0. No bank switching, 16KB PRG is mirrored into 0xc000..=0xffff.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Nrom {
+    #[serde(skip)]
+    prgrom: Vec<u8>,
+    #[serde(with = "savestate::array")]
+    prgram: [u8; PRG_RAM_SIZE],
+    #[serde(skip)]
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(cartridge: Cartridge) -> Nrom {
+        let (chr, chr_is_ram) = chr_or_ram(cartridge.chrrom);
+        Nrom {
+            prgrom: cartridge.prgrom,
+            prgram: [0; PRG_RAM_SIZE],
+            chr,
+            chr_is_ram,
+            mirroring: cartridge.mirroring,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn box_clone(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+    fn load_trainer(&mut self, trainer: &[u8]) -> bool {
+        copy_trainer(&mut self.prgram, trainer);
+        true
+    }
+    #[cfg(feature = "std")]
+    fn save_state(&self) -> Vec<u8> {
+        save_with_chr(self, &self.chr, self.chr_is_ram)
+    }
+    #[cfg(feature = "std")]
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let state: Nrom = load_with_chr(state, &mut self.chr, self.chr_is_ram)?;
+        *self = Nrom {
+            prgrom: mem::take(&mut self.prgrom),
+            chr: mem::take(&mut self.chr),
+            ..state
+        };
+        Ok(())
+    }
+    fn peek_prg(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff => self.prgram[(addr - 0x6000) as usize],
+            _ => self
+                .prg_rom_offset(addr)
+                .map_or(0, |offset| self.prgrom[offset]),
+        }
+    }
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xffff => Some((addr - 0x8000) as usize % self.prgrom.len()),
+            _ => None,
+        }
+    }
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7fff = addr {
+            self.prgram[(addr - 0x6000) as usize] = data
+        }
+    }
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr[addr as usize % self.chr.len()]
+    }
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let len = self.chr.len();
+            self.chr[addr as usize % len] = data
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
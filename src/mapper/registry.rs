@@ -0,0 +1,194 @@
+use alloc::{boxed::Box, vec::Vec};
+use core::{error::Error, fmt};
+
+use crate::cartridge::Cartridge;
+
+use super::{Camerica, Mapper, Mmc4, Namco108, Namco163, Nrom, Uxrom, Vrc4, Vrc6};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MapperInfo {
+    pub number: u8,
+    pub name: &'static str,
+}
+
+struct Entry {
+    info: MapperInfo,
+    build: fn(Cartridge) -> Box<dyn Mapper>,
+}
+
+const fn entry(number: u8, name: &'static str, build: fn(Cartridge) -> Box<dyn Mapper>) -> Entry {
+    Entry {
+        info: MapperInfo { number, name },
+        build,
+    }
+}
+
+static SUPPORTED: [Entry; 12] = [
+    entry(0, "NROM", |c| Box::new(Nrom::new(c))),
+    entry(2, "UxROM", |c| Box::new(Uxrom::new(c))),
+    entry(10, "MMC4", |c| Box::new(Mmc4::new(c))),
+    entry(19, "Namco 163", |c| Box::new(Namco163::new(c))),
+    entry(21, "VRC4a/VRC4c", |c| Box::new(Vrc4::new(c))),
+    entry(22, "VRC2a", |c| Box::new(Vrc4::new(c))),
+    entry(23, "VRC2b/VRC4e/VRC4f", |c| Box::new(Vrc4::new(c))),
+    entry(24, "VRC6a", |c| Box::new(Vrc6::new(c))),
+    entry(25, "VRC2c/VRC4b/VRC4d", |c| Box::new(Vrc4::new(c))),
+    entry(26, "VRC6b", |c| Box::new(Vrc6::new(c))),
+    entry(71, "Camerica/Codemasters", |c| Box::new(Camerica::new(c))),
+    entry(206, "Namco 108/DxROM", |c| Box::new(Namco108::new(c))),
+];
+
+// Common boards we can at least name when refusing to run them
+static KNOWN_UNSUPPORTED: [(u8, &str); 15] = [
+    (1, "MMC1"),
+    (3, "CNROM"),
+    (4, "MMC3"),
+    (5, "MMC5"),
+    (7, "AxROM"),
+    (9, "MMC2"),
+    (11, "Color Dreams"),
+    (16, "Bandai FCG"),
+    (34, "BNROM/NINA-001"),
+    (66, "GxROM"),
+    (69, "Sunsoft FME-7"),
+    (85, "VRC7"),
+    (118, "TxSROM"),
+    (119, "TQROM"),
+    (228, "Action 52"),
+];
+
+// UNIF board names (prefix stripped) and the mapper/submapper they boil down to
+static BOARDS: [(&str, u8, u8); 31] = [
+    ("NROM", 0, 0),
+    ("NROM-128", 0, 0),
+    ("NROM-256", 0, 0),
+    ("RROM", 0, 0),
+    ("SNROM", 1, 0),
+    ("SLROM", 1, 0),
+    ("SXROM", 1, 0),
+    ("UNROM", 2, 0),
+    ("UOROM", 2, 0),
+    ("CNROM", 3, 0),
+    ("TLROM", 4, 0),
+    ("TSROM", 4, 0),
+    ("TKROM", 4, 0),
+    ("ELROM", 5, 0),
+    ("EKROM", 5, 0),
+    ("AOROM", 7, 0),
+    ("ANROM", 7, 0),
+    ("PNROM", 9, 0),
+    ("FJROM", 10, 0),
+    ("FKROM", 10, 0),
+    ("NAMCOT-163", 19, 0),
+    ("BF9093", 71, 0),
+    ("BF9096", 71, 0),
+    ("BF9097", 71, 1),
+    ("BNROM", 34, 0),
+    ("GNROM", 66, 0),
+    ("TLSROM", 118, 0),
+    ("TQROM", 119, 0),
+    ("DEROM", 206, 0),
+    ("DE1ROM", 206, 0),
+    ("DRROM", 206, 0),
+];
+
+#[derive(Debug, PartialEq)]
+pub struct UnsupportedMapper {
+    pub number: u8,
+    pub name: &'static str,
+}
+
+impl fmt::Display for UnsupportedMapper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Mapper {} ({}) is not supported.",
+            self.number, self.name
+        )
+    }
+}
+
+impl Error for UnsupportedMapper {}
+
+/**
+ * Every mapper number this emulator can run, in ascending order.
+ */
+pub fn supported_mappers() -> Vec<MapperInfo> {
+    SUPPORTED.iter().map(|e| e.info).collect()
+}
+
+/**
+ * Name of any mapper we know about, supported or not.
+ */
+pub fn mapper_name(number: u8) -> &'static str {
+    SUPPORTED
+        .iter()
+        .map(|e| (e.info.number, e.info.name))
+        .chain(KNOWN_UNSUPPORTED.iter().copied())
+        .find(|(n, _)| *n == number)
+        .map(|(_, name)| name)
+        .unwrap_or("unknown")
+}
+
+/**
+ * Mapper and submapper for a UNIF board name, e.g. "NROM-256" or "BF9097".
+ */
+pub fn board_mapper(board: &str) -> Option<(u8, u8)> {
+    BOARDS
+        .iter()
+        .find(|(name, _, _)| name.eq_ignore_ascii_case(board))
+        .map(|(_, mapper, submapper)| (*mapper, *submapper))
+}
+
+pub fn from_cartridge(cartridge: Cartridge) -> Result<Box<dyn Mapper>, UnsupportedMapper> {
+    match SUPPORTED.iter().find(|e| e.info.number == cartridge.mapper) {
+        Some(entry) => Ok((entry.build)(cartridge)),
+        None => Err(UnsupportedMapper {
+            number: cartridge.mapper,
+            name: mapper_name(cartridge.mapper),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod registry_test {
+    use crate::cartridge::{Cartridge, Mirroring};
+
+    use super::{from_cartridge, supported_mappers, UnsupportedMapper};
+
+    fn make_cartridge(mapper: u8) -> Cartridge {
+        Cartridge {
+            prgrom: vec![0; 0x8000],
+            chrrom: vec![0; 0x2000],
+            mirroring: Mirroring::Horizontal,
+            mapper,
+            submapper: 0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_unsupported_mapper() {
+        let err = from_cartridge(make_cartridge(4)).err().unwrap();
+        assert_eq!(
+            err,
+            UnsupportedMapper {
+                number: 4,
+                name: "MMC3"
+            }
+        );
+        assert_eq!(
+            from_cartridge(make_cartridge(250)).err().unwrap().name,
+            "unknown"
+        );
+    }
+
+    #[test]
+    fn test_supported_mappers() {
+        let supported = supported_mappers();
+        assert!(supported.windows(2).all(|w| w[0].number < w[1].number));
+        for info in supported {
+            assert!(from_cartridge(make_cartridge(info.number)).is_ok());
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use alloc::{boxed::Box, vec::Vec};
+#[cfg(feature = "std")]
+use core::mem;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cartridge::{Cartridge, Mirroring};
+#[cfg(feature = "std")]
+use crate::savestate::StateError;
+
+use super::{bank_offset, chr_or_ram, last_bank, Mapper};
+#[cfg(feature = "std")]
+use super::{load_with_chr, save_with_chr};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+
+/**
+ * UxROM, mapper 2 (Mega Man, Castlevania, Contra, Duck Tales).
+ *
+ * 0x8000..=0xbfff switchable 16KB bank, 0xc000..=0xffff fixed to the last bank.
+ * UNROM/UOROM only wire up 3-4 bank bits but the iNES mapper allows all 8,
+ * i.e. up to 4MB of PRG, so the whole byte is kept and wrapped by the bank count.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Uxrom {
+    #[serde(skip)]
+    prgrom: Vec<u8>,
+    #[serde(skip)]
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_bank: u8,
+    mirroring: Mirroring,
+}
+
+impl Uxrom {
+    pub fn new(cartridge: Cartridge) -> Uxrom {
+        let (chr, chr_is_ram) = chr_or_ram(cartridge.chrrom);
+        Uxrom {
+            prgrom: cartridge.prgrom,
+            chr,
+            chr_is_ram,
+            prg_bank: 0,
+            mirroring: cartridge.mirroring,
+        }
+    }
+}
+
+impl Mapper for Uxrom {
+    fn box_clone(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+    #[cfg(feature = "std")]
+    fn save_state(&self) -> Vec<u8> {
+        save_with_chr(self, &self.chr, self.chr_is_ram)
+    }
+    #[cfg(feature = "std")]
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let state: Uxrom = load_with_chr(state, &mut self.chr, self.chr_is_ram)?;
+        *self = Uxrom {
+            prgrom: mem::take(&mut self.prgrom),
+            chr: mem::take(&mut self.chr),
+            ..state
+        };
+        Ok(())
+    }
+    fn power_cycle(&mut self) {
+        self.prg_bank = 0
+    }
+    fn peek_prg(&self, addr: u16) -> u8 {
+        self.prg_rom_offset(addr)
+            .map_or(0, |offset| self.prgrom[offset])
+    }
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        let bank = match addr {
+            0x8000..=0xbfff => self.prg_bank as usize,
+            0xc000..=0xffff => last_bank(&self.prgrom, PRG_BANK_SIZE),
+            _ => return None,
+        };
+        Some(bank_offset(
+            &self.prgrom,
+            PRG_BANK_SIZE,
+            bank,
+            addr as usize,
+        ))
+    }
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if let 0x8000..=0xffff = addr {
+            self.prg_bank = data
+        }
+    }
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr[addr as usize % self.chr.len()]
+    }
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let len = self.chr.len();
+            self.chr[addr as usize % len] = data
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod uxrom_test {
+    use crate::cartridge::Cartridge;
+
+    use super::{Mapper, Uxrom};
+
+    #[test]
+    fn test_large_prg() {
+        // 512KB, well past what two fixed 16KB windows could ever hold
+        let prgrom = (0..32).flat_map(|b| vec![b as u8; 0x4000]).collect();
+        let mut uxrom = Uxrom::new(Cartridge {
+            prgrom,
+            mapper: 2,
+            ..Default::default()
+        });
+        assert_eq!(uxrom.read_prg(0x8000), 0);
+        assert_eq!(uxrom.read_prg(0xffff), 31);
+
+        uxrom.write_prg(0x8000, 17);
+        assert_eq!(uxrom.read_prg(0x8000), 17);
+        assert_eq!(uxrom.read_prg(0xbfff), 17);
+        // bank numbers past the end wrap
+        uxrom.write_prg(0x8000, 33);
+        assert_eq!(uxrom.read_prg(0x8000), 1);
+        assert_eq!(uxrom.read_prg(0xc000), 31);
+    }
+
+    #[test]
+    fn test_small_prg() {
+        // 8KB, less than the one fixed bank
+        let mut uxrom = Uxrom::new(Cartridge {
+            prgrom: (0..0x2000).map(|i| (i / 0x1000) as u8).collect(),
+            mapper: 2,
+            ..Default::default()
+        });
+        assert_eq!(uxrom.read_prg(0xc000), 0);
+        assert_eq!(uxrom.read_prg(0xd000), 1);
+        assert_eq!(uxrom.read_prg(0xe000), 0);
+        assert_eq!(uxrom.read_prg(0xffff), 1);
+        uxrom.write_prg(0x8000, 3);
+        assert_eq!(uxrom.read_prg(0x9000), 1);
+    }
+}
@@ -0,0 +1,335 @@
+use alloc::{boxed::Box, vec::Vec};
+#[cfg(feature = "std")]
+use core::mem;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "std")]
+use crate::savestate::StateError;
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    savestate,
+};
+
+use super::{
+    bank_offset, chr_or_ram, copy_trainer, last_bank, vrc_irq::VrcIrq, Mapper, PRG_RAM_SIZE,
+};
+#[cfg(feature = "std")]
+use super::{load_with_chr, save_with_chr};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
+
+/**
+ * Boards wire different CPU address lines to the chip's two register select
+ * pins. Each mask holds the address line(s) feeding one pin; for iNES 1 dumps
+ * (submapper 0) we can't tell the variants apart so both candidates are OR'd.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+struct Wiring {
+    pin0: u16,
+    pin1: u16,
+    vrc2: bool,
+    // VRC2a only has 7 bit CHR bank registers, the lowest bit is ignored
+    chr_shift: bool,
+}
+
+impl Wiring {
+    fn new(mapper: u8, submapper: u8) -> Wiring {
+        let (pin0, pin1, vrc2, chr_shift) = match (mapper, submapper) {
+            // VRC4a
+            (21, 1) => (0x02, 0x04, false, false),
+            // VRC4c
+            (21, 2) => (0x40, 0x80, false, false),
+            (21, _) => (0x42, 0x84, false, false),
+            // VRC2a
+            (22, _) => (0x02, 0x01, true, true),
+            // VRC4f
+            (23, 1) => (0x01, 0x02, false, false),
+            // VRC4e
+            (23, 2) => (0x04, 0x08, false, false),
+            // VRC2b
+            (23, 3) => (0x01, 0x02, true, false),
+            (23, _) => (0x05, 0x0a, false, false),
+            // VRC4b
+            (25, 1) => (0x02, 0x01, false, false),
+            // VRC4d
+            (25, 2) => (0x08, 0x04, false, false),
+            // VRC2c
+            (25, 3) => (0x02, 0x01, true, false),
+            // 25, the registry doesn't send anything else here
+            _ => (0x0a, 0x05, false, false),
+        };
+        Wiring {
+            pin0,
+            pin1,
+            vrc2,
+            chr_shift,
+        }
+    }
+    fn register(&self, addr: u16) -> u16 {
+        let bit0 = (addr & self.pin0 != 0) as u16;
+        let bit1 = (addr & self.pin1 != 0) as u16;
+        (addr & 0xf000) | bit1 << 1 | bit0
+    }
+}
+
+/**
+ * Konami VRC2 and VRC4, mappers 21, 22, 23 and 25 (TMNT, Gradius II, Crisis Force).
+ *
+ * PRG: two switchable 8KB banks and two fixed to the last banks. The VRC4 can
+ * swap 0x8000 and 0xc000.
+ * CHR: eight switchable 1KB banks.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Vrc4 {
+    #[serde(skip)]
+    prgrom: Vec<u8>,
+    #[serde(with = "savestate::array")]
+    prgram: [u8; PRG_RAM_SIZE],
+    #[serde(skip)]
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    wiring: Wiring,
+    prg_banks: [u8; 2],
+    prg_swap: bool,
+    chr_banks: [u16; 8],
+    mirroring: Mirroring,
+    irq: VrcIrq,
+}
+
+impl Vrc4 {
+    pub fn new(cartridge: Cartridge) -> Vrc4 {
+        let wiring = Wiring::new(cartridge.mapper, cartridge.submapper);
+        let (chr, chr_is_ram) = chr_or_ram(cartridge.chrrom);
+        Vrc4 {
+            prgrom: cartridge.prgrom,
+            prgram: [0; PRG_RAM_SIZE],
+            chr,
+            chr_is_ram,
+            wiring,
+            prg_banks: [0; 2],
+            prg_swap: false,
+            chr_banks: [0; 8],
+            mirroring: cartridge.mirroring,
+            irq: Default::default(),
+        }
+    }
+    fn prg_bank(&self, addr: u16) -> usize {
+        // PRG under 16KB has no second to last bank, bank_offset wraps what's left
+        let last = last_bank(&self.prgrom, PRG_BANK_SIZE);
+        match (addr, self.prg_swap) {
+            (0x8000..=0x9fff, false) | (0xc000..=0xdfff, true) => self.prg_banks[0] as usize,
+            (0x8000..=0x9fff, true) | (0xc000..=0xdfff, false) => last.saturating_sub(1),
+            (0xa000..=0xbfff, _) => self.prg_banks[1] as usize,
+            _ => last,
+        }
+    }
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE];
+        let bank = if self.wiring.chr_shift {
+            bank >> 1
+        } else {
+            bank
+        };
+        bank_offset(&self.chr, CHR_BANK_SIZE, bank as usize, addr as usize)
+    }
+    fn write_chr_bank(&mut self, reg: u16, data: u8) {
+        // 0xb000 + 0/1 -> bank 0 lo/hi, 0xb000 + 2/3 -> bank 1 lo/hi, ...
+        let idx = (((reg >> 12) - 0xb) * 2 + ((reg & 0b10) >> 1)) as usize;
+        let bank = self.chr_banks[idx];
+        self.chr_banks[idx] = if reg & 0b01 == 0 {
+            (bank & 0x1f0) | (data & 0x0f) as u16
+        } else {
+            let hi_mask = if self.wiring.vrc2 { 0x0f } else { 0x1f };
+            (bank & 0x0f) | ((data & hi_mask) as u16) << 4
+        }
+    }
+}
+
+impl Mapper for Vrc4 {
+    fn box_clone(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+    fn load_trainer(&mut self, trainer: &[u8]) -> bool {
+        copy_trainer(&mut self.prgram, trainer);
+        true
+    }
+    #[cfg(feature = "std")]
+    fn save_state(&self) -> Vec<u8> {
+        save_with_chr(self, &self.chr, self.chr_is_ram)
+    }
+    #[cfg(feature = "std")]
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let state: Vrc4 = load_with_chr(state, &mut self.chr, self.chr_is_ram)?;
+        *self = Vrc4 {
+            prgrom: mem::take(&mut self.prgrom),
+            chr: mem::take(&mut self.chr),
+            ..state
+        };
+        Ok(())
+    }
+    fn power_cycle(&mut self) {
+        self.prg_banks = [0; 2];
+        self.prg_swap = false;
+        self.chr_banks = [0; 8];
+        self.irq = Default::default()
+    }
+    fn peek_prg(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff => self.prgram[(addr - 0x6000) as usize],
+            _ => self
+                .prg_rom_offset(addr)
+                .map_or(0, |offset| self.prgrom[offset]),
+        }
+    }
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xffff => {
+                let bank = self.prg_bank(addr);
+                Some(bank_offset(
+                    &self.prgrom,
+                    PRG_BANK_SIZE,
+                    bank,
+                    addr as usize,
+                ))
+            }
+            _ => None,
+        }
+    }
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7fff = addr {
+            self.prgram[(addr - 0x6000) as usize] = data;
+            return;
+        }
+
+        let reg = self.wiring.register(addr);
+        match reg {
+            0x8000..=0x8003 => self.prg_banks[0] = data & 0x1f,
+            0x9000..=0x9003 if self.wiring.vrc2 => {
+                self.mirroring = match data & 0x01 {
+                    0 => Mirroring::Vertical,
+                    _ => Mirroring::Horizontal,
+                }
+            }
+            0x9000 | 0x9001 => {
+                self.mirroring = match data & 0b11 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                }
+            }
+            0x9002 | 0x9003 => self.prg_swap = data & 0b10 != 0,
+            0xa000..=0xa003 => self.prg_banks[1] = data & 0x1f,
+            0xb000..=0xefff => self.write_chr_bank(reg, data),
+            0xf000 if !self.wiring.vrc2 => self.irq.write_latch_lo(data),
+            0xf001 if !self.wiring.vrc2 => self.irq.write_latch_hi(data),
+            0xf002 if !self.wiring.vrc2 => self.irq.write_control(data),
+            0xf003 if !self.wiring.vrc2 => self.irq.acknowledge(),
+            _ => (),
+        }
+    }
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = data
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+    fn tick(&mut self, cpu_cycles: u64) {
+        self.irq.tick(cpu_cycles)
+    }
+    fn irq_pending(&self) -> bool {
+        self.irq.pending()
+    }
+}
+
+#[cfg(test)]
+mod vrc4_test {
+    use crate::cartridge::{Cartridge, Mirroring};
+
+    use super::{Mapper, Vrc4};
+
+    fn make_vrc4(mapper: u8, submapper: u8) -> Vrc4 {
+        let prgrom = (0..8).flat_map(|b| vec![b as u8; 0x2000]).collect();
+        let chrrom = (0..16).flat_map(|b| vec![b as u8; 0x400]).collect();
+        Vrc4::new(Cartridge {
+            prgrom,
+            chrrom,
+            mirroring: Mirroring::Vertical,
+            mapper,
+            submapper,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_wiring_variants() {
+        // VRC4e selects registers with A2/A3, VRC4f with A0/A1
+        let mut vrc4e = make_vrc4(23, 2);
+        vrc4e.write_prg(0xb008, 0x05);
+        assert_eq!(vrc4e.read_chr(0x400), 5);
+
+        let mut vrc4f = make_vrc4(23, 1);
+        vrc4f.write_prg(0xb002, 0x06);
+        assert_eq!(vrc4f.read_chr(0x400), 6);
+
+        // VRC2a drops the low bit of the CHR bank number
+        let mut vrc2a = make_vrc4(22, 0);
+        vrc2a.write_prg(0xb000, 0x07);
+        assert_eq!(vrc2a.read_chr(0x000), 3);
+    }
+
+    #[test]
+    fn test_prg_swap_mode() {
+        let mut vrc4 = make_vrc4(21, 1);
+        vrc4.write_prg(0x8000, 2);
+        assert_eq!(vrc4.read_prg(0x8000), 2);
+        assert_eq!(vrc4.read_prg(0xc000), 6);
+
+        vrc4.write_prg(0x9004, 0b10);
+        assert_eq!(vrc4.read_prg(0x8000), 6);
+        assert_eq!(vrc4.read_prg(0xc000), 2);
+        assert_eq!(vrc4.read_prg(0xe000), 7);
+    }
+
+    #[test]
+    fn test_small_prg() {
+        // 8KB, and 4KB which NES 2.0 can describe too, mirrored through the bank
+        for (mapper, size) in [(21, 0x2000), (21, 0x1000), (23, 0x1000)] {
+            let mut vrc4 = Vrc4::new(Cartridge {
+                prgrom: (0..size).map(|i| (i / 0x800) as u8).collect(),
+                chrrom: vec![0; 0x2000],
+                mapper,
+                ..Default::default()
+            });
+            for addr in [0x8000, 0xa000, 0xc000, 0xe000] {
+                assert_eq!(vrc4.read_prg(addr), 0);
+                assert_eq!(vrc4.read_prg(addr + 0x800), 1);
+                assert_eq!(vrc4.read_prg(addr + 0x1fff), ((size - 1) / 0x800) as u8);
+            }
+            vrc4.write_prg(0x9004, 0b10);
+            assert_eq!(vrc4.read_prg(0x8800), 1);
+        }
+    }
+
+    #[test]
+    fn test_cycle_mode_irq() {
+        let mut vrc4 = make_vrc4(21, 1);
+        vrc4.write_prg(0xf000, 0x0e);
+        vrc4.write_prg(0xf002, 0x0f);
+        vrc4.write_prg(0xf004, 0b110);
+        vrc4.tick(1);
+        assert!(!vrc4.irq_pending());
+        vrc4.tick(1);
+        assert!(vrc4.irq_pending());
+        vrc4.write_prg(0xf006, 0);
+        assert!(!vrc4.irq_pending());
+    }
+}
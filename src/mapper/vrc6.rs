@@ -0,0 +1,333 @@
+use alloc::{boxed::Box, vec::Vec};
+#[cfg(feature = "std")]
+use core::mem;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "std")]
+use crate::savestate::StateError;
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    savestate,
+};
+
+use super::{
+    bank_offset, chr_or_ram, copy_trainer, last_bank, vrc_irq::VrcIrq, Mapper, PRG_RAM_SIZE,
+};
+#[cfg(feature = "std")]
+use super::{load_with_chr, save_with_chr};
+
+const CHR_BANK_SIZE: usize = 0x400;
+
+/**
+ * Konami VRC6, mappers 24 (VRC6a) and 26 (VRC6b, A0 and A1 swapped).
+ * (Akumajou Densetsu, Madara, Esper Dream 2)
+ *
+ * PRG: 16KB switchable at 0x8000, 8KB switchable at 0xc000, last 8KB fixed at 0xe000.
+ * CHR: eight 1KB registers arranged by the banking mode in $B003.
+ *
+ * The expansion audio registers ($9000..=$B002) are accepted and ignored.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Vrc6 {
+    #[serde(skip)]
+    prgrom: Vec<u8>,
+    #[serde(with = "savestate::array")]
+    prgram: [u8; PRG_RAM_SIZE],
+    #[serde(skip)]
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    swap_a0_a1: bool,
+    prg_16k: u8,
+    prg_8k: u8,
+    chr_regs: [u8; 8],
+    /**
+     * $B003
+     * bits 0-1: CHR banking mode
+     * bits 2-3: mirroring
+     * bit 5: CHR A10 comes from the register rather than the PPU in 2KB modes
+     * bit 7: PRG RAM enable
+     */
+    ppu_banking: u8,
+    irq: VrcIrq,
+}
+
+impl Vrc6 {
+    pub fn new(cartridge: Cartridge) -> Vrc6 {
+        let (chr, chr_is_ram) = chr_or_ram(cartridge.chrrom);
+        Vrc6 {
+            prgrom: cartridge.prgrom,
+            prgram: [0; PRG_RAM_SIZE],
+            chr,
+            chr_is_ram,
+            swap_a0_a1: cartridge.mapper == 26,
+            prg_16k: 0,
+            prg_8k: 0,
+            chr_regs: [0; 8],
+            ppu_banking: 0,
+            irq: Default::default(),
+        }
+    }
+    fn register(&self, addr: u16) -> u16 {
+        let reg = addr & 0xf003;
+        if self.swap_a0_a1 {
+            (reg & 0xf000) | (reg & 0b01) << 1 | (reg & 0b10) >> 1
+        } else {
+            reg
+        }
+    }
+    fn prgram_enabled(&self) -> bool {
+        self.ppu_banking & 0x80 != 0
+    }
+    // 1KB bank number mapped at the given pattern table address
+    fn chr_bank(&self, addr: u16) -> usize {
+        let slot = addr as usize / CHR_BANK_SIZE;
+        let a10 = (addr as usize >> 10) & 0x01;
+        let two_kb = |reg: u8| {
+            if self.ppu_banking & 0x20 != 0 {
+                reg as usize
+            } else {
+                (reg as usize & !0x01) | a10
+            }
+        };
+        match self.ppu_banking & 0b11 {
+            0 => self.chr_regs[slot] as usize,
+            1 => two_kb(self.chr_regs[slot / 2]),
+            _ if slot < 4 => self.chr_regs[slot] as usize,
+            _ => two_kb(self.chr_regs[4 + (slot - 4) / 2]),
+        }
+    }
+    fn chr_offset(&self, addr: u16) -> usize {
+        bank_offset(&self.chr, CHR_BANK_SIZE, self.chr_bank(addr), addr as usize)
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn box_clone(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+    fn load_trainer(&mut self, trainer: &[u8]) -> bool {
+        copy_trainer(&mut self.prgram, trainer);
+        true
+    }
+    #[cfg(feature = "std")]
+    fn save_state(&self) -> Vec<u8> {
+        save_with_chr(self, &self.chr, self.chr_is_ram)
+    }
+    #[cfg(feature = "std")]
+    fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let state: Vrc6 = load_with_chr(state, &mut self.chr, self.chr_is_ram)?;
+        *self = Vrc6 {
+            prgrom: mem::take(&mut self.prgrom),
+            chr: mem::take(&mut self.chr),
+            ..state
+        };
+        Ok(())
+    }
+    fn power_cycle(&mut self) {
+        self.prg_16k = 0;
+        self.prg_8k = 0;
+        self.chr_regs = [0; 8];
+        self.ppu_banking = 0;
+        self.irq = Default::default()
+    }
+    fn peek_prg(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff if self.prgram_enabled() => self.prgram[(addr - 0x6000) as usize],
+            _ => self
+                .prg_rom_offset(addr)
+                .map_or(0, |offset| self.prgrom[offset]),
+        }
+    }
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        let (bank_size, bank) = match addr {
+            0x8000..=0xbfff => (0x4000, self.prg_16k as usize),
+            0xc000..=0xdfff => (0x2000, self.prg_8k as usize),
+            0xe000..=0xffff => (0x2000, last_bank(&self.prgrom, 0x2000)),
+            _ => return None,
+        };
+        Some(bank_offset(&self.prgrom, bank_size, bank, addr as usize))
+    }
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7fff = addr {
+            if self.prgram_enabled() {
+                self.prgram[(addr - 0x6000) as usize] = data
+            }
+            return;
+        }
+
+        match self.register(addr) {
+            0x8000..=0x8003 => self.prg_16k = data & 0x0f,
+            0xb003 => self.ppu_banking = data,
+            0xc000..=0xc003 => self.prg_8k = data & 0x1f,
+            reg @ 0xd000..=0xd003 => self.chr_regs[(reg & 0b11) as usize] = data,
+            reg @ 0xe000..=0xe003 => self.chr_regs[4 + (reg & 0b11) as usize] = data,
+            0xf000 => self.irq.write_latch(data),
+            0xf001 => self.irq.write_control(data),
+            0xf002 => self.irq.acknowledge(),
+            // expansion audio
+            _ => (),
+        }
+    }
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = data
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        match (self.ppu_banking >> 2) & 0b11 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+    fn tick(&mut self, cpu_cycles: u64) {
+        self.irq.tick(cpu_cycles)
+    }
+    fn irq_pending(&self) -> bool {
+        self.irq.pending()
+    }
+}
+
+#[cfg(test)]
+mod vrc6_test {
+    use crate::cartridge::{Cartridge, Mirroring};
+
+    use super::{Mapper, Vrc6};
+
+    fn make_vrc6(mapper: u8) -> Vrc6 {
+        // 16 8KB PRG banks and 32 1KB CHR banks, each filled with its number
+        let prgrom = (0..16).flat_map(|b| vec![b as u8; 0x2000]).collect();
+        let chrrom = (0..32).flat_map(|b| vec![b as u8; 0x400]).collect();
+        Vrc6::new(Cartridge {
+            prgrom,
+            chrrom,
+            mirroring: Mirroring::Vertical,
+            mapper,
+            submapper: 0,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_prg_banking() {
+        let mut vrc6 = make_vrc6(24);
+        vrc6.write_prg(0x8000, 3);
+        vrc6.write_prg(0xc000, 5);
+        assert_eq!(vrc6.read_prg(0x8000), 6);
+        assert_eq!(vrc6.read_prg(0xa000), 7);
+        assert_eq!(vrc6.read_prg(0xc000), 5);
+        assert_eq!(vrc6.read_prg(0xe000), 15);
+    }
+
+    #[test]
+    fn test_chr_modes() {
+        let mut vrc6 = make_vrc6(24);
+        for (reg, addr) in [
+            0xd000, 0xd001, 0xd002, 0xd003, 0xe000, 0xe001, 0xe002, 0xe003,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            vrc6.write_prg(addr, 10 + reg as u8);
+        }
+
+        // mode 0: eight 1KB banks
+        assert_eq!(vrc6.read_chr(0x0400), 11);
+        assert_eq!(vrc6.read_chr(0x1c00), 17);
+
+        // mode 1: four 2KB banks from R0..=R3, A10 from the PPU
+        vrc6.write_prg(0xb003, 0x01);
+        assert_eq!(vrc6.read_chr(0x0000), 10);
+        assert_eq!(vrc6.read_chr(0x0400), 11);
+        assert_eq!(vrc6.read_chr(0x0800), 10);
+        // or from the register with bit 5
+        vrc6.write_prg(0xb003, 0x21);
+        assert_eq!(vrc6.read_chr(0x0400), 10);
+
+        // modes 2 and 3: 1KB banks below $1000, 2KB banks from R4 and R5 above
+        vrc6.write_prg(0xb003, 0x02);
+        assert_eq!(vrc6.read_chr(0x0c00), 13);
+        assert_eq!(vrc6.read_chr(0x1000), 14);
+        assert_eq!(vrc6.read_chr(0x1400), 15);
+        assert_eq!(vrc6.read_chr(0x1800), 14);
+
+        for (banking, mirroring) in [
+            (0x00, Mirroring::Vertical),
+            (0x04, Mirroring::Horizontal),
+            (0x08, Mirroring::SingleScreenLower),
+            (0x0c, Mirroring::SingleScreenUpper),
+        ] {
+            vrc6.write_prg(0xb003, banking);
+            assert_eq!(vrc6.mirroring(), mirroring);
+        }
+    }
+
+    #[test]
+    fn test_swapped_address_lines() {
+        let mut vrc6a = make_vrc6(24);
+        let mut vrc6b = make_vrc6(26);
+        for vrc6 in [&mut vrc6a, &mut vrc6b] {
+            vrc6.write_prg(0xd001, 9);
+            vrc6.write_prg(0xd002, 4);
+        }
+        assert_eq!(vrc6a.read_chr(0x0400), 9);
+        assert_eq!(vrc6a.read_chr(0x0800), 4);
+        assert_eq!(vrc6b.read_chr(0x0400), 4);
+        assert_eq!(vrc6b.read_chr(0x0800), 9);
+
+        // $F002 is the IRQ control on a VRC6b
+        vrc6b.write_prg(0xf000, 0xff);
+        vrc6b.write_prg(0xf002, 0b110);
+        vrc6b.tick(1);
+        assert!(vrc6b.irq_pending());
+    }
+
+    #[test]
+    fn test_prgram_enable() {
+        let mut vrc6 = make_vrc6(24);
+        vrc6.write_prg(0x6000, 0x42);
+        assert_eq!(vrc6.read_prg(0x6000), 0);
+
+        vrc6.write_prg(0xb003, 0x80);
+        vrc6.write_prg(0x6000, 0x42);
+        assert_eq!(vrc6.read_prg(0x6000), 0x42);
+        vrc6.write_prg(0xb003, 0x00);
+        assert_eq!(vrc6.read_prg(0x6000), 0);
+        vrc6.write_prg(0xb003, 0x80);
+        assert_eq!(vrc6.read_prg(0x7fff), 0);
+        assert_eq!(vrc6.read_prg(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_cycle_mode_irq() {
+        let mut vrc6 = make_vrc6(24);
+        vrc6.write_prg(0xf000, 0xfe);
+        vrc6.write_prg(0xf001, 0b110);
+        vrc6.tick(1);
+        assert!(!vrc6.irq_pending());
+        vrc6.tick(1);
+        assert!(vrc6.irq_pending());
+        vrc6.write_prg(0xf002, 0);
+        assert!(!vrc6.irq_pending());
+    }
+
+    #[test]
+    fn test_small_prg() {
+        // 4KB, mirrored through every bank
+        let mut vrc6 = Vrc6::new(Cartridge {
+            prgrom: (0..0x1000).map(|i| (i / 0x800) as u8).collect(),
+            mapper: 24,
+            ..Default::default()
+        });
+        assert_eq!(vrc6.read_prg(0xe000), 0);
+        assert_eq!(vrc6.read_prg(0xe800), 1);
+        assert_eq!(vrc6.read_prg(0xffff), 1);
+        assert_eq!(vrc6.read_prg(0x9800), 1);
+    }
+}
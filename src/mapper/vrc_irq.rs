@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+
+// Konami VRC IRQ counter, shared by the VRC4, VRC6 and VRC7.
+
+const PRESCALER_RELOAD: i16 = 341;
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub(crate) struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    // ticks down by 3 every cpu cycle so the counter is clocked once per scanline
+    prescaler: i16,
+    enabled: bool,
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl VrcIrq {
+    pub fn write_latch_lo(&mut self, data: u8) {
+        self.latch = (self.latch & 0xf0) | (data & 0x0f)
+    }
+    pub fn write_latch_hi(&mut self, data: u8) {
+        self.latch = (self.latch & 0x0f) | ((data & 0x0f) << 4)
+    }
+    pub fn write_latch(&mut self, data: u8) {
+        self.latch = data
+    }
+    /**
+     * Control register
+     * bit 0: enable again after acknowledgement
+     * bit 1: enable (also reloads the counter from the latch)
+     * bit 2: 1 = cycle mode, 0 = scanline mode
+     */
+    pub fn write_control(&mut self, data: u8) {
+        self.enable_after_ack = data & 0b001 != 0;
+        self.enabled = data & 0b010 != 0;
+        self.cycle_mode = data & 0b100 != 0;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = PRESCALER_RELOAD;
+        }
+        self.pending = false
+    }
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack
+    }
+    pub fn pending(&self) -> bool {
+        self.pending
+    }
+    pub fn tick(&mut self, cpu_cycles: u64) {
+        for _ in 0..cpu_cycles {
+            self.clock()
+        }
+    }
+    fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.cycle_mode {
+            self.clock_counter()
+        } else {
+            self.prescaler -= 3;
+            if self.prescaler <= 0 {
+                self.prescaler += PRESCALER_RELOAD;
+                self.clock_counter()
+            }
+        }
+    }
+    fn clock_counter(&mut self) {
+        if self.counter == 0xff {
+            self.counter = self.latch;
+            self.pending = true
+        } else {
+            self.counter += 1
+        }
+    }
+}
@@ -0,0 +1,678 @@
+use std::{collections::BTreeMap, error::Error, fmt, fs, io, path::Path};
+
+use crate::{
+    controller::Buttons,
+    cpu::CPU,
+    fm2,
+    savestate::{SaveState, StateError, CHECKSUM_INTERVAL},
+};
+
+const MOVIE_TAG: &str = "nes-movie 1";
+// FM2's column order, right most bit of `Buttons` last
+pub const BUTTON_CHARS: [(Buttons, char); 8] = [
+    (Buttons::RIGHT, 'R'),
+    (Buttons::LEFT, 'L'),
+    (Buttons::DOWN, 'D'),
+    (Buttons::UP, 'U'),
+    (Buttons::START, 'T'),
+    (Buttons::SELECT, 'S'),
+    (Buttons::B, 'B'),
+    (Buttons::A, 'A'),
+];
+
+#[derive(Debug)]
+pub enum MovieError {
+    Io(io::Error),
+    // missing the "nes-movie 1" first line
+    BadMagic,
+    BadLine { line: usize, reason: &'static str },
+    // valid movie using something we can't play back
+    Unsupported(&'static str),
+    // the save state it starts from
+    State(StateError),
+    WrongState { expected: u32, found: u32 },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::Io(e) => write!(f, "Unable to access movie: {}", e),
+            MovieError::BadMagic => write!(f, "File is not a movie."),
+            MovieError::BadLine { line, reason } => {
+                write!(f, "Bad movie, line {}: {}", line, reason)
+            }
+            MovieError::Unsupported(what) => {
+                write!(f, "Movie uses {}, which isn't supported.", what)
+            }
+            MovieError::State(e) => write!(f, "Unable to start the movie: {}", e),
+            MovieError::WrongState { expected, found } => write!(
+                f,
+                "Movie starts from a different save state (CRC32 {:08x}, this is {:08x}).",
+                expected, found
+            ),
+        }
+    }
+}
+
+impl Error for MovieError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MovieError::Io(e) => Some(e),
+            MovieError::State(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MovieError {
+    fn from(e: io::Error) -> Self {
+        MovieError::Io(e)
+    }
+}
+
+impl From<StateError> for MovieError {
+    fn from(e: StateError) -> Self {
+        MovieError::State(e)
+    }
+}
+
+/**
+ * Where a movie starts from: power on, right after the rom is loaded, or a
+ * save state. The state is kept in the movie or in a file of its own, either
+ * way with a CRC32 that has to match before the movie plays.
+ */
+#[derive(Clone, PartialEq, Debug, Default)]
+pub enum MovieStart {
+    #[default]
+    PowerOn,
+    Embedded(Vec<u8>),
+    // relative to the movie
+    File {
+        path: String,
+        crc32: u32,
+    },
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(text.get(idx..idx + 2)?, 16).ok())
+        .collect()
+}
+
+/**
+ * Controller state for one frame, one entry per port (3 and 4 through the
+ * Four Score).
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FrameInput {
+    pub buttons: [Buttons; 4],
+    // reset button pressed before this frame
+    pub soft_reset: bool,
+    // switched off and on again before this frame
+    pub power_cycle: bool,
+}
+
+impl Default for FrameInput {
+    fn default() -> Self {
+        FrameInput {
+            buttons: [Buttons::empty(); 4],
+            soft_reset: false,
+            power_cycle: false,
+        }
+    }
+}
+
+impl FrameInput {
+    pub fn capture(cpu: &mut CPU) -> FrameInput {
+        let mut input = FrameInput::default();
+        for (port, buttons) in input.buttons.iter_mut().enumerate() {
+            *buttons = cpu.controller(port).buttons()
+        }
+        input
+    }
+    pub fn apply(&self, cpu: &mut CPU) {
+        if self.power_cycle {
+            cpu.power_cycle()
+        }
+        if self.soft_reset {
+            cpu.soft_reset()
+        }
+        for (port, buttons) in self.buttons.iter().enumerate() {
+            cpu.set_controller_state(port, *buttons)
+        }
+    }
+}
+
+fn buttons_to_text(buttons: Buttons) -> String {
+    BUTTON_CHARS
+        .iter()
+        .map(|(button, c)| if buttons.contains(*button) { *c } else { '.' })
+        .collect()
+}
+
+/**
+ * FM2 style "RLDUTSBA" column, anything but ' ' and '.' is a held button.
+ */
+pub(crate) fn buttons_from_text(text: &str) -> Option<Buttons> {
+    if text.chars().count() != BUTTON_CHARS.len() {
+        return None;
+    }
+    let mut buttons = Buttons::empty();
+    for (c, (button, _)) in text.chars().zip(BUTTON_CHARS.iter()) {
+        buttons.set(*button, c != '.' && c != ' ')
+    }
+    Some(buttons)
+}
+
+/**
+ * A recording of the input for every frame, played back against the same rom
+ * it reproduces the run exactly.
+ *
+ * On disk it's a text file: a few `key value` header lines then one
+ * `|RLDUTSBA|RLDUTSBA|RLDUTSBA|RLDUTSBA|` line per frame, followed by `reset`
+ * on frames where the reset button was pressed and `check <crc32>` on frames
+ * with a checksum of the machine right before them. A movie from a save state
+ * has `start state <crc32>` and the state in hex on a `state` line, or
+ * `start state-file <crc32> <path>`.
+ */
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Movie {
+    // CRC32 of the rom the movie was recorded against, imported movies may not
+    // have one
+    pub rom_crc32: Option<u32>,
+    pub start: MovieStart,
+    pub frames: Vec<FrameInput>,
+    // every `CHECKSUM_INTERVAL` frames, playback that doesn't match has
+    // desynced
+    pub checksums: BTreeMap<usize, u32>,
+}
+
+impl Movie {
+    pub fn new(rom_crc32: u32) -> Movie {
+        Movie {
+            rom_crc32: Some(rom_crc32),
+            start: MovieStart::PowerOn,
+            frames: Vec::new(),
+            checksums: BTreeMap::new(),
+        }
+    }
+    /**
+     * Grab the controllers as they are right before a frame runs.
+     */
+    pub fn record_frame(&mut self, cpu: &mut CPU) {
+        self.frames.push(FrameInput::capture(cpu))
+    }
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", MOVIE_TAG);
+        if let Some(crc32) = self.rom_crc32 {
+            text.push_str(&format!("rom_crc32 {:08x}\n", crc32));
+        }
+        match &self.start {
+            MovieStart::PowerOn => text.push_str("start power-on\n"),
+            MovieStart::Embedded(state) => {
+                let crc32 = crc32fast::hash(state);
+                text.push_str(&format!("start state {:08x}\n", crc32));
+                text.push_str(&format!("state {}\n", to_hex(state)))
+            }
+            MovieStart::File { path, crc32 } => {
+                text.push_str(&format!("start state-file {:08x} {}\n", crc32, path))
+            }
+        }
+        for (idx, frame) in self.frames.iter().enumerate() {
+            text.push('|');
+            for buttons in frame.buttons {
+                text.push_str(&buttons_to_text(buttons));
+                text.push('|');
+            }
+            if frame.power_cycle {
+                text.push_str(" power");
+            }
+            if frame.soft_reset {
+                text.push_str(" reset");
+            }
+            if let Some(checksum) = self.checksums.get(&idx) {
+                text.push_str(&format!(" check {:08x}", checksum));
+            }
+            text.push('\n');
+        }
+        text
+    }
+    pub fn parse(text: &str) -> Result<Movie, MovieError> {
+        let mut lines = text.lines().enumerate();
+        match lines.next() {
+            Some((_, line)) if line.trim() == MOVIE_TAG => (),
+            _ => return Err(MovieError::BadMagic),
+        }
+        let mut movie = Movie::default();
+        // an embedded state's line and CRC32, checked against the state once
+        // both are in
+        let mut state_crc32 = None;
+        let mut state = None;
+        for (idx, line) in lines {
+            let bad = |reason| MovieError::BadLine {
+                line: idx + 1,
+                reason,
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('|') {
+                let mut input = FrameInput::default();
+                let (line, commands) = line.rsplit_once('|').unwrap_or_default();
+                let mut commands = commands.split_whitespace();
+                while let Some(command) = commands.next() {
+                    match command {
+                        "reset" => input.soft_reset = true,
+                        "power" => input.power_cycle = true,
+                        "check" => {
+                            let checksum = commands
+                                .next()
+                                .and_then(|crc| u32::from_str_radix(crc, 16).ok())
+                                .ok_or(bad("bad checksum"))?;
+                            movie.checksums.insert(movie.frames.len(), checksum);
+                        }
+                        _ => return Err(bad("unknown command")),
+                    }
+                }
+                let columns: Vec<&str> = line.trim_matches('|').split('|').collect();
+                if columns.len() > input.buttons.len() {
+                    return Err(bad("too many controllers"));
+                }
+                for (buttons, column) in input.buttons.iter_mut().zip(columns) {
+                    *buttons = buttons_from_text(column).ok_or(bad("bad controller column"))?
+                }
+                movie.frames.push(input);
+                continue;
+            }
+            match line.split_once(' ') {
+                Some(("rom_crc32", crc)) => {
+                    movie.rom_crc32 = Some(
+                        u32::from_str_radix(crc.trim(), 16).map_err(|_| bad("bad rom_crc32"))?,
+                    )
+                }
+                Some(("start", "power-on")) => movie.start = MovieStart::PowerOn,
+                Some(("start", start)) => {
+                    let crc32 = |text: &str| u32::from_str_radix(text, 16).ok();
+                    match start.split_once(' ') {
+                        Some(("state", crc)) => {
+                            let crc = crc32(crc.trim()).ok_or(bad("bad state CRC32"))?;
+                            state_crc32 = Some((idx + 1, crc))
+                        }
+                        Some(("state-file", rest)) => {
+                            let (crc, path) =
+                                rest.split_once(' ').ok_or(bad("missing the path"))?;
+                            movie.start = MovieStart::File {
+                                path: path.trim().to_string(),
+                                crc32: crc32(crc).ok_or(bad("bad state CRC32"))?,
+                            }
+                        }
+                        _ => return Err(bad("unknown start")),
+                    }
+                }
+                Some(("state", hex)) => state = Some(from_hex(hex.trim()).ok_or(bad("bad state"))?),
+                // unknown keys are ignored so newer movies still load
+                _ => (),
+            }
+        }
+        match (state_crc32, state) {
+            (Some((_, expected)), Some(state)) => {
+                let found = crc32fast::hash(&state);
+                if found != expected {
+                    return Err(MovieError::WrongState { expected, found });
+                }
+                movie.start = MovieStart::Embedded(state)
+            }
+            (Some((line, _)), None) => {
+                return Err(MovieError::BadLine {
+                    line,
+                    reason: "the state is missing",
+                })
+            }
+            _ => (),
+        }
+        Ok(movie)
+    }
+    /**
+     * Loads our own movies as well as FCEUX .fm2 ones.
+     */
+    pub fn load(path: &str) -> Result<Movie, MovieError> {
+        let text = fs::read_to_string(path)?;
+        if text.starts_with(fm2::FM2_TAG) {
+            fm2::parse(&text)
+        } else {
+            Movie::parse(&text)
+        }
+    }
+    pub fn save(&self, path: &str) -> Result<(), MovieError> {
+        fs::write(path, self.to_text())?;
+        Ok(())
+    }
+    /**
+     * The state to load before the first frame, None from power on. `path` is
+     * the movie's, a state file is found next to it.
+     */
+    pub fn start_state(&self, path: &str) -> Result<Option<SaveState>, MovieError> {
+        let state = match &self.start {
+            MovieStart::PowerOn => return Ok(None),
+            MovieStart::Embedded(state) => SaveState::from_bytes(state)?,
+            MovieStart::File {
+                path: state_path,
+                crc32,
+            } => {
+                let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+                let bytes = fs::read(dir.join(state_path))?;
+                let found = crc32fast::hash(&bytes);
+                if found != *crc32 {
+                    return Err(MovieError::WrongState {
+                        expected: *crc32,
+                        found,
+                    });
+                }
+                SaveState::from_bytes(&bytes)?
+            }
+        };
+        Ok(Some(state))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MovieMode {
+    Recording,
+    Playing,
+    // played to the end, input is back to the keyboard
+    Finished,
+}
+
+/**
+ * A movie being recorded or played back, driven once per frame by the
+ * frontend.
+ */
+pub struct MovieSession {
+    pub movie: Movie,
+    mode: MovieMode,
+    frame: usize,
+    // the last frame whose checksum matched, and the first that didn't
+    matched: usize,
+    desync: Option<usize>,
+}
+
+impl MovieSession {
+    pub fn record(movie: Movie) -> MovieSession {
+        let frame = movie.frames.len();
+        MovieSession {
+            movie,
+            mode: MovieMode::Recording,
+            frame,
+            matched: 0,
+            desync: None,
+        }
+    }
+    pub fn play(movie: Movie) -> MovieSession {
+        MovieSession {
+            movie,
+            mode: MovieMode::Playing,
+            frame: 0,
+            matched: 0,
+            desync: None,
+        }
+    }
+    pub fn mode(&self) -> MovieMode {
+        self.mode
+    }
+    // frames recorded or played so far
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+    /**
+     * Where playback first went its own way: the last frame the machine still
+     * matched the recording on and the frame it no longer did.
+     */
+    pub fn desync(&self) -> Option<(usize, usize)> {
+        self.desync.map(|frame| (self.matched, frame))
+    }
+    /**
+     * Recording (or playing frames that lost theirs to an edit) notes the
+     * checksum, playing compares it.
+     */
+    fn check(&mut self, cpu: &CPU) {
+        if !self.frame.is_multiple_of(CHECKSUM_INTERVAL) || self.desync.is_some() {
+            return;
+        }
+        let checksum = cpu.save_state().checksum();
+        match self.movie.checksums.get(&self.frame) {
+            Some(&expected) if expected != checksum => self.desync = Some(self.frame),
+            Some(_) => self.matched = self.frame,
+            None => {
+                self.movie.checksums.insert(self.frame, checksum);
+            }
+        }
+    }
+    /**
+     * Call right before running a frame: records the controllers or overwrites
+     * them with the movie's input. Returns true on the frame playback runs out.
+     */
+    pub fn advance(&mut self, cpu: &mut CPU) -> bool {
+        match self.mode {
+            MovieMode::Recording => {
+                self.movie.checksums.remove(&self.frame);
+                self.check(cpu);
+                self.movie.record_frame(cpu);
+                self.frame += 1;
+                false
+            }
+            MovieMode::Playing => match self.movie.frames.get(self.frame).copied() {
+                Some(input) => {
+                    self.check(cpu);
+                    input.apply(cpu);
+                    self.frame += 1;
+                    false
+                }
+                None => {
+                    self.mode = MovieMode::Finished;
+                    true
+                }
+            },
+            MovieMode::Finished => false,
+        }
+    }
+    /**
+     * Carry on from right before `frame`, once the machine's been put back
+     * to how it was then. The rest plays back, or at the end recording
+     * carries on.
+     */
+    pub fn seek(&mut self, frame: usize) {
+        self.frame = frame.min(self.movie.frames.len());
+        // checked again on the way
+        self.matched = self.matched.min(self.frame);
+        if self.desync.is_some_and(|desync| desync >= self.frame) {
+            self.desync = None
+        }
+        self.mode = if self.frame == self.movie.frames.len() {
+            MovieMode::Recording
+        } else {
+            MovieMode::Playing
+        }
+    }
+    /**
+     * Stop playback where it is and hand the controllers back to the player,
+     * the rest of the movie is dropped and recording carries on from here.
+     */
+    pub fn take_over(&mut self, cpu: &mut CPU) {
+        if self.mode == MovieMode::Recording {
+            return;
+        }
+        self.movie.frames.truncate(self.frame);
+        self.movie.checksums.split_off(&self.frame);
+        FrameInput::default().apply(cpu);
+        self.mode = MovieMode::Recording;
+    }
+}
+
+#[cfg(test)]
+mod movie_test {
+    use crate::{bus::Bus, controller::Buttons, cpu::CPU, ppu::PPU};
+
+    use super::{FrameInput, Movie, MovieError, MovieMode, MovieSession, MovieStart};
+
+    #[test]
+    fn test_round_trip() {
+        let mut movie = Movie::new(0x3337ec46);
+        movie.frames.push(FrameInput::default());
+        movie.frames.push(FrameInput {
+            buttons: [
+                Buttons::A | Buttons::RIGHT,
+                Buttons::START,
+                Buttons::empty(),
+                Buttons::UP,
+            ],
+            soft_reset: true,
+            power_cycle: false,
+        });
+        movie.frames.push(FrameInput {
+            power_cycle: true,
+            ..FrameInput::default()
+        });
+        let text = movie.to_text();
+        assert!(text.contains("|R......A|....T...|........|...U....| reset"));
+        assert!(text.contains("|........|........|........|........| power"));
+        assert_eq!(Movie::parse(&text).unwrap(), movie);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(Movie::parse("hello"), Err(MovieError::BadMagic)));
+        assert!(matches!(
+            Movie::parse("nes-movie 1\n|RLD|\n"),
+            Err(MovieError::BadLine { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_start_state() {
+        let mut cpu = CPU::new(Bus::new(PPU::new()));
+        cpu.poke(0x10, 0x42);
+        let state = cpu.save_state().to_bytes();
+        let mut movie = Movie::new(0);
+        movie.start = MovieStart::Embedded(state.clone());
+        let text = movie.to_text();
+        assert_eq!(Movie::parse(&text).unwrap(), movie);
+        let loaded = movie.start_state("movie.txt").unwrap().unwrap();
+        assert_eq!(loaded.to_bytes(), state);
+        // one byte of the state changed
+        let idx = text.find("state 4e").unwrap() + 10;
+        let mut corrupt = text.clone();
+        corrupt.replace_range(
+            idx..idx + 1,
+            if &text[idx..idx + 1] == "0" { "1" } else { "0" },
+        );
+        assert!(matches!(
+            Movie::parse(&corrupt),
+            Err(MovieError::WrongState { .. })
+        ));
+
+        let dir = std::env::temp_dir().join("movie_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("start.state"), &state).unwrap();
+        let movie_path = dir.join("movie.txt");
+        let movie_path = movie_path.to_str().unwrap();
+        let text = format!(
+            "nes-movie 1\nstart state-file {:08x} start.state\n",
+            crc32fast::hash(&state)
+        );
+        let movie = Movie::parse(&text).unwrap();
+        assert_eq!(movie.to_text(), text);
+        assert!(movie.start_state(movie_path).unwrap().is_some());
+        let movie = Movie::parse("nes-movie 1\nstart state-file 12345678 start.state\n").unwrap();
+        assert!(matches!(
+            movie.start_state(movie_path),
+            Err(MovieError::WrongState {
+                expected: 0x12345678,
+                ..
+            })
+        ));
+    }
+
+    fn make_movie() -> Movie {
+        let mut movie = Movie::new(0);
+        for buttons in [Buttons::A, Buttons::B, Buttons::START] {
+            movie.frames.push(FrameInput {
+                buttons: [
+                    buttons,
+                    Buttons::empty(),
+                    Buttons::empty(),
+                    Buttons::empty(),
+                ],
+                soft_reset: false,
+                power_cycle: false,
+            })
+        }
+        movie
+    }
+
+    #[test]
+    fn test_playback() {
+        let mut cpu = CPU::new(Bus::new(PPU::new()));
+        let mut session = MovieSession::play(make_movie());
+        assert!(!session.advance(&mut cpu));
+        assert_eq!(cpu.controller(0).buttons(), Buttons::A);
+        assert!(!session.advance(&mut cpu));
+        assert!(!session.advance(&mut cpu));
+        assert_eq!(cpu.controller(0).buttons(), Buttons::START);
+        // out of input
+        assert!(session.advance(&mut cpu));
+        assert_eq!(session.mode(), MovieMode::Finished);
+        assert!(!session.advance(&mut cpu));
+    }
+
+    #[test]
+    fn test_desync() {
+        let mut cpu = CPU::new(Bus::new(PPU::new()));
+        let mut session = MovieSession::record(Movie::new(0));
+        for _ in 0..150 {
+            session.advance(&mut cpu);
+            cpu.run_frame()
+        }
+        let movie = session.movie;
+        assert_eq!(movie.checksums.keys().collect::<Vec<_>>(), [&0, &60, &120]);
+        let text = movie.to_text();
+        assert!(text.contains(&format!(" check {:08x}\n", movie.checksums[&60])));
+        assert_eq!(Movie::parse(&text).unwrap(), movie);
+
+        let play = |poke_at: Option<usize>| {
+            let mut cpu = CPU::new(Bus::new(PPU::new()));
+            let mut session = MovieSession::play(movie.clone());
+            while !session.advance(&mut cpu) {
+                if Some(session.frame()) == poke_at {
+                    cpu.poke(0x10, 0x42)
+                }
+                cpu.run_frame()
+            }
+            session.desync()
+        };
+        assert_eq!(play(None), None);
+        assert_eq!(play(Some(70)), Some((60, 120)));
+    }
+
+    #[test]
+    fn test_take_over() {
+        let mut cpu = CPU::new(Bus::new(PPU::new()));
+        let mut session = MovieSession::play(make_movie());
+        session.advance(&mut cpu);
+        session.take_over(&mut cpu);
+        assert_eq!(session.mode(), MovieMode::Recording);
+        assert_eq!(cpu.controller(0).buttons(), Buttons::empty());
+        cpu.controller(0).set_buttons(Buttons::SELECT);
+        session.advance(&mut cpu);
+        assert_eq!(session.movie.frames.len(), 2);
+        assert_eq!(session.movie.frames[1].buttons[0], Buttons::SELECT);
+    }
+}
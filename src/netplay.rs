@@ -0,0 +1,578 @@
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt,
+    io::{self, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    controller::Buttons,
+    cpu::CPU,
+    savestate::{self, SaveState, StateError, CHECKSUM_INTERVAL},
+};
+
+// bumped when the messages change, both ends have to agree
+const PROTOCOL: u16 = 1;
+pub const DEFAULT_DELAY: u8 = 2;
+const CHECK_INTERVAL: u64 = CHECKSUM_INTERVAL as u64;
+// checksums the host keeps waiting for the client's to come in
+const CHECKS_KEPT: usize = 4;
+// how long a frame waits for the other end before the frontend gets a turn
+const POLL: Duration = Duration::from_millis(4);
+// no input for this long and the other end is gone
+const STALL_TIMEOUT: Duration = Duration::from_secs(15);
+// a save state is the biggest thing sent, with room to spare
+const MAX_MESSAGE: usize = 16 << 20;
+
+#[derive(Debug)]
+pub enum NetplayError {
+    Io(io::Error),
+    Encoding(bincode::Error),
+    State(StateError),
+    // ours, theirs
+    WrongGame(u32, u32),
+    Protocol(&'static str),
+    Disconnected,
+    TimedOut,
+}
+
+impl fmt::Display for NetplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetplayError::Io(e) => write!(f, "Netplay connection failed: {}", e),
+            NetplayError::Encoding(e) => write!(f, "Bad netplay message: {}", e),
+            NetplayError::State(e) => write!(f, "Unable to sync with the host: {}", e),
+            NetplayError::WrongGame(ours, theirs) => write!(
+                f,
+                "The other player has a different game ({:08x}, this is {:08x})",
+                theirs, ours
+            ),
+            NetplayError::Protocol(what) => write!(f, "Netplay went wrong: {}", what),
+            NetplayError::Disconnected => write!(f, "The other player left"),
+            NetplayError::TimedOut => write!(f, "The other player stopped responding"),
+        }
+    }
+}
+
+impl Error for NetplayError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            NetplayError::Io(e) => Some(e),
+            NetplayError::Encoding(e) => Some(e),
+            NetplayError::State(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for NetplayError {
+    fn from(e: io::Error) -> Self {
+        NetplayError::Io(e)
+    }
+}
+
+impl From<bincode::Error> for NetplayError {
+    fn from(e: bincode::Error) -> Self {
+        NetplayError::Encoding(e)
+    }
+}
+
+impl From<StateError> for NetplayError {
+    fn from(e: StateError) -> Self {
+        NetplayError::State(e)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum Message {
+    Hello {
+        protocol: u16,
+        crc32: u32,
+        // the host's say goes
+        delay: u8,
+    },
+    // the sender's controller on `frame`
+    Input {
+        frame: u64,
+        buttons: Buttons,
+    },
+    // CRC32 of the client's save state right before `frame` runs, made after
+    // the `resyncs`th state from the host
+    Checksum {
+        frame: u64,
+        crc32: u32,
+        resyncs: u32,
+    },
+    // the host's machine right before `frame` runs, to carry on from
+    State {
+        frame: u64,
+        state: Vec<u8>,
+    },
+    Bye,
+}
+
+/**
+ * A length, as a little endian u32, then the message.
+ */
+fn send(stream: &mut TcpStream, message: &Message) -> Result<(), NetplayError> {
+    let bytes = savestate::encode(message);
+    stream.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    stream.write_all(&bytes)?;
+    Ok(())
+}
+
+fn receive(stream: &mut TcpStream) -> Result<Message, NetplayError> {
+    let mut len = [0; 4];
+    match stream.read_exact(&mut len) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+            return Err(NetplayError::Disconnected)
+        }
+        result => result?,
+    }
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_MESSAGE {
+        return Err(NetplayError::Protocol("message too big"));
+    }
+    let mut bytes = vec![0; len];
+    stream.read_exact(&mut bytes)?;
+    Ok(bincode::deserialize(&bytes)?)
+}
+
+/**
+ * Lockstep play with one other emulator over TCP. The host is player 1 and
+ * the one who joins player 2. Each end sends its controller for `delay`
+ * frames ahead, and a frame only runs once both ends' input for it is in,
+ * so both machines see the same input on the same frame and stay the same.
+ *
+ * Joining starts from the host's save state. Once a second the client sends
+ * a checksum of its machine, and when it doesn't match the host's the host
+ * sends its state from then: the client loads it and runs the frames since
+ * again. The same happens when the host loads a state of its own.
+ */
+pub struct Netplay {
+    stream: TcpStream,
+    incoming: Receiver<Result<Message, NetplayError>>,
+    host: bool,
+    delay: u64,
+    // the next frame to run
+    frame: u64,
+    // the local player's keys, what gets sent
+    held: Buttons,
+    // input for every frame so far, ours and theirs
+    local: Vec<Buttons>,
+    remote: Vec<Buttons>,
+    // host: ours by frame with the state they're of, and the client's
+    checks: VecDeque<(u64, u32, Vec<u8>)>,
+    their_checks: VecDeque<(u64, u32)>,
+    // host: the last frame both machines were the same on
+    matched: u64,
+    // states sent by the host, or loaded by the client
+    resyncs: u32,
+    // client: a state to carry on from once its frame comes
+    pending: Option<(u64, Vec<u8>)>,
+    waiting_since: Option<Instant>,
+    messages: Vec<String>,
+}
+
+impl Netplay {
+    /**
+     * Wait for a player to join on `listener`, and start them off from where
+     * `cpu` is.
+     */
+    pub fn host(
+        listener: &TcpListener,
+        cpu: &mut CPU,
+        crc32: u32,
+        delay: u8,
+    ) -> Result<Netplay, NetplayError> {
+        let (mut stream, peer) = listener.accept()?;
+        match receive(&mut stream)? {
+            Message::Hello { protocol, .. } if protocol != PROTOCOL => {
+                return Err(NetplayError::Protocol("a different version"))
+            }
+            Message::Hello { crc32: theirs, .. } if theirs != crc32 => {
+                return Err(NetplayError::WrongGame(crc32, theirs))
+            }
+            Message::Hello { .. } => (),
+            _ => return Err(NetplayError::Protocol("expected hello")),
+        }
+        let hello = Message::Hello {
+            protocol: PROTOCOL,
+            crc32,
+            delay,
+        };
+        send(&mut stream, &hello)?;
+        let state = cpu.save_state().to_bytes();
+        send(&mut stream, &Message::State { frame: 0, state })?;
+        let mut netplay = Netplay::new(stream, true, delay)?;
+        netplay
+            .messages
+            .push(format!("{} joined as player 2", peer));
+        Ok(netplay)
+    }
+
+    /**
+     * Join the game hosted at `addr`, `cpu` takes on the host's state.
+     */
+    pub fn join(
+        addr: impl ToSocketAddrs,
+        cpu: &mut CPU,
+        crc32: u32,
+    ) -> Result<Netplay, NetplayError> {
+        let mut stream = TcpStream::connect(addr)?;
+        let hello = Message::Hello {
+            protocol: PROTOCOL,
+            crc32,
+            delay: 0,
+        };
+        send(&mut stream, &hello)?;
+        let delay = match receive(&mut stream)? {
+            Message::Hello { delay, .. } => delay,
+            _ => return Err(NetplayError::Protocol("expected hello")),
+        };
+        match receive(&mut stream)? {
+            Message::State { state, .. } => cpu.load_state(&SaveState::from_bytes(&state)?)?,
+            _ => return Err(NetplayError::Protocol("expected the host's state")),
+        }
+        let mut netplay = Netplay::new(stream, false, delay)?;
+        netplay.messages.push("Joined as player 2".to_string());
+        Ok(netplay)
+    }
+
+    fn new(stream: TcpStream, host: bool, delay: u8) -> Result<Netplay, NetplayError> {
+        // input is a few bytes a frame, it can't wait
+        stream.set_nodelay(true)?;
+        let mut reader = stream.try_clone()?;
+        let (sender, incoming) = mpsc::channel();
+        thread::spawn(move || loop {
+            let message = receive(&mut reader);
+            let done = message.is_err();
+            if sender.send(message).is_err() || done {
+                break;
+            }
+        });
+        let delay = delay as u64;
+        Ok(Netplay {
+            stream,
+            incoming,
+            host,
+            delay,
+            frame: 0,
+            held: Buttons::empty(),
+            // nobody pressed anything before the start
+            local: vec![Buttons::empty(); delay as usize],
+            remote: vec![Buttons::empty(); delay as usize],
+            checks: VecDeque::new(),
+            their_checks: VecDeque::new(),
+            matched: 0,
+            resyncs: 0,
+            pending: None,
+            waiting_since: None,
+            messages: Vec::new(),
+        })
+    }
+
+    pub fn is_host(&self) -> bool {
+        self.host
+    }
+
+    // which controller port the local player is
+    pub fn port(&self) -> usize {
+        if self.host {
+            0
+        } else {
+            1
+        }
+    }
+
+    pub fn set_button(&mut self, button: Buttons, pressed: bool) {
+        self.held.set(button, pressed)
+    }
+
+    /**
+     * Things that happened since last time, for the OSD.
+     */
+    pub fn take_messages(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.messages)
+    }
+
+    /**
+     * Call right before running a frame: sets both controllers and returns
+     * true, or false when the other player's input isn't here yet and the
+     * frame has to wait. Call again until it's true.
+     */
+    pub fn advance(&mut self, cpu: &mut CPU) -> Result<bool, NetplayError> {
+        let send_frame = self.frame + self.delay;
+        if self.local.len() as u64 == send_frame {
+            self.local.push(self.held);
+            let input = Message::Input {
+                frame: send_frame,
+                buttons: self.held,
+            };
+            send(&mut self.stream, &input)?
+        }
+        self.receive_all(cpu)?;
+        if self.remote.len() as u64 <= self.frame {
+            let since = *self.waiting_since.get_or_insert_with(Instant::now);
+            if since.elapsed() > STALL_TIMEOUT {
+                return Err(NetplayError::TimedOut);
+            }
+            return Ok(false);
+        }
+        self.waiting_since = None;
+        self.apply_input(cpu, self.frame);
+        if self.frame > 0 && self.frame.is_multiple_of(CHECK_INTERVAL) {
+            let state = cpu.save_state();
+            let crc32 = state.checksum();
+            if self.host {
+                let state = state.to_bytes();
+                self.checks.push_back((self.frame, crc32, state));
+                if self.checks.len() > CHECKS_KEPT {
+                    self.checks.pop_front();
+                }
+                self.compare_checks()?
+            } else {
+                let check = Message::Checksum {
+                    frame: self.frame,
+                    crc32,
+                    resyncs: self.resyncs,
+                };
+                send(&mut self.stream, &check)?
+            }
+        }
+        self.frame += 1;
+        Ok(true)
+    }
+
+    /**
+     * The host's machine changed under it (a state was loaded), the client
+     * has to carry on from the same place.
+     */
+    pub fn resync(&mut self, cpu: &CPU) -> Result<(), NetplayError> {
+        if !self.host {
+            return Err(NetplayError::Protocol("only the host can change the game"));
+        }
+        let state = cpu.save_state().to_bytes();
+        self.send_state(self.frame, state)
+    }
+
+    fn send_state(&mut self, frame: u64, state: Vec<u8>) -> Result<(), NetplayError> {
+        // their checksums from before they've loaded it are no good
+        self.resyncs += 1;
+        self.their_checks.clear();
+        send(&mut self.stream, &Message::State { frame, state })
+    }
+
+    fn apply_input(&self, cpu: &mut CPU, frame: u64) {
+        let (ours, theirs) = (self.local[frame as usize], self.remote[frame as usize]);
+        let (player1, player2) = if self.host {
+            (ours, theirs)
+        } else {
+            (theirs, ours)
+        };
+        cpu.set_controller_state(0, player1);
+        cpu.set_controller_state(1, player2);
+    }
+
+    fn receive_all(&mut self, cpu: &mut CPU) -> Result<(), NetplayError> {
+        loop {
+            let waiting = self.remote.len() as u64 <= self.frame;
+            let message = if waiting {
+                self.incoming.recv_timeout(POLL)
+            } else {
+                self.incoming
+                    .try_recv()
+                    .map_err(|_| RecvTimeoutError::Timeout)
+            };
+            let message = match message {
+                Ok(message) => message?,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return Err(NetplayError::Disconnected),
+            };
+            match message {
+                Message::Input { frame, buttons } => {
+                    if frame != self.remote.len() as u64 {
+                        return Err(NetplayError::Protocol("input out of order"));
+                    }
+                    self.remote.push(buttons)
+                }
+                Message::Checksum {
+                    frame,
+                    crc32,
+                    resyncs,
+                } if self.host => {
+                    if resyncs == self.resyncs {
+                        self.their_checks.push_back((frame, crc32));
+                        self.compare_checks()?
+                    }
+                }
+                Message::State { frame, state } if !self.host => {
+                    self.pending = Some((frame, state))
+                }
+                Message::Bye => return Err(NetplayError::Disconnected),
+                _ => return Err(NetplayError::Protocol("unexpected message")),
+            }
+        }
+        if let Some((frame, _)) = &self.pending {
+            if *frame <= self.frame {
+                let (frame, state) = self.pending.take().unwrap();
+                self.catch_up(cpu, frame, &state)?
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * Client: load the host's state from `frame` and run the frames since
+     * again, with the input they had.
+     */
+    fn catch_up(&mut self, cpu: &mut CPU, frame: u64, state: &[u8]) -> Result<(), NetplayError> {
+        cpu.load_state(&SaveState::from_bytes(state)?)?;
+        for replayed in frame..self.frame {
+            self.apply_input(cpu, replayed);
+            cpu.run_frame()
+        }
+        self.resyncs += 1;
+        self.messages.push(format!("Resynced to frame {}", frame));
+        Ok(())
+    }
+
+    /**
+     * Host: checksums for the same frame that differ mean the client's gone
+     * its own way, it gets ours from then.
+     */
+    fn compare_checks(&mut self) -> Result<(), NetplayError> {
+        let mut desync = None;
+        self.their_checks.retain(|&(frame, theirs)| {
+            match self.checks.iter().find(|(ours, _, _)| *ours == frame) {
+                Some((_, crc32, state)) => {
+                    if *crc32 != theirs && desync.is_none() {
+                        desync = Some((frame, state.clone()))
+                    } else if *crc32 == theirs && desync.is_none() {
+                        self.matched = self.matched.max(frame)
+                    }
+                    false
+                }
+                // not run here yet, or too long ago to do anything about
+                None => {
+                    frame
+                        > self
+                            .frame
+                            .saturating_sub(CHECK_INTERVAL * CHECKS_KEPT as u64)
+                }
+            }
+        });
+        if let Some((frame, state)) = desync {
+            self.messages.push(format!(
+                "Out of sync between frames {} and {}, resyncing",
+                self.matched, frame
+            ));
+            // the client carries on from ours
+            self.matched = frame;
+            self.send_state(frame, state)?
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Netplay {
+    fn drop(&mut self) {
+        // it's leaving anyway, the other end finds out one way or another
+        let _ = send(&mut self.stream, &Message::Bye);
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(test)]
+mod netplay_test {
+    use std::{net::TcpListener, thread};
+
+    use crate::{
+        controller::Buttons,
+        cpu::{cpu_with_program, CPU},
+        headless::frame_hash,
+    };
+
+    use super::Netplay;
+
+    const FRAMES: u64 = 200;
+
+    fn machine() -> CPU {
+        let program = [
+            0xa9, 0x01, 0x8d, 0x16, 0x40, // $8000: LDA #$01, STA $4016
+            0xa9, 0x00, 0x8d, 0x16, 0x40, // LDA #$00, STA $4016
+            0xad, 0x16, 0x40, 0x65, 0x10, 0x85, 0x10, // LDA $4016, ADC $10, STA $10
+            0xad, 0x17, 0x40, 0x65, 0x11, 0x85, 0x11, // LDA $4017, ADC $11, STA $11
+            0x4c, 0x00, 0x80, // JMP $8000
+        ];
+        cpu_with_program(&program)
+    }
+
+    // each player holds A on frames of their own, the client goes its own
+    // way at `desync`
+    fn play(netplay: &mut Netplay, cpu: &mut CPU, desync: Option<u64>) -> (u32, [u8; 2]) {
+        let mut frame = 0;
+        while frame < FRAMES {
+            let turn = if netplay.is_host() { 3 } else { 5 };
+            netplay.set_button(Buttons::A, frame % turn == 0);
+            if Some(frame) == desync {
+                cpu.poke(0x10, 0x99)
+            }
+            if netplay.advance(cpu).unwrap() {
+                cpu.run_frame();
+                frame += 1
+            }
+        }
+        (frame_hash(cpu.frame()), [cpu.peek(0x10), cpu.peek(0x11)])
+    }
+
+    #[test]
+    fn test_lockstep() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let host = thread::spawn(move || {
+            let mut cpu = machine();
+            let mut netplay = Netplay::host(&listener, &mut cpu, 0x1234, 2).unwrap();
+            let result = play(&mut netplay, &mut cpu, None);
+            // ahead by up to the delay, the client has to finish first
+            while let Ok(ready) = netplay.advance(&mut cpu) {
+                if ready {
+                    cpu.run_frame()
+                }
+            }
+            (result, netplay.take_messages())
+        });
+        let mut cpu = machine();
+        // somewhere else entirely, joining brings it back to the host's
+        cpu.poke(0x11, 0x42);
+        let mut netplay = Netplay::join(addr, &mut cpu, 0x1234).unwrap();
+        assert_eq!(cpu.peek(0x11), 0);
+        let ours = play(&mut netplay, &mut cpu, Some(30));
+        drop(netplay);
+        let (theirs, messages) = host.join().unwrap();
+        assert_eq!(ours, theirs);
+        assert!(messages.contains(&"Out of sync between frames 0 and 60, resyncing".to_string()));
+    }
+
+    #[test]
+    fn test_wrong_game() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let host = thread::spawn(move || {
+            Netplay::host(&listener, &mut machine(), 0x1234, 2)
+                .err()
+                .map(|e| e.to_string())
+        });
+        assert!(Netplay::join(addr, &mut machine(), 0x5678).is_err());
+        assert_eq!(
+            host.join().unwrap().as_deref(),
+            Some("The other player has a different game (00005678, this is 00001234)")
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use nes::{
+    bus::PowerOnRam,
+    cartridge::{Cartridge, Mirroring, Region},
+    config::{Aspect, Config, ConfigError, Filter},
+    controller::LatchMode,
+};
+
+/**
+ * Settings for one game, from games/<crc32>.toml next to config.toml. Fixes
+ * for bad headers go on the cartridge before it's inserted, the rest is
+ * merged over the global config for the session and never saved back.
+ */
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameOverrides {
+    pub mapper: Option<u8>,
+    pub submapper: Option<u8>,
+    pub mirroring: Option<Mirroring>,
+    pub region: Option<Region>,
+    // a palette name, or a .pal file to use
+    pub palette: Option<String>,
+    pub filter: Option<Filter>,
+    pub aspect: Option<Aspect>,
+    pub zapper: Option<bool>,
+    pub four_score: Option<bool>,
+    pub power_pad: Option<bool>,
+    pub latch: Option<LatchMode>,
+    pub dmc_conflict: Option<bool>,
+    pub power_on_ram: Option<PowerOnRam>,
+}
+
+impl GameOverrides {
+    /**
+     * games/ in the same directory as the config file.
+     */
+    pub fn path(config_path: &Path, crc32: u32) -> PathBuf {
+        config_path
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join("games")
+            .join(format!("{:08x}.toml", crc32))
+    }
+
+    /**
+     * None when the game doesn't have a file, which is most of them.
+     */
+    pub fn load(path: &Path) -> Result<Option<GameOverrides>, ConfigError> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = fs::read_to_string(path)?;
+        toml::from_str(&text).map(Some).map_err(ConfigError::Parse)
+    }
+
+    pub fn apply_to_cartridge(&self, cartridge: &mut Cartridge) {
+        cartridge.mapper = self.mapper.unwrap_or(cartridge.mapper);
+        cartridge.submapper = self.submapper.unwrap_or(cartridge.submapper);
+        cartridge.mirroring = self.mirroring.unwrap_or(cartridge.mirroring);
+        cartridge.region = self.region.unwrap_or(cartridge.region);
+    }
+
+    pub fn apply_to_config(&self, config: &mut Config) {
+        if let Some(palette) = &self.palette {
+            // a file is picked by its name once it's loaded
+            if palette.to_ascii_lowercase().ends_with(".pal") {
+                config.display.palette_files.push(palette.clone());
+                config.display.palette = Path::new(palette)
+                    .file_stem()
+                    .map_or(palette.clone(), |stem| stem.to_string_lossy().into_owned());
+            } else {
+                config.display.palette = palette.clone()
+            }
+        }
+        let display = &mut config.display;
+        display.filter = self.filter.unwrap_or(display.filter);
+        display.aspect = self.aspect.unwrap_or(display.aspect);
+        let input = &mut config.input;
+        input.zapper = self.zapper.unwrap_or(input.zapper);
+        input.four_score = self.four_score.unwrap_or(input.four_score);
+        input.power_pad = self.power_pad.unwrap_or(input.power_pad);
+        input.latch = self.latch.unwrap_or(input.latch);
+        input.dmc_conflict = self.dmc_conflict.unwrap_or(input.dmc_conflict);
+        let emulation = &mut config.emulation;
+        emulation.power_on_ram = self.power_on_ram.unwrap_or(emulation.power_on_ram);
+        // the cartridge has it already, this keeps the global one off it
+        emulation.region = self.region.or(emulation.region);
+    }
+}
+
+#[cfg(test)]
+mod overrides_test {
+    use std::path::Path;
+
+    use nes::{
+        bus::PowerOnRam,
+        cartridge::{Cartridge, Mirroring, Region},
+        config::{Config, Filter},
+    };
+
+    use super::GameOverrides;
+
+    #[test]
+    fn test_path() {
+        assert_eq!(
+            GameOverrides::path(Path::new("/home/me/.config/nes/config.toml"), 0xab),
+            Path::new("/home/me/.config/nes/games/000000ab.toml")
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        let overrides: GameOverrides = toml::from_str(
+            r#"
+            mapper = 71
+            mirroring = "single-screen-lower"
+            region = "pal"
+            palette = "palettes/smooth.pal"
+            filter = "crt"
+            zapper = true
+            power_on_ram = "pattern"
+            "#,
+        )
+        .unwrap();
+
+        let mut cartridge = Cartridge {
+            mapper: 2,
+            ..Default::default()
+        };
+        overrides.apply_to_cartridge(&mut cartridge);
+        assert_eq!(cartridge.mapper, 71);
+        assert_eq!(cartridge.mirroring, Mirroring::SingleScreenLower);
+        assert_eq!(cartridge.region, Region::Pal);
+
+        let mut config = Config::default();
+        overrides.apply_to_config(&mut config);
+        assert_eq!(config.display.palette, "smooth");
+        assert_eq!(config.display.palette_files, ["palettes/smooth.pal"]);
+        assert_eq!(config.display.filter, Filter::Crt);
+        assert!(config.input.zapper);
+        assert_eq!(config.emulation.power_on_ram, PowerOnRam::Pattern);
+        assert_eq!(config.emulation.region, Some(Region::Pal));
+        // the rest is left alone
+        assert_eq!(config.input.four_score, Config::default().input.four_score);
+    }
+
+    #[test]
+    fn test_unknown_setting() {
+        assert!(toml::from_str::<GameOverrides>("turbo = true").is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use serde::{Deserialize, Serialize};
+
+// buttons in the order they come out of bit 3 and bit 4 of $4017
+const D3_ORDER: [usize; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+const D4_ORDER: [usize; 4] = [4, 3, 12, 8];
+
+pub const BUTTONS: usize = 12;
+
+/**
+ * Power Pad (Family Trainer) mat, plugged into port 2 in place of controller 2.
+ *
+ * Side B has 12 sensors, numbered left to right, top to bottom:
+ *
+ *    1  2  3  4
+ *    5  6  7  8
+ *    9 10 11 12
+ *
+ * Strobing latches them into two shift registers read out serially through
+ * bits 3 and 4 of $4017, 1 is a pressed sensor. Bit 4 only carries 4 buttons,
+ * both registers shift in 1s once they run out.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PowerPad {
+    // bit n - 1 is sensor n
+    pressed: u16,
+    strobe: bool,
+    shift: [u8; 2],
+}
+
+impl Default for PowerPad {
+    fn default() -> Self {
+        PowerPad::new()
+    }
+}
+
+impl PowerPad {
+    pub fn new() -> PowerPad {
+        PowerPad {
+            pressed: 0,
+            strobe: false,
+            shift: [0; 2],
+        }
+    }
+    /**
+     * `button` is the sensor number, 1 to 12.
+     */
+    pub fn set_button(&mut self, button: usize, pressed: bool) {
+        assert!(
+            (1..=BUTTONS).contains(&button),
+            "No Power Pad button {}",
+            button
+        );
+        let bit = 1 << (button - 1);
+        if pressed {
+            self.pressed |= bit
+        } else {
+            self.pressed &= !bit
+        }
+    }
+    fn pack(&self, order: &[usize]) -> u8 {
+        let mut report = 0xff;
+        for (bit, button) in order.iter().enumerate() {
+            if self.pressed & (1 << (button - 1)) == 0 {
+                report &= !(1 << bit)
+            }
+        }
+        report
+    }
+    fn latch(&mut self) {
+        self.shift = [self.pack(&D3_ORDER), self.pack(&D4_ORDER)]
+    }
+    pub fn write(&mut self, data: u8) {
+        let strobe = data & 1 == 1;
+        if strobe || self.strobe {
+            self.latch()
+        }
+        self.strobe = strobe
+    }
+    pub fn read(&mut self) -> u8 {
+        if self.strobe {
+            self.latch()
+        }
+        let data = ((self.shift[0] & 1) << 3) | ((self.shift[1] & 1) << 4);
+        for shift in self.shift.iter_mut() {
+            *shift = (*shift >> 1) | 0x80
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod power_pad_test {
+    use super::PowerPad;
+
+    #[test]
+    fn test_read() {
+        let mut pad = PowerPad::new();
+        pad.set_button(1, true);
+        pad.set_button(3, true);
+        pad.set_button(7, true);
+        pad.write(1);
+        pad.write(0);
+        let reads: Vec<u8> = (0..9).map(|_| pad.read()).collect();
+        // bit 3: 2, 1, 5, 9, 6, 10, 11, 7 then 1s
+        // bit 4: 4, 3, 12, 8 then 1s
+        assert_eq!(
+            reads,
+            vec![0x00, 0x18, 0x00, 0x00, 0x10, 0x10, 0x10, 0x18, 0x18]
+        );
+    }
+}
@@ -1,10 +1,19 @@
+use alloc::{vec, vec::Vec};
+
+#[derive(Clone)]
 pub struct Frame {
     data: Vec<u8>
 }
 
+impl Default for Frame {
+  fn default() -> Self {
+    Frame::new()
+  }
+}
+
 impl Frame {
-  const WIDTH: usize = 256;
-  const HEIGHT: usize = 240;
+  pub const WIDTH: usize = 256;
+  pub const HEIGHT: usize = 240;
 
   pub fn new() -> Frame {
     Frame {
@@ -13,9 +22,19 @@ impl Frame {
   } 
 
   pub fn set_pixel(&mut self, x: u8, y: u8, rgb: (u8, u8, u8)) {
-    let addr = (y as usize) * Frame::WIDTH + (x as usize);
+    let addr = ((y as usize) * Frame::WIDTH + (x as usize)) * 3;
     self.data[addr] = rgb.0;
     self.data[addr + 1] = rgb.1;
     self.data[addr + 2] = rgb.2;
   }
+
+  pub fn pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+    let addr = (y * Frame::WIDTH + x) * 3;
+    (self.data[addr], self.data[addr + 1], self.data[addr + 2])
+  }
+
+  // RGB24, row major
+  pub fn data(&self) -> &[u8] {
+    &self.data
+  }
 }
\ No newline at end of file
@@ -1,4 +1,9 @@
+pub use frame::Frame;
+#[cfg(feature = "std")]
+pub use palette::load_palette;
+pub use palette::{grayscale, parse_palette, Palette, PaletteError, SYSTEM_PALLETE};
 pub use ppu::PPU;
+pub use ppubus::{PpuAccess, PpuMemory};
 
 mod ppu;
 mod ppubus;
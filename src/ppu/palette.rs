@@ -1,6 +1,14 @@
-#[rustfmt::skip]
+use core::fmt;
+#[cfg(feature = "std")]
+use std::{fs, io};
+
+/**
+ * RGB for each of the 64 colors the PPU can put out.
+ */
+pub type Palette = [(u8, u8, u8); 64];
 
-pub static SYSTEM_PALLETE: [(u8, u8, u8); 64] = [
+#[rustfmt::skip]
+pub static SYSTEM_PALLETE: Palette = [
    (0x80, 0x80, 0x80), (0x00, 0x3D, 0xA6), (0x00, 0x12, 0xB0), (0x44, 0x00, 0x96), (0xA1, 0x00, 0x5E),
    (0xC7, 0x00, 0x28), (0xBA, 0x06, 0x00), (0x8C, 0x17, 0x00), (0x5C, 0x2F, 0x00), (0x10, 0x45, 0x00),
    (0x05, 0x4A, 0x00), (0x00, 0x47, 0x2E), (0x00, 0x41, 0x66), (0x00, 0x00, 0x00), (0x05, 0x05, 0x05),
@@ -15,3 +23,88 @@ pub static SYSTEM_PALLETE: [(u8, u8, u8); 64] = [
    (0xFF, 0xEF, 0xA6), (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
    (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11)
 ];
+
+#[derive(Debug)]
+pub enum PaletteError {
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    BadSize(usize),
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            PaletteError::Io(e) => write!(f, "Unable to read palette: {}", e),
+            PaletteError::BadSize(size) => write!(
+                f,
+                "Palette should be 192 bytes (or 1536 with emphasis) but is {}.",
+                size
+            ),
+        }
+    }
+}
+
+impl core::error::Error for PaletteError {}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for PaletteError {
+    fn from(e: io::Error) -> Self {
+        PaletteError::Io(e)
+    }
+}
+
+/**
+ * A .pal file: 64 RGB triples. The 512 color kind with every emphasis
+ * combination is accepted too, only the first 64 are used.
+ */
+pub fn parse_palette(data: &[u8]) -> Result<Palette, PaletteError> {
+    if data.len() != 64 * 3 && data.len() != 512 * 3 {
+        return Err(PaletteError::BadSize(data.len()));
+    }
+    let mut palette = [(0, 0, 0); 64];
+    for (color, rgb) in palette.iter_mut().zip(data.chunks_exact(3)) {
+        *color = (rgb[0], rgb[1], rgb[2])
+    }
+    Ok(palette)
+}
+
+#[cfg(feature = "std")]
+pub fn load_palette(path: &str) -> Result<Palette, PaletteError> {
+    parse_palette(&fs::read(path)?)
+}
+
+/**
+ * The default palette run through Rec. 601 luma, for a black and white TV.
+ */
+pub fn grayscale(palette: &Palette) -> Palette {
+    palette.map(|(r, g, b)| {
+        let luma = ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8;
+        (luma, luma, luma)
+    })
+}
+
+#[cfg(test)]
+mod palette_test {
+    use super::{grayscale, parse_palette, PaletteError, SYSTEM_PALLETE};
+
+    #[test]
+    fn test_parse_palette() {
+        let mut data: Vec<u8> = (0..192).map(|n| n as u8).collect();
+        let palette = parse_palette(&data).unwrap();
+        assert_eq!(palette[1], (3, 4, 5));
+        data.resize(1536, 0xff);
+        assert_eq!(parse_palette(&data).unwrap()[63], (189, 190, 191));
+        assert!(matches!(
+            parse_palette(&data[..100]),
+            Err(PaletteError::BadSize(100))
+        ));
+    }
+
+    #[test]
+    fn test_grayscale() {
+        let gray = grayscale(&SYSTEM_PALLETE);
+        assert_eq!(gray[0x30], (0xff, 0xff, 0xff));
+        assert!(gray.iter().all(|&(r, g, b)| r == g && g == b));
+    }
+}
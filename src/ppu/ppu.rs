@@ -1,26 +1,37 @@
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::mem;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cartridge::Region, debug::PpuRegisters, device::BusDevice, mapper::SharedMapper, savestate,
+};
+
 use super::{
     frame::Frame,
-    palette::SYSTEM_PALLETE,
-    ppubus::{PPUBus, BACKGROUND_COLOR},
+    palette::{Palette, SYSTEM_PALLETE},
+    ppubus::{PPUBus, PpuAccess, PpuMemory, BACKGROUND_COLOR},
     registers::{OAMADDR, OAMDATA, PPUADDR, PPUCTRL, PPUDATA, PPUMASK, PPUSCROLL, PPUSTATUS},
 };
 
-#[derive(Default)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 struct InternalRegisters {
-    // coarse coordinates track x and y coord (at the tile level, e.g. 8 X 8)
-    // for the current tile
-    coarse_col: u16,
-    coarse_row: u16,
-    // fine coordinates track x and y coordinate at the pixel level
-    fine_col: u8,
-    fine_row: u8,
     nt_select: u8,
     w: bool,
 }
 
+fn system_palette() -> Palette {
+    SYSTEM_PALLETE
+}
+
+// a save state leaves out the picture, the colors and the region, see
+// `load_state`
+#[derive(Clone, Serialize, Deserialize)]
 pub struct PPU {
     bus: PPUBus,
+    #[serde(skip, default = "Frame::new")]
     curr_frame: Frame,
+    #[serde(with = "savestate::array")]
     oam: [u8; 64 * 4],
     // IO mapped registers
     ppuctrl: PPUCTRL,
@@ -33,9 +44,22 @@ pub struct PPU {
     ppudata: PPUDATA,
     // ********
     nmi_pin: bool,
+    frame_complete: bool,
     cycles: usize,
     scanline: u16,
     internal_reg: InternalRegisters,
+    #[serde(skip, default = "system_palette")]
+    palette: Palette,
+    #[serde(skip)]
+    region: Region,
+    #[serde(skip)]
+    skip_rendering: bool,
+}
+
+impl Default for PPU {
+    fn default() -> Self {
+        PPU::new()
+    }
 }
 
 impl PPU {
@@ -53,9 +77,13 @@ impl PPU {
             ppuaddr: PPUADDR::new(),
             ppudata: PPUDATA(0),
             nmi_pin: false,
+            frame_complete: false,
             cycles: 0,
             scanline: 0,
             internal_reg: Default::default(),
+            palette: SYSTEM_PALLETE,
+            region: Region::Ntsc,
+            skip_rendering: false,
         }
     }
     pub fn poll_generate_nmi(&self) -> bool {
@@ -64,138 +92,468 @@ impl PPU {
     pub fn clear_generate_nmi(&mut self) {
         self.nmi_pin = false
     }
+    pub fn load_mapper(&mut self, mapper: SharedMapper) {
+        self.bus.load_mapper(mapper)
+    }
     fn fetch_chr_row(&self, addr: u16) -> (u8, u8) {
         (
-            self.bus.read_memory(addr as u16),
-            self.bus.read_memory((addr as u16) + 8),
+            self.bus.fetch_pattern(addr as u16),
+            self.bus.fetch_pattern((addr as u16) + 8),
         )
     }
     /**
-     * Increments coarse coordinates
-     * Stores fetched data
+     * Index into the palette for `color` (0..=3) of `palette` (0..=3
+     * background, 4..=7 sprites). Color 0 is always the shared backdrop.
      */
-    fn fetch_bg_tile_row(&mut self) -> usize {
-        let nt_addr = self.internal_reg.coarse_row * 32 + self.internal_reg.coarse_col;
-        let base_nt: u16 = match self.ppuctrl.get_base_nt() {
-            0 => 0x2000,
-            1 => 0x2400,
-            2 => 0x2800,
-            3 => 0x2c00,
-            _ => panic!(),
+    fn palette_color(&self, palette: u16, color: u8) -> usize {
+        let addr = if color == 0 {
+            BACKGROUND_COLOR as u16
+        } else {
+            BACKGROUND_COLOR as u16 + palette * 4 + color as u16
         };
-        let chr_idx = self.bus.read_memory(base_nt.wrapping_add(nt_addr));
-        let base_chr = if self.ppuctrl.contains(PPUCTRL::BACKGROUND_PATTERN_TABLE) {
+        (self.bus.read_memory(addr) & 0x3f) as usize
+    }
+    /**
+     * Background for the current scanline, returns which pixels are opaque so
+     * sprites know what they're behind.
+     *
+     * TODO this is a scanline renderer, scroll and ctrl are sampled once per
+     * line rather than tracking the real v/t registers.
+     */
+    fn render_background_line(&mut self, draw: bool) -> [bool; Frame::WIDTH] {
+        let y = self.scanline as usize;
+        let mut opaque = [false; Frame::WIDTH];
+        let show = self.ppumask.contains(PPUMASK::SHOW_BACKGROUND);
+        let show_left = self.ppumask.contains(PPUMASK::SHOW_BACKGROUND_LEFTMOST);
+        let (scroll_x, scroll_y) = self.ppuscroll.get();
+        let base_nt = self.ppuctrl.get_base_nt() as usize;
+        let base_chr: u16 = if self.ppuctrl.contains(PPUCTRL::BACKGROUND_PATTERN_TABLE) {
             0x1000
         } else {
             0
         };
+        // (pattern address, palette, lo plane, hi plane) of the last tile fetched
+        let mut tile_cache: Option<(u16, u16, u8, u8)> = None;
 
-        let attr_idx =
-            ((self.internal_reg.coarse_row / 4) * 8) + (self.internal_reg.coarse_col / 4);
-        let attr = self.bus.read_memory(base_nt + 0x3c0 + attr_idx);
-
-        let palette_choice = {
-            let palette_idx = match (
-                self.internal_reg.coarse_col % 4 / 2,
-                self.internal_reg.coarse_row % 4 / 2,
-            ) {
-                (0, 0) => attr & 0b11,
-                (1, 0) => (attr >> 2) & 0b11,
-                (0, 1) => (attr >> 4) & 0b11,
-                (1, 1) => (attr >> 6) & 0b11,
-                _ => panic!(),
-            } as u16;
-            match palette_idx {
-                0 => 0x3f01,
-                1 => 0x3f05,
-                2 => 0x3f09,
-                3 => 0x3f0d,
-                _ => panic!(),
+        for (x, pixel_opaque) in opaque.iter_mut().enumerate() {
+            if !show || (x < 8 && !show_left) {
+                if !draw {
+                    continue;
+                }
+                let rgb = self.palette[self.palette_color(0, 0)];
+                self.curr_frame.set_pixel(x as u8, y as u8, rgb);
+                continue;
             }
-        };
+            // position in the 512x480 world made of the four nametables
+            let world_x = (x + scroll_x as usize + (base_nt & 1) * 256) % 512;
+            let world_y = (y + scroll_y as usize + (base_nt >> 1) * 240) % 480;
+            let nt = 0x2000 + (world_x / 256) * 0x400 + (world_y / 240) * 0x800;
+            let (tile_x, tile_y) = ((world_x % 256) / 8, (world_y % 240) / 8);
 
-        let palette: (u8, u8, u8, u8) = (
-            self.bus.read_memory(BACKGROUND_COLOR as u16),
-            self.bus.read_memory(palette_choice),
-            self.bus.read_memory(palette_choice + 1),
-            self.bus.read_memory(palette_choice + 2),
-        );
+            let tile = self.bus.read_memory((nt + tile_y * 32 + tile_x) as u16);
+            let pattern_addr = base_chr + tile as u16 * 16 + (world_y % 8) as u16;
+            let (palette, lo_plane, hi_plane) = match tile_cache {
+                Some((addr, palette, lo, hi)) if addr == pattern_addr && x % 8 != 0 => {
+                    (palette, lo, hi)
+                }
+                _ => {
+                    let attr = self
+                        .bus
+                        .read_memory((nt + 0x3c0 + (tile_y / 4) * 8 + tile_x / 4) as u16);
+                    let shift = ((tile_y % 4) / 2) * 4 + ((tile_x % 4) / 2) * 2;
+                    let palette = ((attr >> shift) & 0b11) as u16;
+                    let (lo, hi) = self.fetch_chr_row(pattern_addr);
+                    tile_cache = Some((pattern_addr, palette, lo, hi));
+                    (palette, lo, hi)
+                }
+            };
 
-        // lo_plane controls bit 0 and hi_plane bit 1
-        let (lo_plane, hi_plane) = self.fetch_chr_row(base_chr + chr_idx as u16);
-
-        (0..=7).rev().for_each(|n| {
-            let palette_idx = ((hi_plane >> n) & 1) << 1 | ((lo_plane >> n) & 1);
-            let rgb = SYSTEM_PALLETE[match palette_idx {
-                0 => palette.0,
-                1 => palette.1,
-                2 => palette.2,
-                3 => palette.3,
-                _ => panic!(),
-            } as usize];
-            self.curr_frame.set_pixel(
-                self.internal_reg.fine_col as u8,
-                self.internal_reg.fine_row as u8,
-                rgb,
-            );
-            self.internal_reg.fine_col += 1
-        });
+            // lo_plane controls bit 0 and hi_plane bit 1
+            let bit = 7 - (world_x % 8);
+            let color = ((hi_plane >> bit) & 1) << 1 | ((lo_plane >> bit) & 1);
+            *pixel_opaque = color != 0;
+            if !draw {
+                continue;
+            }
+            let rgb = self.palette[self.palette_color(palette, color)];
+            self.curr_frame.set_pixel(x as u8, y as u8, rgb);
+        }
+        opaque
+    }
+    /**
+     * Up to 8 sprites on the current scanline, lower OAM index wins.
+     */
+    fn render_sprite_line(&mut self, bg_opaque: &[bool; Frame::WIDTH], draw: bool) {
+        if !self.ppumask.contains(PPUMASK::SHOW_SPRITE) {
+            return;
+        }
+        let y = self.scanline as usize;
+        let show_left = self.ppumask.contains(PPUMASK::SHOW_SPRITES_LEFTMOST);
+        let tall = self.ppuctrl.contains(PPUCTRL::SPRITE_SIZE);
+        let height = self.sprite_height();
+        let sprite_table: u16 = if self.ppuctrl.contains(PPUCTRL::SPRITE_TABLE_ADDR) {
+            0x1000
+        } else {
+            0
+        };
 
-        if self.internal_reg.coarse_col == 31 {
-            self.internal_reg.fine_col = 0;
-            self.internal_reg.coarse_col = 0;
+        let oam = self.oam;
+        let on_line: Vec<(usize, &[u8])> = oam
+            .chunks(4)
+            .enumerate()
+            .filter(|(_, s)| self.sprite_on_line(s[0]))
+            .collect();
+        if on_line.len() > 8 {
+            self.ppustatus.set(PPUSTATUS::SPRITE_OVERFLOW, true)
+        }
 
-            if self.internal_reg.fine_row % 8 == 0 {
-                self.internal_reg.coarse_row += 1;
+        let mut drawn = [false; Frame::WIDTH];
+        for (idx, sprite) in on_line.into_iter().take(8) {
+            let (tile, attr, sprite_x) = (sprite[1] as u16, sprite[2], sprite[3] as usize);
+            let palette = 4 + (attr & 0b11) as u16;
+            let behind_bg = attr & 0x20 != 0;
+            let flip_x = attr & 0x40 != 0;
+            let flip_y = attr & 0x80 != 0;
+
+            let row = y - (sprite[0] as usize + 1);
+            let row = (if flip_y { height - 1 - row } else { row }) as u16;
+            let pattern_addr = if tall {
+                // 8x16 sprites pick the pattern table with bit 0 of the tile
+                (tile & 1) * 0x1000 + ((tile & 0xfe) + row / 8) * 16 + row % 8
+            } else {
+                sprite_table + tile * 16 + row
+            };
+            let (lo_plane, hi_plane) = self.fetch_chr_row(pattern_addr);
+            // only sprite 0 can be noticed without its pixels
+            if !draw && idx != 0 {
+                continue;
             }
 
-            self.internal_reg.fine_row += 1;
+            for col in 0..8 {
+                let x = sprite_x + col;
+                if x >= Frame::WIDTH || drawn[x] || (x < 8 && !show_left) {
+                    continue;
+                }
+                let bit = if flip_x { col } else { 7 - col };
+                let color = ((hi_plane >> bit) & 1) << 1 | ((lo_plane >> bit) & 1);
+                if color == 0 {
+                    continue;
+                }
+                drawn[x] = true;
+                if idx == 0 && bg_opaque[x] && x != 255 {
+                    self.ppustatus.set(PPUSTATUS::SPRITE_0_HIT, true)
+                }
+                // a sprite behind the background still hides the sprites after it
+                if draw && !(behind_bg && bg_opaque[x]) {
+                    let rgb = self.palette[self.palette_color(palette, color)];
+                    self.curr_frame.set_pixel(x as u8, y as u8, rgb);
+                }
+            }
+        }
+    }
+    fn sprite_height(&self) -> usize {
+        if self.ppuctrl.contains(PPUCTRL::SPRITE_SIZE) {
+            16
         } else {
-            self.internal_reg.coarse_col += 1
+            8
         }
-
-        // TODO going to leave this coarse grained for now
-        // and just treat this function as an atomic operation
-        8
+    }
+    // sprites are drawn one line below their OAM y
+    fn sprite_on_line(&self, sprite_y: u8) -> bool {
+        let y = self.scanline as usize;
+        y > sprite_y as usize && y <= sprite_y as usize + self.sprite_height()
+    }
+    fn render_scanline(&mut self) {
+        if !self.skip_rendering {
+            let bg_opaque = self.render_background_line(true);
+            return self.render_sprite_line(&bg_opaque, true);
+        }
+        // nothing's drawn, the background's only needed where sprite 0 could
+        // hit it or when the mapper follows the pattern fetches
+        let bg_opaque = if self.sprite_on_line(self.oam[0]) || self.bus.watches_fetches() {
+            self.render_background_line(false)
+        } else {
+            [false; Frame::WIDTH]
+        };
+        self.render_sprite_line(&bg_opaque, false)
     }
 
     // TODO not yet considering odd/even cycle skips
     pub fn tick(&mut self, cycles: usize) {
-        let mut remaining = cycles;
-        while remaining > 0 {
-            if self.cycles >= 340 {
-                // if we are at the end of scanline 261
-                // set scanline back to 0 to loop again
-                if self.scanline == 261 {
-                    self.scanline = 0
-                } else {
-                    self.scanline += 1;
-
-                    // if we are entering scanline 241 and ppuctrl
-                    // has the GENERATE_NMI flag set, it's nmi time baby
-                    if self.scanline == 241 && self.ppuctrl.contains(PPUCTRL::GENERATE_NMI) {
-                        self.ppustatus.set(PPUSTATUS::VBLANK_START, true);
+        let mut left = cycles;
+        while left > 0 {
+            let visible = self.scanline < Frame::HEIGHT as u16;
+            // straight to the next dot where anything happens
+            let next = if visible && self.cycles < 257 {
+                257
+            } else {
+                341
+            };
+            let dots = (next - self.cycles).min(left);
+            self.cycles += dots;
+            left -= dots;
+            // the whole visible line is drawn once its dots are done
+            if self.cycles == 257 && visible {
+                self.render_scanline()
+            }
+            if self.cycles <= 340 {
+                continue;
+            }
+            self.cycles = 0;
+            self.scanline += 1;
+            let scanlines = self.region.scanlines();
+            match self.scanline {
+                // entering vblank, if ppuctrl has the GENERATE_NMI flag set
+                // it's nmi time baby
+                line if line == self.region.vblank_line() => {
+                    self.ppustatus.set(PPUSTATUS::VBLANK_START, true);
+                    if self.ppuctrl.contains(PPUCTRL::GENERATE_NMI) {
                         self.nmi_pin = true
                     }
-
-                    // if we are enterining scanline 261, toggle nmi_pin
-                    // and we are no longer in vblank
-                    if self.scanline == 261 {
-                        self.ppustatus.set(PPUSTATUS::VBLANK_START, false);
-                        self.nmi_pin = false
-                    }
+                    self.frame_complete = true
                 }
-
-                match self.scanline {
-                    0 => self.cycles += 1,
-                    1..=256 => {
-                        let cycles_run = self.fetch_bg_tile_row();
-                        self.cycles += cycles_run
-                    }
-                    257..=320 => todo!(),
+                // pre-render line, we are no longer in vblank
+                line if line == scanlines - 1 => {
+                    self.ppustatus.set(PPUSTATUS::VBLANK_START, false);
+                    self.ppustatus.set(PPUSTATUS::SPRITE_0_HIT, false);
+                    self.ppustatus.set(PPUSTATUS::SPRITE_OVERFLOW, false);
+                    self.nmi_pin = false
+                }
+                // end of the pre-render line (or past it, when the region
+                // changed mid frame), loop back to 0
+                line if line >= scanlines => self.scanline = 0,
+                _ => (),
+            }
+        }
+    }
+    /**
+     * Dots from here until vblank starts, which is when the NMI comes and
+     * the frame's done.
+     */
+    pub fn dots_to_vblank(&self) -> u64 {
+        let scanlines = self.region.scanlines();
+        let next = if self.scanline + 1 >= scanlines {
+            0
+        } else {
+            self.scanline + 1
+        };
+        let lines = (self.region.vblank_line() + scanlines - next) % scanlines;
+        (341 - self.cycles + lines as usize * 341) as u64
+    }
+    /**
+     * Set once the last visible scanline is done and the frame can be shown.
+     */
+    pub fn poll_frame_complete(&self) -> bool {
+        self.frame_complete
+    }
+    pub fn clear_frame_complete(&mut self) {
+        self.frame_complete = false
+    }
+    pub fn frame(&self) -> &Frame {
+        &self.curr_frame
+    }
+    /**
+     * Colors to render with from here on.
+     */
+    pub fn set_palette(&mut self, palette: &Palette) {
+        self.palette = *palette
+    }
+    /**
+     * Frame timing from here on, see `Region`.
+     */
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region
+    }
+    pub fn region(&self) -> Region {
+        self.region
+    }
+    /**
+     * Stop drawing, for runs nobody's watching. Timing, NMIs, sprite 0 hits,
+     * sprite overflow and the mapper's view of the pattern fetches stay as
+     * they were, `frame` just keeps the last picture drawn.
+     */
+    pub fn set_skip_rendering(&mut self, skip: bool) {
+        self.skip_rendering = skip
+    }
+    pub fn skipping_rendering(&self) -> bool {
+        self.skip_rendering
+    }
+    /**
+     * Take on `state`'s memory, registers and position in the frame, keeping
+     * the mapper, the picture so far, the colors, the region and whether it's
+     * drawing.
+     */
+    pub fn load_state(&mut self, state: &PPU) {
+        let mut bus = mem::replace(&mut self.bus, PPUBus::new());
+        bus.load_state(&state.bus);
+        *self = PPU {
+            bus,
+            curr_frame: mem::take(&mut self.curr_frame),
+            palette: self.palette,
+            region: self.region,
+            skip_rendering: self.skip_rendering,
+            ..state.clone()
+        }
+    }
+    /**
+     * Memory, registers and position in the frame as they come up at power
+     * on, keeping what `load_state` keeps.
+     */
+    pub fn power_cycle(&mut self) {
+        self.load_state(&PPU::new())
+    }
+    /**
+     * The reset line: control, mask, scroll and the read buffer are cleared
+     * along with the write toggle. Status, the address, OAM and memory carry
+     * on as they were.
+     */
+    pub fn reset(&mut self) {
+        self.ppuctrl = PPUCTRL::new();
+        self.ppumask = PPUMASK::new();
+        self.ppuscroll = PPUSCROLL::new();
+        self.ppudata = PPUDATA(0);
+        self.internal_reg = Default::default()
+    }
+    pub fn scanline(&self) -> usize {
+        self.scanline as usize
+    }
+    pub fn registers(&self) -> PpuRegisters {
+        PpuRegisters {
+            ctrl: self.ppuctrl.bits(),
+            mask: self.ppumask.bits(),
+            status: self.ppustatus.bits(),
+            scanline: self.scanline(),
+            dot: self.dot(),
+        }
+    }
+    /**
+     * Draw the 8x8 tile whose pattern starts at `pattern_addr` into an RGB24
+     * `image` that's `width` pixels wide, top left corner at `x`, `y`.
+     */
+    fn draw_tile(
+        &self,
+        image: &mut [u8],
+        width: usize,
+        (x, y): (usize, usize),
+        pattern_addr: u16,
+        palette: u16,
+        (flip_x, flip_y): (bool, bool),
+    ) {
+        for row in 0..8 {
+            let src_row = if flip_y { 7 - row } else { row };
+            let (lo, hi) = self.fetch_chr_row(pattern_addr + src_row as u16);
+            for col in 0..8 {
+                let bit = if flip_x { col } else { 7 - col };
+                let color = ((hi >> bit) & 1) << 1 | (lo >> bit) & 1;
+                let rgb = self.palette[self.palette_color(palette, color)];
+                let addr = ((y + row) * width + x + col) * 3;
+                image[addr..addr + 3].copy_from_slice(&[rgb.0, rgb.1, rgb.2]);
+            }
+        }
+    }
+    /**
+     * Pattern table 0 or 1 as a 128x128 RGB24 image, 16x16 tiles drawn with
+     * `palette` (0..=7), for debuggers.
+     */
+    pub fn pattern_table(&self, table: u16, palette: u16) -> Vec<u8> {
+        let mut pixels = vec![0; 128 * 128 * 3];
+        for tile in 0..256u16 {
+            let at = ((tile % 16) as usize * 8, (tile / 16) as usize * 8);
+            let addr = table * 0x1000 + tile * 16;
+            self.draw_tile(&mut pixels, 128, at, addr, palette, (false, false));
+        }
+        pixels
+    }
+    /**
+     * All four nametables as a 512x480 RGB24 image laid out like the PPU
+     * addresses them, mirrors included, with the current background pattern
+     * table and attributes. No scrolling, that's where the screen sits in it.
+     */
+    pub fn nametables(&self) -> Vec<u8> {
+        const WIDTH: usize = Frame::WIDTH * 2;
+        let mut pixels = vec![0; WIDTH * Frame::HEIGHT * 2 * 3];
+        let base_chr: u16 = if self.ppuctrl.contains(PPUCTRL::BACKGROUND_PATTERN_TABLE) {
+            0x1000
+        } else {
+            0
+        };
+        for nt in 0..4 {
+            let base = 0x2000 + nt as u16 * 0x400;
+            let (nt_x, nt_y) = ((nt % 2) * Frame::WIDTH, (nt / 2) * Frame::HEIGHT);
+            for tile_y in 0..30u16 {
+                for tile_x in 0..32u16 {
+                    let tile = self.bus.read_memory(base + tile_y * 32 + tile_x) as u16;
+                    let attr = self
+                        .bus
+                        .read_memory(base + 0x3c0 + (tile_y / 4) * 8 + tile_x / 4);
+                    let shift = ((tile_y % 4) / 2) * 4 + ((tile_x % 4) / 2) * 2;
+                    let palette = ((attr >> shift) & 0b11) as u16;
+                    let at = (nt_x + tile_x as usize * 8, nt_y + tile_y as usize * 8);
+                    let addr = base_chr + tile * 16;
+                    self.draw_tile(&mut pixels, WIDTH, at, addr, palette, (false, false));
                 }
             }
         }
+        pixels
+    }
+    /**
+     * The 64 sprites in OAM order as an 8x8 grid of 8x16 cells, 64x128 RGB24.
+     * 8x8 sprites leave the bottom half of their cell to the backdrop.
+     */
+    pub fn sprites(&self) -> Vec<u8> {
+        const WIDTH: usize = 64;
+        let backdrop = self.palette[self.palette_color(0, 0)];
+        let mut pixels: Vec<u8> = [backdrop.0, backdrop.1, backdrop.2].repeat(WIDTH * 128);
+        let tall = self.ppuctrl.contains(PPUCTRL::SPRITE_SIZE);
+        let sprite_table: u16 = if self.ppuctrl.contains(PPUCTRL::SPRITE_TABLE_ADDR) {
+            0x1000
+        } else {
+            0
+        };
+        for (idx, sprite) in self.oam.chunks(4).enumerate() {
+            let (tile, attr) = (sprite[1] as u16, sprite[2]);
+            let palette = 4 + (attr & 0b11) as u16;
+            let flip = (attr & 0x40 != 0, attr & 0x80 != 0);
+            let (x, y) = ((idx % 8) * 8, (idx / 8) * 16);
+            if tall {
+                let top = (tile & 1) * 0x1000 + (tile & 0xfe) * 16;
+                // flipped vertically the halves swap too
+                let (first, second) = if flip.1 {
+                    (top + 16, top)
+                } else {
+                    (top, top + 16)
+                };
+                self.draw_tile(&mut pixels, WIDTH, (x, y), first, palette, flip);
+                self.draw_tile(&mut pixels, WIDTH, (x, y + 8), second, palette, flip);
+            } else {
+                let addr = sprite_table + tile * 16;
+                self.draw_tile(&mut pixels, WIDTH, (x, y), addr, palette, flip);
+            }
+        }
+        pixels
+    }
+    /**
+     * The 32 entries of palette RAM as colors, backgrounds then sprites.
+     */
+    pub fn palette_ram(&self) -> Vec<(u8, u8, u8)> {
+        (0..32)
+            .map(|idx| {
+                self.palette[(self.bus.read_memory(BACKGROUND_COLOR as u16 + idx) & 0x3f) as usize]
+            })
+            .collect()
+    }
+    /**
+     * PPU address space without touching PPUADDR or the read buffer, for
+     * debuggers. Mirrored down to 14 bits like the real bus.
+     */
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.bus.read_memory(addr & 0x3fff)
+    }
+    pub fn oam(&self) -> &[u8; 64 * 4] {
+        &self.oam
+    }
+    pub fn dot(&self) -> usize {
+        self.cycles
     }
     // TODO In general, we aren't handling any of the tricky
     // edge cases mentioned on the Registers NESDev page
@@ -220,12 +578,36 @@ impl PPU {
     pub fn write_oamaddr(&mut self, data: u8) {
         self.oamaddr.0 = data
     }
-    pub fn read_oamdata(&self) -> u8 {
+    pub fn read_oamdata(&mut self) -> u8 {
+        let addr = self.oamaddr.0;
+        self.oamdata.0 = self.oam[addr as usize];
+        self.record_oam(addr, self.oamdata.0, false);
         self.oamdata.0
     }
     pub fn write_oamdata(&mut self, data: u8) {
+        let addr = self.oamaddr.0;
         self.oamdata.0 = data;
-        self.oamaddr.0 += 1
+        self.oam[addr as usize] = data;
+        self.record_oam(addr, data, true);
+        self.oamaddr.0 = addr.wrapping_add(1)
+    }
+    fn record_oam(&mut self, addr: u8, value: u8, write: bool) {
+        self.bus.record(PpuAccess {
+            memory: PpuMemory::Oam,
+            addr: addr as u16,
+            value,
+            write,
+        })
+    }
+    /**
+     * Keep track of the CPU's reads and writes of PPU memory, for
+     * watchpoints. See `take_accesses`.
+     */
+    pub fn set_watching(&mut self, watching: bool) {
+        self.bus.set_watching(watching)
+    }
+    pub fn take_accesses(&mut self) -> Vec<PpuAccess> {
+        self.bus.take_accesses()
     }
     pub fn write_ppuscroll(&mut self, data: u8) {
         self.ppuscroll.update(data, self.internal_reg.w);
@@ -243,7 +625,15 @@ impl PPU {
     // is issued against an address between 0x3f00..0x3fff
     pub fn read_ppudata(&mut self) -> u8 {
         let read = self.ppudata.0;
-        self.ppudata.0 = self.bus.read_memory(self.ppuaddr.get());
+        let addr = self.ppuaddr.get();
+        self.ppudata.0 = self.bus.read_memory(addr);
+        // the access is to the address, though the value comes a read later
+        self.bus.record(PpuAccess {
+            memory: PpuMemory::Vram,
+            addr,
+            value: self.ppudata.0,
+            write: false,
+        });
         self.increment_ppu_addr();
         read
     }
@@ -252,8 +642,212 @@ impl PPU {
         self.increment_ppu_addr()
     }
     pub fn write_dma(&mut self, bytes: &[u8]) {
-        (self.oamaddr.0..=255)
-            .zip(bytes)
-            .for_each(|(idx, byte)| self.oam[idx as usize] = *byte)
+        (self.oamaddr.0..=255).zip(bytes).for_each(|(idx, byte)| {
+            self.oam[idx as usize] = *byte;
+            self.record_oam(idx, *byte, true)
+        })
+    }
+}
+
+// the registers at $2000-$2007, mirrored every 8 bytes up to $3FFF
+impl BusDevice for PPU {
+    fn box_clone(&self) -> Box<dyn BusDevice> {
+        Box::new(self.clone())
+    }
+    fn read(&mut self, addr: u16) -> u8 {
+        match addr & 0x7 {
+            0x2 => self.read_ppustatus(),
+            0x4 => self.read_oamdata(),
+            0x7 => self.read_ppudata(),
+            // write only, on hardware it's whatever was last on the PPU's data
+            // bus. Nothing here keeps that, so it reads as nothing
+            _ => 0,
+        }
+    }
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr & 0x7 {
+            0x0 => self.write_ppu_ctrl(data),
+            0x1 => self.write_ppumask(data),
+            0x3 => self.write_oamaddr(data),
+            0x4 => self.write_oamdata(data),
+            0x5 => self.write_ppuscroll(data),
+            0x6 => self.write_ppuaddr(data),
+            0x7 => self.write_ppudata(data),
+            // PPUSTATUS is read only, writing it does nothing
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod ppu_test {
+    use alloc::boxed::Box;
+
+    use crate::{
+        cartridge::{Cartridge, Mirroring},
+        mapper::{self, Nrom},
+    };
+
+    use super::{PPU, SYSTEM_PALLETE};
+
+    const BACKDROP: u8 = 0x0f;
+    const BG_COLOR: u8 = 0x30;
+    const SPRITE_COLOR: u8 = 0x16;
+
+    // NROM with CHR RAM, tile 1 is solid color 1 and tile 2 only has its top
+    // left pixel set, so flips show up
+    fn make_ppu() -> PPU {
+        let mut ppu = PPU::new();
+        ppu.load_mapper(mapper::share(Box::new(Nrom::new(Cartridge {
+            prgrom: vec![0; 0x4000],
+            mirroring: Mirroring::Vertical,
+            ..Default::default()
+        }))));
+        write(&mut ppu, 0x0010, &[0xff; 8]);
+        write(&mut ppu, 0x0020, &[0x80]);
+        write(&mut ppu, 0x3f00, &[BACKDROP, BG_COLOR]);
+        write(&mut ppu, 0x3f11, &[SPRITE_COLOR]);
+        ppu
+    }
+
+    fn write(ppu: &mut PPU, addr: u16, bytes: &[u8]) {
+        ppu.write_ppuaddr((addr >> 8) as u8);
+        ppu.write_ppuaddr(addr as u8);
+        bytes.iter().for_each(|byte| ppu.write_ppudata(*byte))
+    }
+
+    // everything off screen but `sprites`, from OAM index 0 up
+    fn set_sprites(ppu: &mut PPU, sprites: &[[u8; 4]]) {
+        let mut oam = [0xff; 64 * 4];
+        for (idx, sprite) in sprites.iter().enumerate() {
+            oam[idx * 4..idx * 4 + 4].copy_from_slice(sprite);
+        }
+        ppu.write_oamaddr(0);
+        ppu.write_dma(&oam)
+    }
+
+    fn run_frame(ppu: &mut PPU) {
+        ppu.clear_frame_complete();
+        while !ppu.poll_frame_complete() {
+            ppu.tick(341)
+        }
+    }
+
+    fn color(ppu: &PPU, x: usize, y: usize) -> (u8, u8, u8) {
+        ppu.frame().pixel(x, y)
+    }
+
+    #[test]
+    fn test_sprite_0_hit() {
+        let mut ppu = make_ppu();
+        ppu.write_ppumask(0x1e);
+        set_sprites(&mut ppu, &[[9, 1, 0, 16]]);
+        run_frame(&mut ppu);
+        // nothing opaque behind it yet
+        assert_eq!(ppu.read_ppustatus() & 0x40, 0);
+
+        // background tile at (2, 1) is under the sprite
+        write(&mut ppu, 0x2000 + 32 + 2, &[1]);
+        run_frame(&mut ppu);
+        assert_eq!(ppu.read_ppustatus() & 0x40, 0x40);
+
+        // only sprite 0 counts
+        set_sprites(&mut ppu, &[[0xff, 1, 0, 0], [9, 1, 0, 16]]);
+        run_frame(&mut ppu);
+        assert_eq!(ppu.read_ppustatus() & 0x40, 0);
+
+        // still noticed when nothing is being drawn
+        set_sprites(&mut ppu, &[[9, 1, 0, 16]]);
+        ppu.set_skip_rendering(true);
+        run_frame(&mut ppu);
+        assert_eq!(ppu.read_ppustatus() & 0x40, 0x40);
+    }
+
+    #[test]
+    fn test_sprite_overflow() {
+        let mut ppu = make_ppu();
+        ppu.write_ppumask(0x1e);
+        let sprites: Vec<[u8; 4]> = (0..9).map(|idx| [49, 1, 0, idx * 8]).collect();
+        set_sprites(&mut ppu, &sprites[..8]);
+        run_frame(&mut ppu);
+        assert_eq!(ppu.read_ppustatus() & 0x20, 0);
+
+        set_sprites(&mut ppu, &sprites);
+        run_frame(&mut ppu);
+        assert_eq!(ppu.read_ppustatus() & 0x20, 0x20);
+        // the ninth isn't drawn
+        assert_eq!(color(&ppu, 60, 50), SYSTEM_PALLETE[SPRITE_COLOR as usize]);
+        assert_eq!(color(&ppu, 68, 50), SYSTEM_PALLETE[BACKDROP as usize]);
+
+        // cleared again on the pre-render line
+        set_sprites(&mut ppu, &sprites[..8]);
+        run_frame(&mut ppu);
+        assert_eq!(ppu.read_ppustatus() & 0x20, 0);
+    }
+
+    #[test]
+    fn test_scroll_wrap() {
+        let (bg, backdrop) = (
+            SYSTEM_PALLETE[BG_COLOR as usize],
+            SYSTEM_PALLETE[BACKDROP as usize],
+        );
+        let mut ppu = make_ppu();
+        ppu.write_ppumask(0x0a);
+        // a single tile in the top left of the first nametable
+        write(&mut ppu, 0x2000, &[1]);
+        run_frame(&mut ppu);
+        assert_eq!(color(&ppu, 0, 0), bg);
+        assert_eq!(color(&ppu, 8, 0), backdrop);
+
+        // 240 lines down wraps to the nametable below, which mirrors the first
+        ppu.write_ppuscroll(0);
+        ppu.write_ppuscroll(232);
+        run_frame(&mut ppu);
+        assert_eq!(color(&ppu, 0, 0), backdrop);
+        assert_eq!(color(&ppu, 0, 8), bg);
+
+        // starting from the second nametable, 512 pixels across wraps back to
+        // the first
+        ppu.write_ppu_ctrl(0x01);
+        ppu.write_ppuscroll(248);
+        ppu.write_ppuscroll(0);
+        run_frame(&mut ppu);
+        assert_eq!(color(&ppu, 0, 0), backdrop);
+        assert_eq!(color(&ppu, 8, 0), bg);
+    }
+
+    #[test]
+    fn test_sprite_flips() {
+        let sprite = SYSTEM_PALLETE[SPRITE_COLOR as usize];
+        let mut ppu = make_ppu();
+        ppu.write_ppumask(0x1e);
+        // (attributes, where the one pixel lands)
+        let cases = [
+            (0x00, (40, 20)),
+            (0x40, (47, 20)),
+            (0x80, (40, 27)),
+            (0xc0, (47, 27)),
+        ];
+        for (attr, (x, y)) in cases {
+            set_sprites(&mut ppu, &[[19, 2, attr, 40]]);
+            run_frame(&mut ppu);
+            for (px, py) in [(40, 20), (47, 20), (40, 27), (47, 27)] {
+                assert_eq!(
+                    color(&ppu, px, py) == sprite,
+                    (px, py) == (x, y),
+                    "attr {:#x} at ({}, {})",
+                    attr,
+                    px,
+                    py
+                );
+            }
+        }
+
+        // 8x16 flipped vertically puts the top tile's pixel at the bottom
+        ppu.write_ppu_ctrl(0x20);
+        set_sprites(&mut ppu, &[[19, 2, 0x80, 40]]);
+        run_frame(&mut ppu);
+        assert_eq!(color(&ppu, 40, 35), sprite);
+        assert_ne!(color(&ppu, 40, 20), sprite);
     }
 }
@@ -1,28 +1,108 @@
-use crate::cartridge::Mirroring;
+use alloc::vec::Vec;
 
-use super::PPU;
+use serde::{Deserialize, Serialize};
+
+use crate::{cartridge::Mirroring, mapper::SharedMapper, savestate};
 
 pub const BACKGROUND_COLOR: usize = 0x3f00;
 
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PpuMemory {
+    // pattern tables through palettes, $0000-$3FFF
+    Vram,
+    Oam,
+}
+
+/**
+ * A read or write the CPU made to the PPU's memory, through PPUDATA, OAMDATA
+ * or OAM DMA. Rendering's own reads aren't counted.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PpuAccess {
+    pub memory: PpuMemory,
+    pub addr: u16,
+    pub value: u8,
+    pub write: bool,
+}
+
+// the mapper is still shared, see `Bus::deep_clone`
+#[derive(Clone, Serialize, Deserialize)]
 pub struct PPUBus {
-    chr_rom: [u8; 0x1fff],
+    // pattern tables and mirroring are owned by the cartridge mapper
+    #[serde(skip)]
+    mapper: Option<SharedMapper>,
+    #[serde(with = "savestate::array")]
     name_tables: [u8; 0x800],
     palette_table: [u8; 32], /* stores an index into SYSTEM_PALETTE */
-    mirroring: Mirroring,
+    // for watchpoints, None unless a debugger's asked
+    #[serde(skip)]
+    accesses: Option<Vec<PpuAccess>>,
 }
 
 impl PPUBus {
     pub fn new() -> PPUBus {
         PPUBus {
-            chr_rom: [0; 0x1fff],
+            mapper: None,
             name_tables: [0; 2048],
             palette_table: [0; 32],
-            mirroring: Mirroring::Horizontal,
+            accesses: None,
+        }
+    }
+    pub fn set_watching(&mut self, watching: bool) {
+        if watching != self.accesses.is_some() {
+            self.accesses = watching.then(Vec::new)
+        }
+    }
+    pub fn record(&mut self, access: PpuAccess) {
+        if let Some(accesses) = &mut self.accesses {
+            accesses.push(access)
+        }
+    }
+    /**
+     * What's been recorded since the last time, oldest first.
+     */
+    pub fn take_accesses(&mut self) -> Vec<PpuAccess> {
+        self.accesses
+            .as_mut()
+            .map(core::mem::take)
+            .unwrap_or_default()
+    }
+    pub fn load_mapper(&mut self, mapper: SharedMapper) {
+        self.mapper = Some(mapper)
+    }
+    /**
+     * Whether the mapper has to see every pattern fetch, see
+     * `Mapper::watches_ppu_fetches`.
+     */
+    pub fn watches_fetches(&self) -> bool {
+        self.mapper
+            .as_ref()
+            .is_some_and(|mapper| mapper.borrow().watches_ppu_fetches())
+    }
+    pub fn load_state(&mut self, state: &PPUBus) {
+        self.name_tables = state.name_tables;
+        self.palette_table = state.palette_table;
+    }
+    fn mirroring(&self) -> Mirroring {
+        match &self.mapper {
+            Some(mapper) => mapper.borrow().mirroring(),
+            None => Mirroring::Horizontal,
         }
     }
-    pub fn load_chr_rom(&mut self, chr_rom: [u8; 0x1fff], mirroring: Mirroring) {
-        self.chr_rom = chr_rom;
-        self.mirroring = mirroring
+    /**
+     * Pattern table read made while rendering. Unlike `read_memory` this lets
+     * the mapper observe the fetch (see `Mapper::notify_ppu_fetch`).
+     */
+    pub fn fetch_pattern(&self, addr: u16) -> u8 {
+        match &self.mapper {
+            Some(mapper) => {
+                let mut mapper = mapper.borrow_mut();
+                let value = mapper.read_chr(addr);
+                mapper.notify_ppu_fetch(addr);
+                value
+            }
+            None => 0,
+        }
     }
 
     fn mirror_palette_addr(addr: u16) -> u16 {
@@ -50,49 +130,74 @@ impl PPUBus {
      [a] [b]
     */
     fn mirror_nametable_addr(addr: u16, mirroring: &Mirroring) -> u16 {
-        let base = match addr {
-            0x2000..=0x2fff => addr - 0x2000,
-            0x3000..=0x3eff => addr - 0x3000,
-            _ => panic!("Attemping to mirror down address outside of addressable nametable range."),
-        };
+        // $3000-$3EFF mirrors $2000-$2EFF
+        let base = (addr - 0x2000) % 0x1000;
         let (table, idx) = (base / 0x400, base % 0x400);
         match (table, mirroring) {
+            (_, Mirroring::SingleScreenLower) => idx,
+            (_, Mirroring::SingleScreenUpper) => 0x400 + idx,
             (0, _) => base,
-            (1 | 3, Mirroring::Vertical) => 0x400 + idx,
-            (2, Mirroring::Vertical) => idx,
+            // the other two tables of four screen are RAM on the cartridge,
+            // which isn't there yet. Vertical at least gets the top half right
+            (1 | 3, Mirroring::Vertical | Mirroring::FourScreen) => 0x400 + idx,
+            (_, Mirroring::Vertical | Mirroring::FourScreen) => idx,
             (1, Mirroring::Horizontal) => idx,
-            (2 | 3, Mirroring::Horizontal) => 0x400 + idx,
-            (_, Mirroring::FourScreen) => todo!(),
-            _ => panic!("Inconceivable!"),
+            (_, Mirroring::Horizontal) => 0x400 + idx,
         }
     }
     pub fn read_memory(&self, addr: u16) -> u8 {
+        // only 14 address lines, the rest mirrors
+        let addr = addr & 0x3fff;
         match addr {
-            0x00..=0x1ff => self.chr_rom[addr as usize],
+            0x00..=0x1fff => match &self.mapper {
+                Some(mapper) => mapper.borrow().read_chr(addr),
+                None => 0,
+            },
             0x2000..=0x3eff => {
-                let addr = PPUBus::mirror_nametable_addr(addr, &self.mirroring) as usize;
+                if let Some(value) = self
+                    .mapper
+                    .as_ref()
+                    .and_then(|m| m.borrow().read_nametable(addr))
+                {
+                    return value;
+                }
+                let addr = PPUBus::mirror_nametable_addr(addr, &self.mirroring()) as usize;
                 self.name_tables[addr]
             }
-            0x3f00..=0x3fff => {
+            // $3F00-$3FFF
+            _ => {
                 let addr = PPUBus::mirror_palette_addr(addr);
                 self.palette_table[addr as usize]
             }
-            _ => panic!("Only 14 bits can be addressed"),
         }
     }
     pub fn write_memory(&mut self, addr: u16, value: u8) {
-        // TODO for now, only allow writes to name_tables
+        self.record(PpuAccess {
+            memory: PpuMemory::Vram,
+            addr,
+            value,
+            write: true,
+        });
+        let addr = addr & 0x3fff;
         match addr {
-            0x00..=0x1ff => self.chr_rom[addr as usize] = value,
+            0x00..=0x1fff => {
+                if let Some(mapper) = &self.mapper {
+                    mapper.borrow_mut().write_chr(addr, value)
+                }
+            }
             0x2000..=0x3eff => {
-                let addr = PPUBus::mirror_nametable_addr(addr, &self.mirroring) as usize;
+                if let Some(mapper) = &self.mapper {
+                    if mapper.borrow_mut().write_nametable(addr, value) {
+                        return;
+                    }
+                }
+                let addr = PPUBus::mirror_nametable_addr(addr, &self.mirroring()) as usize;
                 self.name_tables[addr] = value
             }
-            0x3f00..=0x3fff => {
+            _ => {
                 let addr = PPUBus::mirror_palette_addr(addr);
                 self.palette_table[addr as usize] = value
             }
-            _ => panic!("can only write to to addresses 0x00..0x3fff."),
         }
     }
 }
@@ -128,5 +233,11 @@ mod ppubus_test {
 
         let horizontal3 = PPUBus::mirror_nametable_addr(0x2c05, &Mirroring::Horizontal);
         assert_eq!(horizontal3, 0x405, "actual: {:#x}", horizontal3);
+
+        let lower = PPUBus::mirror_nametable_addr(0x2c05, &Mirroring::SingleScreenLower);
+        assert_eq!(lower, 0x05, "actual: {:#x}", lower);
+
+        let upper = PPUBus::mirror_nametable_addr(0x2005, &Mirroring::SingleScreenUpper);
+        assert_eq!(upper, 0x405, "actual: {:#x}", upper);
     }
 }
@@ -1,8 +1,10 @@
 use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
 
 // Memory-mapped registers read from and written to by CPU
 bitflags! {
   // 0x2000 - Write
+  #[derive(Serialize, Deserialize)]
   pub struct PPUCTRL: u8 {
     const BASE_NAMETABLE_0 = 0x01;
     const BASE_NAMETABLE_1 = 0b00000010;
@@ -29,6 +31,7 @@ impl PPUCTRL {
 
 bitflags! {
   // 0x2001 - Write
+  #[derive(Serialize, Deserialize)]
   pub struct PPUMASK: u8 {
     const GRAYSCALE = 0x01;
     const SHOW_BACKGROUND_LEFTMOST = 0b00000010;
@@ -51,6 +54,7 @@ impl PPUMASK {
 
 bitflags! {
   // 0x2002
+  #[derive(Serialize, Deserialize)]
   pub struct PPUSTATUS: u8 {
     const SPRITE_OVERFLOW = 0b00100000;
     const SPRITE_0_HIT = 0b01000000;
@@ -64,12 +68,15 @@ impl PPUSTATUS {
 }
 
 // 0x2003
+#[derive(Clone, Serialize, Deserialize)]
 pub struct OAMADDR(pub u8);
 
 // 0x2004
+#[derive(Clone, Serialize, Deserialize)]
 pub struct OAMDATA(pub u8);
 
 // 2005
+#[derive(Clone, Serialize, Deserialize)]
 pub struct PPUSCROLL {
     // (x scroll, y scroll)
     value: (u8, u8),
@@ -85,9 +92,13 @@ impl PPUSCROLL {
             self.value.0 = value
         }
     }
+    pub fn get(&self) -> (u8, u8) {
+        self.value
+    }
 }
 
 // 2006
+#[derive(Clone, Serialize, Deserialize)]
 pub struct PPUADDR {
     // (msb, lsb)
     value: (u8, u8),
@@ -124,5 +135,6 @@ impl PPUADDR {
 }
 
 // 2007
+#[derive(Clone, Serialize, Deserialize)]
 pub struct PPUDATA(pub u8);
 // ********
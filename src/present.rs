@@ -0,0 +1,136 @@
+#[cfg(feature = "std")]
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::PathBuf,
+};
+
+use alloc::string::String;
+#[cfg(feature = "std")]
+use alloc::{format, string::ToString};
+
+use crate::ppu::Frame;
+
+/**
+ * Where a finished picture is shown: a window, files on disk, nowhere. The
+ * core only hands over pixels, RGB24 row by row, so it knows nothing of
+ * textures or surfaces. The picture is `width` x `height`, the frame's size
+ * unless a filter's been at it. An error says why it couldn't be shown, for
+ * the frontend to report.
+ */
+pub trait Presenter {
+    fn present(&mut self, pixels: &[u8], width: usize, height: usize) -> Result<(), String>;
+
+    fn present_frame(&mut self, frame: &Frame) -> Result<(), String> {
+        self.present(frame.data(), Frame::WIDTH, Frame::HEIGHT)
+    }
+}
+
+/**
+ * Shows nothing, for runs nobody's watching. It counts what it was given.
+ */
+#[derive(Default)]
+pub struct NullPresenter {
+    pub presented: u64,
+}
+
+impl NullPresenter {
+    pub fn new() -> NullPresenter {
+        NullPresenter::default()
+    }
+}
+
+impl Presenter for NullPresenter {
+    fn present(&mut self, _: &[u8], _: usize, _: usize) -> Result<(), String> {
+        self.presented += 1;
+        Ok(())
+    }
+}
+
+/**
+ * Each picture to a PNG of its own in `dir`, numbered from 0 (00000.png,
+ * 00001.png, ...). The directory's made if it isn't there.
+ */
+#[cfg(feature = "std")]
+pub struct PngWriter {
+    dir: PathBuf,
+    next: u64,
+}
+
+#[cfg(feature = "std")]
+impl PngWriter {
+    pub fn new(dir: impl Into<PathBuf>) -> PngWriter {
+        PngWriter {
+            dir: dir.into(),
+            next: 0,
+        }
+    }
+    /**
+     * Where the next picture goes.
+     */
+    pub fn next_path(&self) -> PathBuf {
+        self.dir.join(format!("{:05}.png", self.next))
+    }
+}
+
+#[cfg(feature = "std")]
+impl Presenter for PngWriter {
+    fn present(&mut self, pixels: &[u8], width: usize, height: usize) -> Result<(), String> {
+        let path = self.next_path();
+        let failed = |e: String| format!("Unable to write {}: {}", path.display(), e);
+        fs::create_dir_all(&self.dir).map_err(|e| failed(e.to_string()))?;
+        let file = BufWriter::new(File::create(&path).map_err(|e| failed(e.to_string()))?);
+        let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(pixels))
+            .map_err(|e| failed(e.to_string()))?;
+        self.next += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod present_test {
+    use std::{fs, io::BufReader, path::Path};
+
+    use crate::ppu::Frame;
+
+    use super::{NullPresenter, PngWriter, Presenter};
+
+    #[test]
+    fn test_null() {
+        let mut presenter = NullPresenter::new();
+        presenter.present_frame(&Frame::new()).unwrap();
+        presenter.present(&[0; 12], 2, 2).unwrap();
+        assert_eq!(presenter.presented, 2);
+    }
+
+    #[test]
+    fn test_png() {
+        let dir = std::env::temp_dir().join(format!("nes-png-{}", std::process::id()));
+        let mut writer = PngWriter::new(&dir);
+        let mut frame = Frame::new();
+        frame.set_pixel(1, 0, (0xff, 0x80, 0x00));
+        writer.present_frame(&frame).unwrap();
+        writer.present(&[0xff; 2 * 3 * 3], 2, 3).unwrap();
+        assert_eq!(writer.next_path(), dir.join("00002.png"));
+
+        // (width, height, pixels)
+        let read = |path: &Path| {
+            let file = BufReader::new(fs::File::open(path).unwrap());
+            let mut reader = png::Decoder::new(file).read_info().unwrap();
+            let mut pixels = vec![0; reader.output_buffer_size().unwrap()];
+            let info = reader.next_frame(&mut pixels).unwrap();
+            (info.width as usize, info.height as usize, pixels)
+        };
+        let (width, height, pixels) = read(&dir.join("00000.png"));
+        assert_eq!((width, height), (Frame::WIDTH, Frame::HEIGHT));
+        assert_eq!(&pixels[..6], &[0, 0, 0, 0xff, 0x80, 0x00]);
+        let (width, height, _) = read(&dir.join("00001.png"));
+        assert_eq!((width, height), (2, 3));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
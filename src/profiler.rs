@@ -0,0 +1,190 @@
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+/**
+ * Where the time goes. The APU has a slot for when there is one.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Subsystem {
+    Cpu,
+    // mapper clocking included, it's a rounding error next to the PPU
+    Ppu,
+    Apu,
+    // drawing the picture and the overlays
+    Frontend,
+}
+
+impl Subsystem {
+    pub const ALL: [Subsystem; 4] = [
+        Subsystem::Cpu,
+        Subsystem::Ppu,
+        Subsystem::Apu,
+        Subsystem::Frontend,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Subsystem::Cpu => "CPU",
+            Subsystem::Ppu => "PPU",
+            Subsystem::Apu => "APU",
+            Subsystem::Frontend => "Frontend",
+        }
+    }
+}
+
+/**
+ * Averages over the last second.
+ */
+#[derive(Serialize, Clone, Copy, Default, Debug, PartialEq)]
+pub struct ProfileStats {
+    // milliseconds per emulated frame, in `Subsystem::ALL` order
+    pub frame_ms: [f64; 4],
+    pub fps: f64,
+    pub cycles_per_sec: f64,
+}
+
+impl ProfileStats {
+    pub fn ms(&self, subsystem: Subsystem) -> f64 {
+        self.frame_ms[subsystem as usize]
+    }
+
+    /**
+     * "CPU 3.1ms PPU 5.2ms APU 0.0ms Frontend 0.4ms" then
+     * "60.1 FPS 1.79M cycles/s", for the OSD and the console.
+     */
+    pub fn lines(&self) -> [String; 2] {
+        let times: Vec<String> = Subsystem::ALL
+            .iter()
+            .map(|&subsystem| format!("{} {:.1}ms", subsystem.name(), self.ms(subsystem)))
+            .collect();
+        [
+            times.join(" "),
+            format!(
+                "{:.1} FPS {:.2}M cycles/s",
+                self.fps,
+                self.cycles_per_sec / 1e6
+            ),
+        ]
+    }
+}
+
+/**
+ * Time spent per subsystem, added up as the machine runs and turned into
+ * `ProfileStats` once a second. Only there while profiling, timing every
+ * instruction isn't free.
+ */
+pub struct Profiler {
+    totals: [Duration; 4],
+    frames: u32,
+    cycles: u64,
+    since: Instant,
+    stats: ProfileStats,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            totals: [Duration::ZERO; 4],
+            frames: 0,
+            cycles: 0,
+            since: Instant::now(),
+            stats: ProfileStats::default(),
+        }
+    }
+
+    pub fn add(&mut self, subsystem: Subsystem, time: Duration) {
+        self.totals[subsystem as usize] += time
+    }
+
+    /**
+     * Time added so far this second.
+     */
+    pub fn total(&self, subsystem: Subsystem) -> Duration {
+        self.totals[subsystem as usize]
+    }
+
+    /**
+     * Call at the end of every emulated frame with the CPU cycles it took.
+     * Returns true when the stats have just been updated.
+     */
+    pub fn end_frame(&mut self, cycles: u64) -> bool {
+        self.frames += 1;
+        self.cycles += cycles;
+        let elapsed = self.since.elapsed();
+        if elapsed < Duration::from_secs(1) {
+            return false;
+        }
+        self.update(elapsed);
+        true
+    }
+
+    /**
+     * Update the stats from what's been added so far, for runs that end
+     * before the second is up.
+     */
+    pub fn flush(&mut self) {
+        if self.frames > 0 {
+            self.update(self.since.elapsed())
+        }
+    }
+
+    fn update(&mut self, elapsed: Duration) {
+        let frames = self.frames.max(1) as f64;
+        let secs = elapsed.as_secs_f64();
+        self.stats = ProfileStats {
+            frame_ms: self
+                .totals
+                .map(|total| total.as_secs_f64() * 1000.0 / frames),
+            fps: self.frames as f64 / secs,
+            cycles_per_sec: self.cycles as f64 / secs,
+        };
+        *self = Profiler {
+            stats: self.stats,
+            ..Profiler::new()
+        }
+    }
+
+    pub fn stats(&self) -> ProfileStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod profiler_test {
+    use std::time::Duration;
+
+    use super::{Profiler, Subsystem};
+
+    #[test]
+    fn test_stats() {
+        let mut profiler = Profiler::new();
+        for _ in 0..4 {
+            profiler.add(Subsystem::Cpu, Duration::from_millis(3));
+            profiler.add(Subsystem::Ppu, Duration::from_millis(5));
+            assert!(!profiler.end_frame(29780));
+        }
+        profiler.add(Subsystem::Frontend, Duration::from_millis(2));
+        profiler.update(Duration::from_secs(2));
+        let stats = profiler.stats();
+        assert_eq!(stats.frame_ms, [3.0, 5.0, 0.0, 0.5]);
+        assert_eq!(stats.fps, 2.0);
+        assert_eq!(stats.cycles_per_sec, 59560.0);
+        assert_eq!(
+            stats.lines(),
+            [
+                "CPU 3.0ms PPU 5.0ms APU 0.0ms Frontend 0.5ms",
+                "2.0 FPS 0.06M cycles/s"
+            ]
+        );
+        // starts over for the next second
+        profiler.update(Duration::from_secs(1));
+        assert_eq!(profiler.stats().fps, 0.0);
+    }
+}
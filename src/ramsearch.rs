@@ -0,0 +1,158 @@
+use crate::cpu::CPU;
+
+// internal RAM, then PRG-RAM
+const RAM: std::ops::Range<u16> = 0x0000..0x0800;
+const PRG_RAM: std::ops::Range<u16> = 0x6000..0x8000;
+
+/**
+ * How a byte has to compare to be kept, against a value or against what it
+ * was at the last search.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Comparison {
+    Equal(u8),
+    Greater,
+    Less,
+    // by this much, wrapping
+    ChangedBy(i16),
+    Changed,
+    Unchanged,
+}
+
+impl Comparison {
+    pub fn name(self) -> &'static str {
+        match self {
+            Comparison::Equal(_) => "Equal to",
+            Comparison::Greater => "Greater than before",
+            Comparison::Less => "Less than before",
+            Comparison::ChangedBy(_) => "Changed by",
+            Comparison::Changed => "Changed",
+            Comparison::Unchanged => "Unchanged",
+        }
+    }
+
+    fn keeps(self, before: u8, now: u8) -> bool {
+        match self {
+            Comparison::Equal(value) => now == value,
+            Comparison::Greater => now > before,
+            Comparison::Less => now < before,
+            Comparison::ChangedBy(by) => now == before.wrapping_add(by as u8),
+            Comparison::Changed => now != before,
+            Comparison::Unchanged => now == before,
+        }
+    }
+}
+
+/**
+ * Narrowing down where a game keeps something (lives, health, a timer) by
+ * searching RAM and PRG-RAM over and over as it changes.
+ */
+pub struct RamSearch {
+    // addresses still in the running
+    candidates: Vec<u16>,
+    // every byte at the last search, RAM then PRG-RAM
+    snapshot: Vec<u8>,
+    searches: usize,
+}
+
+fn index(addr: u16) -> usize {
+    if RAM.contains(&addr) {
+        addr as usize
+    } else {
+        RAM.len() + (addr - PRG_RAM.start) as usize
+    }
+}
+
+fn snapshot(cpu: &CPU) -> Vec<u8> {
+    RAM.chain(PRG_RAM).map(|addr| cpu.peek(addr)).collect()
+}
+
+impl RamSearch {
+    /**
+     * Every address is a candidate to start with.
+     */
+    pub fn new(cpu: &CPU) -> RamSearch {
+        RamSearch {
+            candidates: RAM.chain(PRG_RAM).collect(),
+            snapshot: snapshot(cpu),
+            searches: 0,
+        }
+    }
+
+    /**
+     * Keep the candidates that compare, then take a new snapshot for the
+     * next one.
+     */
+    pub fn search(&mut self, cpu: &CPU, comparison: Comparison) {
+        let now = snapshot(cpu);
+        let before = &self.snapshot;
+        self.candidates
+            .retain(|&addr| comparison.keeps(before[index(addr)], now[index(addr)]));
+        self.snapshot = now;
+        self.searches += 1
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+
+    /**
+     * What `addr` held at the last search.
+     */
+    pub fn value(&self, addr: u16) -> u8 {
+        self.snapshot[index(addr)]
+    }
+
+    pub fn searches(&self) -> usize {
+        self.searches
+    }
+}
+
+#[cfg(test)]
+mod ramsearch_test {
+    use crate::{bus::Bus, cpu::CPU, ppu::PPU};
+
+    use super::{Comparison, RamSearch};
+
+    #[test]
+    fn test_search() {
+        let mut cpu = CPU::new(Bus::new(PPU::new()));
+        // lives at $0075, a timer at $6010
+        cpu.poke(0x75, 3);
+        cpu.poke(0x6010, 200);
+        let mut search = RamSearch::new(&cpu);
+        assert_eq!(search.candidates().len(), 0x800 + 0x2000);
+
+        search.search(&cpu, Comparison::Equal(3));
+        assert_eq!(search.candidates(), [0x75]);
+
+        let mut search = RamSearch::new(&cpu);
+        cpu.poke(0x75, 2);
+        cpu.poke(0x6010, 199);
+        search.search(&cpu, Comparison::Less);
+        assert_eq!(search.candidates(), [0x75, 0x6010]);
+        search.search(&cpu, Comparison::Unchanged);
+        assert_eq!(search.candidates().len(), 2);
+        cpu.poke(0x6010, 195);
+        search.search(&cpu, Comparison::ChangedBy(-4));
+        assert_eq!(search.candidates(), [0x6010]);
+        assert_eq!(search.value(0x6010), 195);
+        assert_eq!(search.searches(), 3);
+
+        // wrapping around
+        cpu.poke(0x6010, 1);
+        search.search(&cpu, Comparison::ChangedBy(62));
+        assert_eq!(search.candidates(), [0x6010]);
+    }
+
+    #[test]
+    fn test_changed() {
+        let mut cpu = CPU::new(Bus::new(PPU::new()));
+        let mut search = RamSearch::new(&cpu);
+        cpu.poke(0x10, 1);
+        search.search(&cpu, Comparison::Changed);
+        assert_eq!(search.candidates(), [0x10]);
+        search.search(&cpu, Comparison::Greater);
+        assert!(search.candidates().is_empty());
+    }
+}
@@ -0,0 +1,446 @@
+use std::io::{self, BufRead, Write};
+
+use crate::{
+    assertion::Assertion,
+    cheats::CheatEdit,
+    cpu::{IrqSource, CPU},
+    debugger::{
+        disassembly_view, instruction_line, status_line, Access, Breakpoint, Debugger, Stop,
+        RUN_FRAMES,
+    },
+    hexdump::ROW_BYTES,
+    ppu::PpuMemory,
+};
+
+// instructions shown before PC and, by default, after it
+const DISASM_BEFORE: usize = 4;
+const DISASM_AFTER: usize = 10;
+
+// instructions shown leading up to a failed assertion
+const ASSERT_CONTEXT: usize = 8;
+
+const HELP: &str = "\
+step, s [N]         run N instructions
+back, b [N]         undo N instructions
+continue, c         run until a breakpoint
+frame, f [N]        run N frames, stopping at breakpoints
+break, bp <addr>    stop before running the instruction at an address or label
+break scanline <N> [dot]
+                    stop when the PPU gets there
+break nmi|brk       stop at the top of the handler
+break irq [frame|dmc|mapper]
+watch [read|write|any] <addr[-addr]>|palette|oam [addr[-addr]]
+                    stop when the CPU gets at PPU memory, writes by default
+assert <condition>  stop when it's no longer true, like \"sp >= 20\" or
+                    \"ctrl & 80 == 0 while rendering\", see the readme
+delete, d <N>       remove breakpoint N
+unassert <N>        remove assertion N
+list, l             list the breakpoints and assertions
+regs, r             show the registers
+disasm, u [N]       disassemble around PC, N instructions on from it
+mem, m <addr> [N]   show N bytes of memory
+cheat [add <code> [name] | remove|enable|disable <N>]
+                    list the game's cheats, or change and save them
+help, h             this
+quit, q             leave
+An empty line repeats the last command.";
+
+#[derive(Clone, PartialEq, Debug)]
+enum Command {
+    Step(usize),
+    Back(usize),
+    Continue,
+    Frame(u64),
+    // an address or a label, looked up when it's set
+    Break(String),
+    BreakBeam(usize, usize),
+    BreakOn(Breakpoint),
+    Assert(Assertion),
+    Delete(usize),
+    Unassert(usize),
+    List,
+    Regs,
+    Disasm(usize),
+    Mem(String, u16),
+    Cheat(Option<CheatEdit>),
+    Help,
+    Quit,
+}
+
+fn count<T: std::str::FromStr>(arg: Option<&str>, default: T) -> Result<T, String> {
+    match arg {
+        Some(text) => text.parse().map_err(|_| format!("Not a number: {}", text)),
+        None => Ok(default),
+    }
+}
+
+fn parse(line: &str) -> Result<Command, String> {
+    let mut words = line.split_whitespace();
+    let name = words.next().unwrap_or("");
+    let arg = words.next();
+    let command = match name {
+        "step" | "s" => Command::Step(count(arg, 1)?),
+        "back" | "b" => Command::Back(count(arg, 1)?),
+        "continue" | "c" => Command::Continue,
+        "frame" | "f" => Command::Frame(count(arg, 1)?),
+        "break" | "bp" => match arg.ok_or("break needs an address")? {
+            "scanline" => {
+                let scanline = words.next().ok_or("break scanline needs a number")?;
+                Command::BreakBeam(count(Some(scanline), 0)?, count(words.next(), 0)?)
+            }
+            "nmi" => Command::BreakOn(Breakpoint::Nmi),
+            "brk" => Command::BreakOn(Breakpoint::Brk),
+            "irq" => {
+                let source = match words.next() {
+                    None => None,
+                    Some("frame") => Some(IrqSource::FrameCounter),
+                    Some("dmc") => Some(IrqSource::Dmc),
+                    Some("mapper") => Some(IrqSource::Mapper),
+                    Some(other) => return Err(format!("Unknown IRQ source {}", other)),
+                };
+                Command::BreakOn(Breakpoint::Irq(source))
+            }
+            addr => Command::Break(addr.to_string()),
+        },
+        "watch" | "w" => Command::BreakOn(watch(words_from(arg, words))?),
+        "assert" => {
+            let text = words_from(arg, words).collect::<Vec<_>>().join(" ");
+            Command::Assert(Assertion::parse(&text)?)
+        }
+        "delete" | "d" => Command::Delete(count(Some(arg.ok_or("delete needs a number")?), 0)?),
+        "unassert" => Command::Unassert(count(Some(arg.ok_or("unassert needs a number")?), 0)?),
+        "list" | "l" => Command::List,
+        "regs" | "r" => Command::Regs,
+        "disasm" | "u" => Command::Disasm(count(arg, DISASM_AFTER)?),
+        "mem" | "m" => Command::Mem(
+            arg.ok_or("mem needs an address")?.to_string(),
+            count(words.next(), ROW_BYTES as u16)?,
+        ),
+        "cheat" => match arg {
+            None => Command::Cheat(None),
+            Some(_) => Command::Cheat(Some(
+                CheatEdit::parse(words_from(arg, words))
+                    .ok_or("Try help for the cheat commands")?,
+            )),
+        },
+        "help" | "h" => Command::Help,
+        "quit" | "q" => Command::Quit,
+        _ => return Err(format!("Unknown command {}, try help", name)),
+    };
+    Ok(command)
+}
+
+fn words_from<'a>(
+    first: Option<&'a str>,
+    rest: impl Iterator<Item = &'a str>,
+) -> impl Iterator<Item = &'a str> {
+    first.into_iter().chain(rest)
+}
+
+// "2000-23FF" or one address, in hex
+fn range(text: &str, max: u16) -> Result<(u16, u16), String> {
+    let hex = |text: &str| u16::from_str_radix(text.trim_start_matches('$'), 16).ok();
+    let (start, end) = match text.split_once('-') {
+        Some((start, end)) => (hex(start), hex(end)),
+        None => (hex(text), hex(text)),
+    };
+    match (start, end) {
+        (Some(start), Some(end)) if start <= end && end <= max => Ok((start, end)),
+        _ => Err(format!("Not a range up to ${:X}: {}", max, text)),
+    }
+}
+
+/**
+ * "watch write 2000-23FF", "watch palette", "watch read oam 00-03".
+ */
+fn watch<'a>(words: impl Iterator<Item = &'a str>) -> Result<Breakpoint, String> {
+    let mut words = words.peekable();
+    let access = match words.peek() {
+        Some(&"read") => Some(Access::Read),
+        Some(&"write") => Some(Access::Write),
+        Some(&"any") => Some(Access::Any),
+        _ => None,
+    };
+    if access.is_some() {
+        words.next();
+    }
+    let (memory, (start, end)) = match words.next().ok_or("watch needs an address")? {
+        "palette" => (PpuMemory::Vram, (0x3f00, 0x3fff)),
+        "oam" => match words.next() {
+            Some(text) => (PpuMemory::Oam, range(text, 0xff)?),
+            None => (PpuMemory::Oam, (0, 0xff)),
+        },
+        text => (PpuMemory::Vram, range(text, 0x3fff)?),
+    };
+    Ok(Breakpoint::Watch {
+        memory,
+        start,
+        end,
+        access: access.unwrap_or(Access::Write),
+    })
+}
+
+/**
+ * "$C000", "c000" or a label from the symbol files.
+ */
+fn address(cpu: &CPU, text: &str) -> Result<u16, String> {
+    cpu.symbol(text)
+        .or_else(|| u16::from_str_radix(text.trim_start_matches('$'), 16).ok())
+        .ok_or_else(|| format!("Not an address or label: {}", text))
+}
+
+fn report(cpu: &CPU, debugger: &Debugger, stop: Stop, out: &mut impl Write) -> io::Result<()> {
+    match stop {
+        Stop::Done => (),
+        Stop::Breakpoint(idx) => {
+            writeln!(out, "Breakpoint {}: {}", idx, debugger.breakpoints[idx])?
+        }
+        Stop::Assertion(idx) => {
+            writeln!(
+                out,
+                "Assertion {} failed: {}",
+                idx, debugger.assertions[idx]
+            )?;
+            let history = cpu.history();
+            for regs in &history[history.len().saturating_sub(ASSERT_CONTEXT)..] {
+                writeln!(out, "  {}", instruction_line(cpu, regs))?
+            }
+        }
+        Stop::OutOfFrames => writeln!(out, "No breakpoint hit")?,
+    }
+    writeln!(out, "{}", status_line(cpu))
+}
+
+fn add_breakpoint(
+    debugger: &mut Debugger,
+    breakpoint: Breakpoint,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    debugger.breakpoints.push(breakpoint);
+    writeln!(
+        out,
+        "Breakpoint {}: {}",
+        debugger.breakpoints.len() - 1,
+        breakpoint
+    )
+}
+
+/**
+ * The debugger as a prompt, reading commands from `input` until it runs
+ * out or gets a quit. It starts by saying why the machine `stopped`.
+ */
+pub fn run(
+    cpu: &mut CPU,
+    debugger: &mut Debugger,
+    stopped: Stop,
+    input: impl BufRead,
+    mut out: impl Write,
+) -> io::Result<()> {
+    report(cpu, debugger, stopped, &mut out)?;
+    let mut last = None;
+    let mut lines = input.lines();
+    loop {
+        write!(out, "> ")?;
+        out.flush()?;
+        let Some(line) = lines.next() else {
+            // off the prompt
+            return writeln!(out);
+        };
+        let line = line?;
+        let command = if line.trim().is_empty() {
+            match last.clone() {
+                Some(command) => command,
+                None => continue,
+            }
+        } else {
+            match parse(&line) {
+                Ok(command) => command,
+                Err(e) => {
+                    writeln!(out, "{}", e)?;
+                    continue;
+                }
+            }
+        };
+        last = Some(command.clone());
+        match command {
+            Command::Step(n) => {
+                let stop = debugger.step_over(cpu, n);
+                report(cpu, debugger, stop, &mut out)?
+            }
+            Command::Back(n) => {
+                let undone = debugger.step_back(cpu, n);
+                if undone < n {
+                    writeln!(out, "Only {} instructions back", undone)?
+                }
+                report(cpu, debugger, Stop::Done, &mut out)?
+            }
+            Command::Continue => {
+                let stop = debugger.run(cpu, RUN_FRAMES);
+                report(cpu, debugger, stop, &mut out)?
+            }
+            Command::Frame(n) => {
+                let stop = match debugger.run(cpu, n) {
+                    Stop::OutOfFrames => Stop::Done,
+                    stop => stop,
+                };
+                report(cpu, debugger, stop, &mut out)?
+            }
+            Command::Break(text) => match address(cpu, &text) {
+                Ok(addr) => add_breakpoint(debugger, Breakpoint::Exec(addr), &mut out)?,
+                Err(e) => writeln!(out, "{}", e)?,
+            },
+            Command::BreakBeam(scanline, dot) if scanline < 262 && dot < 341 => {
+                add_breakpoint(debugger, Breakpoint::Beam { scanline, dot }, &mut out)?
+            }
+            Command::BreakBeam(..) => writeln!(out, "Scanlines go to 261 and dots to 340")?,
+            Command::BreakOn(breakpoint) => add_breakpoint(debugger, breakpoint, &mut out)?,
+            Command::Assert(assertion) => {
+                writeln!(
+                    out,
+                    "Assertion {}: {}",
+                    debugger.assertions.len(),
+                    assertion
+                )?;
+                debugger.assertions.push(assertion)
+            }
+            Command::Delete(idx) if idx < debugger.breakpoints.len() => {
+                debugger.breakpoints.remove(idx);
+            }
+            Command::Delete(idx) => writeln!(out, "No breakpoint {}", idx)?,
+            Command::Unassert(idx) if idx < debugger.assertions.len() => {
+                debugger.assertions.remove(idx);
+            }
+            Command::Unassert(idx) => writeln!(out, "No assertion {}", idx)?,
+            Command::List => {
+                for (idx, bp) in debugger.breakpoints.iter().enumerate() {
+                    writeln!(out, "{}: {}", idx, bp)?
+                }
+                for (idx, assertion) in debugger.assertions.iter().enumerate() {
+                    writeln!(out, "assert {}: {}", idx, assertion)?
+                }
+            }
+            Command::Regs => writeln!(out, "{}", status_line(cpu))?,
+            Command::Disasm(after) => {
+                for line in disassembly_view(cpu, DISASM_BEFORE, after) {
+                    writeln!(out, "{}", line)?
+                }
+            }
+            Command::Mem(text, len) => match address(cpu, &text) {
+                Ok(start) => {
+                    let addrs: Vec<u16> = (0..len).map(|n| start.wrapping_add(n)).collect();
+                    for row in addrs.chunks(ROW_BYTES) {
+                        let hex: Vec<String> = row
+                            .iter()
+                            .map(|&addr| format!("{:02X}", cpu.peek(addr)))
+                            .collect();
+                        writeln!(out, "{:04X}  {}", row[0], hex.join(" "))?
+                    }
+                }
+                Err(e) => writeln!(out, "{}", e)?,
+            },
+            Command::Cheat(edit) => {
+                let result = cpu.edit_cheats(|cheats| match &edit {
+                    Some(edit) => cheats.apply(edit).and_then(|_| cheats.save()),
+                    None => Ok(()),
+                });
+                match result {
+                    Ok(()) => writeln!(out, "{}", cpu.cheats())?,
+                    Err(e) => writeln!(out, "{}", e)?,
+                }
+            }
+            Command::Help => writeln!(out, "{}", HELP)?,
+            Command::Quit => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod repl_test {
+    use crate::{
+        assertion::Assertion,
+        cheats::CheatEdit,
+        cpu::{cpu_with_program, IrqSource},
+        debugger::{Access, Breakpoint, Debugger, Stop},
+        ppu::PpuMemory,
+    };
+
+    use super::{parse, run, Command};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("s"), Ok(Command::Step(1)));
+        assert_eq!(parse("back 20"), Ok(Command::Back(20)));
+        assert_eq!(parse("bp Reset"), Ok(Command::Break("Reset".to_string())));
+        assert_eq!(parse("break scanline 241"), Ok(Command::BreakBeam(241, 0)));
+        assert_eq!(parse("bp scanline 30 200"), Ok(Command::BreakBeam(30, 200)));
+        assert_eq!(
+            parse("break irq mapper"),
+            Ok(Command::BreakOn(Breakpoint::Irq(Some(IrqSource::Mapper))))
+        );
+        assert!(parse("break irq apu").is_err());
+        assert_eq!(parse("u"), Ok(Command::Disasm(10)));
+        assert_eq!(
+            parse("watch 2000-23FF"),
+            Ok(Command::BreakOn(Breakpoint::Watch {
+                memory: PpuMemory::Vram,
+                start: 0x2000,
+                end: 0x23ff,
+                access: Access::Write
+            }))
+        );
+        assert_eq!(
+            parse("watch any oam 4"),
+            Ok(Command::BreakOn(Breakpoint::Watch {
+                memory: PpuMemory::Oam,
+                start: 4,
+                end: 4,
+                access: Access::Any
+            }))
+        );
+        assert!(parse("watch palette").is_ok());
+        assert!(parse("watch oam 100").is_err());
+        assert!(parse("watch read").is_err());
+        assert_eq!(parse("m $0300 4"), Ok(Command::Mem("$0300".to_string(), 4)));
+        assert_eq!(parse("cheat"), Ok(Command::Cheat(None)));
+        assert_eq!(
+            parse("cheat disable 0"),
+            Ok(Command::Cheat(Some(CheatEdit::Disable(0))))
+        );
+        assert!(parse("cheat add").is_err());
+        assert!(parse("step many").is_err());
+        assert!(parse("delete").is_err());
+        assert_eq!(
+            parse("assert sp >= 20"),
+            Ok(Command::Assert(Assertion::parse("sp >= 20").unwrap()))
+        );
+        assert!(parse("assert sp").is_err());
+        assert!(parse("jump").is_err());
+    }
+
+    #[test]
+    fn test_session() {
+        let program = [
+            0xe8, // INX
+            0x86, 0x10, // STX $10
+            0x4c, 0x00, 0x80, // JMP $8000
+        ];
+        let mut cpu = cpu_with_program(&program);
+
+        let commands = "bp 8003\nc\n\nm 10 1\nb\nquit\nstep\n";
+        let mut out = Vec::new();
+        run(
+            &mut cpu,
+            &mut Debugger::new(),
+            Stop::Done,
+            commands.as_bytes(),
+            &mut out,
+        )
+        .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Breakpoint 0: exec $8003"));
+        // twice round, the empty line continued
+        assert!(out.contains("0010  02"));
+        assert_eq!(cpu.registers().pc, 0x8001);
+        assert_eq!(cpu.registers().x, 2);
+    }
+}
@@ -0,0 +1,23 @@
+use crate::cartridge::Mirroring;
+
+/**
+ * A known dump, keyed by the CRC32 of its PRG + CHR.
+ * `None` fields mean the iNES header is trusted as-is.
+ */
+pub struct RomDbEntry {
+    pub crc32: u32,
+    pub title: &'static str,
+    pub mapper: Option<u8>,
+    pub mirroring: Option<Mirroring>,
+}
+
+static ROMDB: &[RomDbEntry] = &[RomDbEntry {
+    crc32: 0x3337ec46,
+    title: "Super Mario Bros. (World)",
+    mapper: Some(0),
+    mirroring: Some(Mirroring::Vertical),
+}];
+
+pub fn lookup(crc32: u32) -> Option<&'static RomDbEntry> {
+    ROMDB.iter().find(|e| e.crc32 == crc32)
+}
@@ -0,0 +1,498 @@
+use core::{error::Error, fmt};
+#[cfg(feature = "std")]
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "std")]
+use serde::{de::DeserializeOwned, Serialize};
+
+#[cfg(feature = "std")]
+use crate::{
+    bus::BusState,
+    controller::{Controller, FourScore},
+    debug::Registers,
+    power_pad::PowerPad,
+    ppu::PPU,
+    zapper::Zapper,
+};
+
+// bumped whenever a chunk's contents change, with a migration from the last
+pub const VERSION: u16 = 1;
+
+// the chunks are bincode, which needs std, so anything that reads or writes
+// them does too
+#[cfg(feature = "std")]
+const MAGIC: &[u8; 4] = b"NESS";
+
+#[cfg(feature = "std")]
+type Tag = [u8; 4];
+#[cfg(feature = "std")]
+const CPU_CHUNK: Tag = *b"CPU ";
+#[cfg(feature = "std")]
+const BUS_CHUNK: Tag = *b"BUS ";
+#[cfg(feature = "std")]
+const PPU_CHUNK: Tag = *b"PPU ";
+#[cfg(feature = "std")]
+const MAPPER_CHUNK: Tag = *b"MAPR";
+
+#[derive(Debug)]
+pub enum StateError {
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    #[cfg(feature = "std")]
+    Encoding(bincode::Error),
+    // which chunk didn't decode
+    #[cfg(feature = "std")]
+    Chunk(&'static str, bincode::Error),
+    Missing(&'static str),
+    Truncated,
+    // the version it's in
+    TooNew(u16),
+    // what about the machine it doesn't fit
+    Mismatch(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            StateError::Io(e) => write!(f, "Unable to access the save state: {}", e),
+            #[cfg(feature = "std")]
+            StateError::Encoding(e) => write!(f, "Unable to read the save state: {}", e),
+            #[cfg(feature = "std")]
+            StateError::Chunk(name, e) => {
+                write!(f, "Unable to read the save state's {}: {}", name, e)
+            }
+            StateError::Missing(name) => write!(f, "The save state has no {} in it", name),
+            StateError::Truncated => write!(f, "The save state is cut short"),
+            StateError::TooNew(version) => write!(
+                f,
+                "The save state is from a newer version of the emulator (format {}, this one reads up to {})",
+                version, VERSION
+            ),
+            StateError::Mismatch(what) => {
+                write!(f, "The save state is for a different game ({})", what)
+            }
+        }
+    }
+}
+
+impl Error for StateError {
+    #[cfg(feature = "std")]
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StateError::Io(e) => Some(e),
+            StateError::Encoding(e) | StateError::Chunk(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for StateError {
+    fn from(e: io::Error) -> Self {
+        StateError::Io(e)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<bincode::Error> for StateError {
+    fn from(e: bincode::Error) -> Self {
+        StateError::Encoding(e)
+    }
+}
+
+#[cfg(feature = "std")]
+pub fn encode<T: Serialize>(value: &T) -> Vec<u8> {
+    // plain data into memory, this can't fail
+    bincode::serialize(value).unwrap()
+}
+
+#[cfg(feature = "std")]
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, StateError> {
+    Ok(bincode::deserialize(bytes)?)
+}
+
+/**
+ * Copy `from` over RAM that's `to`'s size, which it has to be.
+ */
+pub fn fill(to: &mut [u8], from: &[u8], what: &'static str) -> Result<(), StateError> {
+    if to.len() != from.len() {
+        return Err(StateError::Mismatch(what));
+    }
+    to.copy_from_slice(from);
+    Ok(())
+}
+
+// quick save slots per game
+pub const SLOTS: usize = 10;
+
+// frames between checksums of the machine, for movies and netplay to notice
+// when they've gone their own way
+pub const CHECKSUM_INTERVAL: usize = 60;
+
+/**
+ * For `#[serde(with = "...")]` on byte arrays, serde only does up to 32.
+ */
+pub mod array {
+    use alloc::vec::Vec;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        bytes: &[u8; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(bytes)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        let bytes: Vec<u8> = Deserialize::deserialize(deserializer)?;
+        let len = bytes.len();
+        bytes
+            .try_into()
+            .map_err(|_| D::Error::invalid_length(len, &"as many bytes as the RAM holds"))
+    }
+}
+
+/**
+ * Tagged pieces of a save state, one per part of the machine, so a version
+ * can tell what it's looking at and a migration can rework one without
+ * touching the rest.
+ */
+#[cfg(feature = "std")]
+struct Chunks(Vec<(Tag, Vec<u8>)>);
+
+#[cfg(feature = "std")]
+impl Chunks {
+    /**
+     * A 4 byte tag, the length as a little endian u32 and that many bytes,
+     * one after the other to the end.
+     */
+    fn parse(mut bytes: &[u8]) -> Result<Chunks, StateError> {
+        let mut chunks = Vec::new();
+        while !bytes.is_empty() {
+            if bytes.len() < 8 {
+                return Err(StateError::Truncated);
+            }
+            let (header, rest) = bytes.split_at(8);
+            let len = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+            if rest.len() < len {
+                return Err(StateError::Truncated);
+            }
+            chunks.push((header[..4].try_into().unwrap(), rest[..len].to_vec()));
+            bytes = &rest[len..];
+        }
+        Ok(Chunks(chunks))
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        for (tag, data) in &self.0 {
+            out.extend_from_slice(tag);
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(data)
+        }
+    }
+
+    fn get(&self, tag: Tag, name: &'static str) -> Result<&[u8], StateError> {
+        self.0
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, data)| data.as_slice())
+            .ok_or(StateError::Missing(name))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, tag: Tag, name: &'static str) -> Result<T, StateError> {
+        bincode::deserialize(self.get(tag, name)?).map_err(|e| StateError::Chunk(name, e))
+    }
+}
+
+// next to the config file
+#[cfg(feature = "std")]
+fn states_dir(config_path: &Path) -> PathBuf {
+    config_path.parent().unwrap_or(Path::new("")).join("states")
+}
+
+/**
+ * The first save states were the whole thing bincoded in one go, no header:
+ * the registers, the instruction count and then the bus with the PPU and
+ * the mapper in amongst it. Cut up into the chunks of version 1.
+ */
+#[cfg(feature = "std")]
+fn migrate_unversioned(bytes: &[u8]) -> Result<Chunks, StateError> {
+    type Unversioned = (
+        Registers,
+        usize,
+        Vec<u8>,
+        PPU,
+        [Controller; 4],
+        Option<FourScore>,
+        Option<Zapper>,
+        Option<PowerPad>,
+        u16,
+        (usize, usize),
+        Vec<u8>,
+    );
+    let (
+        registers,
+        executed,
+        ram,
+        ppu,
+        controllers,
+        four_score,
+        zapper,
+        power_pad,
+        last_read,
+        rom_sizes,
+        mapper,
+    ): Unversioned = decode(bytes)?;
+    let bus = (
+        ram,
+        controllers,
+        four_score,
+        zapper,
+        power_pad,
+        last_read,
+        rom_sizes,
+    );
+    Ok(Chunks(vec![
+        (CPU_CHUNK, encode(&(registers, executed))),
+        (BUS_CHUNK, encode(&bus)),
+        (PPU_CHUNK, encode(&ppu)),
+        (MAPPER_CHUNK, mapper),
+    ]))
+}
+
+/**
+ * Everything that makes the machine run the way it does: CPU registers,
+ * RAM, the PPU, the controllers and the cartridge's registers and RAM (there's
+ * no APU yet). Loading one puts the machine back exactly, the tooling
+ * (traces, hooks, cheats) stays as it is. ROM isn't kept, it has to be loaded
+ * into the same game. Nor is the picture, the next frame draws it again.
+ *
+ * On disk it's "NESS", the format `VERSION` as a little endian u16, then the
+ * CPU, bus, PPU and mapper chunks. Older versions are migrated forward as
+ * they're read, chunks a version doesn't know about are passed over.
+ */
+#[cfg(feature = "std")]
+pub struct SaveState {
+    pub(crate) registers: Registers,
+    pub(crate) executed: usize,
+    pub(crate) bus: BusState,
+}
+
+#[cfg(feature = "std")]
+impl SaveState {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        Chunks(vec![
+            (CPU_CHUNK, encode(&(self.registers, self.executed))),
+            (BUS_CHUNK, encode(&self.bus)),
+            (PPU_CHUNK, encode(&self.bus.ppu)),
+            (MAPPER_CHUNK, self.bus.mapper.clone()),
+        ])
+        .write(&mut bytes);
+        bytes
+    }
+
+    /**
+     * CRC32 of the machine, the same for the same state whichever version
+     * header it's saved with.
+     */
+    pub fn checksum(&self) -> u32 {
+        crc32fast::hash(&self.to_bytes()[MAGIC.len() + 2..])
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<SaveState, StateError> {
+        let chunks = match bytes.strip_prefix(MAGIC) {
+            Some(rest) if rest.len() >= 2 => {
+                let version = u16::from_le_bytes([rest[0], rest[1]]);
+                if version > VERSION {
+                    return Err(StateError::TooNew(version));
+                }
+                // version 1 is the first with a header, nothing to do yet
+                Chunks::parse(&rest[2..])?
+            }
+            Some(_) => return Err(StateError::Truncated),
+            None => migrate_unversioned(bytes)?,
+        };
+        let (registers, executed) = chunks.decode(CPU_CHUNK, "CPU")?;
+        let mut bus: BusState = chunks.decode(BUS_CHUNK, "bus")?;
+        bus.ppu = chunks.decode(PPU_CHUNK, "PPU")?;
+        bus.mapper = chunks.get(MAPPER_CHUNK, "mapper")?.to_vec();
+        Ok(SaveState {
+            registers,
+            executed,
+            bus,
+        })
+    }
+
+    /**
+     * states/<crc32>-<slot>.state in the same directory as the config file.
+     */
+    pub fn slot_path(config_path: &Path, crc32: u32, slot: usize) -> PathBuf {
+        states_dir(config_path).join(format!("{:08x}-{}.state", crc32, slot))
+    }
+
+    /**
+     * Where the game was left, states/<crc32>-resume.state.
+     */
+    pub fn resume_path(config_path: &Path, crc32: u32) -> PathBuf {
+        states_dir(config_path).join(format!("{:08x}-resume.state", crc32))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), StateError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?
+        }
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<SaveState, StateError> {
+        SaveState::from_bytes(&fs::read(path)?)
+    }
+}
+
+#[cfg(test)]
+mod savestate_test {
+    use crate::{
+        cpu::{cpu_with_prg, CPU},
+        debug::{PpuRegisters, Registers},
+    };
+
+    use super::{encode, Chunks, SaveState, StateError, VERSION};
+
+    fn machine(prg_size: usize) -> CPU {
+        let mut prgrom = vec![0; prg_size];
+        let program = [
+            0xa9, 0x80, 0x8d, 0x00, 0x20, // LDA #$80, STA $2000 NMI on
+            0xa9, 0x20, 0x8d, 0x06, 0x20, // LDA #$20, STA $2006
+            0xa9, 0x00, 0x8d, 0x06, 0x20, // LDA #$00, STA $2006
+            0xe6, 0x10, 0xa5, 0x10, // $800F: INC $10, LDA $10
+            0x8d, 0x00, 0x60, 0x8d, 0x07, 0x20, // STA $6000, STA $2007
+            0x4c, 0x0f, 0x80, // JMP $800F
+            0xe6, 0x11, 0x40, // $801C NMI: INC $11, RTI
+        ];
+        prgrom[..program.len()].copy_from_slice(&program);
+        let vectors = prg_size - 6;
+        prgrom[vectors..].copy_from_slice(&[0x1c, 0x80, 0x00, 0x80, 0x00, 0x80]);
+        cpu_with_prg(prgrom)
+    }
+
+    fn run(cpu: &mut CPU, frames: usize) -> (Registers, PpuRegisters, [u8; 3], Vec<u8>) {
+        for _ in 0..frames {
+            cpu.run_frame()
+        }
+        let memory = [cpu.peek(0x10), cpu.peek(0x11), cpu.peek(0x6000)];
+        (
+            cpu.registers(),
+            cpu.ppu().registers(),
+            memory,
+            cpu.ppu().nametables(),
+        )
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut cpu = machine(0x4000);
+        run(&mut cpu, 3);
+        let bytes = cpu.save_state().to_bytes();
+        let after = run(&mut cpu, 5);
+        // NMIs were taken along the way
+        assert!(after.2[1] > 0);
+
+        let state = SaveState::from_bytes(&bytes).unwrap();
+        cpu.load_state(&state).unwrap();
+        assert_eq!(run(&mut cpu, 5), after);
+        // or into a machine of its own
+        let mut other = machine(0x4000);
+        other.load_state(&state).unwrap();
+        assert_eq!(run(&mut other, 5), after);
+    }
+
+    #[test]
+    fn test_mismatch() {
+        let state = machine(0x4000).save_state();
+        let mut other = machine(0x8000);
+        assert!(matches!(
+            other.load_state(&state),
+            Err(StateError::Mismatch(_))
+        ));
+        assert!(SaveState::from_bytes(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn test_versions() {
+        let mut cpu = machine(0x4000);
+        run(&mut cpu, 2);
+        let bytes = cpu.save_state().to_bytes();
+        assert_eq!(&bytes[..6], b"NESS\x01\x00");
+        let after = run(&mut cpu, 2);
+
+        // from before there was a header, the bus around the PPU
+        let chunks = Chunks::parse(&bytes[6..]).unwrap();
+        let (cpu_chunk, bus, ppu, mapper) = (
+            &chunks.0[0].1,
+            &chunks.0[1].1,
+            &chunks.0[2].1,
+            &chunks.0[3].1,
+        );
+        // RAM's length and 2KB
+        let (ram, rest) = bus.split_at(8 + 0x800);
+        let unversioned = [cpu_chunk, ram, ppu, rest, &encode(mapper)].concat();
+        let state = SaveState::from_bytes(&unversioned).unwrap();
+        cpu.load_state(&state).unwrap();
+        assert_eq!(run(&mut cpu, 2), after);
+
+        // unknown chunks are passed over
+        let mut extra = bytes.clone();
+        extra.extend_from_slice(b"NEW \x01\x00\x00\x00\xff");
+        assert!(SaveState::from_bytes(&extra).is_ok());
+
+        let mut newer = bytes.clone();
+        newer[4] = VERSION as u8 + 1;
+        assert!(matches!(
+            SaveState::from_bytes(&newer),
+            Err(StateError::TooNew(_))
+        ));
+        assert!(matches!(
+            SaveState::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(StateError::Truncated)
+        ));
+        // a chunk that's gone
+        let mut chunks = Chunks::parse(&bytes[6..]).unwrap();
+        chunks.0.remove(2);
+        let mut without_ppu = bytes[..6].to_vec();
+        chunks.write(&mut without_ppu);
+        assert!(matches!(
+            SaveState::from_bytes(&without_ppu),
+            Err(StateError::Missing("PPU"))
+        ));
+    }
+
+    #[test]
+    fn test_slots() {
+        let config = std::env::temp_dir()
+            .join("savestate_test")
+            .join("config.toml");
+        let path = SaveState::slot_path(&config, 0xcfb240a1, 3);
+        assert!(path.ends_with("savestate_test/states/cfb240a1-3.state"));
+        assert!(SaveState::resume_path(&config, 0xcfb240a1)
+            .ends_with("savestate_test/states/cfb240a1-resume.state"));
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+
+        let mut cpu = machine(0x4000);
+        run(&mut cpu, 2);
+        let before = run(&mut cpu, 0);
+        cpu.save_state().save(&path).unwrap();
+        run(&mut cpu, 2);
+        cpu.load_state(&SaveState::load(&path).unwrap()).unwrap();
+        assert_eq!(run(&mut cpu, 0), before);
+    }
+}
@@ -0,0 +1,287 @@
+use std::{
+    cell::{Cell, RefCell},
+    error::Error,
+    fmt, fs, io,
+    rc::Rc,
+};
+
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Map, ParseError, Scope, AST};
+
+use crate::{
+    controller::Buttons,
+    cpu::CPU,
+    debug::{Access, Hooks},
+};
+
+#[derive(Debug)]
+pub enum ScriptError {
+    Io(io::Error),
+    Parse(ParseError),
+    Run(Box<EvalAltResult>),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::Io(e) => write!(f, "Unable to read script: {}", e),
+            ScriptError::Parse(e) => write!(f, "Unable to parse script: {}", e),
+            ScriptError::Run(e) => write!(f, "Script failed: {}", e),
+        }
+    }
+}
+
+impl Error for ScriptError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ScriptError::Io(e) => Some(e),
+            ScriptError::Parse(e) => Some(e),
+            ScriptError::Run(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for ScriptError {
+    fn from(e: io::Error) -> Self {
+        ScriptError::Io(e)
+    }
+}
+
+// the cpu the script's functions work on, only set while a hook runs
+type CpuCell = Rc<Cell<*mut CPU>>;
+
+fn with_cpu<T>(cpu: &CpuCell, f: impl FnOnce(&mut CPU) -> T) -> T {
+    let ptr = cpu.get();
+    assert!(
+        !ptr.is_null(),
+        "Script called into the emulator outside a hook"
+    );
+    // SAFETY: the pointer comes from the &mut CPU a hook was handed and is
+    // nulled again before the hook returns, nothing else can touch the cpu
+    // in between. Everything is on the one thread.
+    f(unsafe { &mut *ptr })
+}
+
+fn button(name: &str) -> Option<Buttons> {
+    let button = match name.to_ascii_lowercase().as_str() {
+        "a" => Buttons::A,
+        "b" => Buttons::B,
+        "select" => Buttons::SELECT,
+        "start" => Buttons::START,
+        "up" => Buttons::UP,
+        "down" => Buttons::DOWN,
+        "left" => Buttons::LEFT,
+        "right" => Buttons::RIGHT,
+        _ => return None,
+    };
+    Some(button)
+}
+
+/**
+ * A rhai script hooked into the CPU. It's run once when loaded, then any of
+ * these it defines get called:
+ *
+ * fn init()                  once, before the first frame
+ * fn on_frame()              after every frame
+ * fn on_instruction(pc)      before every instruction
+ * fn on_read(addr, value)    after an instruction, for each read it made
+ * fn on_write(addr, value)   likewise for writes
+ *
+ * `this` in all of them is a map that's kept for the whole run. They can call
+ * read(addr), write(addr, value), text(x, y, message), press(player, button)
+ * and release(player, button), buttons are named "A", "Start", "Up"...
+ */
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    state: Dynamic,
+    cpu: CpuCell,
+    // drawn by the hooks during the frame, shown once it's done
+    texts: Rc<RefCell<Vec<(usize, usize, String)>>>,
+    shown: Vec<(usize, usize, String)>,
+    // hooks the script defines, dropped once one fails
+    hooks: Vec<&'static str>,
+    needs_init: bool,
+}
+
+impl Script {
+    pub fn load(path: &str) -> Result<Script, ScriptError> {
+        Script::new(&fs::read_to_string(path)?)
+    }
+
+    pub fn new(source: &str) -> Result<Script, ScriptError> {
+        let cpu: CpuCell = Rc::new(Cell::new(std::ptr::null_mut()));
+        let texts = Rc::new(RefCell::new(Vec::new()));
+        let mut engine = Engine::new();
+        let c = cpu.clone();
+        engine.register_fn("read", move |addr: i64| {
+            with_cpu(&c, |cpu| cpu.peek(addr as u16) as i64)
+        });
+        let c = cpu.clone();
+        engine.register_fn("write", move |addr: i64, value: i64| {
+            with_cpu(&c, |cpu| cpu.poke(addr as u16, value as u8))
+        });
+        let t = texts.clone();
+        engine.register_fn("text", move |x: i64, y: i64, message: &str| {
+            t.borrow_mut()
+                .push((x.max(0) as usize, y.max(0) as usize, message.to_string()))
+        });
+        for (name, pressed) in [("press", true), ("release", false)] {
+            let c = cpu.clone();
+            engine.register_fn(
+                name,
+                move |player: i64, name: &str| -> Result<(), Box<EvalAltResult>> {
+                    let button = button(name).ok_or(format!("No button called {}", name))?;
+                    if !(0..4).contains(&player) {
+                        return Err(format!("No player {}", player).into());
+                    }
+                    with_cpu(&c, |cpu| {
+                        cpu.controller(player as usize).set_button(button, pressed)
+                    });
+                    Ok(())
+                },
+            );
+        }
+
+        let ast = engine.compile(source).map_err(ScriptError::Parse)?;
+        let mut scope = Scope::new();
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(ScriptError::Run)?;
+        let defined = |name: &str, params: usize| {
+            ast.iter_functions()
+                .any(|f| f.name == name && f.params.len() == params)
+        };
+        let hooks = [
+            ("on_frame", 0),
+            ("on_instruction", 1),
+            ("on_read", 2),
+            ("on_write", 2),
+        ]
+        .into_iter()
+        .filter(|&(name, params)| defined(name, params))
+        .map(|(name, _)| name)
+        .collect();
+        let needs_init = defined("init", 0);
+        Ok(Script {
+            engine,
+            ast,
+            scope,
+            state: Dynamic::from_map(Map::new()),
+            cpu,
+            texts,
+            shown: Vec::new(),
+            hooks,
+            needs_init,
+        })
+    }
+
+    fn has(&self, hook: &str) -> bool {
+        self.hooks.contains(&hook)
+    }
+
+    /**
+     * Call `name` with the cpu lent to it. A hook that fails is reported and
+     * not called again, the game carries on.
+     */
+    fn call(&mut self, cpu: &mut CPU, name: &'static str, args: impl FuncArgs) {
+        self.cpu.set(cpu);
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.state);
+        let result = self.engine.call_fn_with_options::<Dynamic>(
+            options,
+            &mut self.scope,
+            &self.ast,
+            name,
+            args,
+        );
+        self.cpu.set(std::ptr::null_mut());
+        if let Err(e) = result {
+            eprintln!(
+                "{} in {}, it won't be called again",
+                ScriptError::Run(e),
+                name
+            );
+            self.hooks.retain(|hook| *hook != name);
+            self.needs_init = false
+        }
+    }
+}
+
+impl Hooks for Script {
+    fn instruction(&mut self, cpu: &mut CPU) {
+        if self.needs_init {
+            self.needs_init = false;
+            self.call(cpu, "init", ());
+        }
+        if self.has("on_instruction") {
+            let pc = cpu.registers().pc as i64;
+            self.call(cpu, "on_instruction", (pc,))
+        }
+    }
+
+    fn accesses(&mut self, cpu: &mut CPU, accesses: &[Access]) {
+        for access in accesses {
+            let hook = if access.write { "on_write" } else { "on_read" };
+            if self.has(hook) {
+                self.call(cpu, hook, (access.addr as i64, access.value as i64))
+            }
+        }
+    }
+
+    fn wants_accesses(&self) -> bool {
+        self.has("on_read") || self.has("on_write")
+    }
+
+    fn frame(&mut self, cpu: &mut CPU) {
+        if self.has("on_frame") {
+            self.call(cpu, "on_frame", ())
+        }
+        self.shown = std::mem::take(&mut *self.texts.borrow_mut());
+    }
+
+    fn overlay(&self) -> Vec<(usize, usize, String)> {
+        self.shown.clone()
+    }
+}
+
+#[cfg(test)]
+mod script_test {
+    use crate::{bus::Bus, controller::Buttons, cpu::CPU, debug::Hooks, ppu::PPU};
+
+    use super::Script;
+
+    #[test]
+    fn test_hooks() {
+        let mut script = Script::new(
+            r#"
+            fn init() { this.frames = 0; }
+            fn on_frame() {
+                this.frames += 1;
+                write(0x10, read(0x10) + 1);
+                text(8, 16, "Frame " + this.frames);
+                press(0, "Start");
+            }
+            "#,
+        )
+        .unwrap();
+        let mut cpu = CPU::new(Bus::new(PPU::new()));
+        script.instruction(&mut cpu);
+        script.frame(&mut cpu);
+        script.frame(&mut cpu);
+        assert_eq!(cpu.peek(0x10), 2);
+        assert_eq!(script.overlay(), [(8, 16, "Frame 2".to_string())]);
+        assert_eq!(cpu.controller(0).buttons(), Buttons::START);
+        assert!(!script.wants_accesses());
+    }
+
+    #[test]
+    fn test_failing_hook() {
+        let mut script = Script::new(r#"fn on_frame() { press(0, "Turbo"); }"#).unwrap();
+        let mut cpu = CPU::new(Bus::new(PPU::new()));
+        script.frame(&mut cpu);
+        assert!(!script.has("on_frame"));
+        assert!(Script::new("fn broken( {").is_err());
+    }
+}
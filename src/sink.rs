@@ -0,0 +1,31 @@
+use crate::ppu::Frame;
+
+/**
+ * Where the pictures go. `Nes::run_frame` hands over each one as the PPU
+ * finishes it, the frontend copies it out (to a texture, a canvas, a file)
+ * before the next.
+ */
+pub trait VideoSink {
+    fn frame(&mut self, frame: &Frame);
+}
+
+/**
+ * Where the sound goes, mono at `machine::SAMPLE_RATE`. Called once a frame
+ * with that frame's samples, a little over 733 of them.
+ */
+pub trait AudioSink {
+    fn samples(&mut self, samples: &[i16]);
+}
+
+// so a closure does for a quick sink
+impl<F: FnMut(&Frame)> VideoSink for F {
+    fn frame(&mut self, frame: &Frame) {
+        self(frame)
+    }
+}
+
+impl<F: FnMut(&[i16])> AudioSink for F {
+    fn samples(&mut self, samples: &[i16]) {
+        self(samples)
+    }
+}
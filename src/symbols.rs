@@ -0,0 +1,246 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+// FCEUX numbers its .nl files by 16KB bank
+pub const BANK_SIZE: usize = 0x4000;
+
+#[derive(Debug)]
+pub enum SymbolError {
+    Io(PathBuf, io::Error),
+    // file, line number
+    Parse(PathBuf, usize),
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SymbolError::Io(path, e) => write!(f, "Unable to read {}: {}", path.display(), e),
+            SymbolError::Parse(path, line) => {
+                write!(f, "Bad symbol on line {} of {}", line, path.display())
+            }
+        }
+    }
+}
+
+impl Error for SymbolError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SymbolError::Io(_, e) => Some(e),
+            SymbolError::Parse(..) => None,
+        }
+    }
+}
+
+fn hex(text: &str) -> Option<u32> {
+    let text = text.trim();
+    let digits = text
+        .strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .unwrap_or(text);
+    u32::from_str_radix(digits, 16).ok()
+}
+
+/**
+ * Names for addresses, from FCEUX .nl files or ca65/ld65 output. ROM labels
+ * can belong to a 16KB PRG bank, since the same address means different
+ * things depending on what's mapped in.
+ */
+#[derive(Default, Debug)]
+pub struct Symbols {
+    global: HashMap<u16, String>,
+    banked: HashMap<(usize, u16), String>,
+    by_name: HashMap<String, u16>,
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    /**
+     * Add a symbol file, its format going by the name: "game.nes.ram.nl"
+     * and "game.nes.2.nl" are FCEUX's, .dbg is a ca65 debug file and
+     * anything else an ld65 label file (-Ln).
+     */
+    pub fn load(&mut self, path: &Path) -> Result<(), SymbolError> {
+        let text = fs::read_to_string(path).map_err(|e| SymbolError::Io(path.into(), e))?;
+        let name = path.to_string_lossy().to_ascii_lowercase();
+        let parsed = if let Some(stem) = name.strip_suffix(".nl") {
+            // the bank is in hex, RAM and anything unnumbered are global
+            let bank = stem
+                .rsplit('.')
+                .next()
+                .and_then(|bank| usize::from_str_radix(bank, 16).ok());
+            self.parse_nl(&text, bank)
+        } else if name.ends_with(".dbg") {
+            self.parse_ca65_dbg(&text)
+        } else {
+            self.parse_labels(&text)
+        };
+        parsed.map_err(|line| SymbolError::Parse(path.into(), line))
+    }
+
+    /**
+     * FCEUX keeps a game's names next to it, "game.nes.ram.nl" for RAM and
+     * "game.nes.0.nl", "game.nes.1.nl"... per bank. Loads whichever there
+     * are, returning how many.
+     */
+    pub fn load_fceux(&mut self, rom: &Path, banks: usize) -> Result<usize, SymbolError> {
+        let names =
+            std::iter::once("ram".to_string()).chain((0..banks).map(|b| format!("{:X}", b)));
+        let mut loaded = 0;
+        for name in names {
+            let mut path = rom.as_os_str().to_owned();
+            path.push(format!(".{}.nl", name));
+            let path = PathBuf::from(path);
+            if path.exists() {
+                self.load(&path)?;
+                loaded += 1
+            }
+        }
+        Ok(loaded)
+    }
+
+    /**
+     * "$C000#Reset#comment", one per line. "$0300/10#buffer#" names 16
+     * bytes, only the first gets the name.
+     */
+    fn parse_nl(&mut self, text: &str, bank: Option<usize>) -> Result<(), usize> {
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.splitn(3, '#');
+            let addr = fields.next().unwrap_or("");
+            let addr = addr.split('/').next().unwrap_or(addr);
+            let (Some(addr), Some(name)) = (hex(addr), fields.next()) else {
+                return Err(idx + 1);
+            };
+            // comments on their own are fine, they just don't name anything
+            if !name.is_empty() {
+                self.add(addr as u16, bank, name)
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * ld65's -Ln output, VICE style: "al 00C000 .Reset".
+     */
+    fn parse_labels(&mut self, text: &str) -> Result<(), usize> {
+        for (idx, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                [] => (),
+                ["al", addr, name] => match hex(addr) {
+                    Some(addr) => self.add(addr as u16, None, name.trim_start_matches('.')),
+                    None => return Err(idx + 1),
+                },
+                _ => return Err(idx + 1),
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * The labels out of a ca65 --dbgfile, lines like
+     * `sym id=3,name="Reset",addrsize=absolute,...,val=0xC000,...,type=lab`.
+     * Equates are left out, most are constants rather than addresses.
+     */
+    fn parse_ca65_dbg(&mut self, text: &str) -> Result<(), usize> {
+        for (idx, line) in text.lines().enumerate() {
+            let Some(fields) = line.strip_prefix("sym\t") else {
+                continue;
+            };
+            let field = |key: &str| {
+                fields
+                    .split(',')
+                    .find_map(|field| field.strip_prefix(key)?.strip_prefix('='))
+            };
+            if field("type") != Some("lab") {
+                continue;
+            }
+            let name = field("name").map(|name| name.trim_matches('"'));
+            match (name, field("val").and_then(hex)) {
+                (Some(name), Some(addr)) => self.add(addr as u16, None, name),
+                _ => return Err(idx + 1),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn add(&mut self, addr: u16, bank: Option<usize>, name: &str) {
+        match bank {
+            Some(bank) => self.banked.insert((bank, addr), name.to_string()),
+            None => self.global.insert(addr, name.to_string()),
+        };
+        self.by_name.insert(name.to_string(), addr);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    /**
+     * The name for `addr` with `bank` mapped there, if it's known. A bank's
+     * own names win over global ones.
+     */
+    pub fn label(&self, addr: u16, bank: Option<usize>) -> Option<&str> {
+        bank.and_then(|bank| self.banked.get(&(bank, addr)))
+            .or_else(|| self.global.get(&addr))
+            .map(String::as_str)
+    }
+
+    /**
+     * The other way around, for breakpoints and the like.
+     */
+    pub fn addr(&self, name: &str) -> Option<u16> {
+        self.by_name.get(name).copied()
+    }
+}
+
+#[cfg(test)]
+mod symbols_test {
+    use super::Symbols;
+
+    #[test]
+    fn test_nl() {
+        let mut symbols = Symbols::new();
+        symbols
+            .parse_nl(
+                "$0300/10#buffer#\n$C000#Reset#power on\n$C010##a note\n",
+                Some(1),
+            )
+            .unwrap();
+        symbols.parse_nl("$0010#PlayerX#\n", None).unwrap();
+        assert_eq!(symbols.label(0xc000, Some(1)), Some("Reset"));
+        // another bank is mapped in
+        assert_eq!(symbols.label(0xc000, Some(2)), None);
+        assert_eq!(symbols.label(0x0010, Some(2)), Some("PlayerX"));
+        assert_eq!(symbols.label(0xc010, Some(1)), None);
+        assert_eq!(symbols.addr("buffer"), Some(0x0300));
+        assert_eq!(symbols.parse_nl("C000", None), Err(1));
+    }
+
+    #[test]
+    fn test_ca65() {
+        let mut symbols = Symbols::new();
+        symbols
+            .parse_labels("al 00C000 .Reset\nal 000010 .player_x\n\n")
+            .unwrap();
+        assert_eq!(symbols.label(0xc000, None), Some("Reset"));
+        assert_eq!(symbols.addr("player_x"), Some(0x10));
+
+        let dbg = "version\tmajor=2,minor=0\n\
+            sym\tid=0,name=\"nmi\",addrsize=absolute,scope=0,def=5,val=0xC123,seg=1,type=lab\n\
+            sym\tid=1,name=\"SPEED\",addrsize=zeropage,scope=0,def=6,val=0x3,type=equ\n";
+        symbols.parse_ca65_dbg(dbg).unwrap();
+        assert_eq!(symbols.label(0xc123, Some(0)), Some("nmi"));
+        assert_eq!(symbols.label(0x0003, None), None);
+    }
+}
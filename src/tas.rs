@@ -0,0 +1,303 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    controller::Buttons,
+    cpu::CPU,
+    movie::{FrameInput, MovieMode, MovieSession},
+    savestate::{SaveState, StateError},
+};
+
+// frames between anchors, going anywhere replays at most this many
+pub const ANCHOR_INTERVAL: usize = 30;
+
+/**
+ * A change to a movie from the TAS editor, see `TasEditor::apply`.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MovieEdit {
+    // press or let go of `button` on `port` for `frame`
+    Toggle {
+        frame: usize,
+        port: usize,
+        button: Buttons,
+    },
+    // a frame with nothing pressed in front of `frame`
+    Insert(usize),
+    Remove(usize),
+    // put the machine back to right before `frame`, the input stays
+    Seek(usize),
+    // the same, then drop the input from there and record over it
+    Rerecord(usize),
+}
+
+/**
+ * Frame by frame editing of a movie. Every `ANCHOR_INTERVAL` frames as the
+ * movie runs a save state is kept, so getting back to any frame is loading
+ * the closest one before it and running the movie's input up to it. Which
+ * frames the game didn't read the controllers on (lag frames, where input
+ * makes no difference) is noted along the way.
+ *
+ * Anchors and lag are only good for the input they were made with, an edit
+ * drops the ones after it and the frames are run again.
+ */
+pub struct TasEditor {
+    // the state right before the frame runs
+    anchors: BTreeMap<usize, Vec<u8>>,
+    // by frame, as far as it's been run since the last edit
+    lag: Vec<bool>,
+}
+
+impl TasEditor {
+    /**
+     * `cpu` is where the movie starts from.
+     */
+    pub fn new(cpu: &CPU) -> TasEditor {
+        TasEditor {
+            anchors: BTreeMap::from([(0, cpu.save_state().to_bytes())]),
+            lag: Vec::new(),
+        }
+    }
+
+    /**
+     * Run a frame of the movie: `MovieSession::advance` then the frame.
+     * Returns true on the frame playback runs out, like `advance`.
+     */
+    pub fn run_frame(&mut self, cpu: &mut CPU, session: &mut MovieSession) -> bool {
+        let frame = session.frame();
+        let running = session.mode() != MovieMode::Finished;
+        if running && frame.is_multiple_of(ANCHOR_INTERVAL) {
+            self.anchors
+                .entry(frame)
+                .or_insert_with(|| cpu.save_state().to_bytes());
+        }
+        let finished = session.advance(cpu);
+        cpu.run_frame();
+        if session.frame() > frame {
+            if self.lag.len() <= frame {
+                self.lag.resize(frame + 1, false)
+            }
+            self.lag[frame] = cpu.lag_frame()
+        }
+        finished
+    }
+
+    /**
+     * None for frames not run since they (or ones before them) changed.
+     */
+    pub fn lag(&self, frame: usize) -> Option<bool> {
+        self.lag.get(frame).copied()
+    }
+
+    pub fn lag_frames(&self) -> usize {
+        self.lag.iter().filter(|&&lag| lag).count()
+    }
+
+    /**
+     * The input from `frame` on changed.
+     */
+    pub fn invalidate(&mut self, frame: usize) {
+        self.anchors.split_off(&(frame + 1));
+        self.lag.truncate(frame)
+    }
+
+    /**
+     * Put `cpu` back to right before `frame` of the movie. Past the end is
+     * the end, where recording carries on.
+     */
+    pub fn seek(
+        &mut self,
+        cpu: &mut CPU,
+        session: &mut MovieSession,
+        frame: usize,
+    ) -> Result<(), StateError> {
+        let frame = frame.min(session.movie.frames.len());
+        let (&start, state) = self
+            .anchors
+            .range(..=frame)
+            .next_back()
+            .expect("there's always the one for frame 0");
+        cpu.load_state(&SaveState::from_bytes(state)?)?;
+        session.seek(start);
+        while session.frame() < frame {
+            self.run_frame(cpu, session);
+        }
+        session.seek(frame);
+        Ok(())
+    }
+
+    /**
+     * Edits before the frame the machine is at take it back there, so what
+     * they changed shows.
+     */
+    pub fn apply(
+        &mut self,
+        cpu: &mut CPU,
+        session: &mut MovieSession,
+        edit: MovieEdit,
+    ) -> Result<(), StateError> {
+        let frames = &mut session.movie.frames;
+        let frame = match edit {
+            MovieEdit::Toggle {
+                frame,
+                port,
+                button,
+            } => match frames.get_mut(frame) {
+                Some(input) => {
+                    input.buttons[port].toggle(button);
+                    frame
+                }
+                None => return Ok(()),
+            },
+            MovieEdit::Insert(frame) if frame <= frames.len() => {
+                frames.insert(frame, FrameInput::default());
+                frame
+            }
+            MovieEdit::Remove(frame) if frame < frames.len() => {
+                frames.remove(frame);
+                frame
+            }
+            MovieEdit::Insert(_) | MovieEdit::Remove(_) => return Ok(()),
+            MovieEdit::Seek(frame) => return self.seek(cpu, session, frame),
+            MovieEdit::Rerecord(frame) => {
+                self.seek(cpu, session, frame)?;
+                session.take_over(cpu);
+                self.invalidate(session.frame());
+                return Ok(());
+            }
+        };
+        self.invalidate(frame);
+        session.movie.checksums.split_off(&(frame + 1));
+        if frame < session.frame() {
+            self.seek(cpu, session, frame)?
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tas_test {
+    use crate::{
+        controller::Buttons,
+        cpu::{cpu_with_prg, CPU},
+        movie::{FrameInput, Movie, MovieMode, MovieSession},
+    };
+
+    use super::{MovieEdit, TasEditor};
+
+    // adds up A on every other frame, the ones in between are lag frames
+    fn machine() -> CPU {
+        let mut prgrom = vec![0; 0x4000];
+        let program = [
+            0xa9, 0x80, 0x8d, 0x00, 0x20, // $8000: LDA #$80, STA $2000 NMI on
+            0x4c, 0x05, 0x80, // $8005: JMP $8005
+            0xe6, 0x12, 0xa5, 0x12, // $8008 NMI: INC $12, LDA $12
+            0x29, 0x01, 0xf0, 0x13, // AND #$01, BEQ $8023
+            0xa9, 0x01, 0x8d, 0x16, 0x40, // LDA #$01, STA $4016
+            0xa9, 0x00, 0x8d, 0x16, 0x40, // LDA #$00, STA $4016
+            0xad, 0x16, 0x40, 0x29, 0x01, // LDA $4016, AND #$01
+            0x65, 0x10, 0x85, 0x10, // ADC $10, STA $10
+            0x40, // $8023: RTI
+        ];
+        prgrom[..program.len()].copy_from_slice(&program);
+        prgrom[0x3ffa..].copy_from_slice(&[0x08, 0x80, 0x00, 0x80, 0x00, 0x80]);
+        cpu_with_prg(prgrom)
+    }
+
+    fn held(buttons: Buttons) -> FrameInput {
+        FrameInput {
+            buttons: [
+                buttons,
+                Buttons::empty(),
+                Buttons::empty(),
+                Buttons::empty(),
+            ],
+            soft_reset: false,
+            power_cycle: false,
+        }
+    }
+
+    fn play(movie: &Movie) -> u8 {
+        let mut cpu = machine();
+        let mut session = MovieSession::play(movie.clone());
+        while !session.advance(&mut cpu) {
+            cpu.run_frame()
+        }
+        cpu.peek(0x10)
+    }
+
+    fn recorded(frames: usize) -> (CPU, MovieSession, TasEditor) {
+        let mut cpu = machine();
+        let mut tas = TasEditor::new(&cpu);
+        let mut session = MovieSession::record(Movie::new(0));
+        for frame in 0..frames {
+            cpu.set_controller_state(
+                0,
+                if frame % 3 == 0 {
+                    Buttons::A
+                } else {
+                    Buttons::empty()
+                },
+            );
+            tas.run_frame(&mut cpu, &mut session);
+        }
+        (cpu, session, tas)
+    }
+
+    #[test]
+    fn test_lag() {
+        let (_, _, tas) = recorded(10);
+        let lag: Vec<bool> = (1..10).map(|frame| tas.lag(frame).unwrap()).collect();
+        assert!(lag.windows(2).all(|pair| pair[0] != pair[1]));
+        assert!(tas.lag_frames() >= 4);
+        assert_eq!(tas.lag(10), None);
+    }
+
+    #[test]
+    fn test_seek() {
+        let (mut cpu, mut session, mut tas) = recorded(100);
+        let sum = cpu.peek(0x10);
+        assert_eq!(sum, play(&session.movie));
+        tas.apply(&mut cpu, &mut session, MovieEdit::Seek(45))
+            .unwrap();
+        assert_eq!(session.frame(), 45);
+        assert_eq!(session.mode(), MovieMode::Playing);
+        while !tas.run_frame(&mut cpu, &mut session) {}
+        assert_eq!(cpu.peek(0x10), sum);
+    }
+
+    #[test]
+    fn test_edit() {
+        let (mut cpu, mut session, mut tas) = recorded(100);
+        // A on every frame from 40 to 60, some of them lag frames
+        for frame in 40..60 {
+            if !session.movie.frames[frame].buttons[0].contains(Buttons::A) {
+                let toggle = MovieEdit::Toggle {
+                    frame,
+                    port: 0,
+                    button: Buttons::A,
+                };
+                tas.apply(&mut cpu, &mut session, toggle).unwrap();
+            }
+        }
+        assert_eq!(session.frame(), 40);
+        assert_eq!(tas.lag(40), None);
+        tas.apply(&mut cpu, &mut session, MovieEdit::Insert(20))
+            .unwrap();
+        tas.apply(&mut cpu, &mut session, MovieEdit::Remove(90))
+            .unwrap();
+        assert_eq!(session.movie.frames.len(), 100);
+        while !tas.run_frame(&mut cpu, &mut session) {}
+        assert_eq!(cpu.peek(0x10), play(&session.movie));
+
+        tas.apply(&mut cpu, &mut session, MovieEdit::Rerecord(70))
+            .unwrap();
+        assert_eq!(session.movie.frames.len(), 70);
+        assert_eq!(session.mode(), MovieMode::Recording);
+        cpu.set_controller_state(0, Buttons::A);
+        for _ in 0..10 {
+            tas.run_frame(&mut cpu, &mut session);
+        }
+        assert_eq!(session.movie.frames[75], held(Buttons::A));
+        assert_eq!(cpu.peek(0x10), play(&session.movie));
+    }
+}
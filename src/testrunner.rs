@@ -0,0 +1,472 @@
+use std::{
+    error::Error,
+    fmt, fs, io,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bus::Bus,
+    cartridge::Cartridge,
+    cpu::CPU,
+    crashdump::panic_message,
+    headless::{self, frame_hash, test_message, TestResult},
+    ppu::PPU,
+};
+
+#[derive(Debug)]
+pub enum TestError {
+    Io(PathBuf, io::Error),
+    Parse(toml::de::Error),
+    Serialize(toml::ser::Error),
+    BadHash(String),
+    NoRoms(String),
+    // a rom that wouldn't load or run while blessing
+    Rom(PathBuf, String),
+}
+
+impl fmt::Display for TestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TestError::Io(path, e) => write!(f, "Unable to read {}: {}", path.display(), e),
+            TestError::Parse(e) => write!(f, "Unable to parse test manifest: {}", e),
+            TestError::Serialize(e) => write!(f, "Unable to write test manifest: {}", e),
+            TestError::BadHash(hash) => write!(f, "Bad frame hash \"{}\" in manifest", hash),
+            TestError::NoRoms(path) => write!(f, "No test roms in {}", path),
+            TestError::Rom(path, e) => write!(f, "{}: {}", path.display(), e),
+        }
+    }
+}
+
+impl Error for TestError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TestError::Io(_, e) => Some(e),
+            TestError::Parse(e) => Some(e),
+            TestError::Serialize(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * What a rom has to do to pass.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Expect {
+    // report 0 through blargg's $6000 protocol before running out of frames
+    Blargg,
+    // show this picture after exactly the given number of frames
+    FrameHash(u32),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct TestCase {
+    pub path: PathBuf,
+    // how long it gets, the timeout for blargg roms
+    pub frames: u64,
+    pub expect: Expect,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum Verdict {
+    Passed,
+    // the result code and the rom's explanation
+    Failed(u8, String),
+    WrongFrame(u32),
+    TimedOut,
+    // didn't load, or panicked
+    Error(String),
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Passed => write!(f, "passed"),
+            Verdict::Failed(code, message) if message.is_empty() => {
+                write!(f, "failed ({})", code)
+            }
+            Verdict::Failed(code, message) => write!(f, "failed ({}): {}", code, message),
+            Verdict::WrongFrame(hash) => write!(f, "frame hash {:08x}", hash),
+            Verdict::TimedOut => write!(f, "timed out"),
+            Verdict::Error(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/**
+ * tests.toml, a list of roms with their own criteria:
+ *
+ * [[rom]]
+ * path = "cpu/01-implied.nes"
+ * frames = 1200
+ * hash = "8d2c1e0a"
+ */
+#[derive(Deserialize, Serialize)]
+struct Manifest {
+    #[serde(default)]
+    rom: Vec<ManifestEntry>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ManifestEntry {
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frames: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+}
+
+/**
+ * Paths in the manifest are relative to the directory it's in.
+ */
+pub fn parse_manifest(text: &str, dir: &Path, frames: u64) -> Result<Vec<TestCase>, TestError> {
+    let manifest: Manifest = toml::from_str(text).map_err(TestError::Parse)?;
+    manifest
+        .rom
+        .into_iter()
+        .map(|entry| {
+            let expect = match entry.hash {
+                Some(hash) => Expect::FrameHash(
+                    u32::from_str_radix(&hash, 16).map_err(|_| TestError::BadHash(hash))?,
+                ),
+                None => Expect::Blargg,
+            };
+            Ok(TestCase {
+                path: dir.join(entry.path),
+                frames: entry.frames.unwrap_or(frames),
+                expect,
+            })
+        })
+        .collect()
+}
+
+fn is_rom(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("nes"))
+}
+
+fn roms_in(dir: &Path, roms: &mut Vec<PathBuf>) -> Result<(), TestError> {
+    let entries = fs::read_dir(dir).map_err(|e| TestError::Io(dir.to_path_buf(), e))?;
+    for entry in entries {
+        let path = entry
+            .map_err(|e| TestError::Io(dir.to_path_buf(), e))?
+            .path();
+        if path.is_dir() {
+            roms_in(&path, roms)?
+        } else if is_rom(&path) {
+            roms.push(path)
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Every argument is a rom, a directory searched for .nes files, or a .toml
+ * manifest. Roms found without a manifest are blargg style.
+ */
+pub fn collect(paths: &[String], frames: u64) -> Result<Vec<TestCase>, TestError> {
+    let mut cases = Vec::new();
+    for arg in paths {
+        let path = Path::new(arg);
+        if path.is_dir() {
+            let mut roms = Vec::new();
+            roms_in(path, &mut roms)?;
+            if roms.is_empty() {
+                return Err(TestError::NoRoms(arg.clone()));
+            }
+            roms.sort();
+            cases.extend(roms.into_iter().map(|path| TestCase {
+                path,
+                frames,
+                expect: Expect::Blargg,
+            }))
+        } else if path.extension().is_some_and(|ext| ext == "toml") {
+            let text =
+                fs::read_to_string(path).map_err(|e| TestError::Io(path.to_path_buf(), e))?;
+            let dir = path.parent().unwrap_or(Path::new(""));
+            cases.extend(parse_manifest(&text, dir, frames)?)
+        } else {
+            cases.push(TestCase {
+                path: path.to_path_buf(),
+                frames,
+                expect: Expect::Blargg,
+            })
+        }
+    }
+    Ok(cases)
+}
+
+/**
+ * Returns the verdict and how many frames it took.
+ */
+pub fn run_cpu(cpu: &mut CPU, frames: u64, expect: Expect) -> (Verdict, u64) {
+    // blargg's roms are judged on what's in RAM, the picture doesn't matter
+    cpu.ppu_mut().set_skip_rendering(true);
+    match expect {
+        Expect::Blargg => match headless::run_test(cpu, frames) {
+            (TestResult::Passed, ran) => (Verdict::Passed, ran),
+            (TestResult::Failed(code), ran) => {
+                // the roms print on several lines, a table row is one
+                let message = test_message(cpu);
+                let words: Vec<&str> = message.split_whitespace().collect();
+                (Verdict::Failed(code, words.join(" ")), ran)
+            }
+            (TestResult::TimedOut, ran) => (Verdict::TimedOut, ran),
+        },
+        Expect::FrameHash(expected) => {
+            let hash = last_frame_hash(cpu, frames);
+            if hash == expected {
+                (Verdict::Passed, frames)
+            } else {
+                (Verdict::WrongFrame(hash), frames)
+            }
+        }
+    }
+}
+
+/**
+ * Run `frames` frames, drawing only the last one, and hash it.
+ */
+fn last_frame_hash(cpu: &mut CPU, frames: u64) -> u32 {
+    cpu.ppu_mut().set_skip_rendering(true);
+    headless::run(cpu, Some(frames.saturating_sub(1)), None);
+    cpu.ppu_mut().set_skip_rendering(false);
+    headless::run(cpu, Some(frames.min(1)), None);
+    frame_hash(cpu.frame())
+}
+
+fn load_rom(path: &Path) -> Result<CPU, String> {
+    let cartridge = Cartridge::load(&path.to_string_lossy()).map_err(|e| e.to_string())?;
+    let mut cpu = CPU::new(Bus::new(PPU::new()));
+    cpu.load_cartridge(cartridge).map_err(|e| e.to_string())?;
+    Ok(cpu)
+}
+
+/**
+ * A rom hitting an opcode we don't have panics, that's a failure for the
+ * rom and not the end of the run.
+ */
+fn catch_crash<T>(run: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(run))
+        .map_err(|panic| format!("crashed: {}", panic_message(panic.as_ref())))
+}
+
+pub fn run_case(case: &TestCase) -> (Verdict, u64) {
+    let mut cpu = match load_rom(&case.path) {
+        Ok(cpu) => cpu,
+        Err(e) => return (Verdict::Error(e), 0),
+    };
+    catch_crash(|| run_cpu(&mut cpu, case.frames, case.expect))
+        .unwrap_or_else(|e| (Verdict::Error(e), 0))
+}
+
+/**
+ * Rerun every rom in a manifest that has a `hash` and write down the hash
+ * of the frame it ends on, for new golden frames (`hash = ""`) or after a
+ * change that's meant to change the picture. Returns how many it updated.
+ * The manifest is written back out as toml, comments and all don't survive.
+ */
+pub fn bless(path: &Path, frames: u64) -> Result<usize, TestError> {
+    let text = fs::read_to_string(path).map_err(|e| TestError::Io(path.to_path_buf(), e))?;
+    let mut manifest: Manifest = toml::from_str(&text).map_err(TestError::Parse)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut blessed = 0;
+    for entry in manifest.rom.iter_mut().filter(|entry| entry.hash.is_some()) {
+        let rom = dir.join(&entry.path);
+        let mut cpu = load_rom(&rom).map_err(|e| TestError::Rom(rom.clone(), e))?;
+        let hash = catch_crash(|| last_frame_hash(&mut cpu, entry.frames.unwrap_or(frames)))
+            .map_err(|e| TestError::Rom(rom, e))?;
+        entry.hash = Some(format!("{:08x}", hash));
+        blessed += 1
+    }
+    let text = toml::to_string(&manifest).map_err(TestError::Serialize)?;
+    fs::write(path, text).map_err(|e| TestError::Io(path.to_path_buf(), e))?;
+    Ok(blessed)
+}
+
+/**
+ * One row per rom and a count at the bottom.
+ */
+pub fn summary(results: &[(TestCase, Verdict, u64)]) -> String {
+    let names: Vec<String> = results
+        .iter()
+        .map(|(case, _, _)| case.path.display().to_string())
+        .collect();
+    let width = names
+        .iter()
+        .map(|name| name.len())
+        .max()
+        .unwrap_or(0)
+        .max(3);
+    let mut table = format!("{:<width$}  {:>6}  Result\n", "ROM", "Frames");
+    for (name, (_, verdict, frames)) in names.iter().zip(results) {
+        table += &format!("{:<width$}  {:>6}  {}\n", name, frames, verdict);
+    }
+    let passed = results
+        .iter()
+        .filter(|(_, verdict, _)| *verdict == Verdict::Passed)
+        .count();
+    table += &format!("{} of {} passed", passed, results.len());
+    table
+}
+
+#[cfg(test)]
+mod testrunner_test {
+    use std::path::{Path, PathBuf};
+
+    use std::fs;
+
+    use super::{
+        bless, collect, parse_manifest, run_case, summary, Expect, TestCase, TestError, Verdict,
+    };
+
+    // blargg's suites as they unpack, under test_roms/ like nestest
+    const SUITES: [&str; 6] = [
+        "./test_roms/instr_test-v5/rom_singles",
+        "./test_roms/instr_misc/rom_singles",
+        "./test_roms/cpu_timing_test6",
+        "./test_roms/ppu_vbl_nmi/rom_singles",
+        "./test_roms/sprite_hit_tests_2005.10.05",
+        "./test_roms/mmc3_test_2/rom_singles",
+    ];
+
+    #[test]
+    fn test_parse_manifest() {
+        let cases = parse_manifest(
+            r#"
+            [[rom]]
+            path = "cpu/01-implied.nes"
+
+            [[rom]]
+            path = "ppu/sprites.nes"
+            frames = 30
+            hash = "8d2c1e0a"
+            "#,
+            Path::new("tests"),
+            600,
+        )
+        .unwrap();
+        assert_eq!(
+            cases,
+            [
+                TestCase {
+                    path: PathBuf::from("tests/cpu/01-implied.nes"),
+                    frames: 600,
+                    expect: Expect::Blargg,
+                },
+                TestCase {
+                    path: PathBuf::from("tests/ppu/sprites.nes"),
+                    frames: 30,
+                    expect: Expect::FrameHash(0x8d2c1e0a),
+                },
+            ]
+        );
+        assert!(matches!(
+            parse_manifest("[[rom]]\npath = \"a.nes\"\nhash = \"xyz\"", Path::new(""), 1),
+            Err(TestError::BadHash(hash)) if hash == "xyz"
+        ));
+    }
+
+    #[test]
+    fn test_summary() {
+        let case = |path: &str| TestCase {
+            path: PathBuf::from(path),
+            frames: 60,
+            expect: Expect::Blargg,
+        };
+        let table = summary(&[
+            (case("a.nes"), Verdict::Passed, 12),
+            (case("longer.nes"), Verdict::Failed(2, String::new()), 60),
+            (
+                case("dummy.nes"),
+                Verdict::Failed(3, "Should read twice".to_string()),
+                60,
+            ),
+        ]);
+        assert_eq!(
+            table,
+            "ROM         Frames  Result\n\
+             a.nes           12  passed\n\
+             longer.nes      60  failed (2)\n\
+             dummy.nes       60  failed (3): Should read twice\n\
+             1 of 3 passed"
+        );
+    }
+
+    // with every rom that didn't pass in the message
+    fn assert_all_pass(paths: &[String], frames: u64) {
+        let failures: Vec<String> = collect(paths, frames)
+            .unwrap()
+            .iter()
+            .filter_map(|case| match run_case(case) {
+                (Verdict::Passed, _) => None,
+                (verdict, _) => Some(format!("{}: {}", case.path.display(), verdict)),
+            })
+            .collect();
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn test_bless() {
+        let dir = std::env::temp_dir().join("bless_test");
+        fs::create_dir_all(&dir).unwrap();
+        // a 16KB NROM that spins at $8000
+        let mut rom = vec![0x4e, 0x45, 0x53, 0x1a, 1, 0, 0, 0];
+        rom.resize(16, 0);
+        let mut prg = vec![0; 0x4000];
+        prg[..3].copy_from_slice(&[0x4c, 0x00, 0x80]);
+        prg[0x3ffc..0x3ffe].copy_from_slice(&[0x00, 0x80]);
+        rom.extend(prg);
+        fs::write(dir.join("spin.nes"), rom).unwrap();
+        let manifest = dir.join("golden.toml");
+        fs::write(
+            &manifest,
+            "[[rom]]\npath = \"spin.nes\"\nframes = 2\nhash = \"\"\n\n[[rom]]\npath = \"blargg.nes\"\n",
+        )
+        .unwrap();
+
+        // the blargg rom isn't there, only the one with a hash is run
+        assert_eq!(bless(&manifest, 60).unwrap(), 1);
+        let text = fs::read_to_string(&manifest).unwrap();
+        let cases = parse_manifest(&text, &dir, 60).unwrap();
+        assert!(matches!(cases[0].expect, Expect::FrameHash(_)));
+        assert_eq!(cases[1].expect, Expect::Blargg);
+        assert_eq!(run_case(&cases[0]), (Verdict::Passed, 2));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /**
+     * The golden frames in test_roms/golden.toml have to come out the same,
+     * a PPU change that moves a pixel shows up here. Rerun with `nes test
+     * --bless` when the change is meant to.
+     */
+    #[test]
+    fn golden_frames() {
+        let manifest = "./test_roms/golden.toml";
+        if !Path::new(manifest).exists() {
+            return;
+        }
+        assert_all_pass(&[manifest.to_string()], 60)
+    }
+
+    /**
+     * Every rom in the suites that are there has to pass, the failures are
+     * listed with what the roms said. The roms aren't checked in, missing
+     * suites are left out.
+     */
+    #[test]
+    fn blargg_suites() {
+        let suites: Vec<String> = SUITES
+            .iter()
+            .filter(|dir| Path::new(dir).is_dir())
+            .map(|dir| dir.to_string())
+            .collect();
+        if suites.is_empty() {
+            return;
+        }
+        assert_all_pass(&suites, 3600)
+    }
+}
@@ -0,0 +1,301 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fmt,
+    io::{self, BufRead, Write},
+    rc::Rc,
+};
+
+use regex::Regex;
+
+use crate::cpu::CPU;
+
+/**
+ * The registers on a line of a trace, whichever emulator wrote it. Cycle
+ * counts are optional, not every log has them.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TraceState {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    pub cycles: Option<u64>,
+}
+
+/**
+ * Reads nestest.log lines ("C000  4C F5 C5  JMP $C5F5  A:00 X:00 Y:00 P:24
+ * SP:FD PPU:  0, 21 CYC:7") and Mesen's ("C000 $4C $F5 $C5  JMP $C5F5
+ * A:00 X:00 Y:00 S:FD P:nvUbdIzc ... Cycle:7"), flags as hex or letters.
+ */
+pub struct TraceParser {
+    pc: Regex,
+    register: Regex,
+    flags: Regex,
+    cycles: Regex,
+}
+
+impl Default for TraceParser {
+    fn default() -> Self {
+        TraceParser::new()
+    }
+}
+
+impl TraceParser {
+    pub fn new() -> TraceParser {
+        TraceParser {
+            pc: Regex::new(r"^\s*\$?([0-9A-Fa-f]{4})\b").unwrap(),
+            register: Regex::new(r"\b(A|X|Y|SP|S):([0-9A-Fa-f]{2})\b").unwrap(),
+            flags: Regex::new(r"\bP:(?:([NnVvUu-][A-Za-z-]{7})|([0-9A-Fa-f]{2}))\b").unwrap(),
+            cycles: Regex::new(r"\b(?:CYC|Cycle):(\d+)").unwrap(),
+        }
+    }
+
+    /**
+     * None for lines without the registers, headers and the like.
+     */
+    pub fn parse(&self, line: &str) -> Option<TraceState> {
+        let pc = u16::from_str_radix(&self.pc.captures(line)?[1], 16).ok()?;
+        let (mut a, mut x, mut y, mut sp) = (None, None, None, None);
+        for caps in self.register.captures_iter(line) {
+            let value = u8::from_str_radix(&caps[2], 16).ok();
+            match &caps[1] {
+                "A" => a = value,
+                "X" => x = value,
+                "Y" => y = value,
+                _ => sp = value,
+            }
+        }
+        let flags = self.flags.captures(line)?;
+        let p = match (flags.get(1), flags.get(2)) {
+            // "nvUbdIzc", a capital for every flag that's set
+            (Some(letters), _) => letters
+                .as_str()
+                .chars()
+                .fold(0, |p, c| (p << 1) | c.is_ascii_uppercase() as u8),
+            (_, Some(hex)) => u8::from_str_radix(hex.as_str(), 16).ok()?,
+            _ => return None,
+        };
+        let cycles = self
+            .cycles
+            .captures(line)
+            .and_then(|caps| caps[1].parse().ok());
+        Some(TraceState {
+            pc,
+            a: a?,
+            x: x?,
+            y: y?,
+            p,
+            sp: sp?,
+            cycles,
+        })
+    }
+}
+
+/**
+ * The registers that don't agree. Cycles are compared by how long the
+ * instruction before took, emulators start counting at different places.
+ */
+fn differences(
+    expected: &TraceState,
+    actual: &TraceState,
+    last: Option<&(TraceState, TraceState)>,
+) -> Vec<&'static str> {
+    let mut fields = Vec::new();
+    for (name, differs) in [
+        ("PC", expected.pc != actual.pc),
+        ("A", expected.a != actual.a),
+        ("X", expected.x != actual.x),
+        ("Y", expected.y != actual.y),
+        ("P", expected.p != actual.p),
+        ("SP", expected.sp != actual.sp),
+    ] {
+        if differs {
+            fields.push(name)
+        }
+    }
+    let took = |before: &TraceState, after: &TraceState| Some(after.cycles? - before.cycles?);
+    if let Some((last_expected, last_actual)) = last {
+        match (took(last_expected, expected), took(last_actual, actual)) {
+            (Some(e), Some(a)) if e != a => fields.push("CYC"),
+            _ => (),
+        }
+    }
+    fields
+}
+
+/**
+ * Where the trace went its own way, with the lines leading up to it.
+ */
+#[derive(Debug)]
+pub struct Divergence {
+    // in the reference log, from 1
+    pub line: usize,
+    pub fields: Vec<&'static str>,
+    pub context: Vec<String>,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Diverged at line {} of the reference ({}):",
+            self.line,
+            self.fields.join(", ")
+        )?;
+        for line in &self.context {
+            writeln!(f, "  {}", line)?
+        }
+        writeln!(f, "- {}", self.expected)?;
+        write!(f, "+ {}", self.actual)
+    }
+}
+
+#[derive(Debug)]
+pub enum DiffResult {
+    // every line of the reference, this many
+    Matched(usize),
+    Diverged(Divergence),
+}
+
+// the cpu's trace, caught a line at a time
+#[derive(Clone)]
+struct LineBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for LineBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/**
+ * Run `cpu` an instruction per line of `reference` until they disagree or
+ * the reference runs out, keeping `context` lines from before. Lines the
+ * parser doesn't understand are skipped.
+ */
+pub fn diff(
+    cpu: &mut CPU,
+    reference: impl BufRead,
+    context: usize,
+) -> Result<DiffResult, io::Error> {
+    let parser = TraceParser::new();
+    let buffer = LineBuffer(Rc::new(RefCell::new(Vec::new())));
+    cpu.set_trace(Some(Box::new(buffer.clone())));
+    let mut recent = VecDeque::with_capacity(context);
+    let mut last = None;
+    let mut matched = 0;
+    for (idx, line) in reference.lines().enumerate() {
+        let line = line?;
+        let Some(expected) = parser.parse(&line) else {
+            continue;
+        };
+        cpu.step();
+        let traced = String::from_utf8_lossy(&buffer.0.borrow())
+            .trim_end()
+            .to_string();
+        buffer.0.borrow_mut().clear();
+        let actual = parser
+            .parse(&traced)
+            .expect("the cpu's own trace should parse");
+        let fields = differences(&expected, &actual, last.as_ref());
+        if !fields.is_empty() {
+            cpu.set_trace(None);
+            return Ok(DiffResult::Diverged(Divergence {
+                line: idx + 1,
+                fields,
+                context: recent.into(),
+                expected: line,
+                actual: traced,
+            }));
+        }
+        if context > 0 {
+            if recent.len() == context {
+                recent.pop_front();
+            }
+            recent.push_back(traced)
+        }
+        last = Some((expected, actual));
+        matched += 1
+    }
+    cpu.set_trace(None);
+    Ok(DiffResult::Matched(matched))
+}
+
+#[cfg(test)]
+mod tracediff_test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::cpu::cpu_with_program;
+
+    use super::{diff, DiffResult, LineBuffer, TraceParser, TraceState};
+
+    #[test]
+    fn test_parse() {
+        let parser = TraceParser::new();
+        let expected = TraceState {
+            pc: 0xc000,
+            a: 0,
+            x: 0x01,
+            y: 0x02,
+            p: 0x24,
+            sp: 0xfd,
+            cycles: Some(7),
+        };
+        assert_eq!(
+            parser.parse(
+                "C000  4C F5 C5  JMP $C5F5                       \
+                 A:00 X:01 Y:02 P:24 SP:FD PPU:  0, 21 CYC:7"
+            ),
+            Some(expected)
+        );
+        assert_eq!(
+            parser.parse(
+                "C000 $4C $F5 $C5  JMP $C5F5  A:00 X:01 Y:02 S:FD P:nvUbdIzc V:0 H:21 Fr:0 Cycle:7"
+            ),
+            Some(expected)
+        );
+        assert_eq!(parser.parse("-- trace started --"), None);
+    }
+
+    #[test]
+    fn test_diff() {
+        let program = [
+            0xa9, 0x10, // LDA #$10
+            0xaa, // TAX
+            0xe8, // INX
+            0x4c, 0x03, 0x80, // JMP $8003
+        ];
+        // our own trace is the reference, then one line of it is changed
+        let mut cpu = cpu_with_program(&program);
+        let buffer = LineBuffer(Rc::new(RefCell::new(Vec::new())));
+        cpu.set_trace(Some(Box::new(buffer.clone())));
+        for _ in 0..6 {
+            cpu.step();
+        }
+        cpu.set_trace(None);
+        let reference = String::from_utf8(buffer.0.take()).unwrap();
+        let result = diff(&mut cpu_with_program(&program), reference.as_bytes(), 2).unwrap();
+        assert!(matches!(result, DiffResult::Matched(6)));
+
+        let changed = reference.replacen("X:11", "X:12", 1);
+        let result = diff(&mut cpu_with_program(&program), changed.as_bytes(), 2).unwrap();
+        let DiffResult::Diverged(divergence) = result else {
+            panic!("expected a divergence")
+        };
+        // LDA, TAX, INX then JMP with X:11
+        assert_eq!(divergence.line, 4);
+        assert_eq!(divergence.fields, ["X"]);
+        assert_eq!(divergence.context.len(), 2);
+        assert!(divergence.actual.starts_with("8004"));
+        assert!(divergence.expected.contains("X:12"));
+    }
+}
@@ -0,0 +1,209 @@
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::{
+    cartridge::{Cartridge, CartridgeError, ConsoleType, Mirroring, Region, RomFormat, RomHashes},
+    mapper,
+};
+
+pub const UNIF_TAG: [u8; 4] = *b"UNIF";
+const HEADER_SIZE: usize = 32;
+const CHUNK_HEADER_SIZE: usize = 8;
+
+// chunk id ("MAPR", "PRG0"...) and its data
+type Chunk<'a> = ([u8; 4], &'a [u8]);
+
+/**
+ * Board names may come with a "NES-", "UNL-"... prefix, the registry only
+ * knows the bare name.
+ */
+fn strip_board_prefix(board: &str) -> &str {
+    for prefix in ["NES-", "UNL-", "HVC-", "BTL-", "BMC-"] {
+        if let Some(stripped) = board.strip_prefix(prefix) {
+            return stripped;
+        }
+    }
+    board
+}
+
+fn mirroring(mirr: u8) -> Option<Mirroring> {
+    match mirr {
+        0 => Some(Mirroring::Horizontal),
+        1 => Some(Mirroring::Vertical),
+        2 => Some(Mirroring::SingleScreenLower),
+        3 => Some(Mirroring::SingleScreenUpper),
+        4 => Some(Mirroring::FourScreen),
+        // 5 is mapper controlled
+        _ => None,
+    }
+}
+
+fn c_str(data: &[u8]) -> String {
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).trim().to_string()
+}
+
+/**
+ * Chunk id and data for every chunk after the 32 byte header.
+ */
+fn chunks(bytes: &[u8]) -> Result<Vec<Chunk<'_>>, CartridgeError> {
+    let mut chunks = Vec::new();
+    let mut idx = HEADER_SIZE;
+    while idx < bytes.len() {
+        let header = bytes
+            .get(idx..idx + CHUNK_HEADER_SIZE)
+            .ok_or(CartridgeError::Truncated {
+                expected: idx + CHUNK_HEADER_SIZE,
+                actual: bytes.len(),
+            })?;
+        let id = [header[0], header[1], header[2], header[3]];
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let start = idx + CHUNK_HEADER_SIZE;
+        let data = bytes
+            .get(start..start + len)
+            .ok_or(CartridgeError::Truncated {
+                expected: start + len,
+                actual: bytes.len(),
+            })?;
+        chunks.push((id, data));
+        idx = start + len;
+    }
+    Ok(chunks)
+}
+
+/**
+ * PRG0..PRGF (or CHR0..CHRF) glued together in bank order.
+ */
+fn rom_chunks(chunks: &[Chunk], kind: &[u8; 3]) -> Vec<u8> {
+    let mut banks: Vec<(u8, &[u8])> = chunks
+        .iter()
+        .filter(|(id, _)| id[..3] == kind[..] && id[3].is_ascii_hexdigit())
+        .map(|(id, data)| ((id[3] as char).to_digit(16).unwrap() as u8, *data))
+        .collect();
+    banks.sort_by_key(|(bank, _)| *bank);
+    banks
+        .into_iter()
+        .flat_map(|(_, data)| data.to_vec())
+        .collect()
+}
+
+/**
+ * Parse a UNIF image. Instead of a mapper number UNIF names the board
+ * ("NES-NROM-256", "UNL-BF9097"...), which is looked up in the mapper registry.
+ */
+pub fn parse(bytes: &[u8]) -> Result<Cartridge, CartridgeError> {
+    if bytes.len() < 4 || bytes[0..4] != UNIF_TAG {
+        return Err(CartridgeError::BadMagic);
+    }
+    if bytes.len() < HEADER_SIZE {
+        return Err(CartridgeError::Truncated {
+            expected: HEADER_SIZE,
+            actual: bytes.len(),
+        });
+    }
+    let chunks = chunks(bytes)?;
+    let find = |id: &[u8; 4]| chunks.iter().find(|(c, _)| c == id).map(|(_, data)| *data);
+
+    let board = find(b"MAPR")
+        .map(c_str)
+        .ok_or(CartridgeError::UnknownBoard(String::new()))?;
+    let (mapper, submapper) = mapper::board_mapper(strip_board_prefix(&board))
+        .ok_or_else(|| CartridgeError::UnknownBoard(board.clone()))?;
+
+    let prgrom = rom_chunks(&chunks, b"PRG");
+    if prgrom.is_empty() {
+        return Err(CartridgeError::NoPrgRom);
+    }
+    let chrrom = rom_chunks(&chunks, b"CHR");
+    let mirroring = find(b"MIRR")
+        .and_then(|mirr| mirr.first().copied())
+        .and_then(mirroring)
+        .unwrap_or_default();
+
+    let hashes = RomHashes::compute(&prgrom, &chrrom);
+    let mut cartridge = Cartridge {
+        prgrom,
+        chrrom,
+        mirroring,
+        mapper,
+        submapper,
+        console: ConsoleType::Nes,
+        // 0 NTSC, 1 PAL, 2 runs on both
+        region: match find(b"TVCI").and_then(|data| data.first()) {
+            Some(1) => Region::Pal,
+            _ => Region::Ntsc,
+        },
+        trainer: None,
+        // the chunk's there or it isn't
+        battery: find(b"BATR").is_some(),
+        format: RomFormat::Unif,
+        hashes,
+        title: find(b"NAME").map(c_str).filter(|name| !name.is_empty()),
+    };
+    cartridge.apply_romdb();
+    Ok(cartridge)
+}
+
+#[cfg(test)]
+mod unif_test {
+    use crate::cartridge::{CartridgeError, Mirroring, RomFormat};
+
+    use super::parse;
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend((data.len() as u32).to_le_bytes());
+        chunk.extend(data);
+        chunk
+    }
+
+    fn make_unif(board: &[u8]) -> Vec<u8> {
+        let mut bytes = b"UNIF".to_vec();
+        bytes.extend(7u32.to_le_bytes());
+        bytes.resize(32, 0);
+        bytes.extend(chunk(b"MAPR", board));
+        bytes.extend(chunk(b"NAME", b"Some Game\0"));
+        // out of order on purpose
+        bytes.extend(chunk(b"PRG1", &[0xbb; 0x4000]));
+        bytes.extend(chunk(b"PRG0", &[0xaa; 0x4000]));
+        bytes.extend(chunk(b"CHR0", &[0xcc; 0x2000]));
+        bytes.extend(chunk(b"MIRR", &[1]));
+        bytes
+    }
+
+    #[test]
+    fn test_parse() {
+        let cartridge = parse(&make_unif(b"NES-NROM-256\0")).unwrap();
+        assert_eq!(cartridge.mapper, 0);
+        assert_eq!(cartridge.mirroring, Mirroring::Vertical);
+        assert_eq!(cartridge.prgrom[0], 0xaa);
+        assert_eq!(cartridge.prgrom[0x4000], 0xbb);
+        assert_eq!(cartridge.chrrom, vec![0xcc; 0x2000]);
+        assert_eq!(cartridge.title.as_deref(), Some("Some Game"));
+        assert_eq!(cartridge.format, RomFormat::Unif);
+        assert!(!cartridge.battery);
+
+        let mut battery = make_unif(b"NES-NROM-256\0");
+        battery.extend(chunk(b"BATR", &[1]));
+        assert!(parse(&battery).unwrap().battery);
+
+        let fire_hawk = parse(&make_unif(b"UNL-BF9097\0")).unwrap();
+        assert_eq!((fire_hawk.mapper, fire_hawk.submapper), (71, 1));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            parse(&make_unif(b"UNL-NOT-A-BOARD\0")),
+            Err(CartridgeError::UnknownBoard(board)) if board == "UNL-NOT-A-BOARD"
+        ));
+        let mut truncated = make_unif(b"NES-NROM-256\0");
+        truncated.truncate(0x100);
+        assert!(matches!(
+            parse(&truncated),
+            Err(CartridgeError::Truncated { .. })
+        ));
+    }
+}
@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+
+use crate::ppu::Frame;
+
+// how many scanlines the photodiode keeps reporting light after the beam
+// passes, it's slow to settle
+const LIGHT_LINES: usize = 20;
+// pixels around the cursor the sensor sees
+const SENSOR_RADIUS: usize = 2;
+// average of r, g and b that counts as lit, Duck Hunt flashes white boxes
+// on a black screen
+const BRIGHTNESS_THRESHOLD: usize = 0x80;
+
+/**
+ * Zapper light gun, plugged into port 2 in place of controller 2.
+ *
+ * $4017 reads return the trigger in bit 4 and the light sensor in bit 3
+ * (0 when light is seen). The sensor only fires while the PPU is drawing
+ * something bright around where the gun is pointed, games check it right
+ * after flashing their targets.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Zapper {
+    trigger: bool,
+    // where the gun points in NES pixels, None when off screen
+    aim: Option<(usize, usize)>,
+    // what the sensor saw last time the bus looked, see `sense`
+    #[serde(skip)]
+    light: bool,
+}
+
+impl Default for Zapper {
+    fn default() -> Self {
+        Zapper::new()
+    }
+}
+
+impl Zapper {
+    pub fn new() -> Zapper {
+        Zapper {
+            trigger: false,
+            aim: None,
+            light: false,
+        }
+    }
+    pub fn set_trigger(&mut self, pulled: bool) {
+        self.trigger = pulled
+    }
+    pub fn aim(&mut self, aim: Option<(usize, usize)>) {
+        self.aim = aim
+    }
+    /**
+     * Look at the screen, right before a read. `scanline` and `dot` are
+     * where the PPU currently is, `frame` holds everything it has drawn so
+     * far.
+     */
+    pub fn sense(&mut self, frame: &Frame, scanline: usize, dot: usize) {
+        self.light = self.senses_light(frame, scanline, dot)
+    }
+    pub fn read(&self) -> u8 {
+        (!self.light as u8) << 3 | (self.trigger as u8) << 4
+    }
+    fn senses_light(&self, frame: &Frame, scanline: usize, dot: usize) -> bool {
+        let (x, y) = match self.aim {
+            Some(aim) => aim,
+            None => return false,
+        };
+        // lines are drawn once their visible dots are done
+        let last_drawn = match (scanline, dot) {
+            (line, 257..) if line < Frame::HEIGHT => line,
+            (0, _) => return false,
+            (line, _) => (line - 1).min(Frame::HEIGHT - 1),
+        };
+        if last_drawn < y || last_drawn - y >= LIGHT_LINES {
+            return false;
+        }
+        let xs = x.saturating_sub(SENSOR_RADIUS)..=(x + SENSOR_RADIUS).min(Frame::WIDTH - 1);
+        let ys = y.saturating_sub(SENSOR_RADIUS)..=(y + SENSOR_RADIUS).min(last_drawn);
+        ys.flat_map(|py| xs.clone().map(move |px| (px, py)))
+            .any(|(px, py)| {
+                let (r, g, b) = frame.pixel(px, py);
+                (r as usize + g as usize + b as usize) / 3 >= BRIGHTNESS_THRESHOLD
+            })
+    }
+}
+
+#[cfg(test)]
+mod zapper_test {
+    use crate::ppu::Frame;
+
+    use super::Zapper;
+
+    #[test]
+    fn test_light_window() {
+        let mut frame = Frame::new();
+        frame.set_pixel(100, 50, (0xff, 0xff, 0xff));
+        let mut zapper = Zapper::new();
+        zapper.aim(Some((101, 50)));
+
+        let read = |zapper: &mut Zapper, scanline, dot| {
+            zapper.sense(&frame, scanline, dot);
+            zapper.read()
+        };
+        // beam hasn't got there yet
+        assert_eq!(read(&mut zapper, 50, 100), 0b1000);
+        assert_eq!(read(&mut zapper, 50, 300), 0);
+        assert_eq!(read(&mut zapper, 60, 0), 0);
+        // long gone
+        assert_eq!(read(&mut zapper, 100, 0), 0b1000);
+
+        zapper.set_trigger(true);
+        zapper.aim(None);
+        assert_eq!(read(&mut zapper, 60, 0), 0b11000);
+    }
+}